@@ -5,19 +5,38 @@
 // - Screen capture (DXGI → GDI fallback)
 // - Input injection (SendInput)
 // - Terminal sessions (ConPTY)
+// - RUN_SHELL commands that asked for the user's context
+// - NOTIFY_USER toast notifications
+// - SCREENSHOT commands
 
 use std::collections::HashMap;
 
 use anyhow::{Context, Result};
 use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
+use agent_core::config::SessionIndicatorMode;
+use agent_core::desktop::{self, CapturePool, DesktopConfig, IndicatorState};
 use agent_core::protocol::{self, Message};
-use agent_core::desktop::{self, DesktopConfig};
 use agent_platform::terminal::Terminal;
 
 #[cfg(target_os = "windows")]
-use agent_windows::ipc::{IpcClient, IpcWriter};
+use agent_windows::ipc::{IpcClient, IpcFrame, IpcWriter};
+
+/// Settings the service passes to the helper on its command line
+pub struct HelperOptions {
+    pub indicator_mode: SessionIndicatorMode,
+    /// CreatePseudoConsole flags for helper terminals
+    pub conpty_flags: u32,
+    /// Frame rate ceiling for desktop sessions
+    pub max_fps: u16,
+    /// Whether desktop sessions may send uncompressed RAW tiles
+    pub raw_tiles: bool,
+    /// Close a terminal after this long without input or output
+    pub terminal_idle_timeout: Option<std::time::Duration>,
+    /// Device ID tagged onto every session span
+    pub device_id: String,
+}
 
 struct HelperTerminalSession {
     stdin_tx: mpsc::Sender<Vec<u8>>,
@@ -34,7 +53,7 @@ struct HelperDesktopSession {
 
 /// Run the helper process. Connects to the service pipe and processes messages.
 #[cfg(target_os = "windows")]
-pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
+pub async fn run_helper_mode(pipe_name: &str, options: HelperOptions) -> Result<()> {
     info!("helper mode starting, connecting to pipe: {}", pipe_name);
 
     // Retry connection a few times — the service may still be setting up the pipe
@@ -47,15 +66,41 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
 
     let mut terminal_sessions: HashMap<u16, HelperTerminalSession> = HashMap::new();
     let mut desktop_sessions: HashMap<u16, HelperDesktopSession> = HashMap::new();
+    // Screen captures shared by the desktop sessions
+    let mut captures = CapturePool::new();
+    // The service's connection send queue, as last reported over the pipe
+    let send_queue = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    // "Remote session active" overlay, shown while any desktop is open
+    let mut indicator = IndicatorState::new(
+        options.indicator_mode,
+        agent_windows::indicator::create_session_indicator,
+    );
 
     // Use a Mutex<IpcReader> so we own it properly in the loop
     let mut reader = reader;
 
+    // Tell the service what this session can serve; it folds these into
+    // the AGENT_INFO it sends the server
+    let caps = agent_core::session::session_capabilities();
+    info!("helper session capabilities: {}", caps.join(", "));
+    match Message::control_json(protocol::AGENT_INFO, 0, &caps) {
+        Ok(msg) => {
+            if let Err(e) = writer.lock().await.send_raw(&msg.encode()).await {
+                warn!("failed to send capabilities through pipe: {}", e);
+            }
+        }
+        Err(e) => warn!("failed to encode helper capabilities: {}", e),
+    }
+
     info!("helper connected, entering message loop");
 
     loop {
-        let raw = match reader.recv_raw().await {
-            Ok(data) => data,
+        let raw = match reader.recv().await {
+            Ok(IpcFrame::Message(data)) => data,
+            Ok(IpcFrame::Shutdown) => {
+                info!("service requested shutdown, helper exiting");
+                break;
+            }
             Err(e) => {
                 info!("pipe disconnected, helper shutting down: {}", e);
                 break;
@@ -82,6 +127,7 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
                 if desktop_sessions.contains_key(&channel) {
                     info!("desktop already open on channel {}, closing old", channel);
                     desktop_sessions.remove(&channel);
+                    captures.unsubscribe(channel);
                 }
 
                 let req: protocol::DesktopOpenRequest = match msg.parse_json() {
@@ -97,40 +143,118 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
                     channel, req.quality, req.fps
                 );
 
-                let config = DesktopConfig {
-                    quality: req.quality,
-                    fps: req.fps,
-                    encoding: req.encoding,
-                };
+                let config = DesktopConfig::from_request(req, options.max_fps, options.raw_tiles);
+
+                // Initialize capture and input up front so a failure is
+                // reported to the viewer instead of leaving it waiting
+                let (subscription, mut injector) =
+                    match init_helper_desktop(&mut captures, channel, &config).await {
+                        Ok(backends) => backends,
+                        Err(e) => {
+                            captures.unsubscribe(channel);
+                            error!(
+                                "helper: desktop open failed on channel {}: {:#}",
+                                channel, e
+                            );
+                            if let Ok(err_msg) = protocol::error_response(
+                                channel,
+                                msg.header.request_id,
+                                protocol::ErrorCode::Unavailable,
+                                format!("capture unavailable: {:#}", e),
+                            ) {
+                                let encoded = err_msg.encode();
+                                if let Err(e) = writer.lock().await.send_raw(&encoded).await {
+                                    debug!("failed to send desktop error through pipe: {}", e);
+                                }
+                            }
+                            continue;
+                        }
+                    };
+
+                if let Err(e) = indicator.show() {
+                    captures.unsubscribe(channel);
+                    error!(
+                        "helper: desktop open refused on channel {}: {:#}",
+                        channel, e
+                    );
+                    if let Ok(err_msg) = protocol::error_response(
+                        channel,
+                        msg.header.request_id,
+                        protocol::ErrorCode::Unavailable,
+                        format!("{:#}", e),
+                    ) {
+                        let encoded = err_msg.encode();
+                        if let Err(e) = writer.lock().await.send_raw(&encoded).await {
+                            debug!("failed to send desktop error through pipe: {}", e);
+                        }
+                    }
+                    continue;
+                }
 
                 let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
                 let (quality_tx, mut quality_rx) = mpsc::channel::<DesktopConfig>(8);
 
+                let span = info_span!("desktop", channel, device_id = %options.device_id);
+
                 // Capture task — sends frames back through the pipe
                 let writer_clone = writer.clone();
-                let capture_task = tokio::spawn(async move {
-                    if let Err(e) = run_helper_desktop_capture(channel, config, writer_clone).await {
-                        error!("helper desktop capture error on channel {}: {:#}", channel, e);
+                let send_queue = send_queue.clone();
+                let capture_task = tokio::spawn(
+                    async move {
+                        if let Err(e) = run_helper_desktop_capture(
+                            channel,
+                            config,
+                            subscription,
+                            writer_clone,
+                            send_queue,
+                        )
+                        .await
+                        {
+                            error!(
+                                "helper desktop capture error on channel {}: {:#}",
+                                channel, e
+                            );
+                        }
                     }
-                });
+                    .instrument(span.clone()),
+                );
 
                 // Input task — processes input events from the pipe
+                let event_writer = writer.clone();
                 let input_task = tokio::spawn(async move {
-                    let mut injector = match create_platform_input() {
-                        Ok(i) => i,
-                        Err(e) => {
-                            error!("failed to create input injector: {:#}", e);
-                            return;
-                        }
-                    };
+                    // Tell the viewer when the secure desktop swallows injected input
+                    let monitor_writer = event_writer.clone();
+                    let monitor_task = tokio::spawn(
+                        desktop::monitor_input_state(
+                            channel,
+                            agent_windows::session_detect::input_blocked_reason,
+                            move |msg| {
+                                let writer = monitor_writer.clone();
+                                async move {
+                                    let encoded = msg.encode();
+                                    writer.lock().await.send_raw(&encoded).await.is_ok()
+                                }
+                            },
+                        )
+                        .in_current_span(),
+                    );
 
                     loop {
                         tokio::select! {
                             input = input_rx.recv() => {
                                 match input {
                                     Some(data) => {
-                                        if let Err(e) = desktop::handle_desktop_input(&data, injector.as_mut()) {
-                                            warn!("desktop input error: {:#}", e);
+                                        match desktop::handle_desktop_input(&data, injector.as_mut()) {
+                                            Ok(Some(event)) => {
+                                                if let Ok(msg) = protocol::desktop_event(channel, &event) {
+                                                    let encoded = msg.encode();
+                                                    if let Err(e) = event_writer.lock().await.send_raw(&encoded).await {
+                                                        debug!("failed to send desktop event through pipe: {}", e);
+                                                    }
+                                                }
+                                            }
+                                            Ok(None) => {}
+                                            Err(e) => warn!("desktop input error: {:#}", e),
                                         }
                                     }
                                     None => break,
@@ -146,7 +270,11 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
                             }
                         }
                     }
-                });
+
+                    monitor_task.abort();
+                    // Never leave the local user locked out after the viewer leaves
+                    desktop::release_local_input(injector.as_mut());
+                }.instrument(span));
 
                 desktop_sessions.insert(channel, HelperDesktopSession {
                     input_tx,
@@ -161,6 +289,10 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
                 if desktop_sessions.remove(&channel).is_some() {
                     info!("helper: closed desktop on channel {}", channel);
                 }
+                captures.unsubscribe(channel);
+                if desktop_sessions.is_empty() {
+                    indicator.hide();
+                }
             }
 
             protocol::DESKTOP_INPUT => {
@@ -170,14 +302,19 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
                 }
             }
 
+            protocol::HELPER_SEND_QUEUE => match <[u8; 8]>::try_from(msg.payload.as_slice()) {
+                Ok(bytes) => send_queue.store(
+                    u64::from_le_bytes(bytes) as usize,
+                    std::sync::atomic::Ordering::Relaxed,
+                ),
+                Err(_) => warn!("invalid send queue report from service"),
+            },
+
             protocol::DESKTOP_QUALITY => {
                 let channel = msg.header.channel;
                 if let Ok(req) = msg.parse_json::<protocol::DesktopOpenRequest>() {
-                    let config = DesktopConfig {
-                        quality: req.quality,
-                        fps: req.fps,
-                        encoding: req.encoding,
-                    };
+                    let config =
+                        DesktopConfig::from_request(req, options.max_fps, options.raw_tiles);
                     if let Some(session) = desktop_sessions.get(&channel) {
                         let _ = session.quality_tx.send(config).await;
                     }
@@ -209,23 +346,39 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
                 let (resize_tx, resize_rx) = mpsc::channel::<(u16, u16)>(16);
                 let writer_clone = writer.clone();
 
-                let shell = req.shell;
-                let cols = req.cols;
-                let rows = req.rows;
-
-                let task = tokio::spawn(async move {
-                    if let Err(e) = run_helper_terminal(
-                        channel, shell, cols, rows, stdin_rx, resize_rx, writer_clone,
-                    ).await {
-                        error!("helper terminal session on channel {} error: {:#}", channel, e);
+                let conpty_flags = options.conpty_flags;
+                let idle_timeout = options.terminal_idle_timeout;
+                let span = info_span!("terminal", channel, device_id = %options.device_id);
+                let task = tokio::spawn(
+                    async move {
+                        if let Err(e) = run_helper_terminal(
+                            channel,
+                            req,
+                            stdin_rx,
+                            resize_rx,
+                            writer_clone,
+                            conpty_flags,
+                            idle_timeout,
+                        )
+                        .await
+                        {
+                            error!(
+                                "helper terminal session on channel {} error: {:#}",
+                                channel, e
+                            );
+                        }
                     }
-                });
+                    .instrument(span),
+                );
 
-                terminal_sessions.insert(channel, HelperTerminalSession {
-                    stdin_tx,
-                    resize_tx,
-                    _task: task,
-                });
+                terminal_sessions.insert(
+                    channel,
+                    HelperTerminalSession {
+                        stdin_tx,
+                        resize_tx,
+                        _task: task,
+                    },
+                );
             }
 
             protocol::TERMINAL_CLOSE => {
@@ -253,6 +406,105 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
                 }
             }
 
+            // --- Commands ---
+            protocol::COMMAND => {
+                let request_id = msg.header.request_id;
+                let command: serde_json::Value = match msg.parse_json() {
+                    Ok(c) => c,
+                    Err(e) => {
+                        error!("failed to parse COMMAND: {}", e);
+                        continue;
+                    }
+                };
+                if command["type"] == "NOTIFY_USER" {
+                    let writer_clone = writer.clone();
+                    tokio::spawn(async move {
+                        let result = match crate::notification_text(&command) {
+                            Ok((title, body)) => {
+                                let outcome = tokio::task::spawn_blocking(move || {
+                                    use agent_platform::notification::UserNotifier;
+                                    agent_windows::notification::WindowsNotifier
+                                        .notify(&title, &body)
+                                })
+                                .await
+                                .unwrap_or_else(|e| Err(e.into()));
+                                match outcome {
+                                    Ok(()) => serde_json::json!({ "success": true }),
+                                    Err(e) => {
+                                        serde_json::json!({ "success": false, "error": format!("notification error: {:#}", e) })
+                                    }
+                                }
+                            }
+                            Err(reason) => serde_json::json!({ "success": false, "error": reason }),
+                        };
+                        if let Ok(resp) =
+                            Message::control_json(protocol::COMMAND_RESULT, request_id, &result)
+                        {
+                            let encoded = resp.encode();
+                            if let Err(e) = writer_clone.lock().await.send_raw(&encoded).await {
+                                debug!("failed to send command result through pipe: {}", e);
+                            }
+                        }
+                    });
+                    continue;
+                }
+                if matches!(
+                    command["type"].as_str(),
+                    Some("SCREENSHOT" | "TAKE_SCREENSHOT")
+                ) {
+                    let (quality, scale) = crate::screenshot_options(&command);
+                    // Added by the service from the server connection
+                    let max_payload = command["max_payload"]
+                        .as_u64()
+                        .map_or(protocol::MAX_PAYLOAD_SIZE, |max| max as usize);
+                    let writer_clone = writer.clone();
+                    tokio::spawn(async move {
+                        let result = crate::screenshot_result(quality, scale, max_payload).await;
+                        if let Ok(resp) =
+                            Message::control_json(protocol::COMMAND_RESULT, request_id, &result)
+                        {
+                            let encoded = resp.encode();
+                            if let Err(e) = writer_clone.lock().await.send_raw(&encoded).await {
+                                debug!("failed to send command result through pipe: {}", e);
+                            }
+                        }
+                    });
+                    continue;
+                }
+                if command["type"] != "RUN_SHELL" {
+                    debug!("helper: ignoring command {}", command["type"]);
+                    continue;
+                }
+                let shell_cmd = command["command"].as_str().unwrap_or("").to_string();
+
+                // Commands may run for minutes; don't hold up the pipe
+                let writer_clone = writer.clone();
+                tokio::spawn(async move {
+                    info!(
+                        "helper: executing shell command as the session user: {}",
+                        shell_cmd
+                    );
+                    let result = if shell_cmd.is_empty() {
+                        serde_json::json!({ "success": false, "error": "missing 'command' field" })
+                    } else {
+                        match crate::run_captured(crate::shell_command(&shell_cmd)).await {
+                            Ok(out) => out.to_json(),
+                            Err(e) => {
+                                serde_json::json!({ "success": false, "error": format!("exec error: {}", e) })
+                            }
+                        }
+                    };
+                    if let Ok(resp) =
+                        Message::control_json(protocol::COMMAND_RESULT, request_id, &result)
+                    {
+                        let encoded = resp.encode();
+                        if let Err(e) = writer_clone.lock().await.send_raw(&encoded).await {
+                            debug!("failed to send command result through pipe: {}", e);
+                        }
+                    }
+                });
+            }
+
             other => {
                 debug!("helper: ignoring message type 0x{:02x}", other);
             }
@@ -262,43 +514,58 @@ pub async fn run_helper_mode(pipe_name: &str) -> Result<()> {
     // Cleanup
     terminal_sessions.clear();
     desktop_sessions.clear();
+    indicator.hide();
     info!("helper mode exiting");
     Ok(())
 }
 
-/// Run desktop capture in the helper, sending frames back through the IPC pipe.
+/// Subscribe to the screen capture and create the input injector for a
+/// desktop session in the helper.
+#[cfg(target_os = "windows")]
+async fn init_helper_desktop(
+    captures: &mut CapturePool,
+    channel: u16,
+    config: &DesktopConfig,
+) -> Result<(
+    desktop::FrameSubscription,
+    Box<dyn agent_platform::input::InputInjector>,
+)> {
+    let subscription = captures
+        .subscribe(channel, config, create_platform_screen)
+        .await?;
+    let mut injector = create_platform_input().context("failed to create input injector")?;
+    injector.set_virtual_desktop(config.stitched);
+    Ok((subscription, injector))
+}
+
+/// Encode a channel's frames from its shared capture in the helper, sending
+/// them back through the IPC pipe. Ends when the capture stops.
 #[cfg(target_os = "windows")]
 async fn run_helper_desktop_capture(
     channel: u16,
     config: DesktopConfig,
+    subscription: desktop::FrameSubscription,
     writer: std::sync::Arc<tokio::sync::Mutex<IpcWriter>>,
+    send_queue: std::sync::Arc<std::sync::atomic::AtomicUsize>,
 ) -> Result<()> {
-    let mut screen = create_platform_screen()?;
-
-    let (width, height) = screen.init().await
-        .context("failed to initialize screen capture")?;
-
-    let mut encoder = desktop::TileEncoder::new(width, height, config.quality);
-
-    let frame_interval = std::time::Duration::from_millis(1000 / config.fps.max(1) as u64);
+    let in_flight = || send_queue.load(std::sync::atomic::Ordering::Relaxed);
+    let mut frame_interval = desktop::frame_interval(&config);
+    let (mut width, mut height) = subscription.dimensions;
+    let mut frames = subscription.frames;
+    let mut restarts = subscription.restarts;
+
+    // "auto" encoding tunes itself on the send queue the service reports
+    let mut auto = config.is_auto().then(|| desktop::AutoQuality::new(&config));
+    if let Some(auto) = &auto {
+        frame_interval = desktop::fps_interval(auto.profile().fps);
+    }
+    let mut encoder = desktop::session_encoder(&config, auto.as_ref(), width, height);
+    let mut window_start = tokio::time::Instant::now();
+    let mut window_sent = 0usize;
 
     // Send initial DESKTOP_RESIZE
-    {
-        let resize_msg = protocol::Message::session(
-            protocol::DESKTOP_RESIZE,
-            channel,
-            0,
-            {
-                let mut p = Vec::with_capacity(4);
-                use bytes::BufMut;
-                p.put_u16_le(width as u16);
-                p.put_u16_le(height as u16);
-                p
-            },
-        );
-        let encoded = resize_msg.encode();
-        writer.lock().await.send_raw(&encoded).await?;
-    }
+    let encoded = desktop::resize_message(channel, width, height).encode();
+    writer.lock().await.send_raw(&encoded).await?;
 
     info!(
         "helper desktop capture started on channel {} ({}x{}, {}fps)",
@@ -306,18 +573,79 @@ async fn run_helper_desktop_capture(
     );
 
     let mut interval = tokio::time::interval(frame_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
     loop {
         interval.tick().await;
 
-        let frame = match screen.capture_frame().await {
-            Ok(f) => f,
-            Err(e) => {
-                warn!("screen capture failed: {:#}", e);
-                continue;
-            }
+        // Wait for a frame newer than the last one this channel encoded;
+        // while a reduced keyframe is being refined, the last frame is
+        // encoded again so a static screen still reaches full quality
+        if !encoder.is_refining() && frames.changed().await.is_err() {
+            debug!("screen capture for channel {} stopped", channel);
+            return Ok(());
+        }
+        let Some(frame) = frames.borrow_and_update().clone() else {
+            continue;
         };
 
+        // The capture came back at another size: start over with a new
+        // encoder, whose first frame is a keyframe
+        if (frame.width, frame.height) != (width, height) {
+            info!(
+                "helper desktop on channel {} resized from {}x{} to {}x{}",
+                channel, width, height, frame.width, frame.height
+            );
+            (width, height) = (frame.width, frame.height);
+            encoder = desktop::session_encoder(&config, auto.as_ref(), width, height);
+            let encoded = desktop::resize_message(channel, width, height).encode();
+            writer.lock().await.send_raw(&encoded).await?;
+        }
+
+        // A restarted capture gets a full repaint
+        if restarts.has_changed().unwrap_or(false) {
+            let count = *restarts.borrow_and_update();
+            encoder.request_keyframe();
+            if let Ok(msg) =
+                protocol::desktop_event(channel, &desktop::capture_restarted_event(count))
+            {
+                let encoded = msg.encode();
+                let _ = writer.lock().await.send_raw(&encoded).await;
+            }
+        }
+
+        if let Some(auto) = auto.as_mut() {
+            let elapsed = window_start.elapsed();
+            if elapsed >= desktop::AUTO_ADJUST_INTERVAL {
+                if let Some(profile) = auto.update(elapsed, window_sent, in_flight()) {
+                    debug!(
+                        "auto encoding on channel {}: ~{} kbit/s, quality {}, {}fps, {:?}",
+                        channel,
+                        (auto.bandwidth() * 8.0 / 1000.0) as u64,
+                        profile.quality,
+                        profile.fps,
+                        profile.subsampling
+                    );
+                    encoder.set_quality(profile.quality);
+                    encoder.set_subsampling(profile.subsampling);
+                    interval = tokio::time::interval(desktop::fps_interval(profile.fps));
+                    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                }
+                window_start = tokio::time::Instant::now();
+                window_sent = 0;
+            }
+        }
+
+        // Network stalled: drop this frame instead of queueing more tiles
+        let queued = in_flight();
+        if queued > desktop::MAX_IN_FLIGHT_BYTES {
+            debug!(
+                "skipping frame on channel {}: {} bytes in flight",
+                channel, queued
+            );
+            continue;
+        }
+
         let tiles = match encoder.encode_frame(&frame.data, frame.stride) {
             Ok(t) => t,
             Err(e) => {
@@ -327,13 +655,14 @@ async fn run_helper_desktop_capture(
         };
 
         for tile in tiles {
+            window_sent += tile.data.len();
             let msg = protocol::desktop_frame(
                 channel,
                 tile.x,
                 tile.y,
                 tile.w,
                 tile.h,
-                desktop::ENCODING_JPEG,
+                tile.encoding,
                 tile.flags,
                 tile.data,
             );
@@ -350,28 +679,30 @@ async fn run_helper_desktop_capture(
 #[cfg(target_os = "windows")]
 async fn run_helper_terminal(
     channel: u16,
-    shell: Option<String>,
-    cols: u16,
-    rows: u16,
+    req: protocol::TerminalOpenRequest,
     mut stdin_rx: mpsc::Receiver<Vec<u8>>,
     mut resize_rx: mpsc::Receiver<(u16, u16)>,
     writer: std::sync::Arc<tokio::sync::Mutex<IpcWriter>>,
+    conpty_flags: u32,
+    idle_timeout: Option<std::time::Duration>,
 ) -> Result<()> {
-    let mut terminal = create_platform_terminal()?;
+    let mut terminal = create_platform_terminal(conpty_flags)?;
 
     terminal
-        .spawn(shell.as_deref(), cols, rows)
+        .spawn(req.shell.as_deref(), req.cols, req.rows, req.login)
         .await
         .context("failed to spawn terminal")?;
 
     info!("helper terminal session started on channel {}", channel);
 
+    let mut last_activity = tokio::time::Instant::now();
     loop {
         tokio::select! {
             result = terminal.read_stdout() => {
                 match result {
                     Ok(data) if data.is_empty() => continue,
                     Ok(data) => {
+                        last_activity = tokio::time::Instant::now();
                         let msg = protocol::terminal_data(channel, data);
                         let encoded = msg.encode();
                         if let Err(e) = writer.lock().await.send_raw(&encoded).await {
@@ -389,6 +720,7 @@ async fn run_helper_terminal(
             data = stdin_rx.recv() => {
                 match data {
                     Some(data) => {
+                        last_activity = tokio::time::Instant::now();
                         if let Err(e) = terminal.write_stdin(&data).await {
                             error!("failed to write terminal stdin: {}", e);
                             break;
@@ -411,6 +743,12 @@ async fn run_helper_terminal(
                     None => {}
                 }
             }
+
+            // Nobody has typed or seen output for a while: free the console
+            _ = tokio::time::sleep_until(last_activity + idle_timeout.unwrap_or_default()), if idle_timeout.is_some() => {
+                info!("terminal on channel {} idle, closing", channel);
+                break;
+            }
         }
 
         if !terminal.is_alive() {
@@ -456,7 +794,18 @@ async fn retry_connect(
 // --- Platform factories (same as session.rs but local to helper) ---
 
 #[cfg(target_os = "windows")]
-fn create_platform_screen() -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+fn create_platform_screen(
+    config: &DesktopConfig,
+) -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+    if config.targets_window() {
+        return agent_windows::screen::create_window_capture(
+            config.window_title.as_deref(),
+            config.window_handle,
+        );
+    }
+    if config.stitched {
+        return agent_windows::screen::create_stitched_screen_capture();
+    }
     agent_windows::screen::create_screen_capture()
 }
 
@@ -466,6 +815,8 @@ fn create_platform_input() -> Result<Box<dyn agent_platform::input::InputInjecto
 }
 
 #[cfg(target_os = "windows")]
-fn create_platform_terminal() -> Result<Box<dyn Terminal>> {
-    Ok(Box::new(agent_windows::terminal::WindowsTerminal::new()))
+fn create_platform_terminal(conpty_flags: u32) -> Result<Box<dyn Terminal>> {
+    Ok(Box::new(
+        agent_windows::terminal::WindowsTerminal::with_conpty_flags(conpty_flags),
+    ))
 }
@@ -1,15 +1,20 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info, warn};
 
 use agent_core::auto_update;
 use agent_core::config::AgentConfig;
 use agent_core::connection::{self, ConnectionHandle, ServerEvent};
+use agent_core::control;
 use agent_core::files::FileHandler;
 use agent_core::protocol;
 use agent_core::session::SessionManager;
-use agent_core::telemetry::TelemetryCollector;
+use agent_core::telemetry::{DiskFilter, TelemetryCollector};
+use agent_platform::clock::SystemClock;
+use agent_platform::network::{valid_interface_name, NetworkControl};
+use agent_platform::notification::UserNotifier;
+use agent_platform::service::{ServiceAction, SystemServices};
 
 #[cfg(target_os = "windows")]
 mod helper;
@@ -33,13 +38,18 @@ struct Cli {
     #[arg(long, env = "AGENT_CONFIG_PATH", global = true)]
     config_path: Option<String>,
 
+    /// Writable directory for agent state [default: ProgramData on Windows,
+    /// /var/lib on Linux]
+    #[arg(long, env = "AGENT_DATA_DIR", global = true)]
+    data_dir: Option<String>,
+
     /// Run in foreground (don't daemonize)
     #[arg(long, default_value = "true")]
     foreground: bool,
 
-    /// Log level (trace, debug, info, warn, error)
-    #[arg(long, default_value = "info", env = "AGENT_LOG_LEVEL", global = true)]
-    log_level: String,
+    /// Log level (trace, debug, info, warn, error) [default: config file, then info]
+    #[arg(long, env = "AGENT_LOG_LEVEL", global = true)]
+    log_level: Option<String>,
 
     /// Run as helper process (spawned by service, not user-facing)
     #[arg(long, hide = true)]
@@ -49,6 +59,31 @@ struct Cli {
     #[arg(long, hide = true)]
     pipe_name: Option<String>,
 
+    /// Session indicator mode for the helper (required, optional, off)
+    #[arg(long, hide = true, default_value = "optional")]
+    session_indicator: String,
+
+    /// CreatePseudoConsole flags for helper terminals (default: inherit cursor)
+    #[arg(long, hide = true, default_value = "1")]
+    conpty_flags: u32,
+
+    /// Frame rate ceiling for helper desktop sessions
+    #[arg(long, hide = true, default_value = "30")]
+    max_fps: u16,
+
+    /// Let helper desktop sessions send uncompressed RAW tiles
+    #[arg(long, hide = true)]
+    raw_tiles: bool,
+
+    /// Minutes without input or output before a helper terminal is closed
+    /// (0 disables)
+    #[arg(long, hide = true, default_value = "0")]
+    terminal_idle_timeout_mins: u64,
+
+    /// Device ID the helper tags its session logs with
+    #[arg(long, hide = true, default_value = "default")]
+    device_id: String,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -60,6 +95,26 @@ enum Commands {
         /// Installation directory (default: platform-specific)
         #[arg(long)]
         install_dir: Option<String>,
+
+        /// Telemetry interval in seconds to save in the config
+        #[arg(long)]
+        telemetry_interval: Option<u64>,
+
+        /// Heartbeat interval in seconds to save in the config
+        #[arg(long)]
+        heartbeat_interval: Option<u64>,
+
+        /// Refuse service start/stop/restart commands from the server
+        #[arg(long)]
+        no_service_control: bool,
+
+        /// Refuse clock changes from the server
+        #[arg(long)]
+        no_clock_changes: bool,
+
+        /// Refuse file downloads from the server
+        #[arg(long)]
+        no_downloads: bool,
     },
     /// Remove the agent service and optionally all files
     Uninstall {
@@ -67,15 +122,27 @@ enum Commands {
         #[arg(long)]
         purge: bool,
     },
+    /// Report whether the agent is installed, enrolled, and reachable
+    Status {
+        /// Also check that the configured server is reachable
+        #[arg(long)]
+        ping: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    // Initialize logging
+    // Initialize logging: RUST_LOG, then --log-level / AGENT_LOG_LEVEL,
+    // then the level saved in the config file
+    let log_level = cli
+        .log_level
+        .clone()
+        .or_else(|| configured_log_level(cli.config_path.as_deref()))
+        .unwrap_or_else(|| "info".to_string());
     let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&cli.log_level));
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&log_level));
 
     tracing_subscriber::fmt()
         .with_env_filter(env_filter)
@@ -91,17 +158,34 @@ async fn main() -> Result<()> {
 
     // Dispatch subcommands
     match cli.command {
-        Some(Commands::Install { install_dir }) => {
-            return install::run_install(
-                install_dir,
-                cli.server_url,
-                cli.enroll_token,
-            )
-            .await;
+        Some(Commands::Install {
+            install_dir,
+            telemetry_interval,
+            heartbeat_interval,
+            no_service_control,
+            no_clock_changes,
+            no_downloads,
+        }) => {
+            let settings = install::InstallConfig {
+                log_level: cli.log_level,
+                telemetry_interval_secs: telemetry_interval,
+                heartbeat_interval_secs: heartbeat_interval,
+                policy: agent_core::config::CommandPolicy {
+                    service_control: !no_service_control,
+                    clock_changes: !no_clock_changes,
+                    downloads: !no_downloads,
+                    ..Default::default()
+                },
+            };
+            return install::run_install(install_dir, cli.server_url, cli.enroll_token, settings)
+                .await;
         }
         Some(Commands::Uninstall { purge }) => {
             return install::run_uninstall(purge);
         }
+        Some(Commands::Status { ping }) => {
+            return install::run_status(cli.config_path, ping).await;
+        }
         None => {
             // Run as daemon (default behavior).
             // Installation is handled exclusively by the `install` subcommand,
@@ -122,8 +206,17 @@ async fn main() -> Result<()> {
             .pipe_name
             .as_deref()
             .ok_or_else(|| anyhow::anyhow!("--pipe-name is required with --helper-mode"))?;
+        let options = helper::HelperOptions {
+            indicator_mode: cli.session_indicator.parse()?,
+            conpty_flags: cli.conpty_flags,
+            max_fps: cli.max_fps,
+            raw_tiles: cli.raw_tiles,
+            terminal_idle_timeout: (cli.terminal_idle_timeout_mins > 0)
+                .then(|| std::time::Duration::from_secs(cli.terminal_idle_timeout_mins * 60)),
+            device_id: cli.device_id.clone(),
+        };
         info!("starting in helper mode with pipe: {}", pipe_name);
-        return helper::run_helper_mode(pipe_name).await;
+        return helper::run_helper_mode(pipe_name, options).await;
     }
 
     // Load or create config
@@ -147,9 +240,33 @@ async fn main() -> Result<()> {
     if let Some(token) = cli.enroll_token {
         config.enroll_token = Some(token);
     }
+    if let Some(dir) = cli.data_dir {
+        config.data_dir = Some(dir);
+    }
 
-    if config.server_url.is_empty() {
-        anyhow::bail!("server URL is required (--server-url or config file)");
+    config
+        .validate()
+        .with_context(|| format!("config file: {}", config_path.display()))?;
+    warn_short_telemetry_interval(&mut config);
+
+    let data_dir = config.data_dir();
+    match std::fs::create_dir_all(&data_dir) {
+        Ok(()) => info!("data directory: {}", data_dir.display()),
+        Err(e) => warn!(
+            "failed to create data directory {}: {}",
+            data_dir.display(),
+            e
+        ),
+    }
+    // Downloads are executed from here as SYSTEM; users must not be able
+    // to plant or swap files in it
+    #[cfg(target_os = "windows")]
+    if let Err(e) = agent_windows::filesystem::restrict_to_administrators(&data_dir) {
+        warn!(
+            "failed to restrict data directory {}: {:#}",
+            data_dir.display(),
+            e
+        );
     }
 
     // Enrollment: if we don't have a session token, enroll first
@@ -172,11 +289,38 @@ async fn main() -> Result<()> {
         info!("config saved to {}", config_path.display());
     }
 
+    // When started by the SCM, run under the service dispatcher so a
+    // service stop takes the same graceful path as Ctrl+C
+    #[cfg(target_os = "windows")]
+    {
+        let runtime = tokio::runtime::Handle::current();
+        let service_config = config.clone();
+        let service_config_path = config_path.clone();
+        let dispatched = tokio::task::block_in_place(|| {
+            agent_windows::service::run_service_dispatcher(move |shutdown_rx| {
+                runtime.block_on(run_agent(
+                    service_config.clone(),
+                    service_config_path.clone(),
+                    shutdown_rx,
+                ))
+            })
+        });
+        match dispatched {
+            Ok(()) => return Ok(()),
+            Err(e) => info!("not running as a service, staying in foreground: {:#}", e),
+        }
+    }
+
     // Run the agent
-    run_agent(config, config_path).await
+    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+    run_agent(config, config_path, shutdown_rx).await
 }
 
-async fn run_agent(mut config: AgentConfig, config_path: std::path::PathBuf) -> Result<()> {
+async fn run_agent(
+    mut config: AgentConfig,
+    config_path: std::path::PathBuf,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> Result<()> {
     // Detect if we need the helper process architecture (Windows Session 0)
     #[cfg(target_os = "windows")]
     let use_helper = agent_windows::session_detect::is_system_service_context();
@@ -192,30 +336,70 @@ async fn run_agent(mut config: AgentConfig, config_path: std::path::PathBuf) ->
     let (event_tx, mut event_rx) = mpsc::channel::<ServerEvent>(64);
 
     let handle = connection::run_connection(config.clone(), event_tx).await?;
-    let mut session_mgr = SessionManager::new(handle.clone());
+    let mut session_mgr = SessionManager::new(handle.clone(), &config);
     let mut file_handler = create_file_handler()?;
-    let telemetry = create_telemetry_collector()?;
+    let telemetry = create_telemetry_collector(&config)?;
+
+    // Helper connects, disconnects and closed sessions, reported by the
+    // helper's monitor and relay
+    let (helper_tx, mut helper_rx) = mpsc::channel::<HelperEvent>(64);
+    #[cfg(not(target_os = "windows"))]
+    let _ = helper_tx;
 
     // --- Session 0: set up IPC + helper process ---
     #[cfg(target_os = "windows")]
-    let ipc_writer: Option<std::sync::Arc<tokio::sync::Mutex<agent_windows::ipc::IpcWriter>>> =
-        if use_helper {
-            match setup_helper_ipc(&config, &handle).await {
-                Ok(writer) => Some(writer),
-                Err(e) => {
-                    error!("failed to set up helper IPC: {:#}", e);
-                    error!("desktop/terminal will not work in this session");
-                    None
-                }
+    let ipc_writer: Option<HelperWriter> = if use_helper {
+        match setup_helper_ipc(&config, &handle, helper_tx) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                error!("failed to set up helper IPC: {:#}", e);
+                error!("desktop/terminal will not work in this session");
+                None
             }
-        } else {
-            None
-        };
+        }
+    } else {
+        None
+    };
+
+    // Session features the connected helper reported. Without a helper,
+    // Session 0 has no desktop, audio or user notifications.
+    let mut helper_caps: Option<Vec<String>> = None;
+    let mut capabilities = agent_capabilities(use_helper, helper_caps.as_deref());
+
+    // Sessions opened in the helper, by (open message type, channel)
+    let mut helper_sessions: std::collections::HashSet<(u8, u16)> =
+        std::collections::HashSet::new();
+
+    // Health queries from local monitoring over the control socket
+    let (health_tx, mut health_rx) = mpsc::channel::<control::HealthQuery>(8);
+    {
+        let config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(&config, health_tx).await {
+                warn!("control socket unavailable: {:#}", e);
+            }
+        });
+    }
 
-    // Periodic telemetry every 60 seconds
-    let mut telemetry_interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    // Periodic telemetry (every 60 seconds by default)
+    let mut telemetry_interval = telemetry_ticker(config.telemetry_interval_secs);
     telemetry_interval.tick().await; // consume the immediate first tick
+
+    // Settings that can change without a restart are re-read when the
+    // config file changes
+    let mut config_mtime = config_modified(&config_path);
+    let mut reload_interval = tokio::time::interval(CONFIG_RELOAD_INTERVAL);
+    reload_interval.tick().await;
     let mut authenticated = false;
+    let mut last_disconnect: Option<connection::DisconnectReason> = None;
+
+    // systemd watchdog pings (Linux, only when the unit sets WatchdogSec)
+    let watchdog_period = sd_watchdog_interval();
+    let mut watchdog_interval =
+        tokio::time::interval(watchdog_period.unwrap_or(std::time::Duration::from_secs(60)));
+
+    let mut upload_sweep_interval = tokio::time::interval(UPLOAD_SWEEP_INTERVAL);
+    upload_sweep_interval.tick().await;
 
     info!("agent running, press Ctrl+C to stop");
 
@@ -226,6 +410,8 @@ async fn run_agent(mut config: AgentConfig, config_path: std::path::PathBuf) ->
                     Some(ServerEvent::Authenticated { device_id, session_token }) => {
                         info!("connected and authenticated as device {}", device_id);
                         authenticated = true;
+                        #[cfg(target_os = "linux")]
+                        agent_linux::sd_notify::ready();
                         // Update config with new session token if changed
                         if !session_token.is_empty() && config.session_token.as_deref() != Some(&session_token) {
                             config.session_token = Some(session_token);
@@ -235,57 +421,235 @@ async fn run_agent(mut config: AgentConfig, config_path: std::path::PathBuf) ->
                             }
                         }
                         // Send agent info
-                        if let Err(e) = send_agent_info(&handle).await {
+                        if let Err(e) = send_agent_info(&handle, &capabilities).await {
                             error!("failed to send agent info: {}", e);
                         }
                         // Send initial telemetry
                         telemetry.send_telemetry_quiet(&handle).await;
+                        // Pick up commands issued while we were away
+                        let sync = protocol::Message::control(protocol::COMMAND_SYNC_REQ, 0, b"{}".to_vec());
+                        if let Err(e) = handle.send_message(&sync).await {
+                            error!("failed to request queued commands: {}", e);
+                        }
+                    }
+                    Some(ServerEvent::TokenRefreshed { session_token }) => {
+                        config.session_token = Some(session_token);
+                        if let Err(e) = config.save(&config_path) {
+                            warn!("failed to save refreshed session token: {}", e);
+                        }
+                    }
+                    Some(ServerEvent::ServerChanged { server_url }) => {
+                        config.server_url = server_url;
+                        if let Err(e) = config.save(&config_path) {
+                            warn!("failed to save new server URL: {}", e);
+                        }
                     }
                     Some(ServerEvent::Message(msg)) => {
                         // In Session 0 mode, proxy desktop/terminal messages through IPC
                         #[cfg(target_os = "windows")]
                         if use_helper {
                             if is_session_message(msg.header.msg_type) {
+                                // The helper has no memory guard of its own
+                                if matches!(msg.header.msg_type, protocol::TERMINAL_OPEN | protocol::DESKTOP_OPEN) {
+                                    match session_mgr.refuse_under_memory_pressure(&msg).await {
+                                        Ok(false) => {}
+                                        Ok(true) => continue,
+                                        Err(e) => {
+                                            warn!("failed to refuse session on channel {}: {:#}", msg.header.channel, e);
+                                            continue;
+                                        }
+                                    }
+                                }
+                                track_helper_session(&mut helper_sessions, msg.header.msg_type, msg.header.channel);
                                 if let Some(ref writer) = ipc_writer {
-                                    let encoded = msg.encode();
-                                    if let Err(e) = writer.lock().await.send_raw(&encoded).await {
-                                        error!("failed to forward message to helper: {}", e);
+                                    if let Err(e) = send_to_helper(writer, &msg.encode()).await {
+                                        warn!("dropping session message 0x{:02x}: {:#}", msg.header.msg_type, e);
                                     }
                                 } else {
                                     warn!("no helper IPC — dropping session message 0x{:02x}", msg.header.msg_type);
                                 }
                                 continue;
                             }
+                            // Without a helper these are handled here, where
+                            // they fail or run as SYSTEM with a note
+                            if is_user_session_command(&msg) {
+                                if let Some(ref writer) = ipc_writer {
+                                    let msg = with_payload_limit(msg.clone(), handle.max_payload());
+                                    match send_to_helper(writer, &msg.encode()).await {
+                                        Ok(()) => continue,
+                                        Err(e) => warn!("failed to forward user session command to helper: {:#}", e),
+                                    }
+                                }
+                            }
                         }
 
-                        handle_server_message(msg, &handle, &mut session_mgr, &mut file_handler, &telemetry, &config).await;
+                        handle_server_message(msg, &handle, &mut session_mgr, &mut file_handler, &telemetry, &config, &config_path).await;
+                        handle.set_busy(session_mgr.has_active_sessions());
                     }
-                    Some(ServerEvent::Disconnected) => {
-                        warn!("disconnected from server, will reconnect...");
+                    Some(ServerEvent::Disconnected(reason)) => {
+                        warn!("disconnected from server ({}), will reconnect...", reason);
                         authenticated = false;
+                        last_disconnect = Some(reason);
+                        #[cfg(target_os = "linux")]
+                        agent_linux::sd_notify::reloading();
                         session_mgr.close_all();
+                        file_handler.abandon_uploads();
+                        helper_sessions.clear();
+                        handle.set_busy(false);
+                    }
+                    Some(ServerEvent::Idle) => {
+                        debug!("idle, disconnected until the next check-in");
+                        authenticated = false;
                     }
                     None => {
                         info!("event channel closed, shutting down");
                         session_mgr.close_all();
+                        #[cfg(target_os = "windows")]
+                        shutdown_helper(ipc_writer.as_ref()).await;
                         break;
                     }
                 }
             }
+            Some(reply) = health_rx.recv() => {
+                let _ = reply.send(control::HealthState {
+                    connected: authenticated,
+                    device_id: config.device_id.clone(),
+                    last_telemetry_unix: telemetry.last_sent_unix(),
+                    active_sessions: session_mgr.session_count() + helper_sessions.len(),
+                    last_disconnect: last_disconnect.as_ref().map(ToString::to_string),
+                });
+            }
+            Some(event) = helper_rx.recv() => {
+                // A new or dead helper has no sessions; closes the helper
+                // reports end the ones it had
+                match event {
+                    HelperEvent::Connected => helper_sessions.clear(),
+                    HelperEvent::Disconnected => {
+                        helper_sessions.clear();
+                        helper_caps = None;
+                    }
+                    HelperEvent::Capabilities(caps) => helper_caps = Some(caps),
+                    HelperEvent::Closed { msg_type, channel } => {
+                        track_helper_session(&mut helper_sessions, msg_type, channel);
+                    }
+                }
+                // Tell the server when the helper coming or going changes
+                // what this agent can do
+                let updated = agent_capabilities(use_helper, helper_caps.as_deref());
+                if updated != capabilities {
+                    capabilities = updated;
+                    if authenticated {
+                        if let Err(e) = send_agent_info(&handle, &capabilities).await {
+                            error!("failed to send agent info: {}", e);
+                        }
+                    }
+                }
+            }
             _ = telemetry_interval.tick(), if authenticated => {
                 telemetry.send_telemetry_quiet(&handle).await;
             }
+            _ = upload_sweep_interval.tick() => {
+                file_handler.expire_idle_uploads();
+            }
+            _ = reload_interval.tick() => {
+                let mtime = config_modified(&config_path);
+                if mtime == config_mtime {
+                    continue;
+                }
+                config_mtime = mtime;
+                let reloaded = AgentConfig::load(&config_path).and_then(|mut c| {
+                    c.validate()?;
+                    warn_short_telemetry_interval(&mut c);
+                    Ok(c)
+                });
+                match reloaded {
+                    Ok(reloaded) if reloaded.telemetry_interval_secs != config.telemetry_interval_secs => {
+                        info!(
+                            "telemetry interval changed: {}s -> {}s",
+                            config.telemetry_interval_secs, reloaded.telemetry_interval_secs
+                        );
+                        config.telemetry_interval_secs = reloaded.telemetry_interval_secs;
+                        telemetry_interval = telemetry_ticker(config.telemetry_interval_secs);
+                        telemetry_interval.tick().await;
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("ignoring changed config: {:#}", e),
+                }
+            }
+            _ = watchdog_interval.tick(), if watchdog_period.is_some() => {
+                #[cfg(target_os = "linux")]
+                agent_linux::sd_notify::watchdog();
+            }
+            Ok(()) = shutdown_rx.changed() => {
+                info!("received service stop, shutting down");
+                session_mgr.close_all();
+                #[cfg(target_os = "windows")]
+                shutdown_helper(ipc_writer.as_ref()).await;
+                break;
+            }
             _ = tokio::signal::ctrl_c() => {
                 info!("received Ctrl+C, shutting down");
                 session_mgr.close_all();
+                #[cfg(target_os = "windows")]
+                shutdown_helper(ipc_writer.as_ref()).await;
                 break;
             }
         }
     }
 
+    #[cfg(target_os = "linux")]
+    agent_linux::sd_notify::stopping();
+
     Ok(())
 }
 
+/// How often the config file is checked for changes to reloadable settings
+const CONFIG_RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How often uploads that stopped getting chunks are cleaned up
+const UPLOAD_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Clamp a too-short telemetry interval to the minimum, with a warning
+fn warn_short_telemetry_interval(config: &mut AgentConfig) {
+    if let Some(configured) = config.clamp_telemetry_interval() {
+        warn!(
+            "telemetry_interval_secs {} is below the minimum, using {}",
+            configured, config.telemetry_interval_secs
+        );
+    }
+}
+
+/// Interval for periodic telemetry, never shorter than the configured minimum
+fn telemetry_ticker(secs: u64) -> tokio::time::Interval {
+    let secs = secs.max(agent_core::config::MIN_TELEMETRY_INTERVAL_SECS);
+    tokio::time::interval(std::time::Duration::from_secs(secs))
+}
+
+/// Modification time of the config file, used to notice edits
+fn config_modified(path: &std::path::Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Log level saved in the config file, if any. Read before logging is set
+/// up, so failures are silently ignored.
+fn configured_log_level(config_path: Option<&str>) -> Option<String> {
+    let path = config_path
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(AgentConfig::default_path);
+    AgentConfig::load(&path).ok()?.log_level
+}
+
+/// systemd watchdog period, if the agent runs under a unit with WatchdogSec.
+#[cfg(target_os = "linux")]
+fn sd_watchdog_interval() -> Option<std::time::Duration> {
+    agent_linux::sd_notify::watchdog_interval()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sd_watchdog_interval() -> Option<std::time::Duration> {
+    None
+}
+
 /// Check if a message type is a session message (desktop or terminal)
 /// that should be proxied to the helper process.
 #[cfg(target_os = "windows")]
@@ -303,23 +667,116 @@ fn is_session_message(msg_type: u8) -> bool {
     )
 }
 
-/// Set up IPC pipe server, spawn helper process, and start the relay task
-/// that forwards helper responses back to the WebSocket.
+/// Keep `sessions` in step with the desktop and terminal sessions proxied to
+/// the helper, for health reports. Sees opens and closes from the server
+/// and closes and errors (a failed open) from the helper.
+fn track_helper_session(
+    sessions: &mut std::collections::HashSet<(u8, u16)>,
+    msg_type: u8,
+    channel: u16,
+) {
+    match msg_type {
+        protocol::TERMINAL_OPEN | protocol::DESKTOP_OPEN => {
+            sessions.insert((msg_type, channel));
+        }
+        protocol::TERMINAL_CLOSE => {
+            sessions.remove(&(protocol::TERMINAL_OPEN, channel));
+        }
+        protocol::DESKTOP_CLOSE => {
+            sessions.remove(&(protocol::DESKTOP_OPEN, channel));
+        }
+        protocol::ERROR => {
+            sessions.remove(&(protocol::TERMINAL_OPEN, channel));
+            sessions.remove(&(protocol::DESKTOP_OPEN, channel));
+        }
+        _ => {}
+    }
+}
+
+/// What the helper's monitor and relay report to the main loop
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+enum HelperEvent {
+    /// A new helper connected to the pipe
+    Connected,
+    /// The running helper died or is being replaced
+    Disconnected,
+    /// The session features the helper can serve
+    Capabilities(Vec<String>),
+    /// The helper sent a close or error on a session channel
+    Closed { msg_type: u8, channel: u16 },
+}
+
+/// Check if a message is a command the helper runs in the logged-in user's
+/// session: NOTIFY_USER, SCREENSHOT, or a RUN_SHELL asking for the user's
+/// context.
+/// Oversized commands are left to `handle_command` to reject.
+#[cfg(target_os = "windows")]
+fn is_user_session_command(msg: &protocol::Message) -> bool {
+    if msg.header.msg_type != protocol::COMMAND || check_command_size(&msg.payload).is_err() {
+        return false;
+    }
+    match serde_json::from_slice::<serde_json::Value>(&msg.payload) {
+        Ok(command) => {
+            matches!(
+                command["type"].as_str(),
+                Some("NOTIFY_USER" | "SCREENSHOT" | "TAKE_SCREENSHOT")
+            ) || (command["type"] == "RUN_SHELL" && command["as_user"] == true)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Add the server's payload limit to a SCREENSHOT command for the helper,
+/// which can't see the connection, so it sizes the image to fit
+#[cfg(target_os = "windows")]
+fn with_payload_limit(msg: protocol::Message, max_payload: usize) -> protocol::Message {
+    let Ok(mut command) = msg.parse_json::<serde_json::Value>() else {
+        return msg;
+    };
+    if !matches!(
+        command["type"].as_str(),
+        Some("SCREENSHOT" | "TAKE_SCREENSHOT")
+    ) {
+        return msg;
+    }
+    command["max_payload"] = max_payload.into();
+    protocol::Message::control_json(protocol::COMMAND, msg.header.request_id, &command)
+        .unwrap_or(msg)
+}
+
+/// The service's end of the helper pipe; empty while no helper is connected
+#[cfg(target_os = "windows")]
+type HelperWriter = std::sync::Arc<tokio::sync::Mutex<Option<agent_windows::ipc::IpcWriter>>>;
+
+/// Forward an encoded message to the helper
+#[cfg(target_os = "windows")]
+async fn send_to_helper(writer: &HelperWriter, data: &[u8]) -> Result<()> {
+    match writer.lock().await.as_ref() {
+        Some(writer) => writer.send_raw(data).await,
+        None => anyhow::bail!("no helper connected"),
+    }
+}
+
+/// Set up the IPC pipe server and start the task that spawns the helper,
+/// keeps it running and relays its responses back to the WebSocket. Only
+/// creating the pipe can fail; a helper that can't be started yet (no
+/// console session, nobody logged on) is retried by the monitor.
 #[cfg(target_os = "windows")]
-async fn setup_helper_ipc(
+fn setup_helper_ipc(
     config: &AgentConfig,
     ws_handle: &ConnectionHandle,
-) -> Result<std::sync::Arc<tokio::sync::Mutex<agent_windows::ipc::IpcWriter>>> {
-    use agent_windows::ipc::{IpcServer, pipe_name_for_device};
+    events: mpsc::Sender<HelperEvent>,
+) -> Result<HelperWriter> {
     use agent_windows::helper_launcher::HelperLauncher;
+    use agent_windows::ipc::{pipe_name_for_device, IpcServer};
     use agent_windows::session_detect::get_active_console_session;
 
     let device_id = config.device_id.as_deref().unwrap_or("default");
     let pipe_name = pipe_name_for_device(device_id);
 
     // Create the named pipe server
-    let ipc_server = IpcServer::create(&pipe_name)
-        .context("failed to create IPC pipe server")?;
+    let mut ipc_server =
+        IpcServer::create(&pipe_name).context("failed to create IPC pipe server")?;
 
     // Get the executable path for spawning the helper
     let exe_path = std::env::current_exe()
@@ -327,31 +784,253 @@ async fn setup_helper_ipc(
         .to_string_lossy()
         .to_string();
 
-    // Find the active console session
-    let target_session = get_active_console_session()
-        .ok_or_else(|| anyhow::anyhow!("no active console session found"))?;
+    let mut launcher = HelperLauncher::new(exe_path, pipe_name)
+        .arg("--session-indicator")
+        .arg(config.session_indicator.as_str())
+        .arg("--conpty-flags")
+        .arg(config.conpty_flags.to_string())
+        .arg("--max-fps")
+        .arg(config.max_fps.to_string())
+        .arg("--terminal-idle-timeout-mins")
+        .arg(config.terminal_idle_timeout_mins.to_string())
+        .arg("--device-id")
+        .arg(device_id);
+    if config.raw_tiles {
+        launcher = launcher.arg("--raw-tiles");
+    }
+
+    // A helper that never connects (no interactive desktop in the session,
+    // say) must not hold up the monitor forever
+    let connect_timeout = std::time::Duration::from_secs(config.helper_connect_timeout_secs.max(1));
+    let writer: HelperWriter = std::sync::Arc::new(tokio::sync::Mutex::new(None));
 
-    info!("spawning helper in session {} via {}", target_session, exe_path);
+    // The helper's desktop sessions can't see the connection's send queue,
+    // which "auto" encoding and frame skipping go by; report it regularly
+    let queue_writer = writer.clone();
+    let queue_handle = ws_handle.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(HELPER_SEND_QUEUE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let in_flight = queue_handle.in_flight_bytes() as u64;
+            let msg = protocol::Message::control(
+                protocol::HELPER_SEND_QUEUE,
+                0,
+                in_flight.to_le_bytes().to_vec(),
+            );
+            // Nothing to do while no helper is connected
+            let _ = send_to_helper(&queue_writer, &msg.encode()).await;
+        }
+    });
 
-    // Spawn the helper process in the user session
-    let mut launcher = HelperLauncher::new(exe_path.clone(), pipe_name.clone());
-    launcher.spawn_in_session(target_session)
-        .context("failed to spawn helper process")?;
+    // Spawn a task to monitor helper process health and respawn if needed.
+    // After a respawn the new helper connects to the server's next pipe
+    // instance; its writer is swapped in and a fresh relay is started.
+    // A helper that keeps dying soon after starting is respawned with
+    // exponential backoff. Spawns that fail outright (at the logon screen
+    // there is no user token to start one with) are retried on a slower
+    // timer, and right away when the console session changes. The monitor
+    // never gives up, so desktop and terminal come back once a helper can
+    // run again. When the active console session changes (user switch, RDP
+    // connect) the helper is moved to the new session so it captures the
+    // right desktop. The first check, right away, spawns the first helper.
+    let monitor_writer = writer.clone();
+    let ws_handle_clone = ws_handle.clone();
+    tokio::spawn(async move {
+        let mut check_interval = tokio::time::interval(HELPER_CHECK_INTERVAL);
+        // When the running helper connected; None while there is none
+        let mut connected_at = None;
+        let mut rapid_failures = 0u32;
+        // Spawns that failed in a row, and the session they were tried in
+        let mut spawn_failures = 0u32;
+        let mut failed_session = None;
+        let mut retry_at = tokio::time::Instant::now();
+        loop {
+            check_interval.tick().await;
+            let active_session = get_active_console_session();
+
+            if launcher.is_alive() {
+                let current = launcher.session_id();
+                if let Some(session_id) = active_session.filter(|&s| s != current) {
+                    info!(
+                        "active console session changed from {} to {}, moving helper",
+                        current, session_id
+                    );
+                    rapid_failures = 0;
+                    let _ = events.send(HelperEvent::Disconnected).await;
+                    connected_at = respawn_helper(
+                        &mut launcher,
+                        &mut ipc_server,
+                        session_id,
+                        connect_timeout,
+                        &monitor_writer,
+                        &ws_handle_clone,
+                        &events,
+                    )
+                    .await
+                    .then(std::time::Instant::now);
+                }
+                continue;
+            }
 
-    // Wait for the helper to connect to the pipe
-    info!("waiting for helper to connect...");
-    ipc_server.wait_for_connection().await
-        .context("helper failed to connect to pipe")?;
+            // Only a helper that started and then died counts as a failure
+            if let Some(started) = connected_at.take() {
+                let _ = events.send(HelperEvent::Disconnected).await;
+                if started.elapsed() < HELPER_STABLE_AFTER {
+                    rapid_failures += 1;
+                } else {
+                    rapid_failures = 0;
+                }
+                let delay = helper_respawn_delay(rapid_failures);
+                if delay.is_zero() {
+                    warn!("helper process died, attempting respawn");
+                } else {
+                    warn!(
+                        "helper process died {} times in a row shortly after starting, respawning in {:?}",
+                        rapid_failures, delay
+                    );
+                }
+                retry_at = tokio::time::Instant::now() + delay;
+            }
 
-    info!("helper connected, setting up relay");
+            // The session may have changed since the helper was spawned
+            let Some(session_id) = active_session else {
+                debug!("no active console session, will retry later");
+                continue;
+            };
+            if failed_session.is_some_and(|failed| failed != session_id) {
+                spawn_failures = 0;
+                failed_session = None;
+                retry_at = tokio::time::Instant::now();
+            }
+            if tokio::time::Instant::now() < retry_at {
+                continue;
+            }
 
-    // Split the pipe into reader/writer
-    let (reader, writer) = ipc_server.split();
-    let writer = std::sync::Arc::new(tokio::sync::Mutex::new(writer));
+            let connected = respawn_helper(
+                &mut launcher,
+                &mut ipc_server,
+                session_id,
+                connect_timeout,
+                &monitor_writer,
+                &ws_handle_clone,
+                &events,
+            )
+            .await;
+            if connected {
+                connected_at = Some(std::time::Instant::now());
+                spawn_failures = 0;
+                failed_session = None;
+            } else {
+                spawn_failures += 1;
+                failed_session = Some(session_id);
+                retry_at = tokio::time::Instant::now() + helper_spawn_retry_delay(spawn_failures);
+            }
+        }
+    });
 
-    // Spawn relay task: reads messages from helper pipe → sends to WebSocket
-    let ws_handle_clone = ws_handle.clone();
-    let mut ipc_reader = reader;
+    Ok(writer)
+}
+
+/// Spawn the helper in `session_id` (killing any running one) and swap in
+/// its pipe once it connects, reporting it on `events`. Returns whether it
+/// connected; failures are logged and leave the helper dead, so the monitor
+/// retries.
+#[cfg(target_os = "windows")]
+async fn respawn_helper(
+    launcher: &mut agent_windows::helper_launcher::HelperLauncher,
+    ipc_server: &mut agent_windows::ipc::IpcServer,
+    session_id: u32,
+    connect_timeout: std::time::Duration,
+    writer: &HelperWriter,
+    ws_handle: &ConnectionHandle,
+    events: &mpsc::Sender<HelperEvent>,
+) -> bool {
+    if let Err(e) = launcher.spawn_in_session(session_id) {
+        warn!("failed to spawn helper in session {}: {:#}", session_id, e);
+        return false;
+    }
+    info!(
+        "helper spawned in session {}, waiting for it to connect",
+        session_id
+    );
+
+    match ipc_server.accept(connect_timeout).await {
+        Ok((reader, new_writer)) => {
+            *writer.lock().await = Some(new_writer);
+            spawn_helper_relay(reader, ws_handle.clone(), events.clone());
+            let _ = events.send(HelperEvent::Connected).await;
+            info!("helper connected, relay started");
+            true
+        }
+        Err(e) => {
+            error!("helper failed to connect: {:#}", e);
+            // Kill it so the next attempt spawns a fresh one
+            let _ = launcher.kill();
+            false
+        }
+    }
+}
+
+/// How often the service reports its send queue to the helper
+#[cfg(target_os = "windows")]
+const HELPER_SEND_QUEUE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+/// How often the service checks that the helper process is still running
+#[cfg(target_os = "windows")]
+const HELPER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// A helper that exits sooner than this after being spawned counts as a
+/// rapid failure
+#[cfg(target_os = "windows")]
+const HELPER_STABLE_AFTER: std::time::Duration = std::time::Duration::from_secs(60);
+#[cfg(target_os = "windows")]
+const HELPER_MAX_RESPAWN_DELAY: std::time::Duration = std::time::Duration::from_secs(300);
+/// Longest wait between attempts to spawn a helper that can't be started
+/// at all, e.g. while the console sits at the logon screen
+#[cfg(target_os = "windows")]
+const HELPER_MAX_SPAWN_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Extra wait before respawning after `failures` rapid failures:
+/// none for the first, then the check interval doubling up to a cap
+#[cfg(target_os = "windows")]
+fn helper_respawn_delay(failures: u32) -> std::time::Duration {
+    if failures <= 1 {
+        return std::time::Duration::ZERO;
+    }
+    HELPER_CHECK_INTERVAL
+        .saturating_mul(1 << (failures - 2).min(16))
+        .min(HELPER_MAX_RESPAWN_DELAY)
+}
+
+/// Wait before the next attempt after `failures` spawns in a row failed:
+/// the check interval doubling up to `HELPER_MAX_SPAWN_RETRY_DELAY`
+#[cfg(target_os = "windows")]
+fn helper_spawn_retry_delay(failures: u32) -> std::time::Duration {
+    HELPER_CHECK_INTERVAL
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(HELPER_MAX_SPAWN_RETRY_DELAY)
+}
+
+/// Ask the helper to close its sessions and exit before the pipe is dropped.
+#[cfg(target_os = "windows")]
+async fn shutdown_helper(ipc_writer: Option<&HelperWriter>) {
+    if let Some(writer) = ipc_writer {
+        if let Some(writer) = writer.lock().await.as_ref() {
+            if let Err(e) = writer.send_shutdown().await {
+                warn!("failed to send shutdown to helper: {}", e);
+            }
+        }
+    }
+}
+
+/// Relay messages from the helper pipe to the WebSocket until the pipe
+/// closes, reporting the sessions the helper ends and the capabilities it
+/// announces on `events`.
+#[cfg(target_os = "windows")]
+fn spawn_helper_relay(
+    mut ipc_reader: agent_windows::ipc::IpcReader,
+    ws_handle: ConnectionHandle,
+    events: mpsc::Sender<HelperEvent>,
+) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
         loop {
             match ipc_reader.recv_raw().await {
@@ -359,7 +1038,41 @@ async fn setup_helper_ipc(
                     // Decode and forward to WebSocket
                     match protocol::Message::decode(&raw) {
                         Ok(Some((msg, _))) => {
-                            if let Err(e) = ws_handle_clone.send_message(&msg).await {
+                            let (msg_type, channel) = (msg.header.msg_type, msg.header.channel);
+                            // The helper's AGENT_INFO is for the service alone
+                            if msg_type == protocol::AGENT_INFO {
+                                match msg.parse_json::<Vec<String>>() {
+                                    Ok(caps) => {
+                                        let _ = events.send(HelperEvent::Capabilities(caps)).await;
+                                    }
+                                    Err(e) => warn!("invalid capabilities from helper: {}", e),
+                                }
+                                continue;
+                            }
+                            if matches!(
+                                msg_type,
+                                protocol::TERMINAL_CLOSE
+                                    | protocol::DESKTOP_CLOSE
+                                    | protocol::ERROR
+                            ) {
+                                let _ =
+                                    events.send(HelperEvent::Closed { msg_type, channel }).await;
+                            }
+                            // The helper doesn't know the server's payload limit
+                            let msg = if msg_type == protocol::COMMAND_RESULT
+                                && msg.payload.len() > ws_handle.max_payload()
+                            {
+                                fit_helper_result(msg, ws_handle.max_payload())
+                            } else {
+                                msg
+                            };
+                            if let Err(e) = ws_handle.send_message(&msg).await {
+                                // Only a closed connection ends the relay; a
+                                // message the server can't take is dropped
+                                if e.downcast_ref::<protocol::ProtocolError>().is_some() {
+                                    warn!("dropping helper message 0x{:02x}: {}", msg_type, e);
+                                    continue;
+                                }
                                 error!("failed to relay helper message to server: {}", e);
                                 break;
                             }
@@ -379,41 +1092,67 @@ async fn setup_helper_ipc(
             }
         }
         info!("helper relay task ended");
-    });
+    })
+}
 
-    // Spawn a task to monitor helper process health and respawn if needed
-    let _pipe_name_clone = pipe_name;
-    let _exe_path_clone = exe_path;
-    tokio::spawn(async move {
-        let mut check_interval = tokio::time::interval(std::time::Duration::from_secs(5));
-        loop {
-            check_interval.tick().await;
+/// Shorten a RUN_SHELL result from the helper to fit in `max` bytes. A
+/// result that still doesn't fit becomes an error, so the server hears back.
+#[cfg(target_os = "windows")]
+fn fit_helper_result(msg: protocol::Message, max: usize) -> protocol::Message {
+    let Ok(mut result) = msg.parse_json::<serde_json::Value>() else {
+        return msg;
+    };
+    fit_shell_result(&mut result, max);
+    if serde_json::to_vec(&result).map_or(0, |json| json.len()) > max {
+        result =
+            serde_json::json!({ "success": false, "error": "result too large for the connection" });
+    }
+    protocol::Message::control_json(protocol::COMMAND_RESULT, msg.header.request_id, &result)
+        .unwrap_or(msg)
+}
 
-            if !launcher.is_alive() {
-                warn!("helper process died, attempting respawn");
-
-                // Check if session changed
-                let new_session = get_active_console_session();
-                match new_session {
-                    Some(session_id) => {
-                        if let Err(e) = launcher.spawn_in_session(session_id) {
-                            error!("failed to respawn helper: {:#}", e);
-                        } else {
-                            info!("helper respawned in session {}", session_id);
-                        }
-                    }
-                    None => {
-                        warn!("no active console session, will retry later");
-                    }
-                }
-            }
-        }
-    });
+/// Features advertised in AGENT_INFO. `use_helper` means sessions run in the
+/// helper, and can only be served while a helper is connected and has
+/// reported `helper_caps`.
+fn agent_capabilities(use_helper: bool, helper_caps: Option<&[String]>) -> Vec<String> {
+    let sessions_available = !use_helper || helper_caps.is_some();
+    let mut caps: Vec<String> = if use_helper {
+        helper_caps.map(<[String]>::to_vec).unwrap_or_default()
+    } else {
+        agent_core::session::session_capabilities()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    };
 
-    Ok(writer)
+    let mut extra = vec![];
+    // Taken wherever the desktop is, like sessions
+    if caps.iter().any(|c| c == "desktop") {
+        extra.push("screenshot");
+    }
+    extra.extend([
+        "files",
+        "file_search",
+        "telemetry",
+        "run_shell",
+        "download_url",
+        "update",
+        "reconnect",
+    ]);
+    if cfg!(any(target_os = "linux", target_os = "windows")) {
+        extra.extend(["services", "installed_software", "clock", "network"]);
+        if sessions_available {
+            extra.push("notify_user");
+        }
+    }
+    if cfg!(target_os = "windows") && use_helper && sessions_available {
+        extra.push("run_shell_as_user");
+    }
+    caps.extend(extra.into_iter().map(String::from));
+    caps
 }
 
-async fn send_agent_info(handle: &ConnectionHandle) -> Result<()> {
+async fn send_agent_info(handle: &ConnectionHandle, capabilities: &[String]) -> Result<()> {
     let info = protocol::AgentInfo {
         hostname: hostname::get()
             .map(|h| h.to_string_lossy().to_string())
@@ -426,6 +1165,7 @@ async fn send_agent_info(handle: &ConnectionHandle) -> Result<()> {
         memory: None,
         disks: None,
         network: None,
+        capabilities: capabilities.to_vec(),
     };
 
     let msg = protocol::Message::control_json(protocol::AGENT_INFO, 0, &info)?;
@@ -439,10 +1179,14 @@ async fn handle_server_message(
     file_handler: &mut FileHandler,
     telemetry: &TelemetryCollector,
     config: &AgentConfig,
+    config_path: &std::path::Path,
 ) {
+    let channel = msg.header.channel;
+    let request_id = msg.header.request_id;
+
     match msg.header.msg_type {
         protocol::COMMAND => {
-            handle_command(msg, handle, telemetry, config).await;
+            handle_command(msg, handle, telemetry, config, config_path).await;
         }
         protocol::TERMINAL_OPEN
         | protocol::TERMINAL_CLOSE
@@ -451,13 +1195,24 @@ async fn handle_server_message(
         | protocol::DESKTOP_OPEN
         | protocol::DESKTOP_CLOSE
         | protocol::DESKTOP_INPUT
-        | protocol::DESKTOP_QUALITY => {
+        | protocol::DESKTOP_QUALITY
+        | protocol::AUDIO_OPEN
+        | protocol::AUDIO_CLOSE => {
             if let Err(e) = session_mgr.handle_message(msg).await {
                 error!("session manager error: {:#}", e);
+                let code = protocol::ErrorCode::for_error(e.as_ref());
+                let _ = handle
+                    .send_error(channel, request_id, code, format!("{:#}", e))
+                    .await;
             }
         }
-        protocol::FILE_LIST_REQ | protocol::FILE_DOWNLOAD_REQ | protocol::FILE_UPLOAD_START
-        | protocol::FILE_UPLOAD_DATA | protocol::FILE_DELETE_REQ => {
+        protocol::FILE_LIST_REQ
+        | protocol::FILE_DOWNLOAD_REQ
+        | protocol::FILE_UPLOAD_START
+        | protocol::FILE_UPLOAD_DATA
+        | protocol::FILE_DELETE_REQ
+        | protocol::FILE_SEARCH_REQ
+        | protocol::FILE_SEARCH_CANCEL => {
             file_handler.handle_message(msg, handle).await;
         }
         protocol::TELEMETRY_REQ => {
@@ -466,18 +1221,114 @@ async fn handle_server_message(
                 error!("failed to send telemetry: {:#}", e);
             }
         }
+        protocol::COMMAND_SYNC_RESP => {
+            // The replayed commands themselves arrived as COMMAND messages
+            match msg.parse_json::<protocol::CommandSyncResponse>() {
+                Ok(sync) if sync.count > 0 => {
+                    info!("caught up on {} queued command(s)", sync.count)
+                }
+                Ok(_) => debug!("no queued commands"),
+                Err(e) => warn!("malformed COMMAND_SYNC_RESP: {}", e),
+            }
+        }
+        protocol::ERROR => {
+            // Never answer an ERROR with an ERROR
+            match msg.parse_json::<protocol::ErrorResponse>() {
+                Ok(err) => warn!(
+                    "server reported error {:?} for request {}: {}",
+                    err.code, err.request_id, err.message
+                ),
+                Err(e) => warn!("malformed ERROR from server: {}", e),
+            }
+        }
         other => {
             warn!("unhandled message type: 0x{:02x}", other);
+            let _ = handle
+                .send_error(
+                    channel,
+                    request_id,
+                    protocol::ErrorCode::UnknownMessageType,
+                    format!("unhandled message type 0x{:02x}", other),
+                )
+                .await;
         }
     }
 }
 
+/// Hard cap on any COMMAND payload, checked before any parsing
+const MAX_COMMAND_PAYLOAD: usize = 256 * 1024;
+/// Limit for commands that only carry a few small fields
+const MAX_SMALL_COMMAND_PAYLOAD: usize = 4 * 1024;
+/// How often a running DOWNLOAD_URL reports COMMAND_PROGRESS
+const DOWNLOAD_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Largest payload accepted for a command type
+fn command_size_limit(cmd_type: &str) -> usize {
+    match cmd_type {
+        // The script itself can be long
+        "RUN_SHELL" => MAX_COMMAND_PAYLOAD,
+        _ => MAX_SMALL_COMMAND_PAYLOAD,
+    }
+}
+
+/// Just the `type` of a command. The other fields are skipped by the
+/// parser without being allocated.
+#[derive(serde::Deserialize)]
+struct CommandKind<'a> {
+    #[serde(rename = "type", borrow, default)]
+    kind: Option<std::borrow::Cow<'a, str>>,
+}
+
+/// Reject oversized commands before they are parsed into a full JSON tree
+fn check_command_size(payload: &[u8]) -> std::result::Result<(), String> {
+    if payload.len() > MAX_COMMAND_PAYLOAD {
+        return Err(format!(
+            "command payload too large: {} bytes (max {})",
+            payload.len(),
+            MAX_COMMAND_PAYLOAD
+        ));
+    }
+    if payload.len() <= MAX_SMALL_COMMAND_PAYLOAD {
+        return Ok(());
+    }
+
+    let command = serde_json::from_slice::<CommandKind>(payload)
+        .map_err(|e| format!("malformed command payload ({} bytes): {}", payload.len(), e))?;
+    let cmd_type = command.kind.unwrap_or_default();
+    let limit = command_size_limit(&cmd_type);
+    if payload.len() > limit {
+        return Err(format!(
+            "{} command payload too large: {} bytes (max {})",
+            cmd_type,
+            payload.len(),
+            limit
+        ));
+    }
+    Ok(())
+}
+
 async fn handle_command(
     msg: protocol::Message,
     handle: &ConnectionHandle,
     telemetry: &TelemetryCollector,
     config: &AgentConfig,
+    config_path: &std::path::Path,
 ) {
+    let received_at = unix_millis();
+
+    if let Err(reason) = check_command_size(&msg.payload) {
+        warn!("rejecting command: {}", reason);
+        let _ = handle
+            .send_error(
+                msg.header.channel,
+                msg.header.request_id,
+                protocol::ErrorCode::InvalidRequest,
+                reason,
+            )
+            .await;
+        return;
+    }
+
     let payload_str = match std::str::from_utf8(&msg.payload) {
         Ok(s) => s,
         Err(_) => {
@@ -495,6 +1346,28 @@ async fn handle_command(
     };
 
     let cmd_type = command["type"].as_str().unwrap_or("");
+
+    // Latency probe: answer before anything else, and without logging,
+    // so frequent pings don't skew what they measure
+    if cmd_type == "PING" {
+        let result = serde_json::json!({
+            "success": true,
+            "timestamp": command["timestamp"],
+            "receivedAt": received_at,
+            "sentAt": unix_millis(),
+        });
+        if let Ok(resp) = protocol::Message::control_json(
+            protocol::COMMAND_RESULT,
+            msg.header.request_id,
+            &result,
+        ) {
+            if let Err(e) = handle.send_control(&resp).await {
+                error!("failed to send ping reply: {}", e);
+            }
+        }
+        return;
+    }
+
     info!("received command: {}", cmd_type);
 
     match cmd_type {
@@ -523,37 +1396,48 @@ async fn handle_command(
                 send_command_result(handle, msg.header.request_id, false, Some("missing 'command' field")).await;
                 return;
             }
+            // User-context commands only get here when the helper couldn't
+            // take them
+            let note = if command["as_user"].as_bool().unwrap_or(false) {
+                user_shell_fallback_note()
+            } else {
+                None
+            };
             info!("executing shell command: {}", shell_cmd);
-            let output = {
-                #[cfg(target_os = "windows")]
-                {
-                    std::process::Command::new("cmd").args(["/C", shell_cmd]).output()
-                }
-                #[cfg(not(target_os = "windows"))]
-                {
-                    std::process::Command::new("sh").args(["-c", shell_cmd]).output()
-                }
-            };
-            match output {
-                Ok(out) => {
-                    let stdout = String::from_utf8_lossy(&out.stdout);
-                    let stderr = String::from_utf8_lossy(&out.stderr);
-                    let result = serde_json::json!({
-                        "success": out.status.success(),
-                        "exitCode": out.status.code(),
-                        "stdout": stdout,
-                        "stderr": stderr,
-                    });
-                    if let Ok(resp) = protocol::Message::control_json(protocol::COMMAND_RESULT, msg.header.request_id, &result) {
-                        if let Err(e) = handle.send_message(&resp).await {
-                            error!("failed to send command result: {}", e);
+            // Commands may run for minutes; don't hold up the main loop
+            let cmd = shell_command(shell_cmd);
+            let handle = handle.clone();
+            let request_id = msg.header.request_id;
+            tokio::spawn(async move {
+                match run_captured(cmd).await {
+                    Ok(out) => {
+                        let mut result = out.to_json();
+                        if let Some(note) = note {
+                            warn!("{}", note);
+                            result["note"] = serde_json::Value::String(note.to_string());
+                        }
+                        fit_shell_result(&mut result, handle.max_payload());
+                        if let Ok(resp) = protocol::Message::control_json(
+                            protocol::COMMAND_RESULT,
+                            request_id,
+                            &result,
+                        ) {
+                            if let Err(e) = handle.send_message(&resp).await {
+                                error!("failed to send command result: {}", e);
+                            }
                         }
                     }
+                    Err(e) => {
+                        send_command_result(
+                            &handle,
+                            request_id,
+                            false,
+                            Some(&format!("exec error: {}", e)),
+                        )
+                        .await;
+                    }
                 }
-                Err(e) => {
-                    send_command_result(handle, msg.header.request_id, false, Some(&format!("exec error: {}", e))).await;
-                }
-            }
+            });
         }
         "UPDATE" => {
             info!("received update command, checking for updates...");
@@ -561,6 +1445,8 @@ async fn handle_command(
                 Ok(true) => {
                     send_command_result(handle, msg.header.request_id, true, None).await;
                     info!("update applied, restarting...");
+                    #[cfg(target_os = "linux")]
+                    agent_linux::sd_notify::stopping();
                     if let Err(e) = auto_update::restart_self() {
                         error!("failed to restart after update: {}", e);
                     }
@@ -573,6 +1459,560 @@ async fn handle_command(
                 }
             }
         }
+        "RECONNECT" => {
+            let server_url = match command["server_url"].as_str().map(str::trim) {
+                None | Some("") => None,
+                Some(url) => {
+                    let mut candidate = config.clone();
+                    candidate.server_url = url.to_string();
+                    if let Err(e) = candidate.validate() {
+                        send_command_result(
+                            handle,
+                            msg.header.request_id,
+                            false,
+                            Some(&format!("{:#}", e)),
+                        )
+                        .await;
+                        return;
+                    }
+                    if !config.reconnect_allowed(url) {
+                        let error = format!("{} is not in reconnect_allowed_hosts", url);
+                        send_command_result(handle, msg.header.request_id, false, Some(&error))
+                            .await;
+                        return;
+                    }
+                    Some(candidate.server_url)
+                }
+            };
+            send_command_result(handle, msg.header.request_id, true, None).await;
+            match &server_url {
+                Some(url) => info!("server requested reconnect to {}", url),
+                None => info!("server requested reconnect"),
+            }
+            handle.reconnect(server_url);
+        }
+        "GET_CONNECTION_STATS" => {
+            let result = serde_json::json!({
+                "success": true,
+                "stats": handle.stats(),
+            });
+            if let Ok(resp) = protocol::Message::control_json(
+                protocol::COMMAND_RESULT,
+                msg.header.request_id,
+                &result,
+            ) {
+                if let Err(e) = handle.send_message(&resp).await {
+                    error!("failed to send command result: {}", e);
+                }
+            }
+        }
+        "UNINSTALL" => {
+            let purge = command["purge"].as_bool().unwrap_or(false);
+            warn!("server requested uninstall (purge={})", purge);
+            match install::run_self_uninstall(config_path, purge) {
+                Ok(()) => {
+                    send_command_result(handle, msg.header.request_id, true, None).await;
+                    // Give the result a moment to reach the server
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    info!("agent uninstalled, exiting");
+                    #[cfg(target_os = "linux")]
+                    agent_linux::sd_notify::stopping();
+                    install::stop_running_service();
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    error!("self-uninstall failed: {:#}", e);
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("uninstall error: {:#}", e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "LIST_SERVICES" => {
+            let listing = tokio::task::spawn_blocking(|| create_platform_services()?.list())
+                .await
+                .unwrap_or_else(|e| Err(e.into()));
+            match listing {
+                Ok(services) => {
+                    let result = serde_json::json!({
+                        "success": true,
+                        "services": services,
+                    });
+                    if let Ok(resp) = protocol::Message::control_json(
+                        protocol::COMMAND_RESULT,
+                        msg.header.request_id,
+                        &result,
+                    ) {
+                        if let Err(e) = handle.send_message(&resp).await {
+                            error!("failed to send command result: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("service listing error: {:#}", e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "LIST_INSTALLED_SOFTWARE" => {
+            let inventory =
+                tokio::task::spawn_blocking(|| create_platform_system_info()?.installed_software())
+                    .await
+                    .unwrap_or_else(|e| Err(e.into()));
+            match inventory {
+                Ok(software) => {
+                    let result = serde_json::json!({
+                        "success": true,
+                        "software": software,
+                    });
+                    if let Ok(resp) = protocol::Message::control_json(
+                        protocol::COMMAND_RESULT,
+                        msg.header.request_id,
+                        &result,
+                    ) {
+                        if let Err(e) = handle.send_message(&resp).await {
+                            error!("failed to send command result: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("software inventory error: {:#}", e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "START_SERVICE" | "STOP_SERVICE" | "RESTART_SERVICE" => {
+            if !config.policy.service_control {
+                warn!("refusing {}: service control is disabled", cmd_type);
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("service control is disabled on this agent"),
+                )
+                .await;
+                return;
+            }
+            let name = command["name"].as_str().unwrap_or("").to_string();
+            if name.is_empty() {
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("missing 'name' field"),
+                )
+                .await;
+                return;
+            }
+            let action = match cmd_type {
+                "START_SERVICE" => ServiceAction::Start,
+                "STOP_SERVICE" => ServiceAction::Stop,
+                _ => ServiceAction::Restart,
+            };
+            let outcome = tokio::task::spawn_blocking(move || {
+                create_platform_services()?.control(&name, action)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.into()));
+            match outcome {
+                Ok(()) => send_command_result(handle, msg.header.request_id, true, None).await,
+                Err(e) => {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("service {} error: {:#}", action.as_str(), e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "GET_TIME" => {
+            let info = tokio::task::spawn_blocking(|| create_platform_clock()?.info())
+                .await
+                .unwrap_or_else(|e| Err(e.into()));
+            match info {
+                Ok(clock) => {
+                    let result = serde_json::json!({
+                        "success": true,
+                        "clock": clock,
+                    });
+                    if let Ok(resp) = protocol::Message::control_json(
+                        protocol::COMMAND_RESULT,
+                        msg.header.request_id,
+                        &result,
+                    ) {
+                        if let Err(e) = handle.send_message(&resp).await {
+                            error!("failed to send command result: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("clock error: {:#}", e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "GET_ENV" => {
+            let result = serde_json::json!({
+                "success": true,
+                "env": agent_core::redact::redacted_env(std::env::vars_os()),
+            });
+            if let Ok(resp) = protocol::Message::control_json(
+                protocol::COMMAND_RESULT,
+                msg.header.request_id,
+                &result,
+            ) {
+                if let Err(e) = handle.send_message(&resp).await {
+                    error!("failed to send command result: {}", e);
+                }
+            }
+        }
+        "SET_TIME" | "SYNC_TIME" => {
+            if !config.policy.clock_changes {
+                warn!("refusing {}: clock changes are disabled", cmd_type);
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("clock changes are disabled on this agent"),
+                )
+                .await;
+                return;
+            }
+            if !process_is_elevated() {
+                warn!("refusing {}: agent is not running elevated", cmd_type);
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("changing the clock needs root / administrator rights"),
+                )
+                .await;
+                return;
+            }
+            let timestamp = command["timestamp"].as_u64();
+            let timezone = command["timezone"].as_str().map(str::to_string);
+            if cmd_type == "SET_TIME" && timestamp.is_none() && timezone.is_none() {
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("missing 'timestamp' or 'timezone' field"),
+                )
+                .await;
+                return;
+            }
+            let sync = cmd_type == "SYNC_TIME";
+            let outcome = tokio::task::spawn_blocking(move || {
+                let clock = create_platform_clock()?;
+                if sync {
+                    return clock.sync();
+                }
+                if let Some(timezone) = timezone {
+                    clock.set_timezone(&timezone)?;
+                }
+                if let Some(timestamp) = timestamp {
+                    clock.set_time(timestamp)?;
+                }
+                Ok(())
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.into()));
+            match outcome {
+                Ok(()) => send_command_result(handle, msg.header.request_id, true, None).await,
+                Err(e) => {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("clock error: {:#}", e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "NOTIFY_USER" => {
+            let (title, body) = match notification_text(&command) {
+                Ok(text) => text,
+                Err(reason) => {
+                    send_command_result(handle, msg.header.request_id, false, Some(reason)).await;
+                    return;
+                }
+            };
+            // The Session 0 service only gets here when there is no helper
+            #[cfg(target_os = "windows")]
+            if agent_windows::session_detect::is_system_service_context() {
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("no interactive user session to notify"),
+                )
+                .await;
+                return;
+            }
+            let outcome = tokio::task::spawn_blocking(move || {
+                create_platform_notifier()?.notify(&title, &body)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.into()));
+            match outcome {
+                Ok(()) => send_command_result(handle, msg.header.request_id, true, None).await,
+                Err(e) => {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("notification error: {:#}", e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "FLUSH_DNS" | "RENEW_DHCP" => {
+            if !process_is_elevated() {
+                warn!("refusing {}: agent is not running elevated", cmd_type);
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("network commands need root / administrator rights"),
+                )
+                .await;
+                return;
+            }
+            let interface = command["interface"].as_str().map(str::to_string);
+            if let Some(name) = &interface {
+                if !valid_interface_name(name) {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some("invalid 'interface' name"),
+                    )
+                    .await;
+                    return;
+                }
+            }
+            let flush = cmd_type == "FLUSH_DNS";
+            let outcome = tokio::task::spawn_blocking(move || {
+                let network = create_platform_network()?;
+                if flush {
+                    network.flush_dns()
+                } else {
+                    network.renew_dhcp(interface.as_deref())
+                }
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.into()));
+            match outcome {
+                Ok(()) => send_command_result(handle, msg.header.request_id, true, None).await,
+                Err(e) => {
+                    send_command_result(
+                        handle,
+                        msg.header.request_id,
+                        false,
+                        Some(&format!("network error: {:#}", e)),
+                    )
+                    .await;
+                }
+            }
+        }
+        "SCREENSHOT" | "TAKE_SCREENSHOT" => {
+            let (quality, scale) = screenshot_options(&command);
+            // Capturing and encoding can take seconds; don't hold up the
+            // main loop meanwhile
+            let handle = handle.clone();
+            let request_id = msg.header.request_id;
+            let max_payload = handle.max_payload();
+            tokio::spawn(async move {
+                let result = screenshot_result(quality, scale, max_payload).await;
+                if let Ok(resp) =
+                    protocol::Message::control_json(protocol::COMMAND_RESULT, request_id, &result)
+                {
+                    if let Err(e) = handle.send_message(&resp).await {
+                        error!("failed to send command result: {}", e);
+                    }
+                }
+            });
+        }
+        "DOWNLOAD_URL" => {
+            if !config.policy.downloads {
+                warn!("refusing {}: file writes are disabled", cmd_type);
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("file writes are disabled on this agent"),
+                )
+                .await;
+                return;
+            }
+            let url = command["url"].as_str().unwrap_or("").to_string();
+            let sha256 = command["sha256"].as_str().unwrap_or("").to_string();
+            let path = std::path::PathBuf::from(command["path"].as_str().unwrap_or(""));
+            let execute = command["execute"].as_bool().unwrap_or(false);
+            let args: Vec<String> = command["args"]
+                .as_array()
+                .map(|args| {
+                    args.iter()
+                        .filter_map(|a| a.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default();
+            if url.is_empty() {
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("missing 'url' field"),
+                )
+                .await;
+                return;
+            }
+            if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("'sha256' must be 64 hex digits"),
+                )
+                .await;
+                return;
+            }
+            if !path.is_absolute() {
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("'path' must be an absolute path"),
+                )
+                .await;
+                return;
+            }
+            if execute && !is_within(&path, &config.data_dir()) {
+                send_command_result(
+                    handle,
+                    msg.header.request_id,
+                    false,
+                    Some("files to execute must be downloaded into the data directory"),
+                )
+                .await;
+                return;
+            }
+
+            // Downloads can take minutes: run them off the command loop
+            let handle = handle.clone();
+            let request_id = msg.header.request_id;
+            let max_bytes = config.policy.max_download_bytes;
+            tokio::spawn(async move {
+                info!("downloading {} to {}", url, path.display());
+                let (progress_tx, mut progress_rx) = watch::channel((0u64, None::<u64>));
+                let download = auto_update::download_verified(
+                    &url,
+                    &sha256,
+                    &path,
+                    Some(max_bytes),
+                    move |bytes, total| {
+                        let _ = progress_tx.send((bytes, total));
+                    },
+                );
+                tokio::pin!(download);
+                let mut ticker = tokio::time::interval(DOWNLOAD_PROGRESS_INTERVAL);
+                let outcome = loop {
+                    tokio::select! {
+                        outcome = &mut download => break outcome,
+                        _ = ticker.tick() => {
+                            if !progress_rx.has_changed().unwrap_or(false) {
+                                continue;
+                            }
+                            let (bytes, total) = *progress_rx.borrow_and_update();
+                            let progress = serde_json::json!({ "bytes": bytes, "total": total });
+                            if let Ok(msg) = protocol::Message::control_json(protocol::COMMAND_PROGRESS, request_id, &progress) {
+                                let _ = handle.send_message(&msg).await;
+                            }
+                        }
+                    }
+                };
+                match outcome {
+                    Ok(bytes) => {
+                        info!("downloaded {} bytes to {}", bytes, path.display());
+                        let mut result = serde_json::json!({
+                            "success": true,
+                            "path": path,
+                            "bytes": bytes,
+                        });
+                        if execute {
+                            info!("executing {}", path.display());
+                            // The verified handle stays open until the process
+                            // exits, so the file can't be swapped under it
+                            let run = async {
+                                let verified = auto_update::open_verified(&path, &sha256)?;
+                                run_captured(executable_command(&path, &verified, &args)).await
+                            };
+                            match run.await {
+                                Ok(out) => {
+                                    for (key, value) in
+                                        out.to_json().as_object().into_iter().flatten()
+                                    {
+                                        result[key] = value.clone();
+                                    }
+                                }
+                                Err(e) => {
+                                    send_command_result(
+                                        &handle,
+                                        request_id,
+                                        false,
+                                        Some(&format!("exec error: {:#}", e)),
+                                    )
+                                    .await;
+                                    return;
+                                }
+                            }
+                        }
+                        if let Ok(resp) = protocol::Message::control_json(
+                            protocol::COMMAND_RESULT,
+                            request_id,
+                            &result,
+                        ) {
+                            if let Err(e) = handle.send_message(&resp).await {
+                                error!("failed to send command result: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("download of {} failed: {:#}", url, e);
+                        send_command_result(
+                            &handle,
+                            request_id,
+                            false,
+                            Some(&format!("download error: {:#}", e)),
+                        )
+                        .await;
+                    }
+                }
+            });
+        }
         _ => {
             warn!("unknown command type: {}", cmd_type);
             send_command_result(handle, msg.header.request_id, false, Some(&format!("unknown command: {}", cmd_type))).await;
@@ -580,7 +2020,268 @@ async fn handle_command(
     }
 }
 
-async fn send_command_result(handle: &ConnectionHandle, request_id: u32, success: bool, error: Option<&str>) {
+/// Longest a RUN_SHELL command or an executed download may run before it
+/// is killed
+const PROCESS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+/// Bytes kept from each of a process's stdout and stderr; the rest is read
+/// and discarded
+const PROCESS_OUTPUT_CAP: usize = 1024 * 1024;
+
+/// JPEG quality and scale factor asked for by a SCREENSHOT command
+fn screenshot_options(command: &serde_json::Value) -> (u8, f32) {
+    let quality = command["quality"].as_u64().unwrap_or(70).min(100) as u8;
+    let scale = command["scale"].as_f64().unwrap_or(1.0) as f32;
+    (quality, scale)
+}
+
+/// Room left in a screenshot's COMMAND_RESULT for the fields besides the
+/// image data
+const SCREENSHOT_RESULT_OVERHEAD: usize = 256;
+
+/// Take a screenshot in this process and build the COMMAND_RESULT fields
+/// reporting it. The image is made small enough for the result to fit in
+/// `max_payload` bytes once base64-encoded.
+async fn screenshot_result(quality: u8, scale: f32, max_payload: usize) -> serde_json::Value {
+    use base64::Engine;
+
+    let max_jpeg = max_payload.saturating_sub(SCREENSHOT_RESULT_OVERHEAD) / 4 * 3;
+    match agent_core::session::take_screenshot(quality, scale, max_jpeg).await {
+        Ok(shot) => serde_json::json!({
+            "success": true,
+            "format": "jpeg",
+            "width": shot.width,
+            "height": shot.height,
+            "data": base64::engine::general_purpose::STANDARD.encode(&shot.jpeg),
+        }),
+        Err(e) => {
+            serde_json::json!({ "success": false, "error": format!("screenshot error: {:#}", e) })
+        }
+    }
+}
+
+/// Longest NOTIFY_USER title and body, in characters
+const MAX_NOTIFY_TITLE: usize = 256;
+const MAX_NOTIFY_BODY: usize = 2048;
+
+/// Title and body of a NOTIFY_USER command; at least one must be non-empty
+fn notification_text(
+    command: &serde_json::Value,
+) -> std::result::Result<(String, String), &'static str> {
+    let title = command["title"].as_str().unwrap_or("").trim();
+    let body = command["body"].as_str().unwrap_or("").trim();
+    if title.is_empty() && body.is_empty() {
+        return Err("missing 'title' and 'body' fields");
+    }
+    if title.chars().count() > MAX_NOTIFY_TITLE {
+        return Err("'title' is too long");
+    }
+    if body.chars().count() > MAX_NOTIFY_BODY {
+        return Err("'body' is too long");
+    }
+    Ok((title.to_string(), body.to_string()))
+}
+
+/// The platform shell running `shell_cmd`, as RUN_SHELL executes it
+fn shell_command(shell_cmd: &str) -> tokio::process::Command {
+    #[cfg(target_os = "windows")]
+    {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.args(["/C", shell_cmd]);
+        cmd
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.args(["-c", shell_cmd]);
+        cmd
+    }
+}
+
+/// Why a RUN_SHELL that asked for the user's context (`as_user`) is running
+/// in the agent's own context instead, if it is
+#[cfg(target_os = "windows")]
+fn user_shell_fallback_note() -> Option<&'static str> {
+    // Outside Session 0 the agent already runs as the logged-in user
+    agent_windows::session_detect::is_system_service_context()
+        .then_some("no interactive user session available, ran as SYSTEM")
+}
+
+#[cfg(not(target_os = "windows"))]
+fn user_shell_fallback_note() -> Option<&'static str> {
+    Some("running as the session user is only supported on Windows, ran as the agent's user")
+}
+
+/// Outcome of a process run by `run_captured`
+struct CapturedOutput {
+    status: std::process::ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+}
+
+impl CapturedOutput {
+    /// The fields of a COMMAND_RESULT reporting this run
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "success": self.status.success(),
+            "exitCode": self.status.code(),
+            "stdout": String::from_utf8_lossy(&self.stdout),
+            "stderr": String::from_utf8_lossy(&self.stderr),
+            "truncated": self.truncated,
+        })
+    }
+}
+
+/// Run `cmd` with no stdin, capturing up to `PROCESS_OUTPUT_CAP` bytes of
+/// each output stream. The process is killed once `PROCESS_TIMEOUT` passes.
+async fn run_captured(mut cmd: tokio::process::Command) -> Result<CapturedOutput> {
+    use std::process::Stdio;
+
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = cmd.spawn().context("failed to spawn process")?;
+    let stdout = child.stdout.take().context("stdout not captured")?;
+    let stderr = child.stderr.take().context("stderr not captured")?;
+
+    let run = async { tokio::try_join!(read_capped(stdout), read_capped(stderr), child.wait()) };
+    match tokio::time::timeout(PROCESS_TIMEOUT, run).await {
+        Ok(Ok(((stdout, out_truncated), (stderr, err_truncated), status))) => Ok(CapturedOutput {
+            status,
+            stdout,
+            stderr,
+            truncated: out_truncated || err_truncated,
+        }),
+        Ok(Err(e)) => Err(e).context("failed to run process"),
+        Err(_) => anyhow::bail!("process timed out after {}s", PROCESS_TIMEOUT.as_secs()),
+    }
+}
+
+/// Shorten the stdout and stderr of a RUN_SHELL result until its JSON fits
+/// in `max` bytes, the most the server can receive, keeping the start of
+/// each like `read_capped` does.
+fn fit_shell_result(result: &mut serde_json::Value, max: usize) {
+    while serde_json::to_vec(result).map_or(0, |json| json.len()) > max {
+        let mut shortened = false;
+        for field in ["stdout", "stderr"] {
+            let Some(text) = result[field].as_str().filter(|t| !t.is_empty()) else {
+                continue;
+            };
+            let mut cut = text.len() / 2;
+            while !text.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            result[field] = serde_json::Value::String(text[..cut].to_string());
+            shortened = true;
+        }
+        if !shortened {
+            return;
+        }
+        result["truncated"] = serde_json::Value::Bool(true);
+    }
+}
+
+/// Read `reader` to the end, keeping the first `PROCESS_OUTPUT_CAP` bytes.
+/// Returns whether anything was dropped.
+async fn read_capped(
+    mut reader: impl tokio::io::AsyncRead + Unpin,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    use tokio::io::AsyncReadExt;
+
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok((kept, truncated));
+        }
+        let room = PROCESS_OUTPUT_CAP - kept.len();
+        kept.extend_from_slice(&buf[..n.min(room)]);
+        truncated |= n > room;
+    }
+}
+
+/// Command that runs a downloaded file: scripts through their interpreter
+/// on Windows, anything else directly (marked executable first on Unix,
+/// through the verified handle `file`)
+fn executable_command(
+    path: &std::path::Path,
+    file: &std::fs::File,
+    args: &[String],
+) -> tokio::process::Command {
+    #[cfg(target_os = "windows")]
+    {
+        let _ = file;
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let mut cmd = match extension.as_str() {
+            "ps1" => {
+                let mut cmd = tokio::process::Command::new("powershell");
+                cmd.args([
+                    "-NoProfile",
+                    "-NonInteractive",
+                    "-ExecutionPolicy",
+                    "Bypass",
+                    "-File",
+                ]);
+                cmd.arg(path);
+                cmd
+            }
+            "bat" | "cmd" => {
+                let mut cmd = tokio::process::Command::new("cmd");
+                cmd.arg("/C").arg(path);
+                cmd
+            }
+            _ => tokio::process::Command::new(path),
+        };
+        cmd.args(args);
+        cmd
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(e) = file.set_permissions(std::fs::Permissions::from_mode(0o755)) {
+            warn!("failed to mark {} executable: {}", path.display(), e);
+        }
+        let mut cmd = tokio::process::Command::new(path);
+        cmd.args(args);
+        cmd
+    }
+}
+
+/// Whether the file `path` lies inside `dir`. Both directories are
+/// resolved first, so symlinks and junctions can't lead out of `dir`; the
+/// file name itself must be a plain name.
+fn is_within(path: &std::path::Path, dir: &std::path::Path) -> bool {
+    let (Some(parent), Some(std::path::Component::Normal(_))) =
+        (path.parent(), path.components().next_back())
+    else {
+        return false;
+    };
+    match (parent.canonicalize(), dir.canonicalize()) {
+        (Ok(parent), Ok(dir)) => parent.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// Wall-clock time in milliseconds since the Unix epoch
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+async fn send_command_result(
+    handle: &ConnectionHandle,
+    request_id: u32,
+    success: bool,
+    error: Option<&str>,
+) {
     let mut result = serde_json::json!({ "success": success });
     if let Some(err) = error {
         result["error"] = serde_json::Value::String(err.to_string());
@@ -592,9 +2293,13 @@ async fn send_command_result(handle: &ConnectionHandle, request_id: u32, success
     }
 }
 
-fn create_telemetry_collector() -> Result<TelemetryCollector> {
+fn create_telemetry_collector(config: &AgentConfig) -> Result<TelemetryCollector> {
     let sys_info = create_platform_system_info()?;
-    Ok(TelemetryCollector::new(sys_info))
+    Ok(TelemetryCollector::new(
+        sys_info,
+        config.low_disk_percent,
+        DiskFilter::from_config(config),
+    ))
 }
 
 fn create_file_handler() -> Result<FileHandler> {
@@ -622,6 +2327,102 @@ fn create_platform_filesystem() -> Result<Box<dyn agent_platform::filesystem::Fi
     anyhow::bail!("filesystem not supported on this platform")
 }
 
+#[cfg(target_os = "linux")]
+fn create_platform_clock() -> Result<Box<dyn SystemClock>> {
+    Ok(Box::new(agent_linux::clock::LinuxClock))
+}
+
+#[cfg(target_os = "macos")]
+fn create_platform_clock() -> Result<Box<dyn SystemClock>> {
+    anyhow::bail!("clock control not yet implemented for macOS")
+}
+
+#[cfg(target_os = "windows")]
+fn create_platform_clock() -> Result<Box<dyn SystemClock>> {
+    Ok(Box::new(agent_windows::clock::WindowsClock))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn create_platform_clock() -> Result<Box<dyn SystemClock>> {
+    anyhow::bail!("clock control not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+fn create_platform_notifier() -> Result<Box<dyn UserNotifier>> {
+    Ok(Box::new(agent_linux::notification::LinuxNotifier))
+}
+
+#[cfg(target_os = "macos")]
+fn create_platform_notifier() -> Result<Box<dyn UserNotifier>> {
+    anyhow::bail!("user notifications not yet implemented for macOS")
+}
+
+#[cfg(target_os = "windows")]
+fn create_platform_notifier() -> Result<Box<dyn UserNotifier>> {
+    Ok(Box::new(agent_windows::notification::WindowsNotifier))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn create_platform_notifier() -> Result<Box<dyn UserNotifier>> {
+    anyhow::bail!("user notifications not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+fn create_platform_network() -> Result<Box<dyn NetworkControl>> {
+    Ok(Box::new(agent_linux::network::LinuxNetwork))
+}
+
+#[cfg(target_os = "macos")]
+fn create_platform_network() -> Result<Box<dyn NetworkControl>> {
+    anyhow::bail!("network control not yet implemented for macOS")
+}
+
+#[cfg(target_os = "windows")]
+fn create_platform_network() -> Result<Box<dyn NetworkControl>> {
+    Ok(Box::new(agent_windows::network::WindowsNetwork))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn create_platform_network() -> Result<Box<dyn NetworkControl>> {
+    anyhow::bail!("network control not supported on this platform")
+}
+
+/// Whether the agent runs as root / an elevated administrator
+#[cfg(target_os = "linux")]
+fn process_is_elevated() -> bool {
+    nix::unistd::Uid::effective().is_root()
+}
+
+#[cfg(target_os = "windows")]
+fn process_is_elevated() -> bool {
+    agent_windows::installer::is_elevated()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn process_is_elevated() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn create_platform_services() -> Result<Box<dyn SystemServices>> {
+    Ok(Box::new(agent_linux::service::SystemdServices))
+}
+
+#[cfg(target_os = "macos")]
+fn create_platform_services() -> Result<Box<dyn SystemServices>> {
+    anyhow::bail!("service listing not yet implemented for macOS")
+}
+
+#[cfg(target_os = "windows")]
+fn create_platform_services() -> Result<Box<dyn SystemServices>> {
+    Ok(Box::new(agent_windows::service::ScmServices))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn create_platform_services() -> Result<Box<dyn SystemServices>> {
+    anyhow::bail!("service listing not supported on this platform")
+}
+
 #[cfg(target_os = "linux")]
 fn create_platform_system_info() -> Result<Box<dyn agent_platform::system_info::SystemInfo>> {
     Ok(Box::new(agent_linux::system_info::LinuxSystemInfo::new()))
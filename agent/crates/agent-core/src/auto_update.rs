@@ -1,11 +1,19 @@
 //! Auto-update: check for updates, download, verify checksum, replace binary.
 
+use std::path::{Path, PathBuf};
+
 use anyhow::{Context, Result};
 use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
 use tracing::info;
 
 use crate::config::AgentConfig;
 
+/// FILE_SHARE_READ: other handles may read the file but not write, rename
+/// or delete it
+#[cfg(windows)]
+const FILE_SHARE_READ: u32 = 0x0000_0001;
+
 /// Response from GET /api/agent/latest
 #[derive(Debug, serde::Deserialize)]
 pub struct LatestVersionInfo {
@@ -16,11 +24,7 @@ pub struct LatestVersionInfo {
 
 /// Check for an available update. Returns Some(info) if a newer version exists.
 pub async fn check_for_update(config: &AgentConfig) -> Result<Option<LatestVersionInfo>> {
-    let base = config
-        .server_url
-        .replace("wss://", "https://")
-        .replace("ws://", "http://");
-    let base = base.trim_end_matches('/');
+    let base = config.http_base_url();
 
     let os = std::env::consts::OS;
     let arch = match std::env::consts::ARCH {
@@ -70,39 +74,13 @@ pub async fn download_and_apply(info: &LatestVersionInfo) -> Result<()> {
 
     info!("downloading update from {}", info.url);
 
-    let client = reqwest::Client::new();
-    let resp = client
-        .get(&info.url)
-        .send()
-        .await
-        .context("failed to download update")?;
-
-    if !resp.status().is_success() {
-        anyhow::bail!("download failed: HTTP {}", resp.status());
-    }
-
-    let bytes = resp.bytes().await.context("failed to read update body")?;
-
-    // Verify SHA-256
-    let mut hasher = Sha256::new();
-    hasher.update(&bytes);
-    let hash = format!("{:x}", hasher.finalize());
-
-    if hash != info.sha256 {
-        anyhow::bail!(
-            "checksum mismatch: expected {}, got {}",
-            info.sha256,
-            hash
-        );
-    }
-
-    info!("checksum verified, applying update ({} bytes)", bytes.len());
-
     // Write to a temp file next to the current binary
     let tmp_path = current_exe.with_extension("update");
+    let size = download_verified(&info.url, &info.sha256, &tmp_path, None, |_, _| {})
+        .await
+        .context("failed to download update")?;
 
-    std::fs::write(&tmp_path, &bytes)
-        .with_context(|| format!("failed to write update to {}", tmp_path.display()))?;
+    info!("checksum verified, applying update ({} bytes)", size);
 
     // Set executable permission on Unix
     #[cfg(unix)]
@@ -141,6 +119,166 @@ pub async fn download_and_apply(info: &LatestVersionInfo) -> Result<()> {
     Ok(())
 }
 
+/// Download `url` to `dest`, verifying the body's SHA-256 against `sha256`
+/// (hex). The body is streamed to `<dest>.part`, which only replaces `dest`
+/// once the checksum matches. The `.part` file is always created fresh, so a
+/// file planted there beforehand (keeping its own ACL) is never reused. A body
+/// longer than `max_bytes`, if given, fails the download as soon as it passes
+/// the limit. `on_progress` is called with the bytes received
+/// so far and the expected total, if the server sent one. Returns the size.
+pub async fn download_verified(
+    url: &str,
+    sha256: &str,
+    dest: &Path,
+    max_bytes: Option<u64>,
+    mut on_progress: impl FnMut(u64, Option<u64>),
+) -> Result<u64> {
+    let parsed = reqwest::Url::parse(url).context("invalid download URL")?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host().is_none() {
+        anyhow::bail!("download URL must be http(s) with a host: {}", url);
+    }
+
+    let mut resp = reqwest::Client::new()
+        .get(parsed)
+        .send()
+        .await
+        .context("request failed")?;
+
+    if !resp.status().is_success() {
+        anyhow::bail!("download failed: HTTP {}", resp.status());
+    }
+
+    let total = resp.content_length();
+    if let (Some(total), Some(max)) = (total, max_bytes) {
+        if total > max {
+            anyhow::bail!(
+                "download is {} bytes, more than the {} byte limit",
+                total,
+                max
+            );
+        }
+    }
+    let mut part_name = dest.as_os_str().to_owned();
+    part_name.push(".part");
+    let part_path = PathBuf::from(part_name);
+    // A .part left by an interrupted download goes; create_new then makes
+    // sure the file written is the agent's own
+    match tokio::fs::remove_file(&part_path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("failed to remove stale {}", part_path.display()))
+        }
+    }
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // Nobody else may write to or rename the file while it's being written
+    #[cfg(windows)]
+    options.share_mode(FILE_SHARE_READ);
+    let mut file = options
+        .open(&part_path)
+        .await
+        .with_context(|| format!("failed to create {}", part_path.display()))?;
+
+    let mut hasher = Sha256::new();
+    let mut received = 0u64;
+    let written: Result<()> = async {
+        while let Some(chunk) = resp.chunk().await.context("failed to read body")? {
+            if let Some(max) = max_bytes.filter(|&max| received + chunk.len() as u64 > max) {
+                anyhow::bail!("download is more than the {} byte limit", max);
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .await
+                .context("failed to write body")?;
+            received += chunk.len() as u64;
+            on_progress(received, total);
+        }
+        file.flush().await.context("failed to write body")?;
+        Ok(())
+    }
+    .await;
+    drop(file);
+
+    let hash = format!("{:x}", hasher.finalize());
+    let outcome = written.and_then(|()| {
+        if hash.eq_ignore_ascii_case(sha256) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "checksum mismatch: expected {}, got {}",
+                sha256,
+                hash
+            ))
+        }
+    });
+    if let Err(e) = outcome {
+        let _ = std::fs::remove_file(&part_path);
+        return Err(e);
+    }
+
+    std::fs::rename(&part_path, dest)
+        .with_context(|| format!("failed to move download to {}", dest.display()))?;
+    Ok(received)
+}
+
+/// Open a downloaded file for execution and check it still hashes to
+/// `sha256`. The returned handle denies writes, renames and deletes (on
+/// Windows) until dropped, so the contents can't change between this check
+/// and running the file. Symlinks are refused.
+pub fn open_verified(path: &Path, sha256: &str) -> Result<std::fs::File> {
+    use std::io::Read;
+
+    let meta = std::fs::symlink_metadata(path)
+        .with_context(|| format!("failed to stat {}", path.display()))?;
+    if !meta.file_type().is_file() {
+        anyhow::bail!("{} is not a regular file", path.display());
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.share_mode(FILE_SHARE_READ);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    // The path must not have been swapped for a link between the two looks
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let opened = file.metadata()?;
+        if (opened.dev(), opened.ino()) != (meta.dev(), meta.ino()) {
+            anyhow::bail!("{} was replaced while being opened", path.display());
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let hash = format!("{:x}", hasher.finalize());
+    if !hash.eq_ignore_ascii_case(sha256) {
+        anyhow::bail!(
+            "{} changed after download: expected {}, got {}",
+            path.display(),
+            sha256,
+            hash
+        );
+    }
+    Ok(file)
+}
+
 /// Perform a full update check + download + apply cycle.
 /// Returns true if an update was applied (caller should restart).
 pub async fn perform_update(config: &AgentConfig) -> Result<bool> {
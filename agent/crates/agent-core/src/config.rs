@@ -1,12 +1,34 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::Ipv6Addr;
 use std::path::{Path, PathBuf};
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+
+pub use agent_platform::LOG_LEVELS;
+
+/// Upgrade request headers the WebSocket handshake sets itself, which
+/// `ws_headers` may not override
+const RESERVED_WS_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "upgrade",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-protocol",
+];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
-    /// Server URL (e.g., wss://server:7899)
+    /// Server URL (e.g., wss://server:7899). May include a path prefix when
+    /// the server sits behind a reverse proxy (e.g., https://host/remote).
     pub server_url: String,
 
+    /// Extra path prefix for the agent endpoints, for setups where it can't
+    /// be part of `server_url` (e.g., "/remote")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_path: Option<String>,
+
     /// Enrollment token for first-time registration
     #[serde(skip_serializing_if = "Option::is_none")]
     pub enroll_token: Option<String>,
@@ -23,10 +45,39 @@ pub struct AgentConfig {
     #[serde(default = "default_heartbeat_interval")]
     pub heartbeat_interval_secs: u64,
 
-    /// Telemetry interval in seconds
+    /// WebSocket Ping interval in seconds, independent of the app heartbeat.
+    /// Keeps proxies and NATs from dropping idle connections; 0 disables.
+    #[serde(default = "default_ws_ping_interval")]
+    pub ws_ping_interval_secs: u64,
+
+    /// Telemetry interval in seconds; shorter than `MIN_TELEMETRY_INTERVAL_SECS`
+    /// is raised to it. Picked up from the config file without a restart.
     #[serde(default = "default_telemetry_interval")]
     pub telemetry_interval_secs: u64,
 
+    /// Seconds to wait for the WebSocket connect (TCP, TLS and upgrade)
+    /// before treating the attempt as failed and backing off
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_secs: u64,
+
+    /// Sec-WebSocket-Protocol offered on the relay upgrade request, for
+    /// proxies and gateways that require one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ws_subprotocol: Option<String>,
+
+    /// Extra headers sent on the relay upgrade request, e.g. an API key for
+    /// a gateway that authenticates at the HTTP layer
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ws_headers: BTreeMap<String, String>,
+
+    /// Bearer token for a relay or bastion in front of the server, sent as
+    /// `Authorization` on the upgrade request. Separate from the session
+    /// token, which the agent presents in AUTH_REQUEST. May reference an
+    /// environment variable (`${RELAY_TOKEN}`), which is never written back
+    /// to the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_auth_token: Option<String>,
+
     /// Reconnect base delay in seconds
     #[serde(default = "default_reconnect_base_delay")]
     pub reconnect_base_delay_secs: u64,
@@ -34,14 +85,243 @@ pub struct AgentConfig {
     /// Reconnect max delay in seconds
     #[serde(default = "default_reconnect_max_delay")]
     pub reconnect_max_delay_secs: u64,
+
+    /// Refresh an expiring session token this many seconds before it
+    /// expires (only for servers that report an expiry). Tokens living less
+    /// than twice this long are refreshed halfway through instead.
+    #[serde(default = "default_token_refresh_margin")]
+    pub token_refresh_margin_secs: u64,
+
+    /// Drop the WebSocket after this many minutes without sessions or
+    /// server messages, then only connect briefly every
+    /// `checkin_interval_secs` to pick up queued commands; 0 stays connected
+    #[serde(default)]
+    pub idle_disconnect_mins: u64,
+
+    /// Seconds between check-in connects while idle-disconnected
+    #[serde(default = "default_checkin_interval")]
+    pub checkin_interval_secs: u64,
+
+    /// Messages each outgoing queue (control and bulk) holds before senders
+    /// wait. Larger queues ride out bursts of desktop tiles without stalling
+    /// capture but hold more memory and delay the "auto" encoding's reaction
+    /// to a slow link, since it backs off on bytes still queued.
+    #[serde(default = "default_outgoing_queue_size")]
+    pub outgoing_queue_size: usize,
+
+    /// Writable directory for agent state (logs, uploads, keys), separate
+    /// from the config file's location. Defaults to `default_data_dir()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<String>,
+
+    /// Log level used when neither --log-level nor AGENT_LOG_LEVEL is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+
+    /// Window in milliseconds for coalescing terminal output into one
+    /// TERMINAL_DATA message; 0 sends every PTY read as it arrives
+    #[serde(default = "default_terminal_batch")]
+    pub terminal_batch_ms: u64,
+
+    /// Close a terminal after this many minutes without input or output,
+    /// freeing its PTY; 0 keeps terminals open until the viewer closes them
+    #[serde(default)]
+    pub terminal_idle_timeout_mins: u64,
+
+    /// Windows only: flags for CreatePseudoConsole. The default (1,
+    /// PSEUDOCONSOLE_INHERIT_CURSOR) makes ConPTY query the viewer's cursor
+    /// position on start; set 0 for viewers that don't answer DSR queries.
+    #[serde(default = "default_conpty_flags")]
+    pub conpty_flags: u32,
+
+    /// Windows only: seconds the service waits for a spawned helper to
+    /// connect to its pipe before giving up on it
+    #[serde(default = "default_helper_connect_timeout")]
+    pub helper_connect_timeout_secs: u64,
+
+    /// Telemetry flags a volume as low on disk when its free space drops
+    /// below this percentage of its size; 0 disables the check
+    #[serde(default = "default_low_disk_percent")]
+    pub low_disk_percent: u8,
+
+    /// Filesystem types reported in disk telemetry even though they are
+    /// excluded by default (e.g. "tmpfs")
+    #[serde(default)]
+    pub disk_include_filesystems: Vec<String>,
+
+    /// Filesystem types left out of disk telemetry (e.g. "squashfs")
+    #[serde(default)]
+    pub disk_exclude_filesystems: Vec<String>,
+
+    /// Mount point prefixes always reported in disk telemetry
+    #[serde(default)]
+    pub disk_include_mounts: Vec<String>,
+
+    /// Mount point prefixes left out of disk telemetry (e.g. "/snap").
+    /// Excludes win over includes.
+    #[serde(default)]
+    pub disk_exclude_mounts: Vec<String>,
+
+    /// Upper bound on the frame rate a desktop session may request;
+    /// DESKTOP_OPEN / DESKTOP_QUALITY asking for more are clamped to it
+    #[serde(default = "default_max_fps")]
+    pub max_fps: u16,
+
+    /// Let desktop sessions send uncompressed RAW tiles: viewers may ask for
+    /// encoding "raw", and tiny edge tiles of JPEG sessions skip JPEG.
+    /// Meant for fast LANs; RAW tiles are several times larger than JPEG.
+    #[serde(default)]
+    pub raw_tiles: bool,
+
+    /// New desktop and terminal sessions are refused while the device has
+    /// less than this much memory available; 0 disables the check
+    #[serde(default = "default_min_free_memory")]
+    pub min_free_memory_mb: u64,
+
+    /// Which commands that change the device the server may send
+    #[serde(default)]
+    pub policy: CommandPolicy,
+
+    /// Hosts a RECONNECT command may move the agent to (e.g. "relay2.example.com",
+    /// "[2001:db8::1]"); empty allows any host
+    #[serde(default)]
+    pub reconnect_allowed_hosts: Vec<String>,
+
+    /// Whether the local user sees a "remote session active" overlay while
+    /// a desktop session is open
+    #[serde(default)]
+    pub session_indicator: SessionIndicatorMode,
+
+    /// The file as `load` read it, for `save` to write back
+    #[serde(skip)]
+    template: Option<Box<ConfigTemplate>>,
+}
+
+/// Fields the agent writes itself, which are never expanded
+const UNEXPANDED_FIELDS: &[&str] = &["session_token", "device_id"];
+
+/// A config file as written and as expanded by `load`. A value that still
+/// equals its expansion on `save` is written back as the original
+/// `${VAR}` reference.
+#[derive(Debug, Clone)]
+struct ConfigTemplate {
+    raw: serde_json::Value,
+    expanded: serde_json::Value,
+}
+
+/// Commands that change the device, and the limits they run under. Every
+/// command is allowed unless turned off here; downloads are always capped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandPolicy {
+    /// Accept START_SERVICE / STOP_SERVICE / RESTART_SERVICE; LIST_SERVICES
+    /// is always allowed
+    pub service_control: bool,
+
+    /// Accept SET_TIME / SYNC_TIME; GET_TIME is always allowed
+    pub clock_changes: bool,
+
+    /// Accept DOWNLOAD_URL, which writes files to the device
+    pub downloads: bool,
+
+    /// Largest file a DOWNLOAD_URL may write; bigger downloads are aborted
+    /// as soon as they pass it
+    pub max_download_bytes: u64,
+}
+
+/// Default `max_download_bytes`: 1 GiB
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 1 << 30;
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        Self {
+            service_control: true,
+            clock_changes: true,
+            downloads: true,
+            max_download_bytes: DEFAULT_MAX_DOWNLOAD_BYTES,
+        }
+    }
+}
+
+/// How the on-screen session indicator is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionIndicatorMode {
+    /// Refuse desktop sessions if the indicator can't be shown
+    Required,
+    /// Show the indicator when possible
+    #[default]
+    Optional,
+    /// Never show the indicator
+    Off,
+}
+
+impl SessionIndicatorMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Required => "required",
+            Self::Optional => "optional",
+            Self::Off => "off",
+        }
+    }
+}
+
+impl std::str::FromStr for SessionIndicatorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "required" => Ok(Self::Required),
+            "optional" => Ok(Self::Optional),
+            "off" => Ok(Self::Off),
+            other => anyhow::bail!("invalid session indicator mode: {}", other),
+        }
+    }
 }
 
 fn default_heartbeat_interval() -> u64 {
     30
 }
+fn default_ws_ping_interval() -> u64 {
+    20
+}
+/// Shortest allowed telemetry interval; collecting more often than this costs
+/// the device more than the extra samples are worth
+pub const MIN_TELEMETRY_INTERVAL_SECS: u64 = 10;
+
 fn default_telemetry_interval() -> u64 {
     60
 }
+fn default_connect_timeout() -> u64 {
+    15
+}
+fn default_token_refresh_margin() -> u64 {
+    300
+}
+fn default_checkin_interval() -> u64 {
+    300
+}
+fn default_outgoing_queue_size() -> usize {
+    256
+}
+fn default_terminal_batch() -> u64 {
+    5
+}
+fn default_conpty_flags() -> u32 {
+    0x1 // PSEUDOCONSOLE_INHERIT_CURSOR
+}
+fn default_helper_connect_timeout() -> u64 {
+    30
+}
+fn default_low_disk_percent() -> u8 {
+    10
+}
+fn default_max_fps() -> u16 {
+    30
+}
+fn default_min_free_memory() -> u64 {
+    64
+}
 fn default_reconnect_base_delay() -> u64 {
     1
 }
@@ -53,13 +333,41 @@ impl Default for AgentConfig {
     fn default() -> Self {
         Self {
             server_url: String::new(),
+            base_path: None,
             enroll_token: None,
             session_token: None,
             device_id: None,
             heartbeat_interval_secs: default_heartbeat_interval(),
+            ws_ping_interval_secs: default_ws_ping_interval(),
             telemetry_interval_secs: default_telemetry_interval(),
+            connect_timeout_secs: default_connect_timeout(),
+            ws_subprotocol: None,
+            ws_headers: BTreeMap::new(),
+            relay_auth_token: None,
             reconnect_base_delay_secs: default_reconnect_base_delay(),
             reconnect_max_delay_secs: default_reconnect_max_delay(),
+            token_refresh_margin_secs: default_token_refresh_margin(),
+            idle_disconnect_mins: 0,
+            checkin_interval_secs: default_checkin_interval(),
+            outgoing_queue_size: default_outgoing_queue_size(),
+            data_dir: None,
+            log_level: None,
+            terminal_batch_ms: default_terminal_batch(),
+            terminal_idle_timeout_mins: 0,
+            conpty_flags: default_conpty_flags(),
+            helper_connect_timeout_secs: default_helper_connect_timeout(),
+            max_fps: default_max_fps(),
+            raw_tiles: false,
+            min_free_memory_mb: default_min_free_memory(),
+            policy: CommandPolicy::default(),
+            reconnect_allowed_hosts: Vec::new(),
+            low_disk_percent: default_low_disk_percent(),
+            disk_include_filesystems: Vec::new(),
+            disk_exclude_filesystems: Vec::new(),
+            disk_include_mounts: Vec::new(),
+            disk_exclude_mounts: Vec::new(),
+            session_indicator: SessionIndicatorMode::default(),
+            template: None,
         }
     }
 }
@@ -74,48 +382,741 @@ impl AgentConfig {
         }
     }
 
-    /// Load config from a file path
+    /// Default directory for agent state on this platform
+    pub fn default_data_dir() -> PathBuf {
+        if cfg!(windows) {
+            let program_data =
+                std::env::var("ProgramData").unwrap_or_else(|_| r"C:\ProgramData".to_string());
+            PathBuf::from(program_data).join("AndroidRemoteAgent")
+        } else if cfg!(target_os = "linux") {
+            PathBuf::from("/var/lib/android-remote-agent")
+        } else if let Some(dirs) = directories::ProjectDirs::from("com", "android-remote", "agent")
+        {
+            dirs.data_dir().to_path_buf()
+        } else {
+            PathBuf::from("agent-data")
+        }
+    }
+
+    /// Directory the agent writes its state to
+    pub fn data_dir(&self) -> PathBuf {
+        match &self.data_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => Self::default_data_dir(),
+        }
+    }
+
+    /// Load config from a file path, expanding environment variables (see
+    /// `expand_env_vars`) in every string value. A variable that isn't set
+    /// fails the load. The file as written is kept, so a later `save`
+    /// doesn't replace `${VAR}` references with what they expanded to.
     pub fn load(path: &Path) -> Result<Self> {
         let data = std::fs::read_to_string(path)
             .with_context(|| format!("failed to read config from {}", path.display()))?;
-        let config: Self =
-            serde_json::from_str(&data).with_context(|| "failed to parse config JSON")?;
+        Self::from_json(&data, &|name| std::env::var(name).ok())
+    }
+
+    fn from_json(data: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Self> {
+        let raw: serde_json::Value =
+            serde_json::from_str(data).with_context(|| "failed to parse config JSON")?;
+        let mut expanded = raw.clone();
+        if let serde_json::Value::Object(fields) = &mut expanded {
+            for (name, value) in fields.iter_mut() {
+                if !UNEXPANDED_FIELDS.contains(&name.as_str()) {
+                    expand_strings(value, name, lookup)?;
+                }
+            }
+        }
+
+        let mut config: Self = serde_json::from_value(expanded.clone())
+            .with_context(|| "failed to parse config JSON")?;
+        config.template = Some(Box::new(ConfigTemplate { raw, expanded }));
         Ok(config)
     }
 
+    /// Whether a RECONNECT command may switch the agent to `url`, per
+    /// `reconnect_allowed_hosts`. Hosts compare case-insensitively, ignoring
+    /// the port.
+    pub fn reconnect_allowed(&self, url: &str) -> bool {
+        if self.reconnect_allowed_hosts.is_empty() {
+            return true;
+        }
+        let url = url.trim();
+        let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+        // An IPv6 literal keeps its brackets
+        let host = match authority.find(']') {
+            Some(end) => &authority[..=end],
+            None => authority.split(':').next().unwrap_or(""),
+        };
+        self.reconnect_allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    /// Raise `telemetry_interval_secs` to `MIN_TELEMETRY_INTERVAL_SECS`.
+    /// Returns the configured value if it was too short, for the caller to
+    /// warn about; shorter intervals are clamped rather than rejected so
+    /// configs written before the minimum keep loading.
+    pub fn clamp_telemetry_interval(&mut self) -> Option<u64> {
+        let configured = self.telemetry_interval_secs;
+        (configured < MIN_TELEMETRY_INTERVAL_SECS).then(|| {
+            self.telemetry_interval_secs = MIN_TELEMETRY_INTERVAL_SECS;
+            configured
+        })
+    }
+
+    /// Check the settings for values that would only fail later at runtime.
+    /// The error lists every problem found, one per line.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        let url = self.server_url.trim();
+        if url.is_empty() {
+            problems.push("server_url is required (--server-url or config file)".to_string());
+        } else {
+            let rest = match url.split_once("://") {
+                Some((scheme, rest)) => {
+                    if !matches!(scheme, "ws" | "wss" | "http" | "https") {
+                        problems.push(format!(
+                            "server_url scheme must be ws, wss, http or https (got \"{}\")",
+                            scheme
+                        ));
+                    }
+                    rest
+                }
+                None => url,
+            };
+            let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+            if host.is_empty() {
+                problems.push(format!("server_url has no host: \"{}\"", url));
+            } else if host.contains(char::is_whitespace) {
+                problems.push(format!("server_url host contains whitespace: \"{}\"", host));
+            } else if let Some(bracketed) = host.strip_prefix('[') {
+                let literal = bracketed.split(']').next().unwrap_or("");
+                // A zone id is percent-encoded: [fe80::1%25eth0]
+                let addr = literal.split('%').next().unwrap_or("");
+                if !bracketed.contains(']') || addr.parse::<Ipv6Addr>().is_err() {
+                    problems.push(format!(
+                        "server_url has an invalid IPv6 address: \"{}\"",
+                        host
+                    ));
+                }
+            } else if host.matches(':').count() > 1 {
+                problems.push(format!(
+                    "server_url IPv6 addresses must be in brackets, e.g. wss://[2001:db8::1]:7899 (got \"{}\")",
+                    host
+                ));
+            }
+        }
+
+        if let Some(base_path) = &self.base_path {
+            if base_path.contains(['?', '#']) {
+                problems.push("base_path must be a plain path without '?' or '#'".to_string());
+            }
+        }
+
+        if self.heartbeat_interval_secs == 0 {
+            problems.push("heartbeat_interval_secs must be > 0".to_string());
+        }
+        if self.connect_timeout_secs == 0 {
+            problems.push("connect_timeout_secs must be > 0".to_string());
+        }
+        if self.reconnect_max_delay_secs == 0 {
+            problems.push("reconnect_max_delay_secs must be > 0".to_string());
+        }
+        if self.reconnect_base_delay_secs > self.reconnect_max_delay_secs {
+            problems.push(format!(
+                "reconnect_base_delay_secs ({}) must not exceed reconnect_max_delay_secs ({})",
+                self.reconnect_base_delay_secs, self.reconnect_max_delay_secs
+            ));
+        }
+        if self.low_disk_percent > 100 {
+            problems.push(format!(
+                "low_disk_percent must be 0-100 (got {})",
+                self.low_disk_percent
+            ));
+        }
+        if self.max_fps == 0 {
+            problems.push("max_fps must be > 0".to_string());
+        }
+        if self.helper_connect_timeout_secs == 0 {
+            problems.push("helper_connect_timeout_secs must be > 0".to_string());
+        }
+        if self.outgoing_queue_size == 0 {
+            problems.push("outgoing_queue_size must be > 0".to_string());
+        }
+        if self.idle_disconnect_mins > 0 && self.checkin_interval_secs == 0 {
+            problems.push(
+                "checkin_interval_secs must be > 0 when idle_disconnect_mins is set".to_string(),
+            );
+        }
+
+        if let Some(protocol) = &self.ws_subprotocol {
+            if protocol.is_empty() || !protocol.chars().all(|c| c.is_ascii_graphic() && c != ',') {
+                problems.push(format!(
+                    "ws_subprotocol is not a valid protocol token: \"{}\"",
+                    protocol
+                ));
+            }
+        }
+        for (name, value) in &self.ws_headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                problems.push(format!("ws_headers: invalid header name \"{}\"", name));
+            } else if RESERVED_WS_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                problems.push(format!(
+                    "ws_headers: \"{}\" is set by the WebSocket handshake",
+                    name
+                ));
+            }
+            if HeaderValue::from_str(value).is_err() {
+                problems.push(format!("ws_headers: invalid value for \"{}\"", name));
+            }
+            if self.relay_auth_token.is_some() && name.eq_ignore_ascii_case("authorization") {
+                problems.push(format!(
+                    "ws_headers: \"{}\" conflicts with relay_auth_token",
+                    name
+                ));
+            }
+        }
+        if let Some(token) = &self.relay_auth_token {
+            if token.is_empty() || HeaderValue::from_str(token).is_err() {
+                problems.push("relay_auth_token must be a non-empty header value".to_string());
+            }
+        }
+
+        if let Some(level) = &self.log_level {
+            if !LOG_LEVELS.contains(&level.as_str()) {
+                problems.push(format!(
+                    "log_level must be one of {} (got \"{}\")",
+                    LOG_LEVELS.join(", "),
+                    level
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        anyhow::bail!("invalid configuration:\n  - {}", problems.join("\n  - "))
+    }
+
     /// Save config to a file path
     pub fn save(&self, path: &Path) -> Result<()> {
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create config dir {}", parent.display()))?;
         }
-        let data = serde_json::to_string_pretty(self)?;
+        let data = self.to_json()?;
         std::fs::write(path, data)
             .with_context(|| format!("failed to write config to {}", path.display()))?;
         Ok(())
     }
 
+    /// The config as JSON, with the `${VAR}` references it was loaded from
+    /// put back wherever the value hasn't changed since
+    fn to_json(&self) -> Result<String> {
+        let Some(template) = &self.template else {
+            return Ok(serde_json::to_string_pretty(self)?);
+        };
+        let mut value = serde_json::to_value(self)?;
+        restore_templates(&mut value, &template.raw, &template.expanded);
+        // Back through the struct so fields keep their declared order
+        let restored: Self = serde_json::from_value(value)?;
+        Ok(serde_json::to_string_pretty(&restored)?)
+    }
+
+    /// Server base URL with the scheme mapped for WebSocket (ws/wss) or HTTP
+    /// (http/https) use. Keeps any path prefix in `server_url`, then appends
+    /// `base_path`; drops the query, fragment, and trailing slashes so
+    /// endpoint paths can be appended.
+    fn base_url(&self, websocket: bool) -> String {
+        let url = self.server_url.trim();
+        let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+        let secure = matches!(scheme, "https" | "wss");
+        let scheme = match (websocket, secure) {
+            (true, true) => "wss",
+            (true, false) => "ws",
+            (false, true) => "https",
+            (false, false) => "http",
+        };
+
+        let rest = rest.split(['?', '#']).next().unwrap_or("");
+        let mut base = format!("{}://{}", scheme, rest.trim_end_matches('/'));
+
+        if let Some(prefix) = self
+            .base_path
+            .as_deref()
+            .map(|p| p.trim_matches('/'))
+            .filter(|p| !p.is_empty())
+        {
+            base.push('/');
+            base.push_str(prefix);
+        }
+        base
+    }
+
     /// Get the relay WebSocket URL
     pub fn relay_url(&self) -> String {
-        let base = self.server_url.trim_end_matches('/');
-        // Convert http(s) scheme to ws(s) for WebSocket connections
-        let ws_base = if base.starts_with("https://") {
-            base.replacen("https://", "wss://", 1)
-        } else if base.starts_with("http://") {
-            base.replacen("http://", "ws://", 1)
-        } else {
-            base.to_string()
-        };
-        format!("{}/relay", ws_base)
+        format!("{}/relay", self.base_url(true))
+    }
+
+    /// Get the server's HTTP base URL (ws(s) schemes mapped to http(s))
+    pub fn http_base_url(&self) -> String {
+        self.base_url(false)
     }
 
     /// Get the enrollment HTTP URL
     pub fn enroll_url(&self) -> String {
-        let base = self
-            .server_url
-            .replace("wss://", "https://")
-            .replace("ws://", "http://");
-        let base = base.trim_end_matches('/');
-        format!("{}/api/enroll/device", base)
+        format!("{}/api/enroll/device", self.http_base_url())
+    }
+
+    /// Get the session token refresh HTTP URL
+    pub fn token_refresh_url(&self) -> String {
+        format!("{}/api/agent/token/refresh", self.http_base_url())
+    }
+}
+
+/// The address of a bracketed IPv6 URL host (`[2001:db8::1]`)
+pub(crate) fn ipv6_literal(host: &str) -> Option<Ipv6Addr> {
+    host.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+/// Expand every string in a config value in place. `path` names the value
+/// in errors (e.g. "ws_headers.X-Api-Key").
+fn expand_strings(
+    value: &mut serde_json::Value,
+    path: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<()> {
+    match value {
+        serde_json::Value::String(s) => {
+            *s = expand_with(s, cfg!(windows), lookup).with_context(|| path.to_string())?;
+        }
+        serde_json::Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                expand_strings(item, &format!("{}[{}]", path, i), lookup)?;
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                expand_strings(field, &format!("{}.{}", path, name), lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Put `raw` strings back into `value` wherever it still holds what they
+/// expanded to (`expanded`)
+fn restore_templates(
+    value: &mut serde_json::Value,
+    raw: &serde_json::Value,
+    expanded: &serde_json::Value,
+) {
+    use serde_json::Value;
+    match (value, raw, expanded) {
+        (Value::String(s), Value::String(raw), Value::String(expanded)) if s == expanded => {
+            s.clone_from(raw);
+        }
+        (Value::Array(items), Value::Array(raw), Value::Array(expanded)) => {
+            for ((item, raw), expanded) in items.iter_mut().zip(raw).zip(expanded) {
+                restore_templates(item, raw, expanded);
+            }
+        }
+        (Value::Object(fields), Value::Object(raw), Value::Object(expanded)) => {
+            for (name, field) in fields.iter_mut() {
+                if let (Some(raw), Some(expanded)) = (raw.get(name), expanded.get(name)) {
+                    restore_templates(field, raw, expanded);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Expand `${VAR}` references (and `%VAR%` on Windows) from the process
+/// environment. A variable that isn't set is an error rather than being
+/// left in place as a literal.
+pub fn expand_env_vars(input: &str) -> Result<String> {
+    expand_with(input, cfg!(windows), |name| std::env::var(name).ok())
+}
+
+/// `${VAR}` expansion, plus `%VAR%` when `percent` is set (`%%` is a literal
+/// percent sign). `lookup` resolves variable names.
+fn expand_with(
+    input: &str,
+    percent: bool,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(pos) = rest.find(|c| c == '$' || (percent && c == '%')) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+
+        let (name, consumed) = if let Some(body) = tail.strip_prefix("${") {
+            let end = body
+                .find('}')
+                .with_context(|| format!("unterminated \"${{\" in \"{}\"", input))?;
+            (&body[..end], end + 3)
+        } else if let Some(body) = tail.strip_prefix('%').filter(|_| percent) {
+            if let Some(after) = body.strip_prefix('%') {
+                out.push('%');
+                rest = after;
+                continue;
+            }
+            let end = body
+                .find('%')
+                .with_context(|| format!("unterminated \"%\" in \"{}\"", input))?;
+            (&body[..end], end + 2)
+        } else {
+            // A lone '$' is literal
+            out.push('$');
+            rest = &tail[1..];
+            continue;
+        };
+
+        if name.is_empty() {
+            anyhow::bail!("empty variable name in \"{}\"", input);
+        }
+        let value =
+            lookup(name).with_context(|| format!("environment variable {} is not set", name))?;
+        out.push_str(&value);
+        rest = &tail[consumed..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_for(server_url: &str, base_path: Option<&str>) -> AgentConfig {
+        AgentConfig {
+            server_url: server_url.to_string(),
+            base_path: base_path.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_urls_at_root() {
+        let config = config_for("wss://server:7899", None);
+        assert_eq!(config.relay_url(), "wss://server:7899/relay");
+        assert_eq!(config.enroll_url(), "https://server:7899/api/enroll/device");
+
+        let config = config_for("http://server:7899/", None);
+        assert_eq!(config.relay_url(), "ws://server:7899/relay");
+        assert_eq!(config.enroll_url(), "http://server:7899/api/enroll/device");
+    }
+
+    #[test]
+    fn test_urls_keep_path_prefix() {
+        let config = config_for("https://host/remote/", None);
+        assert_eq!(config.relay_url(), "wss://host/remote/relay");
+        assert_eq!(config.enroll_url(), "https://host/remote/api/enroll/device");
+
+        // Query and fragment don't end up in front of the endpoint path
+        let config = config_for("wss://host/a/b?x=1#frag", None);
+        assert_eq!(config.relay_url(), "wss://host/a/b/relay");
+    }
+
+    #[test]
+    fn test_urls_with_base_path() {
+        let config = config_for("wss://host:7899", Some("/remote/"));
+        assert_eq!(config.relay_url(), "wss://host:7899/remote/relay");
+        assert_eq!(
+            config.enroll_url(),
+            "https://host:7899/remote/api/enroll/device"
+        );
+
+        let config = config_for("https://host/outer", Some("inner"));
+        assert_eq!(config.http_base_url(), "https://host/outer/inner");
+    }
+
+    #[test]
+    fn test_urls_with_ipv6_literal() {
+        let config = config_for("wss://[2001:db8::1]:7899", None);
+        assert_eq!(config.relay_url(), "wss://[2001:db8::1]:7899/relay");
+        assert_eq!(
+            config.enroll_url(),
+            "https://[2001:db8::1]:7899/api/enroll/device"
+        );
+        assert!(config.validate().is_ok());
+
+        assert_eq!(
+            ipv6_literal("[2001:db8::1]"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(ipv6_literal("2001:db8::1"), None);
+        assert_eq!(ipv6_literal("host"), None);
+    }
+
+    #[test]
+    fn test_validate_ipv6_literals() {
+        assert!(config_for("ws://[::1]/remote", None).validate().is_ok());
+        assert!(config_for("wss://[fe80::1%25eth0]:7899", None)
+            .validate()
+            .is_ok());
+
+        let err = format!(
+            "{:#}",
+            config_for("wss://2001:db8::1:7899", None)
+                .validate()
+                .unwrap_err()
+        );
+        assert!(err.contains("must be in brackets"));
+        let err = format!(
+            "{:#}",
+            config_for("wss://[2001:db8::zz]:7899", None)
+                .validate()
+                .unwrap_err()
+        );
+        assert!(err.contains("invalid IPv6 address"));
+        let err = format!(
+            "{:#}",
+            config_for("wss://[2001:db8::1:7899", None)
+                .validate()
+                .unwrap_err()
+        );
+        assert!(err.contains("invalid IPv6 address"));
+    }
+
+    #[test]
+    fn test_session_indicator_mode() {
+        let config: AgentConfig = serde_json::from_str(r#"{"server_url":"wss://host"}"#).unwrap();
+        assert_eq!(config.session_indicator, SessionIndicatorMode::Optional);
+
+        let config: AgentConfig =
+            serde_json::from_str(r#"{"server_url":"wss://host","session_indicator":"required"}"#)
+                .unwrap();
+        assert_eq!(config.session_indicator, SessionIndicatorMode::Required);
+
+        assert_eq!(
+            "off".parse::<SessionIndicatorMode>().unwrap(),
+            SessionIndicatorMode::Off
+        );
+        assert!("always".parse::<SessionIndicatorMode>().is_err());
+    }
+
+    #[test]
+    fn test_command_policy() {
+        let config: AgentConfig = serde_json::from_str(r#"{"server_url":"wss://host"}"#).unwrap();
+        assert_eq!(config.policy, CommandPolicy::default());
+        assert_eq!(config.policy.max_download_bytes, DEFAULT_MAX_DOWNLOAD_BYTES);
+
+        // Fields left out keep their defaults
+        let config: AgentConfig = serde_json::from_str(
+            r#"{"server_url":"wss://host","policy":{"clock_changes":false,"max_download_bytes":1024}}"#,
+        )
+        .unwrap();
+        assert!(!config.policy.clock_changes);
+        assert!(config.policy.service_control);
+        assert_eq!(config.policy.max_download_bytes, 1024);
+    }
+
+    #[test]
+    fn test_validate_accepts_defaults_with_url() {
+        assert!(config_for("wss://server:7899", None).validate().is_ok());
+        assert!(config_for("server.example.com/remote", None)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate_lists_every_problem() {
+        let mut config = config_for("ftp://", None);
+        config.heartbeat_interval_secs = 0;
+        config.reconnect_base_delay_secs = 120;
+        config.log_level = Some("verbose".to_string());
+        config.idle_disconnect_mins = 10;
+        config.checkin_interval_secs = 0;
+        config.max_fps = 0;
+        config.low_disk_percent = 150;
+        config.helper_connect_timeout_secs = 0;
+        config.outgoing_queue_size = 0;
+        config.connect_timeout_secs = 0;
+
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(err.contains("scheme must be ws, wss, http or https"));
+        assert!(err.contains("server_url has no host"));
+        assert!(err.contains("heartbeat_interval_secs must be > 0"));
+        assert!(err.contains("must not exceed reconnect_max_delay_secs"));
+        assert!(err.contains("log_level must be one of"));
+        assert!(err.contains("checkin_interval_secs must be > 0"));
+        assert!(err.contains("max_fps must be > 0"));
+        assert!(err.contains("low_disk_percent must be 0-100"));
+        assert!(err.contains("helper_connect_timeout_secs must be > 0"));
+        assert!(err.contains("outgoing_queue_size must be > 0"));
+        assert!(err.contains("connect_timeout_secs must be > 0"));
+        assert!(!err.contains("telemetry_interval_secs"));
+    }
+
+    #[test]
+    fn test_reconnect_allowed_hosts() {
+        let mut config = config_for("wss://relay1.example.com", None);
+        assert!(config.reconnect_allowed("wss://anywhere.example.net"));
+
+        config.reconnect_allowed_hosts = vec![
+            "relay2.example.com".to_string(),
+            "[2001:db8::1]".to_string(),
+        ];
+        assert!(config.reconnect_allowed("wss://Relay2.example.com:7899/remote"));
+        assert!(config.reconnect_allowed("https://[2001:db8::1]:443"));
+        assert!(!config.reconnect_allowed("wss://relay2.example.com.evil.net"));
+        assert!(!config.reconnect_allowed("wss://relay2.example.com@evil.net"));
+        assert!(!config.reconnect_allowed("wss://relay1.example.com"));
+    }
+
+    #[test]
+    fn test_validate_ws_handshake_settings() {
+        let mut config = config_for("wss://server:7899", None);
+        config.ws_subprotocol = Some("relay.v1".to_string());
+        config
+            .ws_headers
+            .insert("X-Api-Key".to_string(), "secret".to_string());
+        assert!(config.validate().is_ok());
+
+        config.ws_subprotocol = Some("a, b".to_string());
+        config
+            .ws_headers
+            .insert("Bad Name".to_string(), "x".to_string());
+        config
+            .ws_headers
+            .insert("Sec-WebSocket-Key".to_string(), "x".to_string());
+        config
+            .ws_headers
+            .insert("X-Multi".to_string(), "a\nb".to_string());
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(err.contains("ws_subprotocol is not a valid protocol token"));
+        assert!(err.contains("invalid header name \"Bad Name\""));
+        assert!(err.contains("\"Sec-WebSocket-Key\" is set by the WebSocket handshake"));
+        assert!(err.contains("invalid value for \"X-Multi\""));
+        assert!(!err.contains("X-Api-Key"));
+    }
+
+    #[test]
+    fn test_validate_relay_auth_token() {
+        let mut config = config_for("wss://server:7899", None);
+        config.relay_auth_token = Some("relay-secret".to_string());
+        assert!(config.validate().is_ok());
+
+        config.relay_auth_token = Some("a\nb".to_string());
+        config
+            .ws_headers
+            .insert("authorization".to_string(), "Basic x".to_string());
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(err.contains("relay_auth_token must be a non-empty header value"));
+        assert!(err.contains("\"authorization\" conflicts with relay_auth_token"));
+    }
+
+    #[test]
+    fn test_telemetry_interval_clamped_to_minimum() {
+        let mut config = config_for("wss://server:7899", None);
+        config.telemetry_interval_secs = MIN_TELEMETRY_INTERVAL_SECS;
+        assert_eq!(config.clamp_telemetry_interval(), None);
+        assert_eq!(config.telemetry_interval_secs, 10);
+
+        // Too short still loads, at the minimum
+        config.telemetry_interval_secs = 5;
+        assert!(config.validate().is_ok());
+        assert_eq!(config.clamp_telemetry_interval(), Some(5));
+        assert_eq!(config.telemetry_interval_secs, 10);
+    }
+
+    #[test]
+    fn test_data_dir_defaults_to_platform_dir() {
+        let mut config = AgentConfig::default();
+        assert_eq!(config.data_dir(), AgentConfig::default_data_dir());
+
+        config.data_dir = Some("/srv/agent".to_string());
+        assert_eq!(config.data_dir(), PathBuf::from("/srv/agent"));
+    }
+
+    #[test]
+    fn test_load_expands_without_rewriting_config() {
+        let mut config = AgentConfig::from_json(
+            r#"{"server_url":"wss://server:7899","data_dir":"${HOME}/agent","relay_auth_token":"${HOME}","ws_headers":{"X-Home":"${HOME}"}}"#,
+            &lookup,
+        )
+        .unwrap();
+        assert_eq!(config.data_dir(), PathBuf::from("/home/agent/agent"));
+        assert_eq!(config.relay_auth_token.as_deref(), Some("/home/agent"));
+        assert_eq!(config.ws_headers["X-Home"], "/home/agent");
+
+        // Unchanged values are saved as written; changed ones as they are now
+        config.session_token = Some("${HOME}".to_string());
+        config.relay_auth_token = Some("new-token".to_string());
+        let saved: serde_json::Value = serde_json::from_str(&config.to_json().unwrap()).unwrap();
+        assert_eq!(saved["data_dir"], "${HOME}/agent");
+        assert_eq!(saved["ws_headers"]["X-Home"], "${HOME}");
+        assert_eq!(saved["relay_auth_token"], "new-token");
+        assert_eq!(saved["session_token"], "${HOME}");
+    }
+
+    #[test]
+    fn test_load_rejects_unresolved_variable() {
+        let err =
+            AgentConfig::from_json(r#"{"server_url":"${ANDROID_REMOTE_TEST_UNSET}"}"#, &lookup)
+                .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "server_url: environment variable ANDROID_REMOTE_TEST_UNSET is not set"
+        );
+
+        let err = AgentConfig::from_json(
+            r#"{"server_url":"wss://host","ws_headers":{"X-Key":"${NOPE}"}}"#,
+            &lookup,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).starts_with("ws_headers.X-Key: "));
+
+        // Fields the agent writes itself are taken as they are
+        let config = AgentConfig::from_json(
+            r#"{"server_url":"wss://host","device_id":"${NOPE}"}"#,
+            &lookup,
+        )
+        .unwrap();
+        assert_eq!(config.device_id.as_deref(), Some("${NOPE}"));
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/agent".to_string()),
+            "ProgramData" => Some("C:\\ProgramData".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand_env_vars() {
+        assert_eq!(
+            expand_with("${HOME}/remote", false, lookup).unwrap(),
+            "/home/agent/remote"
+        );
+        assert_eq!(expand_with("cost $5", false, lookup).unwrap(), "cost $5");
+        // %VAR% is only expanded where the platform uses it
+        assert_eq!(
+            expand_with("%ProgramData%", false, lookup).unwrap(),
+            "%ProgramData%"
+        );
+        assert_eq!(
+            expand_with("%ProgramData%\\agent 100%%", true, lookup).unwrap(),
+            "C:\\ProgramData\\agent 100%"
+        );
+    }
+
+    #[test]
+    fn test_expand_env_vars_rejects_unresolved() {
+        let err = expand_with("${NOPE}/x", false, lookup).unwrap_err();
+        assert!(err.to_string().contains("NOPE is not set"));
+        assert!(expand_with("${HOME", false, lookup).is_err());
+        assert!(expand_with("%NOPE%", true, lookup).is_err());
     }
 }
@@ -1,11 +1,25 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::{bail, Context, Result};
 use futures_util::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, Notify};
 use tokio::time::{self, Duration, Instant};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as WsMessage};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::http::header::{
+    HeaderName, HeaderValue, AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL,
+};
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+};
 use tracing::{debug, error, info, warn};
 
-use crate::config::AgentConfig;
+use crate::config::{ipv6_literal, AgentConfig};
 use crate::protocol::{self, AuthRequest, AuthResponse, Message};
 
 /// Events received from the server
@@ -16,35 +30,327 @@ pub enum ServerEvent {
         device_id: String,
         session_token: String,
     },
+    /// The session token was refreshed ahead of its expiry
+    TokenRefreshed { session_token: String },
     /// Received a protocol message from server
     Message(Message),
-    /// Connection lost
-    Disconnected,
+    /// Connection lost, and why
+    Disconnected(DisconnectReason),
+    /// Hung up after sitting idle (`idle_disconnect_mins`). The agent comes
+    /// back on the next check-in or as soon as it has something to send.
+    Idle,
+    /// Authenticated on the server a requested reconnect switched to, so
+    /// the agent can keep using it. Sent after its `Authenticated`.
+    ServerChanged { server_url: String },
+}
+
+/// Why a connection ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The server sent a close frame
+    ServerClosed,
+    /// The stream ended without a close frame
+    ConnectionLost,
+    /// No heartbeat ACK within three heartbeat intervals
+    HeartbeatTimeout,
+    /// Closed for a requested reconnect (RECONNECT command)
+    ReconnectRequested,
+    /// The agent is shutting down and dropped its end of the connection
+    Shutdown,
+    /// Connecting, authenticating or a socket read or write failed
+    Error(String),
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::ServerClosed => f.write_str("closed by server"),
+            DisconnectReason::ConnectionLost => f.write_str("connection lost"),
+            DisconnectReason::HeartbeatTimeout => f.write_str("heartbeat timeout"),
+            DisconnectReason::ReconnectRequested => f.write_str("reconnect requested"),
+            DisconnectReason::Shutdown => f.write_str("agent shutting down"),
+            DisconnectReason::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+/// Failed connections to a server a RECONNECT switched to before the agent
+/// goes back to the one it came from
+const SERVER_SWITCH_ATTEMPTS: u32 = 5;
+
+/// A switch to another server that hasn't authenticated yet
+struct ServerSwitch {
+    previous_url: String,
+    failures: u32,
+}
+
+/// How long a check-in connection waits for queued commands before it
+/// hangs up again
+const CHECKIN_WINDOW: Duration = Duration::from_secs(15);
+
+/// Why `connect_and_run` returned without an error
+enum ConnectionEnd {
+    Closed(DisconnectReason),
+    Idle,
+    Reconnect,
+}
+
+/// Transport counters since the agent started, kept across reconnects.
+/// Updated with relaxed atomics from the connection loop.
+struct ConnectionStats {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    /// Messages sent and received, indexed by message type
+    sent_by_type: Vec<AtomicU64>,
+    received_by_type: Vec<AtomicU64>,
+    /// Authenticated connections so far; all but the first are reconnects
+    connections: AtomicU64,
+    /// Round trip of the last answered heartbeat in ms, `u64::MAX` if none
+    rtt_ms: AtomicU64,
+}
+
+/// Snapshot of the connection counters, the GET_CONNECTION_STATS reply.
+/// Message counts are keyed by hex message type (e.g. "0x20").
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatsSnapshot {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: BTreeMap<String, u64>,
+    pub messages_received: BTreeMap<String, u64>,
+    pub reconnects: u64,
+    pub rtt_ms: Option<u64>,
+}
+
+impl ConnectionStats {
+    fn new() -> Self {
+        Self {
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            sent_by_type: (0..256).map(|_| AtomicU64::new(0)).collect(),
+            received_by_type: (0..256).map(|_| AtomicU64::new(0)).collect(),
+            connections: AtomicU64::new(0),
+            rtt_ms: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Count an encoded message written to the socket
+    fn record_sent(&self, data: &[u8]) {
+        self.bytes_sent
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+        if let Some(&msg_type) = data.first() {
+            self.sent_by_type[msg_type as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> ConnectionStatsSnapshot {
+        let by_type = |counters: &[AtomicU64]| {
+            counters
+                .iter()
+                .enumerate()
+                .filter_map(|(msg_type, count)| {
+                    let count = count.load(Ordering::Relaxed);
+                    (count > 0).then(|| (format!("0x{:02x}", msg_type), count))
+                })
+                .collect()
+        };
+        let rtt_ms = self.rtt_ms.load(Ordering::Relaxed);
+        ConnectionStatsSnapshot {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: by_type(&self.sent_by_type),
+            messages_received: by_type(&self.received_by_type),
+            reconnects: self.connections.load(Ordering::Relaxed).saturating_sub(1),
+            rtt_ms: (rtt_ms != u64::MAX).then_some(rtt_ms),
+        }
+    }
 }
 
 /// Handle to send messages to the server
 #[derive(Clone)]
 pub struct ConnectionHandle {
+    /// Control traffic: heartbeats, commands, terminal, input events
     tx: mpsc::Sender<Vec<u8>>,
+    /// Bulk traffic (desktop frames, file data), sent only when no control
+    /// message is waiting
+    bulk_tx: mpsc::Sender<Vec<u8>>,
+    /// Protocol version negotiated during the last successful auth
+    protocol_version: Arc<AtomicU16>,
+    /// Bytes queued for the socket but not yet written
+    in_flight: Arc<AtomicUsize>,
+    /// Sessions are open, so the connection must not be idle-disconnected
+    busy: Arc<AtomicBool>,
+    /// Wakes the connection to close it and reconnect
+    reconnect: Arc<Notify>,
+    /// Server to switch to on the requested reconnect
+    next_server_url: Arc<Mutex<Option<String>>>,
+    /// Transport counters for GET_CONNECTION_STATS
+    stats: Arc<ConnectionStats>,
+}
+
+/// Receiving ends of the outgoing queues, drained by the connection loop
+struct OutgoingQueues {
+    control: mpsc::Receiver<Vec<u8>>,
+    bulk: mpsc::Receiver<Vec<u8>>,
 }
 
 impl ConnectionHandle {
+    /// Protocol version agreed with the server, for gating newer features.
+    /// Reflects the most recent handshake; version 1 before the first one.
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol_version.load(Ordering::Relaxed)
+    }
+
+    /// Largest payload the server can receive over the agreed protocol
+    pub fn max_payload(&self) -> usize {
+        protocol::max_payload_for(self.protocol_version())
+    }
+
+    /// Bytes handed to the connection that haven't reached the socket yet.
+    /// Producers of bulk data (desktop frames) back off when this is high.
+    pub fn in_flight_bytes(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Mark whether the agent has sessions open. A busy agent never drops
+    /// its connection for being idle.
+    pub fn set_busy(&self, busy: bool) {
+        self.busy.store(busy, Ordering::Relaxed);
+    }
+
+    /// Close the current connection and reconnect right away, to
+    /// `server_url` if given. Messages queued before the call are sent
+    /// first.
+    pub fn reconnect(&self, server_url: Option<String>) {
+        if let Some(url) = server_url {
+            *self
+                .next_server_url
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = Some(url);
+        }
+        self.reconnect.notify_one();
+    }
+
+    /// Bytes and messages sent and received, reconnects and the latest
+    /// heartbeat round trip, since the agent started
+    pub fn stats(&self) -> ConnectionStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Send a message on the queue its type belongs to (see
+    /// `protocol::is_bulk`).
     pub async fn send_message(&self, msg: &Message) -> Result<()> {
-        self.tx
-            .send(msg.encode())
-            .await
-            .map_err(|_| anyhow::anyhow!("connection channel closed"))
+        if protocol::is_bulk(msg.header.msg_type) {
+            self.send_bulk(msg).await
+        } else {
+            self.send_control(msg).await
+        }
     }
 
-    pub async fn send_raw(&self, data: Vec<u8>) -> Result<()> {
-        self.tx
-            .send(data)
-            .await
-            .map_err(|_| anyhow::anyhow!("connection channel closed"))
+    /// Send on the high-priority queue, ahead of any queued bulk data.
+    pub async fn send_control(&self, msg: &Message) -> Result<()> {
+        let data = msg.encode_for(self.protocol_version())?;
+        self.enqueue(&self.tx, data).await
+    }
+
+    /// Send on the bulk queue, behind any pending control messages.
+    pub async fn send_bulk(&self, msg: &Message) -> Result<()> {
+        let data = msg.encode_for(self.protocol_version())?;
+        self.enqueue(&self.bulk_tx, data).await
+    }
+
+    /// Reply to a failed request with a typed ERROR message
+    pub async fn send_error(
+        &self,
+        channel: u16,
+        request_id: u32,
+        code: protocol::ErrorCode,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        let msg = protocol::error_response(channel, request_id, code, message)?;
+        self.send_control(&msg).await
+    }
+
+    async fn enqueue(&self, tx: &mpsc::Sender<Vec<u8>>, data: Vec<u8>) -> Result<()> {
+        let len = data.len();
+        self.in_flight.fetch_add(len, Ordering::Relaxed);
+        tx.send(data).await.map_err(|_| {
+            self.in_flight.fetch_sub(len, Ordering::Relaxed);
+            anyhow::anyhow!("connection channel closed")
+        })
     }
 }
 
-/// Enroll with the server via HTTP to get a session token
+/// Attempts at the enrollment request before giving up on transient failures
+const ENROLL_ATTEMPTS: u32 = 5;
+/// Delay before the first enrollment retry; doubles after each attempt
+const ENROLL_RETRY_BASE: Duration = Duration::from_secs(2);
+const ENROLL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Why an HTTP request never got a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NetworkFailure {
+    Dns,
+    Tls,
+    Refused,
+    Timeout,
+    Other,
+}
+
+impl NetworkFailure {
+    fn classify(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            return Self::Timeout;
+        }
+
+        // reqwest wraps hyper, which wraps the resolver / TLS / socket error;
+        // the useful detail is only in the source chain
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+        let mut chain = String::new();
+        while let Some(e) = source {
+            if let Some(io) = e.downcast_ref::<std::io::Error>() {
+                if io.kind() == std::io::ErrorKind::ConnectionRefused {
+                    return Self::Refused;
+                }
+            }
+            chain.push_str(&e.to_string().to_lowercase());
+            chain.push('\n');
+            source = e.source();
+        }
+
+        if chain.contains("dns error") || chain.contains("failed to lookup address") {
+            Self::Dns
+        } else if ["certificate", "tls", "ssl", "handshake"]
+            .iter()
+            .any(|s| chain.contains(s))
+        {
+            Self::Tls
+        } else if chain.contains("connection refused") {
+            Self::Refused
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Failures that may clear up on their own (network still coming up
+    /// during provisioning). TLS errors are almost always configuration.
+    fn is_transient(self) -> bool {
+        !matches!(self, Self::Tls)
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Dns => "DNS lookup failed (check the server hostname and the machine's resolver)",
+            Self::Tls => "TLS handshake failed (check the server certificate, or use ws:// / http:// for a plain server)",
+            Self::Refused => "connection refused (is the server running and the port right?)",
+            Self::Timeout => "request timed out",
+            Self::Other => "network error",
+        }
+    }
+}
+
+/// Enroll with the server via HTTP to get a session token. DNS, connection
+/// and timeout failures are retried with backoff; TLS and HTTP 4xx errors
+/// fail immediately.
 pub async fn enroll(config: &AgentConfig) -> Result<(String, String)> {
     let url = config.enroll_url();
     let token = config
@@ -68,12 +374,48 @@ pub async fn enroll(config: &AgentConfig) -> Result<(String, String)> {
     });
 
     info!("enrolling with server at {}", url);
-    let client = reqwest::Client::new();
-    let resp = client.post(&url).json(&body).send().await?;
+    let client = reqwest::Client::builder().timeout(ENROLL_TIMEOUT).build()?;
 
-    if !resp.status().is_success() {
-        let status = resp.status();
+    let mut attempt = 1;
+    let resp = loop {
+        let reason = match client.post(&url).json(&body).send().await {
+            Ok(resp) if resp.status().is_server_error() && attempt < ENROLL_ATTEMPTS => {
+                format!("server error {}", resp.status())
+            }
+            Ok(resp) => break resp,
+            Err(e) => {
+                let kind = NetworkFailure::classify(&e);
+                if !kind.is_transient() || attempt >= ENROLL_ATTEMPTS {
+                    return Err(anyhow::Error::new(e).context(format!(
+                        "enrollment failed after {} attempt(s): {} reaching {}",
+                        attempt,
+                        kind.describe(),
+                        url
+                    )));
+                }
+                kind.describe().to_string()
+            }
+        };
+
+        let delay = ENROLL_RETRY_BASE * 2u32.pow(attempt - 1);
+        warn!(
+            "enrollment attempt {}/{} failed ({}), retrying in {:?}",
+            attempt, ENROLL_ATTEMPTS, reason, delay
+        );
+        time::sleep(delay).await;
+        attempt += 1;
+    };
+
+    let status = resp.status();
+    if !status.is_success() {
         let body = resp.text().await.unwrap_or_default();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            bail!(
+                "enrollment rejected ({}): the enrollment token is invalid, expired or already used - {}",
+                status,
+                body
+            );
+        }
         bail!("enrollment failed: {} - {}", status, body);
     }
 
@@ -91,68 +433,324 @@ pub async fn enroll(config: &AgentConfig) -> Result<(String, String)> {
     Ok((device_id, session_token))
 }
 
+/// A new session token and its expiry (Unix seconds), if the server sets one
+type RefreshedToken = (String, Option<u64>);
+
+/// Exchange the current session token for a fresh one before it expires.
+async fn refresh_session_token(config: &AgentConfig) -> Result<RefreshedToken> {
+    let url = config.token_refresh_url();
+    let token = config
+        .session_token
+        .as_ref()
+        .context("no session token to refresh")?;
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let resp = client
+        .post(&url)
+        .bearer_auth(token)
+        .send()
+        .await
+        .with_context(|| format!("failed to reach {}", url))?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        bail!("token refresh failed: {} - {}", status, body);
+    }
+
+    let result: serde_json::Value = resp.json().await?;
+    let session_token = result["sessionToken"]
+        .as_str()
+        .context("missing sessionToken in refresh response")?
+        .to_string();
+    let expires_at = result["expiresAt"].as_u64();
+
+    Ok((session_token, expires_at))
+}
+
+/// Shortest wait before a token refresh, so a token that is already due
+/// (or a server handing out very short-lived ones) can't have the agent
+/// refreshing in a loop
+const MIN_TOKEN_REFRESH_DELAY_SECS: u64 = 5;
+
+/// When to refresh a token that expires at `expires_at` (Unix seconds),
+/// leaving `margin_secs` of slack
+fn refresh_deadline(expires_at: u64, margin_secs: u64) -> Instant {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Instant::now() + refresh_wait(expires_at, margin_secs, now)
+}
+
+/// How long to wait at `now` before refreshing a token that expires at
+/// `expires_at`. A token living no longer than twice the margin is refreshed
+/// halfway through its remaining lifetime instead, and never sooner than
+/// `MIN_TOKEN_REFRESH_DELAY_SECS`.
+fn refresh_wait(expires_at: u64, margin_secs: u64, now: u64) -> Duration {
+    let remaining = expires_at.saturating_sub(now);
+    let wait = remaining
+        .saturating_sub(margin_secs)
+        .max(remaining / 2)
+        .max(MIN_TOKEN_REFRESH_DELAY_SECS);
+    Duration::from_secs(wait)
+}
+
+/// Check that the server answers HTTP at all, returning the round-trip time.
+/// Any HTTP response counts — this only proves the server is reachable.
+pub async fn ping_server(config: &AgentConfig) -> Result<Duration> {
+    let url = config.http_base_url();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+
+    let started = Instant::now();
+    client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("failed to reach {}", url))?;
+    Ok(started.elapsed())
+}
+
 /// Run the WebSocket connection loop with automatic reconnection.
 /// Returns a handle to send messages and a receiver for server events.
 pub async fn run_connection(
     config: AgentConfig,
     event_tx: mpsc::Sender<ServerEvent>,
 ) -> Result<ConnectionHandle> {
-    let (outgoing_tx, outgoing_rx) = mpsc::channel::<Vec<u8>>(256);
+    let queue_size = config.outgoing_queue_size.max(1);
+    let (control_tx, control_rx) = mpsc::channel::<Vec<u8>>(queue_size);
+    let (bulk_tx, bulk_rx) = mpsc::channel::<Vec<u8>>(queue_size);
     let handle = ConnectionHandle {
-        tx: outgoing_tx.clone(),
+        tx: control_tx,
+        bulk_tx,
+        protocol_version: Arc::new(AtomicU16::new(1)),
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        busy: Arc::new(AtomicBool::new(false)),
+        reconnect: Arc::new(Notify::new()),
+        next_server_url: Arc::new(Mutex::new(None)),
+        stats: Arc::new(ConnectionStats::new()),
+    };
+    let queues = OutgoingQueues {
+        control: control_rx,
+        bulk: bulk_rx,
     };
 
+    // The loop keeps its own handle, so the queues stay open across reconnects
+    let loop_handle = handle.clone();
     tokio::spawn(async move {
-        connection_loop(config, event_tx, outgoing_rx, outgoing_tx).await;
+        connection_loop(config, event_tx, queues, loop_handle).await;
     });
 
     Ok(handle)
 }
 
 async fn connection_loop(
-    config: AgentConfig,
+    mut config: AgentConfig,
     event_tx: mpsc::Sender<ServerEvent>,
-    mut outgoing_rx: mpsc::Receiver<Vec<u8>>,
-    outgoing_tx: mpsc::Sender<Vec<u8>>,
+    mut queues: OutgoingQueues,
+    handle: ConnectionHandle,
 ) {
     let mut attempt = 0u32;
+    let idle_timeout = (config.idle_disconnect_mins > 0)
+        .then(|| Duration::from_secs(config.idle_disconnect_mins * 60));
+    // Set after an idle disconnect: connections are short check-ins until
+    // there is something to do again
+    let mut dormant = false;
+    // Message that woke a dormant agent, sent right after auth
+    let mut pending: Option<Vec<u8>> = None;
+    let mut switch: Option<ServerSwitch> = None;
 
     loop {
         let delay = reconnect_delay(&config, attempt);
         if attempt > 0 {
             info!("reconnecting in {:.1}s (attempt {})", delay.as_secs_f64(), attempt);
             time::sleep(delay).await;
+        } else if dormant {
+            let checkin = Duration::from_secs(config.checkin_interval_secs);
+            tokio::select! {
+                biased;
+
+                Some(data) = queues.control.recv() => {
+                    info!("reconnecting to send a queued message");
+                    pending = Some(data);
+                    dormant = false;
+                }
+                Some(data) = queues.bulk.recv() => {
+                    info!("reconnecting to send a queued message");
+                    pending = Some(data);
+                    dormant = false;
+                }
+                _ = time::sleep(checkin) => {
+                    debug!("idle check-in");
+                }
+            }
         }
 
-        match connect_and_run(&config, &event_tx, &mut outgoing_rx, &outgoing_tx).await {
-            Ok(()) => {
-                info!("connection closed gracefully");
+        let checkin = dormant && pending.is_none();
+        let result = connect_and_run(
+            &mut config,
+            &event_tx,
+            &mut queues,
+            &handle,
+            &mut pending,
+            &mut switch,
+            idle_timeout,
+            checkin,
+        )
+        .await;
+        let reason = match result {
+            Ok(ConnectionEnd::Closed(reason)) => {
+                info!("connection closed: {}", reason);
+                attempt = 0;
+                dormant = false;
+                reason
+            }
+            Ok(ConnectionEnd::Reconnect) => {
+                attempt = 0;
+                dormant = false;
+                let next = handle
+                    .next_server_url
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take();
+                match next {
+                    Some(url) => {
+                        info!("reconnecting to {} as requested", url);
+                        let previous_url = std::mem::replace(&mut config.server_url, url);
+                        switch = Some(ServerSwitch {
+                            previous_url,
+                            failures: 0,
+                        });
+                    }
+                    None => info!("reconnecting as requested"),
+                }
+                DisconnectReason::ReconnectRequested
+            }
+            Ok(ConnectionEnd::Idle) => {
+                if !dormant {
+                    info!(
+                        "idle for {} min, disconnecting until the next check-in",
+                        config.idle_disconnect_mins
+                    );
+                }
                 attempt = 0;
+                dormant = true;
+                if event_tx.send(ServerEvent::Idle).await.is_err() {
+                    info!("event channel closed, stopping connection loop");
+                    break;
+                }
+                continue;
             }
             Err(e) => {
                 error!("connection error: {:#}", e);
                 attempt = attempt.saturating_add(1);
+                if let Some(pending_switch) = switch.as_mut() {
+                    pending_switch.failures += 1;
+                    if pending_switch.failures >= SERVER_SWITCH_ATTEMPTS {
+                        warn!(
+                            "couldn't authenticate on {} after {} attempts, going back to {}",
+                            config.server_url, pending_switch.failures, pending_switch.previous_url
+                        );
+                        config.server_url = std::mem::take(&mut pending_switch.previous_url);
+                        switch = None;
+                        attempt = 0;
+                    }
+                }
+                DisconnectReason::Error(format!("{:#}", e))
             }
-        }
+        };
 
-        if event_tx.send(ServerEvent::Disconnected).await.is_err() {
+        if event_tx
+            .send(ServerEvent::Disconnected(reason))
+            .await
+            .is_err()
+        {
             info!("event channel closed, stopping connection loop");
             break;
         }
     }
 }
 
+/// The upgrade request for `url`, carrying the configured subprotocol,
+/// extra headers and relay credentials
+fn handshake_request(url: &str, config: &AgentConfig) -> Result<Request> {
+    let mut request = url.into_client_request()?;
+    let headers = request.headers_mut();
+    if let Some(protocol) = &config.ws_subprotocol {
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_str(protocol)?);
+    }
+    for (name, value) in &config.ws_headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    if let Some(token) = &config.relay_auth_token {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    Ok(request)
+}
+
+/// Open the relay WebSocket. tokio-tungstenite hands native-tls the URL host
+/// with its brackets, which breaks SNI and certificate matching for an IPv6
+/// literal, so TLS to one is set up here with the bare address.
+async fn connect_websocket(request: Request) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+    let uri = request.uri();
+    let ip = uri
+        .host()
+        .and_then(ipv6_literal)
+        .filter(|_| uri.scheme_str() == Some("wss"));
+    let Some(ip) = ip else {
+        let (ws_stream, _) = connect_async(request).await?;
+        return Ok(ws_stream);
+    };
+
+    let port = uri.port_u16().unwrap_or(443);
+    let socket = TcpStream::connect((ip, port)).await?;
+    let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+    let tls = connector
+        .connect(&ip.to_string(), socket)
+        .await
+        .context("TLS handshake failed")?;
+    let (ws_stream, _) =
+        tokio_tungstenite::client_async(request, MaybeTlsStream::NativeTls(tls)).await?;
+    Ok(ws_stream)
+}
+
+/// Connect, authenticate and run the message loop until the connection ends.
+/// Keeps `config.session_token` current so reconnects use the latest token.
+///
+/// With `idle_timeout` set, hangs up once nothing has happened for that long.
+/// A `checkin` connection only waits `CHECKIN_WINDOW` for the server to
+/// deliver something before hanging up again.
+#[allow(clippy::too_many_arguments)]
 async fn connect_and_run(
-    config: &AgentConfig,
+    config: &mut AgentConfig,
     event_tx: &mpsc::Sender<ServerEvent>,
-    outgoing_rx: &mut mpsc::Receiver<Vec<u8>>,
-    _outgoing_tx: &mpsc::Sender<Vec<u8>>,
-) -> Result<()> {
+    queues: &mut OutgoingQueues,
+    handle: &ConnectionHandle,
+    pending: &mut Option<Vec<u8>>,
+    switch: &mut Option<ServerSwitch>,
+    idle_timeout: Option<Duration>,
+    checkin: bool,
+) -> Result<ConnectionEnd> {
     let url = config.relay_url();
     info!("connecting to {}", url);
 
-    let (ws_stream, _) = connect_async(&url)
+    // A black-holed address would otherwise hang on the OS connect timeout
+    let connect_timeout = Duration::from_secs(config.connect_timeout_secs);
+    let request =
+        handshake_request(&url, config).context("invalid WebSocket handshake settings")?;
+    let ws_stream = time::timeout(connect_timeout, connect_websocket(request))
         .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s", connect_timeout.as_secs()))
+        .and_then(|result| result)
         .context("failed to connect WebSocket")?;
 
     info!("WebSocket connected");
@@ -172,11 +770,13 @@ async fn connect_and_run(
         os: std::env::consts::OS.to_string(),
         arch: std::env::consts::ARCH.to_string(),
         hostname: gethostname(),
+        protocol_version: protocol::PROTOCOL_VERSION,
     };
 
+    // The handshake is always framed as version 1
     let auth_msg = protocol::auth_request(&auth_req)?;
     ws_sink
-        .send(WsMessage::Binary(auth_msg.encode().into()))
+        .send(WsMessage::Binary(auth_msg.encode_for(1)?.into()))
         .await?;
     debug!("sent AUTH_REQUEST");
 
@@ -186,7 +786,7 @@ async fn connect_and_run(
         while let Some(msg) = ws_stream.next().await {
             match msg? {
                 WsMessage::Binary(data) => {
-                    if let Some((msg, _)) = Message::decode(&data)? {
+                    if let Some((msg, _)) = Message::decode_for(&data, 1)? {
                         if msg.header.msg_type == protocol::AUTH_RESPONSE {
                             let resp: AuthResponse = msg.parse_json()?;
                             return Ok::<AuthResponse, anyhow::Error>(resp);
@@ -210,10 +810,29 @@ async fn connect_and_run(
         );
     }
 
+    let version = protocol::negotiated_version(auth_response.protocol_version)
+        .context("protocol version negotiation failed")?;
+    handle.protocol_version.store(version, Ordering::Relaxed);
+    handle.stats.connections.fetch_add(1, Ordering::Relaxed);
+
     let device_id = auth_response.device_id.unwrap_or_default();
     let new_session_token = auth_response.session_token.unwrap_or_default();
 
-    info!("authenticated, device_id={}", device_id);
+    info!(
+        "authenticated, device_id={}, protocol_version={}",
+        device_id, version
+    );
+
+    if !new_session_token.is_empty() {
+        config.session_token = Some(new_session_token.clone());
+    }
+    let margin = config.token_refresh_margin_secs;
+    let mut refresh_at = auth_response
+        .token_expires_at
+        .map(|expires_at| refresh_deadline(expires_at, margin));
+    // A refresh in progress; it runs in its own task so the HTTP request
+    // doesn't hold up heartbeats and messages
+    let mut refreshing: Option<oneshot::Receiver<Result<RefreshedToken>>> = None;
 
     event_tx
         .send(ServerEvent::Authenticated {
@@ -222,6 +841,30 @@ async fn connect_and_run(
         })
         .await
         .ok();
+    if switch.take().is_some() {
+        info!("switched to {}", config.server_url);
+        event_tx
+            .send(ServerEvent::ServerChanged {
+                server_url: config.server_url.clone(),
+            })
+            .await
+            .ok();
+    }
+
+    if let Some(data) = pending.take() {
+        handle.in_flight.fetch_sub(data.len(), Ordering::Relaxed);
+        handle.stats.record_sent(&data);
+        ws_sink.send(WsMessage::Binary(data.into())).await?;
+    }
+
+    // Server messages and bulk transfers count as activity; heartbeats,
+    // telemetry and the like don't
+    let mut last_activity = Instant::now();
+    let mut idle_limit = if checkin {
+        Some(CHECKIN_WINDOW)
+    } else {
+        idle_timeout
+    };
 
     // Main message loop
     let heartbeat_interval = Duration::from_secs(config.heartbeat_interval_secs);
@@ -229,38 +872,81 @@ async fn connect_and_run(
     heartbeat_timer.tick().await; // skip first immediate tick
 
     let mut last_pong = Instant::now();
+    // When the heartbeat awaiting its ACK went out, for the RTT
+    let mut heartbeat_sent: Option<Instant> = None;
     let heartbeat_timeout = heartbeat_interval * 3;
 
+    // Protocol-level keepalive for intermediaries that only see WebSocket frames
+    let ws_ping_enabled = config.ws_ping_interval_secs > 0;
+    let mut ws_ping_timer =
+        time::interval(Duration::from_secs(config.ws_ping_interval_secs.max(1)));
+    ws_ping_timer.tick().await; // skip first immediate tick
+
     let mut read_buf = Vec::new();
 
     loop {
+        // Arms are polled in order: control messages, incoming data and the
+        // timers all get a turn before the next bulk message goes out
         tokio::select! {
+            biased;
+
+            // Outgoing control messages always go before bulk data
+            outgoing = queues.control.recv() => {
+                match outgoing {
+                    Some(data) => {
+                        handle.in_flight.fetch_sub(data.len(), Ordering::Relaxed);
+                        handle.stats.record_sent(&data);
+                        ws_sink.send(WsMessage::Binary(data.into())).await?;
+                    }
+                    None => {
+                        info!("outgoing channel closed");
+                        return Ok(ConnectionEnd::Closed(DisconnectReason::Shutdown));
+                    }
+                }
+            }
+
+            // Requested reconnect. Comes after the control queue, so a reply
+            // queued before the request still goes out.
+            _ = handle.reconnect.notified() => {
+                ws_sink.send(WsMessage::Close(None)).await.ok();
+                return Ok(ConnectionEnd::Reconnect);
+            }
+
             // Incoming WebSocket messages
             ws_msg = ws_stream.next() => {
                 match ws_msg {
                     Some(Ok(WsMessage::Binary(data))) => {
+                        handle.stats.bytes_received.fetch_add(data.len() as u64, Ordering::Relaxed);
                         read_buf.extend_from_slice(&data);
 
                         // Decode all complete messages from buffer
                         loop {
-                            match Message::decode(&read_buf) {
+                            match Message::decode_for(&read_buf, version) {
                                 Ok(Some((msg, consumed))) => {
                                     read_buf.drain(..consumed);
+                                    handle.stats.received_by_type[msg.header.msg_type as usize].fetch_add(1, Ordering::Relaxed);
 
                                     match msg.header.msg_type {
                                         protocol::HEARTBEAT_ACK => {
                                             last_pong = Instant::now();
+                                            if let Some(sent) = heartbeat_sent.take() {
+                                                let rtt = last_pong.duration_since(sent).as_millis() as u64;
+                                                handle.stats.rtt_ms.store(rtt, Ordering::Relaxed);
+                                            }
                                             debug!("heartbeat ACK received");
                                         }
                                         protocol::HEARTBEAT => {
                                             // Server sent heartbeat, respond with ACK
-                                            let ack = protocol::heartbeat_ack();
-                                            ws_sink.send(WsMessage::Binary(ack.encode().into())).await?;
+                                            let ack = protocol::heartbeat_ack().encode_for(version)?;
+                                            handle.stats.record_sent(&ack);
+                                            ws_sink.send(WsMessage::Binary(ack.into())).await?;
                                         }
                                         _ => {
+                                            last_activity = Instant::now();
+                                            idle_limit = idle_timeout;
                                             if event_tx.send(ServerEvent::Message(msg)).await.is_err() {
                                                 info!("event channel closed");
-                                                return Ok(());
+                                                return Ok(ConnectionEnd::Closed(DisconnectReason::Shutdown));
                                             }
                                         }
                                     }
@@ -279,40 +965,89 @@ async fn connect_and_run(
                     }
                     Some(Ok(WsMessage::Close(_))) => {
                         info!("server sent close frame");
-                        return Ok(());
+                        return Ok(ConnectionEnd::Closed(DisconnectReason::ServerClosed));
                     }
                     Some(Ok(_)) => {} // text, pong
                     Some(Err(e)) => return Err(e.into()),
                     None => {
                         info!("WebSocket stream ended");
-                        return Ok(());
+                        return Ok(ConnectionEnd::Closed(DisconnectReason::ConnectionLost));
                     }
                 }
             }
 
-            // Outgoing messages from agent logic
-            outgoing = outgoing_rx.recv() => {
+            // Heartbeat timer
+            _ = heartbeat_timer.tick() => {
+                if last_pong.elapsed() > heartbeat_timeout {
+                    warn!("heartbeat timeout, disconnecting");
+                    return Ok(ConnectionEnd::Closed(DisconnectReason::HeartbeatTimeout));
+                }
+                let hb = protocol::heartbeat().encode_for(version)?;
+                handle.stats.record_sent(&hb);
+                heartbeat_sent = Some(Instant::now());
+                ws_sink.send(WsMessage::Binary(hb.into())).await?;
+                debug!("sent heartbeat");
+            }
+
+            // WebSocket keepalive
+            _ = ws_ping_timer.tick(), if ws_ping_enabled => {
+                ws_sink.send(WsMessage::Ping(Vec::new())).await?;
+                debug!("sent WebSocket ping");
+            }
+
+            // Refresh the session token before the server stops accepting it
+            _ = time::sleep_until(refresh_at.unwrap_or_else(Instant::now)), if refresh_at.is_some() => {
+                refresh_at = None;
+                let (tx, rx) = oneshot::channel();
+                let refresh_config = config.clone();
+                tokio::spawn(async move {
+                    let _ = tx.send(refresh_session_token(&refresh_config).await);
+                });
+                refreshing = Some(rx);
+            }
+
+            // On failure, reconnect so the full auth handshake can sort it out
+            result = async { refreshing.as_mut().unwrap().await }, if refreshing.is_some() => {
+                refreshing = None;
+                let (session_token, expires_at) = result
+                    .context("session token refresh task ended")?
+                    .context("session token refresh failed")?;
+                info!("session token refreshed");
+
+                config.session_token = Some(session_token.clone());
+                refresh_at = expires_at.map(|expires_at| refresh_deadline(expires_at, margin));
+                event_tx
+                    .send(ServerEvent::TokenRefreshed { session_token })
+                    .await
+                    .ok();
+            }
+
+            // Nothing has happened for a while: hang up until the next check-in
+            _ = time::sleep_until(last_activity + idle_limit.unwrap_or_default()), if idle_limit.is_some() => {
+                if handle.busy.load(Ordering::Relaxed) {
+                    last_activity = Instant::now();
+                    idle_limit = idle_timeout;
+                } else {
+                    ws_sink.send(WsMessage::Close(None)).await.ok();
+                    return Ok(ConnectionEnd::Idle);
+                }
+            }
+
+            // Bulk data, once nothing above is ready
+            outgoing = queues.bulk.recv() => {
                 match outgoing {
                     Some(data) => {
+                        last_activity = Instant::now();
+                        handle.in_flight.fetch_sub(data.len(), Ordering::Relaxed);
+                        handle.stats.record_sent(&data);
                         ws_sink.send(WsMessage::Binary(data.into())).await?;
                     }
                     None => {
                         info!("outgoing channel closed");
-                        return Ok(());
+                        return Ok(ConnectionEnd::Closed(DisconnectReason::Shutdown));
                     }
                 }
             }
-
-            // Heartbeat timer
-            _ = heartbeat_timer.tick() => {
-                if last_pong.elapsed() > heartbeat_timeout {
-                    warn!("heartbeat timeout, disconnecting");
-                    return Ok(());
-                }
-                let hb = protocol::heartbeat();
-                ws_sink.send(WsMessage::Binary(hb.encode().into())).await?;
-                debug!("sent heartbeat");
-            }
         }
     }
 }
@@ -326,17 +1061,29 @@ fn reconnect_delay(config: &AgentConfig, attempt: u32) -> Duration {
     // Exponential backoff: base * 2^(attempt-1), capped at max
     let delay = (base * 2.0f64.powi(attempt as i32 - 1)).min(max);
     // Add jitter: ±25%
-    let jitter = delay * 0.25 * (2.0 * rand_simple() - 1.0);
+    let jitter = delay * 0.25 * (2.0 * jitter_random() - 1.0);
     Duration::from_secs_f64((delay + jitter).max(base))
 }
 
-fn rand_simple() -> f64 {
-    // Simple pseudo-random using time - good enough for jitter
-    let nanos = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .subsec_nanos();
-    (nanos % 1000) as f64 / 1000.0
+/// Uniform value in [0, 1) for reconnect jitter.
+///
+/// xorshift64* seeded once per process from the OS RNG, so agents that all
+/// lose the server at the same moment don't pick the same jitter.
+fn jitter_random() -> f64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        // Must be non-zero; a v4 UUID is 122 random bits from the OS
+        x = (uuid::Uuid::new_v4().as_u128() as u64) | 1;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    STATE.store(x, Ordering::Relaxed);
+
+    // Top 53 bits of the scrambled output map exactly onto an f64 mantissa
+    (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
 }
 
 fn gethostname() -> String {
@@ -344,3 +1091,30 @@ fn gethostname() -> String {
         .map(|h| h.to_string_lossy().to_string())
         .unwrap_or_else(|_| "unknown".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_wait() {
+        let now = 1_000_000;
+        // Long-lived token: refreshed `margin` before it expires
+        assert_eq!(
+            refresh_wait(now + 3600, 300, now),
+            Duration::from_secs(3300)
+        );
+        // TTL shorter than the margin: halfway through, not straight away
+        assert_eq!(refresh_wait(now + 120, 300, now), Duration::from_secs(60));
+        assert_eq!(refresh_wait(now + 400, 300, now), Duration::from_secs(200));
+        // Tiny or already expired tokens still wait the minimum
+        assert_eq!(
+            refresh_wait(now + 4, 300, now),
+            Duration::from_secs(MIN_TOKEN_REFRESH_DELAY_SECS)
+        );
+        assert_eq!(
+            refresh_wait(now - 10, 300, now),
+            Duration::from_secs(MIN_TOKEN_REFRESH_DELAY_SECS)
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Local control socket for host-level monitoring.
+//!
+//! Listens on a Unix domain socket (`<data_dir>/control.sock`, mode 0600) or,
+//! on Windows, the named pipe `\\.\pipe\android-remote-agent-control`, which
+//! only SYSTEM and Administrators may open. A client connects, writes one
+//! request line and reads one response line, after which the agent closes
+//! the connection.
+//!
+//! The only request is `health`. Its response is one JSON object:
+//!
+//! ```text
+//! {
+//!   "status": "ok" | "disconnected" | "unresponsive",
+//!   "version": "0.1.0",            // agent version
+//!   "uptime_secs": 3600,           // since the agent started
+//!   "connected": true,             // connected and authenticated to the server
+//!   "device_id": "abc" | null,
+//!   "last_telemetry_unix": 1700000000 | null,  // last telemetry sent, unix seconds
+//!   "active_sessions": 2,          // desktop, terminal and audio sessions
+//!   "last_disconnect": "heartbeat timeout" | null  // why the last connection dropped
+//! }
+//! ```
+//!
+//! `unresponsive` means the agent's main loop didn't answer within
+//! `REQUEST_TIMEOUT`; the fields after `uptime_secs` then hold their empty
+//! values. Any other request gets `{"error": "..."}`.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info};
+
+use crate::config::AgentConfig;
+
+/// Named pipe the control socket listens on under Windows
+#[cfg(windows)]
+pub const CONTROL_PIPE_NAME: &str = r"\\.\pipe\android-remote-agent-control";
+
+/// How long a client gets to send its request, and the agent to answer it
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line read from a client
+const MAX_REQUEST_LEN: u64 = 256;
+
+/// Agent state the main loop reports for a health request
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct HealthState {
+    pub connected: bool,
+    pub device_id: Option<String>,
+    pub last_telemetry_unix: Option<u64>,
+    pub active_sessions: usize,
+    pub last_disconnect: Option<String>,
+}
+
+/// Response to a `health` request
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: &'static str,
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    #[serde(flatten)]
+    pub state: HealthState,
+}
+
+/// A health request waiting for the main loop to fill in the agent's state
+pub type HealthQuery = oneshot::Sender<HealthState>;
+
+/// Path of the control socket
+#[cfg(unix)]
+pub fn socket_path(config: &AgentConfig) -> std::path::PathBuf {
+    config.data_dir().join("control.sock")
+}
+
+/// Serve the control socket until it fails. Health requests are passed to
+/// the main loop through `queries`.
+#[cfg(unix)]
+pub async fn serve(config: &AgentConfig, queries: mpsc::Sender<HealthQuery>) -> Result<()> {
+    let path = socket_path(config);
+    // A socket left behind by an earlier run would make bind fail
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind control socket {}", path.display()))?;
+    // Health reports are for the agent's owner (root, as a service) only
+    std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600))
+        .with_context(|| format!("failed to restrict control socket {}", path.display()))?;
+    info!("control socket listening on {}", path.display());
+
+    let started = Instant::now();
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("control socket accept failed")?;
+        let queries = queries.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, &queries, started).await {
+                debug!("control client error: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Serve the control pipe until it fails. Health requests are passed to the
+/// main loop through `queries`.
+#[cfg(windows)]
+pub async fn serve(_config: &AgentConfig, queries: mpsc::Sender<HealthQuery>) -> Result<()> {
+    let mut server = create_pipe(true)
+        .with_context(|| format!("failed to create control pipe {}", CONTROL_PIPE_NAME))?;
+    info!("control pipe listening on {}", CONTROL_PIPE_NAME);
+
+    let started = Instant::now();
+    loop {
+        server
+            .connect()
+            .await
+            .context("control pipe connect failed")?;
+        // Open the next instance before serving this one so clients never
+        // find the pipe missing
+        let client = std::mem::replace(
+            &mut server,
+            create_pipe(false).context("failed to create control pipe instance")?,
+        );
+        let queries = queries.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(client, &queries, started).await {
+                debug!("control client error: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Create an instance of the control pipe that only SYSTEM and
+/// Administrators may open
+#[cfg(windows)]
+fn create_pipe(first: bool) -> Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    agent_windows::ipc::with_admin_only_security(|attributes| unsafe {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .create_with_security_attributes_raw(CONTROL_PIPE_NAME, attributes)
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub async fn serve(_config: &AgentConfig, _queries: mpsc::Sender<HealthQuery>) -> Result<()> {
+    anyhow::bail!("control socket not supported on this platform")
+}
+
+/// Read one request from `stream` and write its response line
+async fn handle_client<S>(
+    stream: S,
+    queries: &mpsc::Sender<HealthQuery>,
+    started: Instant,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut line = String::new();
+    tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        BufReader::new(reader.take(MAX_REQUEST_LEN)).read_line(&mut line),
+    )
+    .await
+    .context("no request received")?
+    .context("failed to read request")?;
+
+    let response = match line.trim() {
+        "health" => serde_json::to_string(&health_report(queries, started).await)?,
+        other => {
+            serde_json::json!({ "error": format!("unknown request: {:?}", other) }).to_string()
+        }
+    };
+    writer.write_all(response.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Ask the main loop for the agent's state and build the health report
+async fn health_report(queries: &mpsc::Sender<HealthQuery>, started: Instant) -> HealthReport {
+    let (tx, rx) = oneshot::channel();
+    let state = match queries.send(tx).await {
+        Ok(()) => tokio::time::timeout(REQUEST_TIMEOUT, rx)
+            .await
+            .ok()
+            .and_then(Result::ok),
+        Err(_) => None,
+    };
+    let status = match &state {
+        Some(s) if s.connected => "ok",
+        Some(_) => "disconnected",
+        None => "unresponsive",
+    };
+    HealthReport {
+        status,
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: started.elapsed().as_secs(),
+        state: state.unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn request(line: &[u8], queries: &mpsc::Sender<HealthQuery>) -> serde_json::Value {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let queries = queries.clone();
+        let task =
+            tokio::spawn(async move { handle_client(server, &queries, Instant::now()).await });
+        client.write_all(line).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        task.await.unwrap().unwrap();
+        assert!(response.ends_with('\n'));
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_health_report() {
+        let (tx, mut rx) = mpsc::channel::<HealthQuery>(1);
+        tokio::spawn(async move {
+            while let Some(reply) = rx.recv().await {
+                let _ = reply.send(HealthState {
+                    connected: true,
+                    device_id: Some("dev1".to_string()),
+                    last_telemetry_unix: Some(1_700_000_000),
+                    active_sessions: 2,
+                    last_disconnect: Some("closed by server".to_string()),
+                });
+            }
+        });
+
+        let report = request(b"health\n", &tx).await;
+        assert_eq!(report["status"], "ok");
+        assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(report["connected"], true);
+        assert_eq!(report["device_id"], "dev1");
+        assert_eq!(report["last_telemetry_unix"], 1_700_000_000);
+        assert_eq!(report["active_sessions"], 2);
+        assert_eq!(report["last_disconnect"], "closed by server");
+        assert!(report["uptime_secs"].is_u64());
+
+        let unknown = request(b"restart\n", &tx).await;
+        assert!(unknown["error"].as_str().unwrap().contains("restart"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_socket_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("agent-control-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = AgentConfig::default();
+        config.data_dir = Some(dir.to_string_lossy().into_owned());
+        let path = socket_path(&config);
+        let (tx, _rx) = mpsc::channel::<HealthQuery>(1);
+        let server = tokio::spawn(async move { serve(&config, tx).await });
+
+        let mut mode = None;
+        for _ in 0..100 {
+            mode = std::fs::metadata(&path)
+                .ok()
+                .map(|m| m.permissions().mode() & 0o777);
+            if mode == Some(0o600) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        server.abort();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(mode, Some(0o600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_health_report_unresponsive() {
+        // A main loop that never answers
+        let (tx, _rx) = mpsc::channel::<HealthQuery>(1);
+        let report = request(b"health\n", &tx).await;
+        assert_eq!(report["status"], "unresponsive");
+        assert_eq!(report["connected"], false);
+        assert_eq!(report["device_id"], serde_json::Value::Null);
+    }
+}
@@ -1,11 +1,18 @@
 //! Desktop session — tile-based screen capture, diff, and JPEG encoding.
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
 use anyhow::{Context, Result};
+use tokio::sync::watch;
 use tracing::{debug, info, warn};
 
+use agent_platform::indicator::SessionIndicator;
 use agent_platform::input::InputInjector;
-use agent_platform::screen::ScreenCapture;
+use agent_platform::screen::{ScreenCapture, ScreenFrame};
 
+use crate::config::SessionIndicatorMode;
 use crate::connection::ConnectionHandle;
 use crate::protocol;
 
@@ -15,17 +22,91 @@ pub const TILE_SIZE: u32 = 64;
 /// Encoding types for DESKTOP_FRAME
 pub const ENCODING_JPEG: u8 = 0;
 pub const ENCODING_PNG: u8 = 1;
+/// Uncompressed RGB pixels (3 bytes each, row-major)
 pub const ENCODING_RAW: u8 = 2;
+/// zlib-compressed RGB565 pixels (little-endian u16, row-major)
+pub const ENCODING_RGB565: u8 = 3;
+/// zlib-compressed 8-bit pixels in the fixed RGB332 palette (RRRGGGBB)
+pub const ENCODING_PALETTE8: u8 = 4;
 
 /// Frame flags
 pub const FLAG_KEYFRAME: u8 = 0x01;
 
+/// JPEG quality range accepted from the viewer. Below 10 tiles turn to mush;
+/// above 95 they grow several times larger for no visible gain.
+pub const MIN_QUALITY: u8 = 10;
+pub const MAX_QUALITY: u8 = 95;
+
+/// JPEG keyframes go out at the target quality divided by this (but at
+/// least `MIN_QUALITY`) so the first paint is small and fast; the tiles are
+/// then re-sent at full quality over the following frames
+const KEYFRAME_QUALITY_DIVISOR: u8 = 4;
+/// Share of the tiles (1/n) refined on the first frame after a keyframe.
+/// The share doubles each frame, so refinement finishes within a few frames
+/// without repeating the keyframe's burst.
+const REFINE_START_FRACTION: usize = 8;
+
+/// Upper bound on unsent connection bytes before capture stops encoding.
+/// Skipped frames cost nothing: the encoder still diffs against the last
+/// frame it sent, so the next encoded frame carries everything that changed.
+pub const MAX_IN_FLIGHT_BYTES: usize = 8 * 1024 * 1024;
+
+/// A shared capture that produces neither a frame nor a "no change" answer
+/// for this long (it keeps failing, or a call hangs) is re-initialized.
+const CAPTURE_WATCHDOG_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How often `monitor_input_state` checks whether injected input gets through
+const INPUT_STATE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest single wait for a screen change in `capture_on_change` mode.
+/// Much longer than a frame interval, so a static screen wakes the capture a
+/// few times a second instead of once per frame.
+const ON_CHANGE_ACQUIRE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Captures re-initialized by the watchdog since the agent started
+static CAPTURE_RESTARTS: AtomicU64 = AtomicU64::new(0);
+
+/// JPEG sessions with RAW tiles allowed send tiles of at most this many
+/// pixels uncompressed; for slivers at the screen edge the JPEG headers
+/// outweigh the pixels
+pub const RAW_TILE_MAX_PIXELS: u32 = 256;
+
+/// Largest tile data sent in one DESKTOP_FRAME: what fits the version 1
+/// header's u16 length field, so every peer can take every tile
+pub const MAX_TILE_DATA: usize = u16::MAX as usize - protocol::DESKTOP_FRAME_HEADER_SIZE;
+
+// Any encoding of a full tile fits, so no tile ever needs shrinking: RAW is
+// 3 bytes a pixel, deflate adds next to nothing, and turbojpeg's worst case
+// (tjBufSize) is 6 bytes a pixel plus 2 KiB of headers
+const _: () = assert!((TILE_SIZE * TILE_SIZE) as usize * 6 + 2048 <= MAX_TILE_DATA);
+
+/// Queueing delay the "auto" encoding aims to stay under by default
+pub const DEFAULT_TARGET_LATENCY_MS: u32 = 200;
+
 /// Desktop session configuration
 #[derive(Debug, Clone)]
 pub struct DesktopConfig {
     pub quality: u8,
     pub fps: u16,
     pub encoding: String,
+    /// Capture a single window by title instead of the whole screen (Windows)
+    pub window_title: Option<String>,
+    /// Capture a single window by native handle (Windows)
+    pub window_handle: Option<u64>,
+    /// Capture all monitors stitched into one virtual-desktop frame
+    pub stitched: bool,
+    /// "auto" encoding: queueing delay to stay under
+    pub target_latency_ms: u32,
+    /// "auto" encoding: bandwidth cap in kbit/s on top of the measured one
+    pub max_bandwidth_kbps: Option<u32>,
+    /// JPEG restart marker interval in MCU rows; 0 writes no markers
+    pub jpeg_restart_rows: u16,
+    /// Capture when the screen changes, at most `fps` times a second,
+    /// instead of polling at `fps`. A capture shared with a viewer that
+    /// polls polls for everyone.
+    pub capture_on_change: bool,
+    /// Whether the agent allows uncompressed RAW tiles
+    pub raw_tiles: bool,
 }
 
 impl Default for DesktopConfig {
@@ -34,6 +115,84 @@ impl Default for DesktopConfig {
             quality: 70,
             fps: 15,
             encoding: "jpeg".to_string(),
+            window_title: None,
+            window_handle: None,
+            stitched: false,
+            target_latency_ms: DEFAULT_TARGET_LATENCY_MS,
+            max_bandwidth_kbps: None,
+            jpeg_restart_rows: 0,
+            capture_on_change: false,
+            raw_tiles: false,
+        }
+    }
+}
+
+impl DesktopConfig {
+    /// Build the session config from a DESKTOP_OPEN / DESKTOP_QUALITY
+    /// request, clamping fps to `1..=max_fps` and quality to
+    /// `MIN_QUALITY..=MAX_QUALITY`. Encoding "raw" falls back to "jpeg"
+    /// unless `raw_tiles` allows it.
+    pub fn from_request(req: protocol::DesktopOpenRequest, max_fps: u16, raw_tiles: bool) -> Self {
+        let fps = req.fps.clamp(1, max_fps.max(1));
+        if fps != req.fps {
+            warn!("requested fps {} clamped to {}", req.fps, fps);
+        }
+        let quality = req.quality.clamp(MIN_QUALITY, MAX_QUALITY);
+        if quality != req.quality {
+            warn!("requested quality {} clamped to {}", req.quality, quality);
+        }
+        let mut encoding = req.encoding;
+        if encoding == "raw" && !raw_tiles {
+            warn!("raw tiles requested but not enabled, using jpeg");
+            encoding = "jpeg".to_string();
+        }
+
+        Self {
+            quality,
+            fps,
+            encoding,
+            window_title: req.window_title,
+            window_handle: req.window_handle,
+            stitched: req.stitched,
+            target_latency_ms: req
+                .target_latency_ms
+                .unwrap_or(DEFAULT_TARGET_LATENCY_MS)
+                .max(1),
+            max_bandwidth_kbps: req.max_bandwidth_kbps.filter(|&kbps| kbps > 0),
+            jpeg_restart_rows: req.jpeg_restart_rows,
+            capture_on_change: req.capture_on_change,
+            raw_tiles,
+        }
+    }
+
+    /// Whether a single window was requested rather than the whole screen
+    pub fn targets_window(&self) -> bool {
+        self.window_title.is_some() || self.window_handle.is_some()
+    }
+
+    /// What this session captures; sessions on the same source share a capture
+    pub fn capture_source(&self) -> CaptureSource {
+        CaptureSource {
+            window_title: self.window_title.clone(),
+            window_handle: self.window_handle,
+            stitched: self.stitched,
+        }
+    }
+
+    /// Whether quality, fps and subsampling are picked by the session itself
+    pub fn is_auto(&self) -> bool {
+        self.encoding == "auto"
+    }
+
+    /// DESKTOP_FRAME encoding for the requested `encoding` name. "rgb565"
+    /// and "palette8" trade color for bandwidth, "raw" trades bandwidth
+    /// for exact pixels; anything else is JPEG.
+    pub fn encoding_byte(&self) -> u8 {
+        match self.encoding.as_str() {
+            "rgb565" => ENCODING_RGB565,
+            "palette8" => ENCODING_PALETTE8,
+            "raw" => ENCODING_RAW,
+            _ => ENCODING_JPEG,
         }
     }
 }
@@ -46,12 +205,26 @@ pub struct TileEncoder {
     tiles_x: u32,
     /// Number of tiles in Y direction
     tiles_y: u32,
-    /// Previous frame data for diffing (BGRA)
-    prev_frame: Vec<u8>,
+    /// Hash of each tile's pixels as last sent to the viewer, row-major;
+    /// a tile is re-sent only when its hash differs
+    sent_hash: Vec<Option<u64>>,
     /// JPEG quality (1-100)
     quality: u8,
+    /// Tile encoding (ENCODING_*)
+    encoding: u8,
+    /// JPEG chroma subsampling
+    subsampling: Subsampling,
+    /// JPEG restart marker interval in MCU rows (0 = none)
+    restart_rows: u16,
+    /// JPEG tiles of at most this many pixels are sent RAW instead (0 = never)
+    raw_tile_max_pixels: u32,
     /// Whether the next frame should be a keyframe (all tiles sent)
     force_keyframe: bool,
+    /// JPEG quality each tile was last sent at, row-major
+    sent_quality: Vec<u8>,
+    /// Tiles that may be re-sent at full quality on the next frame; 0 when
+    /// no reduced-quality keyframe is being refined
+    refine_budget: usize,
 }
 
 impl TileEncoder {
@@ -69,9 +242,15 @@ impl TileEncoder {
             height,
             tiles_x,
             tiles_y,
-            prev_frame: Vec::new(),
+            sent_hash: vec![None; (tiles_x * tiles_y) as usize],
             quality,
+            encoding: ENCODING_JPEG,
+            subsampling: Subsampling::default(),
+            restart_rows: 0,
+            raw_tile_max_pixels: 0,
             force_keyframe: true, // first frame is always a keyframe
+            sent_quality: vec![0; (tiles_x * tiles_y) as usize],
+            refine_budget: 0,
         }
     }
 
@@ -79,10 +258,39 @@ impl TileEncoder {
         self.quality = quality.clamp(1, 100);
     }
 
+    /// Switch the tile encoding; takes effect from the next keyframe on.
+    pub fn set_encoding(&mut self, encoding: u8) {
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.force_keyframe = true;
+        }
+    }
+
+    /// Change JPEG chroma subsampling; applies to tiles encoded from now on.
+    pub fn set_subsampling(&mut self, subsampling: Subsampling) {
+        self.subsampling = subsampling;
+    }
+
+    /// Write a JPEG restart marker every `rows` MCU rows; 0 writes none.
+    pub fn set_restart_rows(&mut self, rows: u16) {
+        self.restart_rows = rows;
+    }
+
+    /// Send JPEG tiles of at most `pixels` pixels as RAW; 0 never does.
+    pub fn set_raw_tile_max_pixels(&mut self, pixels: u32) {
+        self.raw_tile_max_pixels = pixels;
+    }
+
     pub fn request_keyframe(&mut self) {
         self.force_keyframe = true;
     }
 
+    /// Whether tiles of the last reduced-quality keyframe are still waiting
+    /// to be re-sent at full quality
+    pub fn is_refining(&self) -> bool {
+        self.refine_budget > 0
+    }
+
     /// Encode changed tiles from a BGRA frame.
     /// Returns a list of (tile_x, tile_y, tile_w, tile_h, jpeg_data, flags) tuples.
     pub fn encode_frame(
@@ -90,10 +298,28 @@ impl TileEncoder {
         frame_data: &[u8],
         stride: u32,
     ) -> Result<Vec<TileData>> {
-        let is_keyframe = self.force_keyframe || self.prev_frame.is_empty();
+        let needed = (stride as usize) * (self.height as usize);
+        if frame_data.len() < needed {
+            anyhow::bail!(
+                "frame data too short: {} bytes, expected {}",
+                frame_data.len(),
+                needed
+            );
+        }
+
+        let is_keyframe = self.force_keyframe;
+        let mut quality = self.quality;
         if is_keyframe {
             self.force_keyframe = false;
+            self.refine_budget = 0;
+            let reduced = (self.quality / KEYFRAME_QUALITY_DIVISOR).max(MIN_QUALITY);
+            if self.encoding == ENCODING_JPEG && reduced < self.quality {
+                quality = reduced;
+                self.refine_budget = (self.sent_quality.len() / REFINE_START_FRACTION).max(1);
+            }
         }
+        let mut refine_left = self.refine_budget;
+        let mut unrefined = 0;
 
         let mut tiles = Vec::new();
 
@@ -103,19 +329,65 @@ impl TileEncoder {
                 let pixel_y = ty * TILE_SIZE;
                 let tile_w = (self.width - pixel_x).min(TILE_SIZE);
                 let tile_h = (self.height - pixel_y).min(TILE_SIZE);
+                let index = (ty * self.tiles_x + tx) as usize;
 
-                // Check if tile changed
-                if !is_keyframe && !self.prev_frame.is_empty() {
-                    if !self.tile_changed(frame_data, stride, pixel_x, pixel_y, tile_w, tile_h) {
-                        continue;
+                // Check if tile differs from what the viewer has; unchanged
+                // tiles still showing the reduced keyframe are re-sent while
+                // the budget lasts
+                let hash = tile_hash(frame_data, stride, pixel_x, pixel_y, tile_w, tile_h);
+                if !is_keyframe {
+                    if self.sent_hash[index] == Some(hash) {
+                        if self.refine_budget == 0 || self.sent_quality[index] >= self.quality {
+                            continue;
+                        }
+                        if refine_left == 0 {
+                            unrefined += 1;
+                            continue;
+                        }
+                        refine_left -= 1;
                     }
                 }
 
-                // Extract tile pixels as RGB (convert from BGRA)
-                let rgb = self.extract_tile_rgb(frame_data, stride, pixel_x, pixel_y, tile_w, tile_h);
+                let encoding = if self.encoding == ENCODING_JPEG
+                    && tile_w * tile_h <= self.raw_tile_max_pixels
+                {
+                    ENCODING_RAW
+                } else {
+                    self.encoding
+                };
+                let data = match encoding {
+                    ENCODING_RGB565 | ENCODING_PALETTE8 => {
+                        let pixels = self.extract_tile_reduced(
+                            frame_data, stride, pixel_x, pixel_y, tile_w, tile_h,
+                        );
+                        deflate_tile(&pixels)?
+                    }
+                    ENCODING_RAW => {
+                        self.extract_tile_rgb(frame_data, stride, pixel_x, pixel_y, tile_w, tile_h)
+                    }
+                    _ => {
+                        // Extract tile pixels as RGB (convert from BGRA)
+                        let rgb = self
+                            .extract_tile_rgb(frame_data, stride, pixel_x, pixel_y, tile_w, tile_h);
 
-                // Encode as JPEG using turbojpeg
-                let jpeg_data = encode_jpeg_tile(&rgb, tile_w, tile_h, self.quality)?;
+                        // Encode as JPEG using turbojpeg
+                        encode_jpeg_tile(
+                            &rgb,
+                            tile_w,
+                            tile_h,
+                            quality,
+                            self.subsampling,
+                            self.restart_rows,
+                        )?
+                    }
+                };
+                // RAW tiles are exact and never need refining
+                self.sent_quality[index] = if encoding == ENCODING_RAW {
+                    u8::MAX
+                } else {
+                    quality
+                };
+                self.sent_hash[index] = Some(hash);
 
                 let flags = if is_keyframe { FLAG_KEYFRAME } else { 0 };
 
@@ -124,14 +396,20 @@ impl TileEncoder {
                     y: pixel_y as u16,
                     w: tile_w as u16,
                     h: tile_h as u16,
-                    data: jpeg_data,
+                    data,
+                    encoding,
                     flags,
                 });
             }
         }
 
-        // Store current frame for next comparison
-        self.prev_frame = frame_data.to_vec();
+        if !is_keyframe && self.refine_budget > 0 {
+            self.refine_budget = if unrefined == 0 {
+                0
+            } else {
+                self.refine_budget * 2
+            };
+        }
 
         debug!(
             "encoded {} / {} tiles (keyframe={})",
@@ -143,7 +421,7 @@ impl TileEncoder {
         Ok(tiles)
     }
 
-    fn tile_changed(
+    fn extract_tile_rgb(
         &self,
         frame_data: &[u8],
         stride: u32,
@@ -151,27 +429,32 @@ impl TileEncoder {
         py: u32,
         tw: u32,
         th: u32,
-    ) -> bool {
-        let prev_stride = self.width * 4;
+    ) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity((tw * th * 3) as usize);
+
         for row in 0..th {
             let y = py + row;
-            let new_start = (y * stride + px * 4) as usize;
-            let new_end = new_start + (tw * 4) as usize;
-            let old_start = (y * prev_stride + px * 4) as usize;
-            let old_end = old_start + (tw * 4) as usize;
-
-            if new_end > frame_data.len() || old_end > self.prev_frame.len() {
-                return true;
-            }
+            let row_start = (y * stride + px * 4) as usize;
 
-            if frame_data[new_start..new_end] != self.prev_frame[old_start..old_end] {
-                return true;
+            for col in 0..tw {
+                let offset = row_start + (col * 4) as usize;
+                if offset + 2 < frame_data.len() {
+                    // BGRA -> RGB
+                    rgb.push(frame_data[offset + 2]); // R
+                    rgb.push(frame_data[offset + 1]); // G
+                    rgb.push(frame_data[offset]); // B
+                } else {
+                    rgb.extend_from_slice(&[0, 0, 0]);
+                }
             }
         }
-        false
+
+        rgb
     }
 
-    fn extract_tile_rgb(
+    /// Extract tile pixels at reduced color depth: RGB565 (2 bytes per
+    /// pixel) or RGB332 (1 byte), depending on the encoding.
+    fn extract_tile_reduced(
         &self,
         frame_data: &[u8],
         stride: u32,
@@ -180,7 +463,12 @@ impl TileEncoder {
         tw: u32,
         th: u32,
     ) -> Vec<u8> {
-        let mut rgb = Vec::with_capacity((tw * th * 3) as usize);
+        let bytes_per_pixel = if self.encoding == ENCODING_RGB565 {
+            2
+        } else {
+            1
+        };
+        let mut out = Vec::with_capacity((tw * th) as usize * bytes_per_pixel);
 
         for row in 0..th {
             let y = py + row;
@@ -188,19 +476,59 @@ impl TileEncoder {
 
             for col in 0..tw {
                 let offset = row_start + (col * 4) as usize;
-                if offset + 2 < frame_data.len() {
-                    // BGRA -> RGB
-                    rgb.push(frame_data[offset + 2]); // R
-                    rgb.push(frame_data[offset + 1]); // G
-                    rgb.push(frame_data[offset]);      // B
+                let (r, g, b) = if offset + 2 < frame_data.len() {
+                    (
+                        frame_data[offset + 2],
+                        frame_data[offset + 1],
+                        frame_data[offset],
+                    )
                 } else {
-                    rgb.extend_from_slice(&[0, 0, 0]);
+                    (0, 0, 0)
+                };
+                if self.encoding == ENCODING_RGB565 {
+                    out.extend_from_slice(&rgb565(r, g, b).to_le_bytes());
+                } else {
+                    out.push(rgb332(r, g, b));
                 }
             }
         }
 
-        rgb
+        out
+    }
+}
+
+/// xxh3 hash of a tile's BGRA pixels, independent of the frame stride
+fn tile_hash(frame_data: &[u8], stride: u32, px: u32, py: u32, tw: u32, th: u32) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    for row in 0..th {
+        let start = ((py + row) * stride + px * 4) as usize;
+        let end = (start + (tw * 4) as usize).min(frame_data.len());
+        hasher.update(frame_data.get(start..end).unwrap_or_default());
     }
+    hasher.digest()
+}
+
+fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+fn rgb332(r: u8, g: u8, b: u8) -> u8 {
+    (r & 0xE0) | ((g & 0xE0) >> 3) | (b >> 6)
+}
+
+/// zlib-compress reduced-depth tile pixels. Fast compression is plenty:
+/// flat UI content collapses to a few hundred bytes either way.
+fn deflate_tile(pixels: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::ZlibEncoder::new(
+        Vec::with_capacity(pixels.len() / 4),
+        flate2::Compression::fast(),
+    );
+    encoder
+        .write_all(pixels)
+        .context("tile compression failed")?;
+    encoder.finish().context("tile compression failed")
 }
 
 /// A single encoded tile
@@ -210,14 +538,63 @@ pub struct TileData {
     pub w: u16,
     pub h: u16,
     pub data: Vec<u8>,
+    /// Encoding of `data` (ENCODING_*)
+    pub encoding: u8,
     pub flags: u8,
 }
 
+/// JPEG chroma subsampling. 4:2:0 is turbojpeg's default; less
+/// subsampling keeps colored text sharp at the cost of larger tiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Subsampling {
+    S444,
+    S422,
+    #[default]
+    S420,
+}
+
+impl Subsampling {
+    fn to_turbojpeg(self) -> turbojpeg::Subsamp {
+        match self {
+            Subsampling::S444 => turbojpeg::Subsamp::None,
+            Subsampling::S422 => turbojpeg::Subsamp::Sub2x1,
+            Subsampling::S420 => turbojpeg::Subsamp::Sub2x2,
+        }
+    }
+
+    fn to_raw(self) -> std::os::raw::c_int {
+        (match self {
+            Subsampling::S444 => turbojpeg::raw::TJSAMP_TJSAMP_444,
+            Subsampling::S422 => turbojpeg::raw::TJSAMP_TJSAMP_422,
+            Subsampling::S420 => turbojpeg::raw::TJSAMP_TJSAMP_420,
+        }) as _
+    }
+}
+
 /// Encode RGB pixels to JPEG using turbojpeg
-fn encode_jpeg_tile(rgb: &[u8], width: u32, height: u32, quality: u8) -> Result<Vec<u8>> {
-    let mut compressor = turbojpeg::Compressor::new()
-        .context("failed to create JPEG compressor")?;
+fn encode_jpeg_tile(
+    rgb: &[u8],
+    width: u32,
+    height: u32,
+    quality: u8,
+    subsampling: Subsampling,
+    restart_rows: u16,
+) -> Result<Vec<u8>> {
+    if restart_rows > 0 {
+        return encode_jpeg_tile_with_restarts(
+            rgb,
+            width,
+            height,
+            quality,
+            subsampling,
+            restart_rows,
+        );
+    }
+
+    let mut compressor =
+        turbojpeg::Compressor::new().context("failed to create JPEG compressor")?;
     let _ = compressor.set_quality(quality as i32);
+    let _ = compressor.set_subsamp(subsampling.to_turbojpeg());
 
     let image = turbojpeg::Image {
         pixels: rgb,
@@ -233,13 +610,243 @@ fn encode_jpeg_tile(rgb: &[u8], width: u32, height: u32, quality: u8) -> Result<
     Ok(jpeg)
 }
 
+/// `encode_jpeg_tile` with restart markers. `Compressor` doesn't expose the
+/// restart interval, so this drives the TurboJPEG API directly.
+fn encode_jpeg_tile_with_restarts(
+    rgb: &[u8],
+    width: u32,
+    height: u32,
+    quality: u8,
+    subsampling: Subsampling,
+    restart_rows: u16,
+) -> Result<Vec<u8>> {
+    use turbojpeg::raw;
+
+    unsafe {
+        let handle = raw::tj3Init(raw::TJINIT_TJINIT_COMPRESS as _);
+        if handle.is_null() {
+            anyhow::bail!("failed to create JPEG compressor");
+        }
+
+        let params = [
+            (raw::TJPARAM_TJPARAM_QUALITY, quality as i32),
+            (raw::TJPARAM_TJPARAM_SUBSAMP, subsampling.to_raw()),
+            (raw::TJPARAM_TJPARAM_RESTARTROWS, restart_rows as i32),
+        ];
+        let mut rc = 0;
+        for (param, value) in params {
+            rc |= raw::tj3Set(handle, param as _, value);
+        }
+
+        let mut buf: *mut u8 = std::ptr::null_mut();
+        let mut size = 0;
+        if rc == 0 {
+            rc = raw::tj3Compress8(
+                handle,
+                rgb.as_ptr(),
+                width as _,
+                (width * 3) as _,
+                height as _,
+                raw::TJPF_TJPF_RGB as _,
+                &mut buf,
+                &mut size,
+            );
+        }
+
+        let result = if rc == 0 && !buf.is_null() {
+            Ok(std::slice::from_raw_parts(buf, size).to_vec())
+        } else {
+            let message = raw::tj3GetErrorStr(handle);
+            let message = if message.is_null() {
+                "unknown error".into()
+            } else {
+                std::ffi::CStr::from_ptr(message).to_string_lossy()
+            };
+            Err(anyhow::anyhow!("JPEG compression failed: {}", message))
+        };
+
+        if !buf.is_null() {
+            raw::tj3Free(buf as *mut _);
+        }
+        raw::tj3Destroy(handle);
+        result
+    }
+}
+
+/// Smallest scale factor a screenshot can be taken at
+pub const MIN_SCREENSHOT_SCALE: f32 = 0.05;
+
+/// Longest a screenshot waits for its one frame
+const SCREENSHOT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Quality a too-large screenshot is lowered to before it is scaled down
+const SCREENSHOT_FIT_QUALITY: u8 = 30;
+
+/// A single full-screen JPEG, as returned by the SCREENSHOT command
+pub struct Screenshot {
+    pub jpeg: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Initialize `screen`, grab one frame and JPEG-encode all of it, scaled by
+/// `scale`. A JPEG larger than `max_jpeg` is re-encoded at a lower quality,
+/// then smaller, until it fits. The caller drops the capture afterwards.
+pub async fn capture_screenshot(
+    screen: &mut dyn ScreenCapture,
+    quality: u8,
+    scale: f32,
+    max_jpeg: usize,
+) -> Result<Screenshot> {
+    screen.set_acquire_timeout(std::time::Duration::from_secs(1));
+    screen
+        .init()
+        .await
+        .context("failed to initialize screen capture")?;
+    let frame = tokio::time::timeout(SCREENSHOT_TIMEOUT, screen.capture_frame())
+        .await
+        .context("timed out waiting for a frame")?
+        .context("failed to capture screen")?;
+
+    let mut scale = scale.clamp(MIN_SCREENSHOT_SCALE, 1.0);
+    let mut quality = quality.clamp(MIN_QUALITY, MAX_QUALITY);
+    loop {
+        let width = ((frame.width as f32 * scale).round() as u32).max(1);
+        let height = ((frame.height as f32 * scale).round() as u32).max(1);
+        let rgb = screenshot_rgb(&frame, width, height);
+        let jpeg = encode_jpeg_tile(&rgb, width, height, quality, Subsampling::default(), 0)?;
+        if jpeg.len() <= max_jpeg {
+            return Ok(Screenshot {
+                jpeg,
+                width,
+                height,
+            });
+        }
+
+        debug!(
+            "screenshot {}x{} at quality {} is {} bytes, over the {} byte limit",
+            width,
+            height,
+            quality,
+            jpeg.len(),
+            max_jpeg
+        );
+        if quality > SCREENSHOT_FIT_QUALITY {
+            quality = SCREENSHOT_FIT_QUALITY;
+        } else if scale > MIN_SCREENSHOT_SCALE {
+            scale = (scale * 0.75).max(MIN_SCREENSHOT_SCALE);
+        } else {
+            anyhow::bail!(
+                "screenshot doesn't fit in {} bytes even at {}x{}",
+                max_jpeg,
+                width,
+                height
+            );
+        }
+    }
+}
+
+/// The whole BGRA `frame` as RGB, resized to `width` x `height` by nearest
+/// neighbour
+fn screenshot_rgb(frame: &ScreenFrame, width: u32, height: u32) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        let src_y = (y as u64 * frame.height as u64 / height as u64) as usize;
+        let row_start = src_y * frame.stride as usize;
+        for x in 0..width {
+            let src_x = (x as u64 * frame.width as u64 / width as u64) as usize;
+            let offset = row_start + src_x * 4;
+            match frame.data.get(offset..offset + 3) {
+                // BGRA -> RGB
+                Some(bgr) => rgb.extend_from_slice(&[bgr[2], bgr[1], bgr[0]]),
+                None => rgb.extend_from_slice(&[0, 0, 0]),
+            }
+        }
+    }
+    rgb
+}
+
+/// Build the DESKTOP_EVENT describing whether injected input currently
+/// reaches the desktop. `blocked_reason` is None once input works again.
+pub fn input_state_event(blocked_reason: Option<String>) -> protocol::DesktopEvent {
+    match blocked_reason {
+        Some(reason) => protocol::DesktopEvent {
+            event: protocol::desktop_event::INPUT_BLOCKED.to_string(),
+            reason: Some(reason),
+        },
+        None => protocol::DesktopEvent {
+            event: protocol::desktop_event::INPUT_RESTORED.to_string(),
+            reason: None,
+        },
+    }
+}
+
+/// Poll `blocked_reason` (why injected input is being dropped, e.g. a UAC
+/// prompt or the login screen) every second, and pass `send` a DESKTOP_EVENT
+/// whenever input starts or stops being dropped. Returns once `send` reports
+/// the viewer is gone by returning false.
+pub async fn monitor_input_state<S, F>(
+    channel: u16,
+    mut blocked_reason: impl FnMut() -> Option<String>,
+    mut send: S,
+) where
+    S: FnMut(protocol::Message) -> F,
+    F: std::future::Future<Output = bool>,
+{
+    let mut blocked = false;
+    let mut interval = tokio::time::interval(INPUT_STATE_POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let reason = blocked_reason();
+        if reason.is_some() == blocked {
+            continue;
+        }
+        blocked = reason.is_some();
+
+        match &reason {
+            Some(r) => warn!("desktop input blocked on channel {}: {}", channel, r),
+            None => info!("desktop input restored on channel {}", channel),
+        }
+
+        match protocol::desktop_event(channel, &input_state_event(reason)) {
+            Ok(msg) => {
+                if !send(msg).await {
+                    return;
+                }
+            }
+            Err(e) => warn!("failed to build desktop event: {}", e),
+        }
+    }
+}
+
+/// Build the DESKTOP_EVENT telling the viewer its capture was restarted
+pub fn capture_restarted_event(restarts: u32) -> protocol::DesktopEvent {
+    protocol::DesktopEvent {
+        event: protocol::desktop_event::CAPTURE_RESTARTED.to_string(),
+        reason: Some(format!(
+            "screen capture stalled, restarted ({} so far)",
+            restarts
+        )),
+    }
+}
+
+/// Captures re-initialized by the watchdog since the agent started
+pub fn capture_restarts() -> u64 {
+    CAPTURE_RESTARTS.load(Ordering::Relaxed)
+}
+
 /// Parse a DESKTOP_INPUT message payload and dispatch to the input injector.
+///
+/// Returns the DESKTOP_EVENT to report back to the viewer, if the input
+/// warrants one (block/unblock of local input).
 pub fn handle_desktop_input(
     payload: &[u8],
     injector: &mut dyn InputInjector,
-) -> Result<()> {
+) -> Result<Option<protocol::DesktopEvent>> {
     if payload.is_empty() {
-        return Ok(());
+        return Ok(None);
     }
 
     let input_type = payload[0];
@@ -259,12 +866,12 @@ pub fn handle_desktop_input(
                     0 => agent_platform::input::MouseButton::Left,
                     1 => agent_platform::input::MouseButton::Right,
                     2 => agent_platform::input::MouseButton::Middle,
-                    _ => return Ok(()),
+                    _ => return Ok(None),
                 };
                 let action = match data[1] {
                     0 => agent_platform::input::ButtonAction::Press,
                     1 => agent_platform::input::ButtonAction::Release,
-                    _ => return Ok(()),
+                    _ => return Ok(None),
                 };
                 injector.mouse_button(btn, action)?;
             }
@@ -282,7 +889,7 @@ pub fn handle_desktop_input(
                 let action = match data[2] {
                     0 => agent_platform::input::KeyAction::Press,
                     1 => agent_platform::input::KeyAction::Release,
-                    _ => return Ok(()),
+                    _ => return Ok(None),
                 };
                 let mods = if data.len() >= 5 {
                     let m = data[3];
@@ -298,91 +905,1364 @@ pub fn handle_desktop_input(
                 injector.key_press(scancode, action, mods)?;
             }
         }
+        protocol::desktop_input::KEY_SYM => {
+            if data.len() >= 5 {
+                let keysym = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                let action = match data[4] {
+                    0 => agent_platform::input::KeyAction::Press,
+                    1 => agent_platform::input::KeyAction::Release,
+                    _ => return Ok(None),
+                };
+                let mods = data.get(5).map(|&m| agent_platform::input::Modifiers {
+                    shift: m & 0x01 != 0,
+                    ctrl: m & 0x02 != 0,
+                    alt: m & 0x04 != 0,
+                    meta: m & 0x08 != 0,
+                });
+                injector.key_sym(keysym, action, mods.unwrap_or_default())?;
+            }
+        }
         protocol::desktop_input::TYPE_TEXT => {
             let text = std::str::from_utf8(data).unwrap_or("");
             if !text.is_empty() {
                 injector.type_text(text)?;
             }
         }
+        protocol::desktop_input::BLOCK_LOCAL_INPUT => {
+            return Ok(Some(set_local_input_blocked(injector, true)));
+        }
+        protocol::desktop_input::UNBLOCK_LOCAL_INPUT => {
+            return Ok(Some(set_local_input_blocked(injector, false)));
+        }
         other => {
             warn!("unknown desktop input type: 0x{:02x}", other);
         }
     }
 
-    Ok(())
+    Ok(None)
 }
 
-/// Run the desktop capture loop — captures frames at the configured FPS,
-/// encodes changed tiles, and sends them to the server.
-pub async fn run_desktop_session(
-    channel: u16,
-    config: DesktopConfig,
-    mut screen: Box<dyn ScreenCapture>,
-    handle: ConnectionHandle,
-) -> Result<()> {
-    let (width, height) = screen.init().await
-        .context("failed to initialize screen capture")?;
+/// Block or unblock local input and build the event reporting the outcome.
+fn set_local_input_blocked(
+    injector: &mut dyn InputInjector,
+    blocked: bool,
+) -> protocol::DesktopEvent {
+    match injector.set_local_input_blocked(blocked) {
+        Ok(()) => {
+            info!(
+                "local input {}",
+                if blocked { "blocked" } else { "unblocked" }
+            );
+            let event = if blocked {
+                protocol::desktop_event::LOCAL_INPUT_BLOCKED
+            } else {
+                protocol::desktop_event::LOCAL_INPUT_UNBLOCKED
+            };
+            protocol::DesktopEvent {
+                event: event.to_string(),
+                reason: None,
+            }
+        }
+        Err(e) => {
+            warn!(
+                "failed to {} local input: {:#}",
+                if blocked { "block" } else { "unblock" },
+                e
+            );
+            protocol::DesktopEvent {
+                event: protocol::desktop_event::LOCAL_INPUT_BLOCK_FAILED.to_string(),
+                reason: Some(format!("{:#}", e)),
+            }
+        }
+    }
+}
 
-    let mut encoder = TileEncoder::new(width, height, config.quality);
+/// Give the local keyboard and mouse back when a desktop session ends.
+/// Harmless if input was never blocked.
+pub fn release_local_input(injector: &mut dyn InputInjector) {
+    if let Err(e) = injector.set_local_input_blocked(false) {
+        debug!("releasing local input: {:#}", e);
+    }
+}
 
-    let frame_interval = std::time::Duration::from_millis(1000 / config.fps.max(1) as u64);
+/// Text of the on-screen session indicator
+pub const SESSION_INDICATOR_TEXT: &str = "Remote session active";
 
-    // Send initial DESKTOP_RESIZE so the viewer knows dimensions
-    let resize_msg = protocol::Message::session(
-        protocol::DESKTOP_RESIZE,
-        channel,
-        0,
-        {
-            let mut p = Vec::with_capacity(4);
-            use bytes::BufMut;
-            p.put_u16_le(width as u16);
-            p.put_u16_le(height as u16);
-            p
-        },
-    );
-    handle.send_message(&resize_msg).await?;
+/// Shows the session indicator while at least one desktop channel is open.
+pub struct IndicatorState {
+    mode: SessionIndicatorMode,
+    create: fn() -> Result<Box<dyn SessionIndicator>>,
+    indicator: Option<Box<dyn SessionIndicator>>,
+}
 
-    info!(
-        "desktop session started on channel {} ({}x{}, {}fps, quality {})",
-        channel, width, height, config.fps, config.quality
-    );
+impl IndicatorState {
+    pub fn new(
+        mode: SessionIndicatorMode,
+        create: fn() -> Result<Box<dyn SessionIndicator>>,
+    ) -> Self {
+        Self {
+            mode,
+            create,
+            indicator: None,
+        }
+    }
 
-    let mut interval = tokio::time::interval(frame_interval);
+    /// Show the indicator for a desktop channel that is about to open.
+    /// Only fails when the indicator is required and can't be shown.
+    pub fn show(&mut self) -> Result<()> {
+        if self.mode == SessionIndicatorMode::Off || self.indicator.is_some() {
+            return Ok(());
+        }
 
-    loop {
-        interval.tick().await;
+        let shown = (self.create)().and_then(|mut indicator| {
+            indicator.show(SESSION_INDICATOR_TEXT)?;
+            Ok(indicator)
+        });
 
-        let frame = match screen.capture_frame().await {
-            Ok(f) => f,
-            Err(e) => {
-                warn!("screen capture failed: {:#}", e);
-                continue;
+        match shown {
+            Ok(indicator) => {
+                self.indicator = Some(indicator);
+                Ok(())
+            }
+            Err(e) if self.mode == SessionIndicatorMode::Required => {
+                Err(e.context("session indicator is required but could not be shown"))
             }
-        };
-
-        let tiles = match encoder.encode_frame(&frame.data, frame.stride) {
-            Ok(t) => t,
             Err(e) => {
-                warn!("frame encoding failed: {:#}", e);
-                continue;
+                warn!("session indicator unavailable: {:#}", e);
+                Ok(())
             }
-        };
+        }
+    }
 
-        for tile in tiles {
-            let msg = protocol::desktop_frame(
-                channel,
-                tile.x,
-                tile.y,
-                tile.w,
-                tile.h,
-                ENCODING_JPEG,
-                tile.flags,
-                tile.data,
-            );
-            if let Err(e) = handle.send_message(&msg).await {
-                debug!("failed to send desktop frame: {}", e);
-                return Ok(());
-            }
+    /// Hide the indicator once no desktop channels remain.
+    pub fn hide(&mut self) {
+        if let Some(mut indicator) = self.indicator.take() {
+            indicator.hide();
         }
     }
 }
+
+/// Time between captured frames at the configured FPS
+pub fn frame_interval(config: &DesktopConfig) -> std::time::Duration {
+    fps_interval(config.fps)
+}
+
+/// Time between frames at `fps`
+pub fn fps_interval(fps: u16) -> std::time::Duration {
+    std::time::Duration::from_millis(1000 / fps.max(1) as u64)
+}
+
+/// Initialize a capture backend for a new session, returning (width, height).
+/// Done before the session task is spawned so DESKTOP_OPEN can fail fast.
+pub async fn init_capture(
+    screen: &mut dyn ScreenCapture,
+    config: &DesktopConfig,
+) -> Result<(u32, u32)> {
+    // Backends that wait for screen updates never block longer than a frame
+    screen.set_acquire_timeout(frame_interval(config));
+
+    screen
+        .init()
+        .await
+        .context("failed to initialize screen capture")
+}
+
+/// The screen (or window) a capture is of
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CaptureSource {
+    window_title: Option<String>,
+    window_handle: Option<u64>,
+    stitched: bool,
+}
+
+/// Latest frame of a shared capture; `None` until the first one arrives
+pub type FrameReceiver = watch::Receiver<Option<Arc<ScreenFrame>>>;
+
+/// A channel's subscription to a shared capture
+pub struct FrameSubscription {
+    pub frames: FrameReceiver,
+    pub dimensions: (u32, u32),
+    /// Times the watchdog has re-initialized the capture; subscribers send a
+    /// keyframe when it changes
+    pub restarts: watch::Receiver<u32>,
+}
+
+/// Frame rate and `capture_on_change` setting of one channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChannelPacing {
+    fps: u16,
+    on_change: bool,
+}
+
+impl ChannelPacing {
+    fn of(config: &DesktopConfig) -> Self {
+        Self {
+            fps: config.fps,
+            on_change: config.capture_on_change,
+        }
+    }
+
+    /// The shared capture runs at the fastest subscriber's rate, and only
+    /// waits for screen changes when every subscriber asked for that
+    fn combined<'a>(channels: impl IntoIterator<Item = &'a ChannelPacing>) -> Self {
+        channels.into_iter().fold(
+            Self {
+                fps: 1,
+                on_change: true,
+            },
+            |acc, p| Self {
+                fps: acc.fps.max(p.fps),
+                on_change: acc.on_change && p.on_change,
+            },
+        )
+    }
+}
+
+struct SharedCapture {
+    frames: FrameReceiver,
+    restarts: watch::Receiver<u32>,
+    /// Pacing each subscribed channel asked for
+    rates: Arc<Mutex<HashMap<u16, ChannelPacing>>>,
+    dimensions: (u32, u32),
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for SharedCapture {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Screen captures shared between desktop channels, one per capture source.
+///
+/// A second viewer of the same screen subscribes to the running capture
+/// instead of opening another one (a second DXGI duplication, say). Each
+/// channel still encodes with its own `TileEncoder`, quality and frame rate.
+#[derive(Default)]
+pub struct CapturePool {
+    captures: HashMap<CaptureSource, SharedCapture>,
+    channels: HashMap<u16, CaptureSource>,
+}
+
+impl CapturePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe `channel` to the capture for `config`'s source, creating
+    /// and initializing one with `create` if none is running.
+    pub async fn subscribe<F>(
+        &mut self,
+        channel: u16,
+        config: &DesktopConfig,
+        create: F,
+    ) -> Result<FrameSubscription>
+    where
+        F: FnOnce(&DesktopConfig) -> Result<Box<dyn ScreenCapture>>,
+    {
+        self.unsubscribe(channel);
+        let source = config.capture_source();
+
+        // A capture whose task died can't be joined; start a fresh one
+        if self
+            .captures
+            .get(&source)
+            .is_some_and(|c| c.task.is_finished())
+        {
+            self.captures.remove(&source);
+        }
+
+        if let Some(capture) = self.captures.get(&source) {
+            capture
+                .rates
+                .lock()
+                .unwrap()
+                .insert(channel, ChannelPacing::of(config));
+            self.channels.insert(channel, source);
+            debug!("channel {} joined a running screen capture", channel);
+
+            let mut frames = capture.frames.clone();
+            // Hand the current frame over right away rather than waiting
+            // for the screen to change
+            frames.mark_changed();
+            // The capture may have changed size since it started
+            let dimensions = frames
+                .borrow()
+                .as_ref()
+                .map_or(capture.dimensions, |frame| (frame.width, frame.height));
+            return Ok(FrameSubscription {
+                frames,
+                dimensions,
+                restarts: capture.restarts.clone(),
+            });
+        }
+
+        let mut screen = create(config).context("failed to create screen capture")?;
+        let dimensions = init_capture(screen.as_mut(), config).await?;
+
+        let rates = Arc::new(Mutex::new(HashMap::from([(
+            channel,
+            ChannelPacing::of(config),
+        )])));
+        let (tx, frames) = watch::channel(None);
+        let (restarts_tx, restarts) = watch::channel(0);
+        let task = tokio::spawn(run_shared_capture(
+            screen,
+            dimensions,
+            tx,
+            restarts_tx,
+            rates.clone(),
+        ));
+
+        self.captures.insert(
+            source.clone(),
+            SharedCapture {
+                frames: frames.clone(),
+                restarts: restarts.clone(),
+                rates,
+                dimensions,
+                task,
+            },
+        );
+        self.channels.insert(channel, source);
+
+        Ok(FrameSubscription {
+            frames,
+            dimensions,
+            restarts,
+        })
+    }
+
+    /// Drop `channel`'s subscription, stopping the capture when it was the last
+    pub fn unsubscribe(&mut self, channel: u16) {
+        let Some(source) = self.channels.remove(&channel) else {
+            return;
+        };
+        let Some(capture) = self.captures.get(&source) else {
+            return;
+        };
+
+        let unused = {
+            let mut rates = capture.rates.lock().unwrap();
+            rates.remove(&channel);
+            rates.is_empty()
+        };
+        if unused {
+            self.captures.remove(&source);
+            debug!("screen capture stopped, no channels left");
+        }
+    }
+
+    /// Number of captures currently running
+    pub fn len(&self) -> usize {
+        self.captures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captures.is_empty()
+    }
+}
+
+/// Capture frames at the pacing the subscribers combine to (see
+/// `ChannelPacing::combined`) and publish each one to every subscriber. Runs
+/// until aborted by the pool, or until a watchdog restart comes back with
+/// different dimensions.
+async fn run_shared_capture(
+    mut screen: Box<dyn ScreenCapture>,
+    mut dimensions: (u32, u32),
+    frames: watch::Sender<Option<Arc<ScreenFrame>>>,
+    restarts: watch::Sender<u32>,
+    rates: Arc<Mutex<HashMap<u16, ChannelPacing>>>,
+) {
+    let mut pacing = ChannelPacing {
+        fps: 0,
+        on_change: false,
+    };
+    let mut interval = tokio::time::interval(fps_interval(1));
+    let mut last_frame = tokio::time::Instant::now();
+    let mut last_capture = tokio::time::Instant::now();
+
+    loop {
+        let wanted = ChannelPacing::combined(rates.lock().unwrap().values());
+        if wanted != pacing {
+            pacing = wanted;
+            let ChannelPacing { fps, on_change } = pacing;
+            if on_change {
+                screen.set_acquire_timeout(fps_interval(fps).max(ON_CHANGE_ACQUIRE_TIMEOUT));
+            } else {
+                screen.set_acquire_timeout(fps_interval(fps));
+            }
+            interval = tokio::time::interval(fps_interval(fps));
+            // A capture that waited for a screen update shouldn't trigger a burst
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        }
+        let fps = pacing.fps;
+        if pacing.on_change {
+            // The capture waits for the screen to change; this only keeps
+            // frames from coming faster than the fps cap, and captures right
+            // away after a long wait
+            tokio::time::sleep_until(last_capture + fps_interval(fps)).await;
+            last_capture = tokio::time::Instant::now();
+        } else {
+            interval.tick().await;
+        }
+
+        let deadline = last_frame + CAPTURE_WATCHDOG_TIMEOUT;
+        match tokio::time::timeout_at(deadline, screen.next_frame()).await {
+            Ok(Ok(frame)) => {
+                // An unchanged screen still shows the capture is alive
+                if let Some(frame) = frame {
+                    frames.send_replace(Some(Arc::new(frame)));
+                }
+                last_frame = tokio::time::Instant::now();
+                continue;
+            }
+            Ok(Err(e)) => warn!("screen capture failed: {:#}", e),
+            Err(_) => {}
+        }
+
+        if last_frame.elapsed() < CAPTURE_WATCHDOG_TIMEOUT {
+            continue;
+        }
+        warn!(
+            "no frame captured in {}s, re-initializing screen capture",
+            CAPTURE_WATCHDOG_TIMEOUT.as_secs()
+        );
+        CAPTURE_RESTARTS.fetch_add(1, Ordering::Relaxed);
+        restarts.send_modify(|count| *count += 1);
+        last_frame = tokio::time::Instant::now();
+
+        match screen.init().await {
+            // Sessions see the new size on the next frame and resize
+            Ok(dims) if dims != dimensions => {
+                warn!(
+                    "screen capture came back at {}x{} (was {}x{})",
+                    dims.0, dims.1, dimensions.0, dimensions.1
+                );
+                dimensions = dims;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("screen capture re-initialization failed: {:#}", e),
+        }
+    }
+}
+
+/// One step of the "auto" encoding ladder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoProfile {
+    pub quality: u8,
+    pub fps: u16,
+    pub subsampling: Subsampling,
+}
+
+/// Profiles from cheapest to best; fps is further capped by the viewer's
+const AUTO_PROFILES: [AutoProfile; 5] = [
+    AutoProfile {
+        quality: 25,
+        fps: 5,
+        subsampling: Subsampling::S420,
+    },
+    AutoProfile {
+        quality: 40,
+        fps: 8,
+        subsampling: Subsampling::S420,
+    },
+    AutoProfile {
+        quality: 55,
+        fps: 12,
+        subsampling: Subsampling::S420,
+    },
+    AutoProfile {
+        quality: 70,
+        fps: 20,
+        subsampling: Subsampling::S422,
+    },
+    AutoProfile {
+        quality: 85,
+        fps: 30,
+        subsampling: Subsampling::S444,
+    },
+];
+
+/// How often the "auto" controller re-estimates bandwidth
+pub const AUTO_ADJUST_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// Uncongested windows in a row before stepping up a profile
+const AUTO_CALM_WINDOWS: u32 = 3;
+
+/// Self-tuning controller for the "auto" encoding.
+///
+/// Bandwidth is estimated from how fast the connection drains its send
+/// queue; the queueing delay is what is still in flight divided by that.
+/// The controller steps down the profile ladder when the delay exceeds the
+/// latency budget (or sending outruns the bandwidth cap), and probes one
+/// step up after the queue has stayed nearly empty for a few windows.
+pub struct AutoQuality {
+    level: usize,
+    max_fps: u16,
+    target_latency: std::time::Duration,
+    max_bytes_per_sec: Option<f64>,
+    /// Smoothed drain rate, bytes per second
+    bandwidth: f64,
+    prev_in_flight: usize,
+    calm_windows: u32,
+}
+
+impl AutoQuality {
+    pub fn new(config: &DesktopConfig) -> Self {
+        Self {
+            level: AUTO_PROFILES.len() / 2,
+            max_fps: config.fps.max(1),
+            target_latency: std::time::Duration::from_millis(config.target_latency_ms as u64),
+            max_bytes_per_sec: config
+                .max_bandwidth_kbps
+                .map(|kbps| kbps as f64 * 1000.0 / 8.0),
+            bandwidth: 0.0,
+            prev_in_flight: 0,
+            calm_windows: 0,
+        }
+    }
+
+    pub fn profile(&self) -> AutoProfile {
+        let profile = AUTO_PROFILES[self.level];
+        AutoProfile {
+            fps: profile.fps.min(self.max_fps),
+            ..profile
+        }
+    }
+
+    /// Estimated available bandwidth in bytes per second
+    pub fn bandwidth(&self) -> f64 {
+        self.bandwidth
+    }
+
+    /// Feed one measurement window: `sent` bytes queued during `elapsed`,
+    /// with `in_flight` bytes still unsent at its end. Returns the new
+    /// profile when it changed.
+    pub fn update(
+        &mut self,
+        elapsed: std::time::Duration,
+        sent: usize,
+        in_flight: usize,
+    ) -> Option<AutoProfile> {
+        let secs = elapsed.as_secs_f64().max(0.001);
+        let drained = (self.prev_in_flight + sent).saturating_sub(in_flight);
+        self.prev_in_flight = in_flight;
+
+        let throughput = drained as f64 / secs;
+        self.bandwidth = if self.bandwidth == 0.0 {
+            throughput
+        } else {
+            0.7 * self.bandwidth + 0.3 * throughput
+        };
+
+        let queue_delay = if in_flight == 0 {
+            0.0
+        } else if self.bandwidth > 0.0 {
+            in_flight as f64 / self.bandwidth
+        } else {
+            f64::INFINITY
+        };
+        let target = self.target_latency.as_secs_f64();
+        let over_cap = self
+            .max_bytes_per_sec
+            .is_some_and(|cap| sent as f64 / secs > cap);
+
+        let before = self.level;
+        if queue_delay > target || over_cap {
+            self.calm_windows = 0;
+            self.level = self.level.saturating_sub(1);
+        } else if queue_delay < target / 4.0 {
+            self.calm_windows += 1;
+            if self.calm_windows >= AUTO_CALM_WINDOWS && self.level + 1 < AUTO_PROFILES.len() {
+                self.calm_windows = 0;
+                self.level += 1;
+            }
+        } else {
+            self.calm_windows = 0;
+        }
+
+        (self.level != before).then(|| self.profile())
+    }
+}
+
+/// Tile encoder for a desktop session's `width` x `height` frames, set up
+/// from `config` and the current "auto" profile
+pub fn session_encoder(
+    config: &DesktopConfig,
+    auto: Option<&AutoQuality>,
+    width: u32,
+    height: u32,
+) -> TileEncoder {
+    let mut encoder = TileEncoder::new(width, height, config.quality);
+    encoder.set_encoding(config.encoding_byte());
+    encoder.set_restart_rows(config.jpeg_restart_rows);
+    if config.raw_tiles {
+        encoder.set_raw_tile_max_pixels(RAW_TILE_MAX_PIXELS);
+    }
+    if let Some(auto) = auto {
+        let profile = auto.profile();
+        encoder.set_quality(profile.quality);
+        encoder.set_subsampling(profile.subsampling);
+    }
+    encoder
+}
+
+/// DESKTOP_RESIZE telling the viewer the size of the frames that follow
+pub fn resize_message(channel: u16, width: u32, height: u32) -> protocol::Message {
+    let mut payload = Vec::with_capacity(4);
+    payload.extend_from_slice(&(width as u16).to_le_bytes());
+    payload.extend_from_slice(&(height as u16).to_le_bytes());
+    protocol::Message::session(protocol::DESKTOP_RESIZE, channel, 0, payload)
+}
+
+/// Run a channel's desktop loop — takes the latest frame of its shared
+/// capture at the configured FPS, encodes changed tiles, and sends them to
+/// the server. Ends when the capture stops.
+pub async fn run_desktop_session(
+    channel: u16,
+    config: DesktopConfig,
+    subscription: FrameSubscription,
+    handle: ConnectionHandle,
+) -> Result<()> {
+    let mut frame_interval = frame_interval(&config);
+    let (width, height) = subscription.dimensions;
+    let mut frames = subscription.frames;
+    let mut restarts = subscription.restarts;
+
+    let (mut width, mut height) = (width, height);
+
+    // "auto" encoding starts mid-ladder and retunes as throughput is measured
+    let mut auto = config.is_auto().then(|| AutoQuality::new(&config));
+    if let Some(auto) = &auto {
+        frame_interval = fps_interval(auto.profile().fps);
+    }
+    let mut encoder = session_encoder(&config, auto.as_ref(), width, height);
+    let mut window_start = tokio::time::Instant::now();
+    let mut window_sent = 0usize;
+
+    // Send initial DESKTOP_RESIZE so the viewer knows dimensions
+    handle
+        .send_message(&resize_message(channel, width, height))
+        .await?;
+
+    info!(
+        "desktop session started on channel {} ({}x{}, {}fps, quality {}, {})",
+        channel, width, height, config.fps, config.quality, config.encoding
+    );
+
+    let mut interval = tokio::time::interval(frame_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        // Wait for a frame newer than the last one this channel encoded;
+        // while a reduced keyframe is being refined, the last frame is
+        // encoded again so a static screen still reaches full quality
+        if !encoder.is_refining() && frames.changed().await.is_err() {
+            debug!("screen capture for channel {} stopped", channel);
+            return Ok(());
+        }
+        let Some(frame) = frames.borrow_and_update().clone() else {
+            continue;
+        };
+
+        // The capture came back at another size: start over with a new
+        // encoder, whose first frame is a keyframe
+        if (frame.width, frame.height) != (width, height) {
+            info!(
+                "desktop on channel {} resized from {}x{} to {}x{}",
+                channel, width, height, frame.width, frame.height
+            );
+            (width, height) = (frame.width, frame.height);
+            encoder = session_encoder(&config, auto.as_ref(), width, height);
+            handle
+                .send_message(&resize_message(channel, width, height))
+                .await?;
+        }
+
+        // A restarted capture gets a full repaint
+        if restarts.has_changed().unwrap_or(false) {
+            let count = *restarts.borrow_and_update();
+            encoder.request_keyframe();
+            if let Ok(msg) = protocol::desktop_event(channel, &capture_restarted_event(count)) {
+                let _ = handle.send_message(&msg).await;
+            }
+        }
+
+        if let Some(auto) = auto.as_mut() {
+            let elapsed = window_start.elapsed();
+            if elapsed >= AUTO_ADJUST_INTERVAL {
+                if let Some(profile) = auto.update(elapsed, window_sent, handle.in_flight_bytes()) {
+                    debug!(
+                        "auto encoding on channel {}: ~{} kbit/s, quality {}, {}fps, {:?}",
+                        channel,
+                        (auto.bandwidth() * 8.0 / 1000.0) as u64,
+                        profile.quality,
+                        profile.fps,
+                        profile.subsampling
+                    );
+                    encoder.set_quality(profile.quality);
+                    encoder.set_subsampling(profile.subsampling);
+                    interval = tokio::time::interval(fps_interval(profile.fps));
+                    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                }
+                window_start = tokio::time::Instant::now();
+                window_sent = 0;
+            }
+        }
+
+        // Network stalled: drop this frame instead of piling more encoded
+        // tiles onto the send queue
+        let in_flight = handle.in_flight_bytes();
+        if in_flight > MAX_IN_FLIGHT_BYTES {
+            debug!(
+                "skipping frame on channel {}: {} bytes in flight",
+                channel, in_flight
+            );
+            continue;
+        }
+
+        let tiles = match encoder.encode_frame(&frame.data, frame.stride) {
+            Ok(t) => t,
+            Err(e) => {
+                warn!("frame encoding failed: {:#}", e);
+                continue;
+            }
+        };
+
+        for tile in tiles {
+            window_sent += tile.data.len();
+            let msg = protocol::desktop_frame(
+                channel,
+                tile.x,
+                tile.y,
+                tile.w,
+                tile.h,
+                tile.encoding,
+                tile.flags,
+                tile.data,
+            );
+            if let Err(e) = handle.send_bulk(&msg).await {
+                debug!("failed to send desktop frame: {}", e);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_clamped() {
+        let req: protocol::DesktopOpenRequest =
+            serde_json::from_str(r#"{"fps": 1000, "quality": 100}"#).unwrap();
+        let config = DesktopConfig::from_request(req, 30, false);
+        assert_eq!(config.fps, 30);
+        assert_eq!(config.quality, MAX_QUALITY);
+
+        let req: protocol::DesktopOpenRequest =
+            serde_json::from_str(r#"{"fps": 0, "quality": 0}"#).unwrap();
+        let config = DesktopConfig::from_request(req, 30, false);
+        assert_eq!(config.fps, 1);
+        assert_eq!(config.quality, MIN_QUALITY);
+
+        let req: protocol::DesktopOpenRequest = serde_json::from_str("{}").unwrap();
+        let config = DesktopConfig::from_request(req, 30, false);
+        assert_eq!((config.fps, config.quality), (15, 70));
+    }
+
+    struct FakeScreen;
+
+    #[async_trait::async_trait]
+    impl ScreenCapture for FakeScreen {
+        async fn init(&mut self) -> Result<(u32, u32)> {
+            Ok((64, 64))
+        }
+
+        async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+            Ok(ScreenFrame {
+                width: 64,
+                height: 64,
+                data: vec![0; 64 * 64 * 4],
+                stride: 64 * 4,
+            })
+        }
+
+        fn dimensions(&self) -> (u32, u32) {
+            (64, 64)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_pool_shares_source() {
+        let mut pool = CapturePool::new();
+        let mut created = 0;
+        let mut create = |_: &DesktopConfig| -> Result<Box<dyn ScreenCapture>> {
+            created += 1;
+            Ok(Box::new(FakeScreen))
+        };
+
+        let screen = DesktopConfig::default();
+        let window = DesktopConfig {
+            window_handle: Some(42),
+            ..Default::default()
+        };
+        let mut first = pool.subscribe(1, &screen, &mut create).await.unwrap();
+        pool.subscribe(
+            2,
+            &DesktopConfig {
+                quality: 30,
+                ..screen.clone()
+            },
+            &mut create,
+        )
+        .await
+        .unwrap();
+        pool.subscribe(3, &window, &mut create).await.unwrap();
+        assert_eq!(created, 2);
+        assert_eq!(pool.len(), 2);
+
+        // Both screen channels see the same frames
+        first.frames.changed().await.unwrap();
+        assert_eq!(first.dimensions, (64, 64));
+
+        pool.unsubscribe(1);
+        assert_eq!(pool.len(), 2);
+        pool.unsubscribe(2);
+        pool.unsubscribe(3);
+        assert!(pool.is_empty());
+    }
+
+    /// Hangs in `capture_frame` until it has been initialized twice
+    struct StallingScreen {
+        inits: u32,
+    }
+
+    #[async_trait::async_trait]
+    impl ScreenCapture for StallingScreen {
+        async fn init(&mut self) -> Result<(u32, u32)> {
+            self.inits += 1;
+            Ok((64, 64))
+        }
+
+        async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+            if self.inits < 2 {
+                std::future::pending::<()>().await;
+            }
+            FakeScreen.capture_frame().await
+        }
+
+        fn dimensions(&self) -> (u32, u32) {
+            (64, 64)
+        }
+    }
+
+    /// Never has a new frame, like DXGI on a static screen
+    struct StaticScreen {
+        inits: Arc<std::sync::atomic::AtomicU32>,
+    }
+
+    #[async_trait::async_trait]
+    impl ScreenCapture for StaticScreen {
+        async fn init(&mut self) -> Result<(u32, u32)> {
+            self.inits.fetch_add(1, Ordering::Relaxed);
+            Ok((64, 64))
+        }
+
+        async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+            FakeScreen.capture_frame().await
+        }
+
+        async fn next_frame(&mut self) -> Result<Option<ScreenFrame>> {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            Ok(None)
+        }
+
+        fn dimensions(&self) -> (u32, u32) {
+            (64, 64)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_input_state_sent_on_change_only() {
+        // Blocked on the 2nd and 3rd poll, then restored
+        let mut polls = 0;
+        let probe = move || {
+            polls += 1;
+            (2..=3).contains(&polls).then(|| "UAC prompt".to_string())
+        };
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = sent.clone();
+        let monitor = tokio::spawn(monitor_input_state(
+            7,
+            probe,
+            move |msg: protocol::Message| {
+                let sink = sink.clone();
+                async move {
+                    let event: protocol::DesktopEvent = msg.parse_json().unwrap();
+                    sink.lock()
+                        .unwrap()
+                        .push((msg.header.channel, event.event, event.reason));
+                    true
+                }
+            },
+        ));
+
+        tokio::time::sleep(INPUT_STATE_POLL_INTERVAL * 5).await;
+        monitor.abort();
+        let sent = sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            [
+                (
+                    7,
+                    "input_blocked".to_string(),
+                    Some("UAC prompt".to_string())
+                ),
+                (7, "input_restored".to_string(), None),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_ignores_static_screen() {
+        let inits = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let screen = StaticScreen {
+            inits: inits.clone(),
+        };
+        let mut pool = CapturePool::new();
+        let sub = pool
+            .subscribe(
+                1,
+                &DesktopConfig::default(),
+                |_: &DesktopConfig| -> Result<Box<dyn ScreenCapture>> { Ok(Box::new(screen)) },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::sleep(CAPTURE_WATCHDOG_TIMEOUT * 2).await;
+        assert_eq!(inits.load(Ordering::Relaxed), 1);
+        assert_eq!(*sub.restarts.borrow(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_restarts_stalled_capture() {
+        let mut pool = CapturePool::new();
+        let create = |_: &DesktopConfig| -> Result<Box<dyn ScreenCapture>> {
+            Ok(Box::new(StallingScreen { inits: 0 }))
+        };
+        let mut sub = pool
+            .subscribe(1, &DesktopConfig::default(), create)
+            .await
+            .unwrap();
+
+        // The first frame only arrives once the watchdog has re-initialized
+        sub.frames.changed().await.unwrap();
+        assert!(sub.frames.borrow().is_some());
+        assert_eq!(*sub.restarts.borrow(), 1);
+    }
+
+    /// Stalls until re-initialized, then comes back at 128x64
+    struct ResizingScreen {
+        inits: u32,
+    }
+
+    #[async_trait::async_trait]
+    impl ScreenCapture for ResizingScreen {
+        async fn init(&mut self) -> Result<(u32, u32)> {
+            self.inits += 1;
+            Ok(self.dimensions())
+        }
+
+        async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+            if self.inits < 2 {
+                std::future::pending::<()>().await;
+            }
+            Ok(ScreenFrame {
+                width: 128,
+                height: 64,
+                data: vec![0; 128 * 64 * 4],
+                stride: 128 * 4,
+            })
+        }
+
+        fn dimensions(&self) -> (u32, u32) {
+            if self.inits < 2 {
+                (64, 64)
+            } else {
+                (128, 64)
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_capture_keeps_running_after_resize() {
+        let mut pool = CapturePool::new();
+        let create = |_: &DesktopConfig| -> Result<Box<dyn ScreenCapture>> {
+            Ok(Box::new(ResizingScreen { inits: 0 }))
+        };
+        let mut sub = pool
+            .subscribe(1, &DesktopConfig::default(), create)
+            .await
+            .unwrap();
+        assert_eq!(sub.dimensions, (64, 64));
+
+        // Frames at the new size keep coming after the watchdog re-init
+        for _ in 0..2 {
+            sub.frames.changed().await.unwrap();
+            let frame = sub.frames.borrow_and_update().clone().unwrap();
+            assert_eq!((frame.width, frame.height), (128, 64));
+        }
+
+        // Channels joining later start at the new size
+        let unused = |_: &DesktopConfig| -> Result<Box<dyn ScreenCapture>> { unreachable!() };
+        let joined = pool
+            .subscribe(2, &DesktopConfig::default(), unused)
+            .await
+            .unwrap();
+        assert_eq!(joined.dimensions, (128, 64));
+    }
+
+    /// Records how the capture is driven
+    struct CountingScreen {
+        captures: Arc<std::sync::atomic::AtomicU32>,
+        acquire_timeout: Arc<Mutex<Option<std::time::Duration>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ScreenCapture for CountingScreen {
+        async fn init(&mut self) -> Result<(u32, u32)> {
+            Ok((64, 64))
+        }
+
+        async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+            self.captures.fetch_add(1, Ordering::Relaxed);
+            FakeScreen.capture_frame().await
+        }
+
+        fn dimensions(&self) -> (u32, u32) {
+            (64, 64)
+        }
+
+        fn set_acquire_timeout(&mut self, timeout: std::time::Duration) {
+            *self.acquire_timeout.lock().unwrap() = Some(timeout);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_capture_on_change_capped_at_fps() {
+        let captures = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let acquire_timeout = Arc::new(Mutex::new(None));
+        let screen = CountingScreen {
+            captures: captures.clone(),
+            acquire_timeout: acquire_timeout.clone(),
+        };
+        let mut pool = CapturePool::new();
+        let config = DesktopConfig {
+            fps: 5,
+            capture_on_change: true,
+            ..Default::default()
+        };
+        let _sub = pool
+            .subscribe(
+                1,
+                &config,
+                |_: &DesktopConfig| -> Result<Box<dyn ScreenCapture>> { Ok(Box::new(screen)) },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let count = captures.load(Ordering::Relaxed);
+        assert!(
+            (5..=6).contains(&count),
+            "{} captures in 1.1s at 5fps",
+            count
+        );
+        assert_eq!(
+            *acquire_timeout.lock().unwrap(),
+            Some(ON_CHANGE_ACQUIRE_TIMEOUT)
+        );
+
+        // A polling viewer joining switches the shared capture to polling
+        let polling = DesktopConfig {
+            fps: 5,
+            ..Default::default()
+        };
+        let _second = pool
+            .subscribe(
+                2,
+                &polling,
+                |_: &DesktopConfig| -> Result<Box<dyn ScreenCapture>> { unreachable!() },
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert_eq!(*acquire_timeout.lock().unwrap(), Some(fps_interval(5)));
+
+        // ... and back once it leaves
+        pool.unsubscribe(2);
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert_eq!(
+            *acquire_timeout.lock().unwrap(),
+            Some(ON_CHANGE_ACQUIRE_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn test_channel_pacing_combined() {
+        let on_change = ChannelPacing {
+            fps: 5,
+            on_change: true,
+        };
+        let polling = ChannelPacing {
+            fps: 15,
+            on_change: false,
+        };
+        assert_eq!(ChannelPacing::combined([&on_change]), on_change);
+        assert_eq!(
+            ChannelPacing::combined([&on_change, &polling]),
+            ChannelPacing {
+                fps: 15,
+                on_change: false
+            }
+        );
+        assert_eq!(
+            ChannelPacing::combined([]),
+            ChannelPacing {
+                fps: 1,
+                on_change: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_reduced_color_pixels() {
+        assert_eq!(rgb565(0xFF, 0xFF, 0xFF), 0xFFFF);
+        assert_eq!(rgb565(0xFF, 0, 0), 0xF800);
+        assert_eq!(rgb565(0, 0xFF, 0), 0x07E0);
+        assert_eq!(rgb332(0xFF, 0xFF, 0xFF), 0xFF);
+        assert_eq!(rgb332(0, 0xFF, 0), 0x1C);
+        assert_eq!(rgb332(0, 0, 0xFF), 0x03);
+    }
+
+    #[test]
+    fn test_reduced_tile_roundtrip() {
+        use std::io::Read;
+
+        // 2x1 BGRA frame: red, blue
+        let frame = [0, 0, 0xFF, 0xFF, 0xFF, 0, 0, 0xFF];
+        let mut encoder = TileEncoder::new(2, 1, 70);
+        encoder.set_encoding(ENCODING_RGB565);
+        let tiles = encoder.encode_frame(&frame, 8).unwrap();
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].encoding, ENCODING_RGB565);
+
+        let mut pixels = Vec::new();
+        flate2::read::ZlibDecoder::new(&tiles[0].data[..])
+            .read_to_end(&mut pixels)
+            .unwrap();
+        assert_eq!(pixels, [0x00, 0xF8, 0x1F, 0x00]);
+    }
+
+    #[test]
+    fn test_raw_tiles() {
+        let req: protocol::DesktopOpenRequest =
+            serde_json::from_str(r#"{"encoding": "raw"}"#).unwrap();
+        assert_eq!(
+            DesktopConfig::from_request(req.clone(), 30, false).encoding_byte(),
+            ENCODING_JPEG
+        );
+        assert_eq!(
+            DesktopConfig::from_request(req, 30, true).encoding_byte(),
+            ENCODING_RAW
+        );
+
+        // 2x1 BGRA frame: red, blue
+        let frame = [0, 0, 0xFF, 0xFF, 0xFF, 0, 0, 0xFF];
+        let mut encoder = TileEncoder::new(2, 1, 70);
+        encoder.set_encoding(ENCODING_RAW);
+        let tiles = encoder.encode_frame(&frame, 8).unwrap();
+        assert_eq!(tiles[0].encoding, ENCODING_RAW);
+        assert_eq!(tiles[0].data, [0xFF, 0, 0, 0, 0, 0xFF]);
+
+        // A JPEG session sends only the tiles under the threshold RAW
+        let (width, height) = (TILE_SIZE + 2, 8);
+        let frame = vec![0x80; (width * height * 4) as usize];
+        let mut encoder = TileEncoder::new(width, height, 70);
+        encoder.set_raw_tile_max_pixels(RAW_TILE_MAX_PIXELS);
+        let tiles = encoder.encode_frame(&frame, width * 4).unwrap();
+        assert_eq!(tiles.len(), 2);
+        assert_eq!(tiles[0].encoding, ENCODING_JPEG);
+        assert_eq!(tiles[1].encoding, ENCODING_RAW);
+        assert_eq!(tiles[1].data.len(), 2 * 8 * 3);
+    }
+
+    #[test]
+    fn test_keyframe_refined_over_following_frames() {
+        // 8x4 tiles, unchanged after the keyframe
+        let (width, height) = (TILE_SIZE * 8, TILE_SIZE * 4);
+        let frame = vec![0x80; (width * height * 4) as usize];
+        let mut encoder = TileEncoder::new(width, height, 80);
+
+        let keyframe = encoder.encode_frame(&frame, width * 4).unwrap();
+        assert_eq!(keyframe.len(), 32);
+        assert!(keyframe.iter().all(|t| t.flags == FLAG_KEYFRAME));
+        assert!(encoder.sent_quality.iter().all(|&q| q == 20));
+
+        // 1/8 of the tiles, then doubling, until every tile is at full quality
+        let refined: Vec<usize> = (0..4)
+            .map(|_| {
+                assert!(encoder.is_refining());
+                encoder.encode_frame(&frame, width * 4).unwrap().len()
+            })
+            .collect();
+        assert_eq!(refined, [4, 8, 16, 4]);
+        assert!(!encoder.is_refining());
+        assert!(encoder.encode_frame(&frame, width * 4).unwrap().is_empty());
+        assert!(encoder.sent_quality.iter().all(|&q| q == 80));
+
+        // Already at the lowest quality: nothing to refine
+        let mut encoder = TileEncoder::new(width, height, MIN_QUALITY);
+        encoder.encode_frame(&frame, width * 4).unwrap();
+        assert!(!encoder.is_refining());
+        assert!(encoder.encode_frame(&frame, width * 4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unchanged_tiles_skipped_by_hash() {
+        // 2x1 tiles with a padded stride
+        let (width, height) = (TILE_SIZE * 2, TILE_SIZE);
+        let stride = width * 4 + 16;
+        let frame_a = vec![0x10; (stride * height) as usize];
+        let mut frame_b = frame_a.clone();
+        frame_b[(TILE_SIZE * 4) as usize] = 0x20; // first pixel of the second tile
+        let mut encoder = TileEncoder::new(width, height, MIN_QUALITY);
+
+        assert_eq!(encoder.encode_frame(&frame_a, stride).unwrap().len(), 2);
+        assert!(encoder.encode_frame(&frame_a, stride).unwrap().is_empty());
+
+        // Padding isn't part of a tile
+        let mut padded = frame_a.clone();
+        padded[(width * 4) as usize] = 0xFF;
+        assert!(encoder.encode_frame(&padded, stride).unwrap().is_empty());
+
+        // Changed, then reverted: both differ from what the viewer shows
+        let changed = encoder.encode_frame(&frame_b, stride).unwrap();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].x, TILE_SIZE as u16);
+        assert_eq!(encoder.encode_frame(&frame_a, stride).unwrap().len(), 1);
+    }
+
+    /// A 256x256 screen of noise, which JPEG can't compress well
+    struct NoisyScreen;
+
+    #[async_trait::async_trait]
+    impl ScreenCapture for NoisyScreen {
+        async fn init(&mut self) -> Result<(u32, u32)> {
+            Ok((256, 256))
+        }
+
+        async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+            let mut seed = 0x2545_f491u32;
+            let data = (0..256 * 256 * 4)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    seed as u8
+                })
+                .collect();
+            Ok(ScreenFrame {
+                width: 256,
+                height: 256,
+                data,
+                stride: 256 * 4,
+            })
+        }
+
+        fn dimensions(&self) -> (u32, u32) {
+            (256, 256)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_fitted_to_limit() {
+        let full = capture_screenshot(&mut NoisyScreen, 90, 1.0, usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!((full.width, full.height), (256, 256));
+
+        let limit = full.jpeg.len() / 8;
+        let fitted = capture_screenshot(&mut NoisyScreen, 90, 1.0, limit)
+            .await
+            .unwrap();
+        assert!(fitted.jpeg.len() <= limit);
+        assert!(fitted.width < 256);
+
+        assert!(capture_screenshot(&mut NoisyScreen, 90, 1.0, 16)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_screenshot_rgb_scales_down() {
+        // 2x2 BGRA frame with a padded stride: red, green / blue, white
+        let frame = ScreenFrame {
+            width: 2,
+            height: 2,
+            stride: 12,
+            data: vec![
+                0, 0, 0xFF, 0xFF, 0, 0xFF, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF,
+                0xFF, 0, 0, 0, 0,
+            ],
+        };
+        assert_eq!(
+            screenshot_rgb(&frame, 2, 2),
+            [0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(screenshot_rgb(&frame, 1, 1), [0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn test_auto_quality_follows_bandwidth() {
+        let config = DesktopConfig {
+            fps: 15,
+            ..Default::default()
+        };
+        let mut auto = AutoQuality::new(&config);
+        let start = auto.profile();
+        let second = std::time::Duration::from_secs(1);
+
+        // 1 MB/s drains fully: calm windows step up, with fps capped at 15
+        assert_eq!(auto.update(second, 1_000_000, 0), None);
+        assert_eq!(auto.update(second, 1_000_000, 0), None);
+        let up = auto.update(second, 1_000_000, 0).unwrap();
+        assert!(up.quality > start.quality);
+        assert!(up.fps <= 15);
+
+        // Queue backs up to a few seconds' worth: step back down
+        let down = auto.update(second, 1_000_000, 3_000_000).unwrap();
+        assert_eq!(down, start);
+    }
+
+    #[test]
+    fn test_auto_quality_respects_bandwidth_cap() {
+        let req: protocol::DesktopOpenRequest =
+            serde_json::from_str(r#"{"encoding": "auto", "max_bandwidth_kbps": 800}"#).unwrap();
+        let config = DesktopConfig::from_request(req, 30, false);
+        assert!(config.is_auto());
+        assert_eq!(config.target_latency_ms, DEFAULT_TARGET_LATENCY_MS);
+
+        // 200 KB/s is over the 100 KB/s cap even with an empty queue
+        let mut auto = AutoQuality::new(&config);
+        let start = auto.profile();
+        let lower = auto
+            .update(std::time::Duration::from_secs(1), 200_000, 0)
+            .unwrap();
+        assert!(lower.quality < start.quality);
+    }
+}
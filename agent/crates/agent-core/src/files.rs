@@ -1,32 +1,107 @@
-use std::collections::HashMap;
 use anyhow::Result;
-use tracing::{error, info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
 
-use agent_platform::filesystem::FileSystem;
 use crate::connection::ConnectionHandle;
 use crate::protocol::{self, Message};
+use agent_platform::filesystem::{FileEntry, FileSystem};
 
-/// Chunk size for file downloads (64 KB)
+/// Chunk size for file downloads (64 KB), less on connections whose
+/// messages can't carry that much
 const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
 
+/// Longest FILE_DOWNLOAD_DATA header: seq, total, CRC-32 and encoding byte
+const CHUNK_HEADER_MAX: usize = 13;
+
+/// Extensions of formats that are already compressed
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avi", "br", "bz2", "cab", "deb", "docx", "flac", "gif", "gz", "heic", "jar",
+    "jpeg", "jpg", "lz4", "lzma", "mkv", "mov", "mp3", "mp4", "msi", "ogg", "pdf", "png", "pptx",
+    "rar", "rpm", "tgz", "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
+
+/// Bits of entropy per byte above which data is treated as incompressible
+const MAX_COMPRESSIBLE_ENTROPY: f64 = 7.5;
+
+/// Hard cap on the entries in one FILE_LIST_RESP page
+const MAX_LIST_PAGE: u32 = 5000;
+
+/// Hard cap on FILE_SEARCH results, whatever the request asks for
+const MAX_SEARCH_RESULTS: u32 = 5000;
+/// Directory levels below the search root that are walked
+const MAX_SEARCH_DEPTH: usize = 32;
+/// Wall-clock budget for one search
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Matches per FILE_SEARCH_RESULT message
+const SEARCH_BATCH: usize = 50;
+
+/// An upload with no chunk for this long is abandoned and its `.part`
+/// file removed
+const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
 /// Handles file operation messages (channel 0, request-response)
 pub struct FileHandler {
-    fs: Box<dyn FileSystem>,
-    /// Tracks pending uploads: request_id -> (path, accumulated data)
+    fs: Arc<dyn FileSystem>,
+    /// Uploads in progress by request_id
     pending_uploads: HashMap<u32, PendingUpload>,
+    /// Running searches by request_id, so they can be cancelled
+    searches: HashMap<u32, tokio::task::JoinHandle<()>>,
+}
+
+/// Uploads are written here next to the destination, and moved into place
+/// once complete
+fn upload_part_path(path: &str) -> String {
+    format!("{}.part", path)
 }
 
 struct PendingUpload {
     path: String,
-    data: Vec<u8>,
+    /// Bytes written to the `.part` file so far
+    received: u64,
     expected_size: u64,
+    /// Chunks between FILE_UPLOAD_ACKs; 0 for no acks
+    ack_every: u32,
+    /// Chunks received so far
+    chunks: u32,
+    /// When the upload started or last got a chunk
+    last_activity: Instant,
+}
+
+impl PendingUpload {
+    /// Count a chunk of `len` bytes that has been written. Returns the ack
+    /// to send for it, if one is due.
+    fn record(&mut self, seq: u32, len: usize) -> Option<protocol::FileUploadAck> {
+        self.received += len as u64;
+        self.chunks += 1;
+        self.last_activity = Instant::now();
+        (self.ack_every > 0 && self.chunks.is_multiple_of(self.ack_every)).then_some(
+            protocol::FileUploadAck {
+                seq,
+                bytes: self.received,
+            },
+        )
+    }
+
+    fn is_complete(&self) -> bool {
+        self.received >= self.expected_size
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        now.duration_since(self.last_activity) >= UPLOAD_IDLE_TIMEOUT
+    }
 }
 
 impl FileHandler {
     pub fn new(fs: Box<dyn FileSystem>) -> Self {
         Self {
-            fs,
+            fs: Arc::from(fs),
             pending_uploads: HashMap::new(),
+            searches: HashMap::new(),
         }
     }
 
@@ -40,6 +115,11 @@ impl FileHandler {
             protocol::FILE_UPLOAD_START => self.handle_upload_start(msg, handle).await,
             protocol::FILE_UPLOAD_DATA => self.handle_upload_data_msg(msg, handle).await,
             protocol::FILE_DELETE_REQ => self.handle_delete(msg, handle).await,
+            protocol::FILE_SEARCH_REQ => self.handle_search(msg, handle),
+            protocol::FILE_SEARCH_CANCEL => {
+                self.cancel_search(request_id);
+                Ok(())
+            }
             _ => {
                 warn!("file handler: unexpected message type 0x{:02x}", msg.header.msg_type);
                 return;
@@ -52,14 +132,58 @@ impl FileHandler {
         }
     }
 
+    /// Drop every upload in progress and its `.part` file, e.g. when the
+    /// connection that was sending them is gone
+    pub fn abandon_uploads(&mut self) {
+        self.remove_uploads(|_| true);
+    }
+
+    /// Drop uploads that haven't had a chunk in `UPLOAD_IDLE_TIMEOUT`
+    pub fn expire_idle_uploads(&mut self) {
+        let now = Instant::now();
+        self.remove_uploads(|upload| upload.is_idle(now));
+    }
+
+    fn remove_uploads(&mut self, abandoned: impl Fn(&PendingUpload) -> bool) {
+        let fs = &self.fs;
+        self.pending_uploads.retain(|request_id, upload| {
+            if !abandoned(upload) {
+                return true;
+            }
+            info!(
+                "abandoning upload {} of {} ({}/{} bytes)",
+                request_id, upload.path, upload.received, upload.expected_size
+            );
+            if let Err(e) = fs.delete(&upload_part_path(&upload.path)) {
+                warn!(
+                    "failed to remove partial upload of {}: {:#}",
+                    upload.path, e
+                );
+            }
+            false
+        });
+    }
+
     async fn handle_list(&self, msg: Message, handle: &ConnectionHandle) -> Result<()> {
         let req: protocol::FileListRequest = msg.parse_json()
             .map_err(|e| anyhow::anyhow!("invalid FILE_LIST_REQ: {}", e))?;
 
         info!("file list: {}", req.path);
 
-        let entries = self.fs.list_dir(&req.path)?;
-        let resp = serde_json::to_vec(&entries)?;
+        let resp = match req.limit {
+            Some(limit) => {
+                let limit = limit.clamp(1, MAX_LIST_PAGE) as usize;
+                let page = self.fs.list_dir_page(&req.path, req.offset, limit)?;
+                debug!(
+                    "file list page: {} entries from offset {}, next {:?}",
+                    page.entries.len(),
+                    req.offset,
+                    page.next_offset
+                );
+                serde_json::to_vec(&page)?
+            }
+            None => serde_json::to_vec(&self.fs.list_dir(&req.path)?)?,
+        };
 
         let reply = Message::control(protocol::FILE_LIST_RESP, msg.header.request_id, resp);
         handle.send_message(&reply).await?;
@@ -72,54 +196,75 @@ impl FileHandler {
 
         info!("file download: {}", req.path);
 
-        let data = self.fs.read_file(&req.path)?;
-        let total_chunks = if data.is_empty() {
-            1
-        } else {
-            (data.len() + DOWNLOAD_CHUNK_SIZE - 1) / DOWNLOAD_CHUNK_SIZE
+        let data = match (req.offset, req.length) {
+            (None, None) => self.fs.read_file(&req.path)?,
+            (offset, length) => {
+                let offset = offset.unwrap_or(0);
+                info!(
+                    "file download range: offset {}, length {:?}",
+                    offset, length
+                );
+                self.fs
+                    .read_file_range(&req.path, offset, length.unwrap_or(u64::MAX))?
+            }
         };
+        let compress = req.compress && worth_compressing(&req.path, &data);
 
-        for (seq, chunk) in data.chunks(DOWNLOAD_CHUNK_SIZE.max(1)).enumerate() {
-            let mut payload = Vec::with_capacity(8 + chunk.len());
-            payload.extend_from_slice(&(seq as u32).to_le_bytes());
-            payload.extend_from_slice(&(total_chunks as u32).to_le_bytes());
-            payload.extend_from_slice(chunk);
+        // Empty files still go out as a single empty chunk
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![&[]]
+        } else {
+            data.chunks(download_chunk_size(handle.max_payload()))
+                .collect()
+        };
+        let total_chunks = chunks.len() as u32;
 
-            let reply = Message::control(
-                protocol::FILE_DOWNLOAD_DATA,
-                msg.header.request_id,
-                payload,
-            );
-            handle.send_message(&reply).await?;
+        if let Some(index) = req.chunk {
+            if index >= total_chunks {
+                anyhow::bail!("chunk {} out of range ({} chunks)", index, total_chunks);
+            }
+            info!("re-sending chunk {} of {}", index, req.path);
         }
 
-        // For empty files, send a single empty chunk
-        if data.is_empty() {
-            let mut payload = Vec::with_capacity(8);
-            payload.extend_from_slice(&0u32.to_le_bytes()); // seq 0
-            payload.extend_from_slice(&1u32.to_le_bytes()); // total 1
-            let reply = Message::control(
-                protocol::FILE_DOWNLOAD_DATA,
-                msg.header.request_id,
-                payload,
-            );
-            handle.send_message(&reply).await?;
+        for (seq, chunk) in chunks.into_iter().enumerate() {
+            let seq = seq as u32;
+            if req.chunk.is_some_and(|index| index != seq) {
+                continue;
+            }
+
+            let payload = chunk_payload(seq, total_chunks, chunk, &req, compress)?;
+            let reply =
+                Message::control(protocol::FILE_DOWNLOAD_DATA, msg.header.request_id, payload);
+            handle.send_bulk(&reply).await?;
         }
 
         Ok(())
     }
 
     async fn handle_upload_start(&mut self, msg: Message, handle: &ConnectionHandle) -> Result<()> {
-        let req: protocol::FileUploadStart = msg.parse_json()
+        let req: protocol::FileUploadStart = msg
+            .parse_json()
             .map_err(|e| anyhow::anyhow!("invalid FILE_UPLOAD_START: {}", e))?;
 
-        info!("file upload start: {} ({} bytes)", req.path, req.size);
-
-        self.pending_uploads.insert(msg.header.request_id, PendingUpload {
-            path: req.path,
-            data: Vec::with_capacity(req.size as usize),
-            expected_size: req.size,
-        });
+        info!(
+            "file upload start: {} ({} bytes, ack every {} chunks)",
+            req.path, req.size, req.ack_every
+        );
+
+        // Start from an empty .part, which also fails early on a bad path
+        self.fs.write_file(&upload_part_path(&req.path), &[])?;
+
+        self.pending_uploads.insert(
+            msg.header.request_id,
+            PendingUpload {
+                path: req.path,
+                received: 0,
+                expected_size: req.size,
+                ack_every: req.ack_every,
+                chunks: 0,
+                last_activity: Instant::now(),
+            },
+        );
 
         send_file_result(handle, msg.header.request_id, true, None).await?;
         Ok(())
@@ -133,27 +278,46 @@ impl FileHandler {
         if payload.len() < 4 {
             anyhow::bail!("FILE_UPLOAD_DATA payload too short");
         }
-        let _seq = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        let seq = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
         let chunk_data = &payload[4..];
 
         if let Some(upload) = self.pending_uploads.get_mut(&request_id) {
-            upload.data.extend_from_slice(chunk_data);
-            info!("file upload data: {} bytes received ({}/{})",
-                chunk_data.len(), upload.data.len(), upload.expected_size);
+            // Write before acking, so an ack means the data is on disk
+            let part_path = upload_part_path(&upload.path);
+            if let Err(e) = self.fs.append_file(&part_path, chunk_data) {
+                self.pending_uploads.remove(&request_id);
+                let _ = self.fs.delete(&part_path);
+                return Err(e);
+            }
+            let ack = upload.record(seq, chunk_data.len());
+            debug!(
+                "file upload data: {} bytes received ({}/{})",
+                chunk_data.len(),
+                upload.received,
+                upload.expected_size
+            );
 
-            // Check if upload is complete (received all expected data)
-            if upload.data.len() as u64 >= upload.expected_size {
+            // Check if upload is complete (received all expected data).
+            // FILE_UPLOAD_DONE then stands in for the last ack.
+            if upload.is_complete() {
                 let upload = self.pending_uploads.remove(&request_id).unwrap();
-                self.fs.write_file(&upload.path, &upload.data)?;
+                self.fs.rename(&part_path, &upload.path)?;
 
                 let done_resp = protocol::FileResult {
                     success: true,
                     error: None,
                 };
-                let reply = Message::control_json(protocol::FILE_UPLOAD_DONE, request_id, &done_resp)?;
+                let reply =
+                    Message::control_json(protocol::FILE_UPLOAD_DONE, request_id, &done_resp)?;
                 handle.send_message(&reply).await?;
 
-                info!("file upload complete: {} ({} bytes)", upload.path, upload.data.len());
+                info!(
+                    "file upload complete: {} ({} bytes)",
+                    upload.path, upload.received
+                );
+            } else if let Some(ack) = ack {
+                let reply = Message::control_json(protocol::FILE_UPLOAD_ACK, request_id, &ack)?;
+                handle.send_message(&reply).await?;
             }
         } else {
             warn!("FILE_UPLOAD_DATA for unknown request_id {}", request_id);
@@ -173,6 +337,209 @@ impl FileHandler {
         Ok(())
     }
 
+    fn handle_search(&mut self, msg: Message, handle: &ConnectionHandle) -> Result<()> {
+        let req: protocol::FileSearchRequest = msg
+            .parse_json()
+            .map_err(|e| anyhow::anyhow!("invalid FILE_SEARCH_REQ: {}", e))?;
+        let request_id = msg.header.request_id;
+
+        info!("file search: {:?} under {}", req.pattern, req.root);
+
+        self.searches.retain(|_, task| !task.is_finished());
+        self.cancel_search(request_id);
+
+        let fs = self.fs.clone();
+        let handle = handle.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = run_search(fs, req, request_id, &handle).await {
+                error!("file search {} failed: {:#}", request_id, e);
+                let _ =
+                    send_file_result(&handle, request_id, false, Some(format!("{:#}", e))).await;
+            }
+        });
+        self.searches.insert(request_id, task);
+        Ok(())
+    }
+
+    fn cancel_search(&mut self, request_id: u32) {
+        if let Some(task) = self.searches.remove(&request_id) {
+            // Dropping the receiver stops the walker at its next send
+            task.abort();
+            info!("file search {} cancelled", request_id);
+        }
+    }
+}
+
+/// One FILE_SEARCH_RESULT payload
+#[derive(Serialize)]
+struct SearchBatch {
+    entries: Vec<FileEntry>,
+    /// Last message for this search
+    done: bool,
+    /// Stopped early on the result, depth or time limit
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+}
+
+/// Walk the tree on a blocking thread and stream its matches back
+async fn run_search(
+    fs: Arc<dyn FileSystem>,
+    req: protocol::FileSearchRequest,
+    request_id: u32,
+    handle: &ConnectionHandle,
+) -> Result<()> {
+    let (tx, mut rx) = mpsc::channel::<Vec<FileEntry>>(4);
+    let walker = tokio::task::spawn_blocking(move || search_tree(fs.as_ref(), &req, &tx));
+
+    let mut found = 0;
+    while let Some(entries) = rx.recv().await {
+        found += entries.len();
+        let batch = SearchBatch {
+            entries,
+            done: false,
+            truncated: false,
+        };
+        let msg = Message::control_json(protocol::FILE_SEARCH_RESULT, request_id, &batch)?;
+        handle.send_message(&msg).await?;
+    }
+
+    let truncated = walker.await??;
+    let batch = SearchBatch {
+        entries: Vec::new(),
+        done: true,
+        truncated,
+    };
+    let msg = Message::control_json(protocol::FILE_SEARCH_RESULT, request_id, &batch)?;
+    handle.send_message(&msg).await?;
+
+    info!(
+        "file search {} finished: {} match(es){}",
+        request_id,
+        found,
+        if truncated { ", truncated" } else { "" }
+    );
+    Ok(())
+}
+
+/// Breadth-first walk from `req.root`, sending matches to `tx` in batches.
+/// Directories are de-duplicated by canonical path, so symlink loops and
+/// bind mounts are walked once. Returns whether a limit cut the walk short.
+fn search_tree(
+    fs: &dyn FileSystem,
+    req: &protocol::FileSearchRequest,
+    tx: &mpsc::Sender<Vec<FileEntry>>,
+) -> Result<bool> {
+    let matcher = NameMatcher::new(&req.pattern);
+    let max_results = req.max_results.clamp(1, MAX_SEARCH_RESULTS) as usize;
+    let deadline = Instant::now() + SEARCH_TIMEOUT;
+
+    // Fail up front if the root itself can't be listed
+    let root_entries = fs.list_dir(&req.root)?;
+
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    if let Ok(root) = std::fs::canonicalize(&req.root) {
+        visited.insert(root);
+    }
+    let mut queue: VecDeque<(Vec<FileEntry>, usize)> = VecDeque::from([(root_entries, 0)]);
+    let mut batch = Vec::new();
+    let mut found = 0;
+    let mut truncated = false;
+
+    'walk: while let Some((entries, depth)) = queue.pop_front() {
+        if tx.is_closed() {
+            // Cancelled
+            return Ok(false);
+        }
+        for entry in entries {
+            if Instant::now() >= deadline {
+                truncated = true;
+                break 'walk;
+            }
+
+            if entry.is_dir {
+                if depth + 1 > MAX_SEARCH_DEPTH {
+                    truncated = true;
+                } else if std::fs::canonicalize(&entry.path).is_ok_and(|p| visited.insert(p)) {
+                    match fs.list_dir(&entry.path) {
+                        Ok(children) => queue.push_back((children, depth + 1)),
+                        Err(e) => debug!("file search: skipping {}: {:#}", entry.path, e),
+                    }
+                }
+            }
+
+            if matcher.matches(&entry.name) {
+                batch.push(entry);
+                found += 1;
+                if batch.len() >= SEARCH_BATCH
+                    && tx.blocking_send(std::mem::take(&mut batch)).is_err()
+                {
+                    // Cancelled
+                    return Ok(false);
+                }
+                if found >= max_results {
+                    truncated = true;
+                    break 'walk;
+                }
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        let _ = tx.blocking_send(batch);
+    }
+    Ok(truncated)
+}
+
+/// Case-insensitive file name match: a glob when the pattern has `*` or
+/// `?`, otherwise a substring
+struct NameMatcher {
+    pattern: Vec<char>,
+    glob: bool,
+}
+
+impl NameMatcher {
+    fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_lowercase().chars().collect(),
+            glob: pattern.contains(['*', '?']),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name: Vec<char> = name.to_lowercase().chars().collect();
+        if self.glob {
+            glob_match(&self.pattern, &name)
+        } else {
+            self.pattern.is_empty()
+                || name
+                    .windows(self.pattern.len())
+                    .any(|w| w == self.pattern.as_slice())
+        }
+    }
+}
+
+/// Iterative wildcard match with single-star backtracking
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((sp, sn)) = star {
+            // Let the last star swallow one more character
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 async fn send_file_result(
@@ -186,3 +553,220 @@ async fn send_file_result(
     handle.send_message(&msg).await?;
     Ok(())
 }
+
+/// Data bytes per download chunk, so that a FILE_DOWNLOAD_DATA payload
+/// stays within `max_payload`
+fn download_chunk_size(max_payload: usize) -> usize {
+    DOWNLOAD_CHUNK_SIZE
+        .min(max_payload.saturating_sub(CHUNK_HEADER_MAX))
+        .max(1)
+}
+
+/// Build one FILE_DOWNLOAD_DATA payload:
+/// `[u32 seq][u32 total][u32 crc32]?[u8 encoding]?[data]`, where the CRC
+/// (over `data` as sent) and encoding byte are only present when the request
+/// asked for checksums / compression.
+fn chunk_payload(
+    seq: u32,
+    total: u32,
+    chunk: &[u8],
+    req: &protocol::FileDownloadRequest,
+    compress: bool,
+) -> Result<Vec<u8>> {
+    let (encoding, data) = match compress.then(|| gzip(chunk)).transpose()? {
+        // Fall back to the raw bytes when gzip doesn't shrink the chunk
+        Some(packed) if packed.len() < chunk.len() => (protocol::file_encoding::GZIP, packed),
+        _ => (protocol::file_encoding::NONE, chunk.to_vec()),
+    };
+
+    let mut payload = Vec::with_capacity(CHUNK_HEADER_MAX + data.len());
+    payload.extend_from_slice(&seq.to_le_bytes());
+    payload.extend_from_slice(&total.to_le_bytes());
+    if req.checksum {
+        let mut crc = flate2::Crc::new();
+        crc.update(&data);
+        payload.extend_from_slice(&crc.sum().to_le_bytes());
+    }
+    if req.compress {
+        payload.push(encoding);
+    }
+    payload.extend_from_slice(&data);
+    Ok(payload)
+}
+
+/// Whether gzip is likely to pay off: skips known compressed formats by
+/// extension, then anything whose first chunk looks like random data.
+fn worth_compressing(path: &str, data: &[u8]) -> bool {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if COMPRESSED_EXTENSIONS.contains(&extension.as_str()) {
+        return false;
+    }
+
+    let sample = &data[..data.len().min(DOWNLOAD_CHUNK_SIZE)];
+    byte_entropy(sample) < MAX_COMPRESSIBLE_ENTROPY
+}
+
+/// Shannon entropy in bits per byte (0 = constant, 8 = random)
+fn byte_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(
+        Vec::with_capacity(data.len() / 2),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_matcher() {
+        let glob = NameMatcher::new("*.LOG");
+        assert!(glob.matches("agent.log"));
+        assert!(glob.matches(".log"));
+        assert!(!glob.matches("agent.log.1"));
+
+        let glob = NameMatcher::new("report-??.*");
+        assert!(glob.matches("Report-07.pdf"));
+        assert!(!glob.matches("report-7.pdf"));
+
+        let substring = NameMatcher::new("conf");
+        assert!(substring.matches("nginx.CONF"));
+        assert!(substring.matches("config.json"));
+        assert!(!substring.matches("cnf"));
+    }
+
+    #[test]
+    fn test_worth_compressing() {
+        let text = b"2026-01-01 INFO agent started\n".repeat(100);
+        assert!(worth_compressing("/var/log/agent.log", &text));
+        assert!(!worth_compressing("/tmp/photo.JPG", &text));
+
+        // Every byte value equally often: maximum entropy
+        let random: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        assert!(!worth_compressing("/tmp/blob.bin", &random));
+    }
+
+    #[test]
+    fn test_upload_acks_every_n_chunks() {
+        let mut upload = PendingUpload {
+            path: "/tmp/a.bin".to_string(),
+            received: 0,
+            expected_size: 10,
+            ack_every: 2,
+            chunks: 0,
+            last_activity: Instant::now(),
+        };
+        assert!(upload.record(0, 2).is_none());
+        let ack = upload.record(1, 2).unwrap();
+        assert_eq!((ack.seq, ack.bytes), (1, 4));
+        assert!(upload.record(2, 2).is_none());
+        assert!(upload.record(3, 2).is_some());
+        assert!(!upload.is_complete());
+        upload.record(4, 2);
+        assert!(upload.is_complete());
+
+        // Fire-and-forget mode never acks
+        upload.ack_every = 0;
+        assert!(upload.record(5, 2).is_none());
+        assert!(upload.record(6, 2).is_none());
+    }
+
+    #[test]
+    fn test_upload_idle_after_timeout() {
+        let mut upload = PendingUpload {
+            path: "/tmp/a.bin".to_string(),
+            received: 0,
+            expected_size: 10,
+            ack_every: 0,
+            chunks: 0,
+            last_activity: Instant::now(),
+        };
+        let later = upload.last_activity + UPLOAD_IDLE_TIMEOUT;
+        assert!(!upload.is_idle(later - Duration::from_secs(1)));
+        assert!(upload.is_idle(later));
+
+        // A chunk starts the wait over
+        upload.last_activity -= UPLOAD_IDLE_TIMEOUT;
+        upload.record(0, 2);
+        assert!(!upload.is_idle(Instant::now()));
+    }
+
+    #[test]
+    fn test_chunk_payload_layout() {
+        let mut req = protocol::FileDownloadRequest {
+            path: "/tmp/a.txt".to_string(),
+            compress: false,
+            checksum: false,
+            chunk: None,
+            offset: None,
+            length: None,
+        };
+        let plain = chunk_payload(2, 5, b"hello", &req, false).unwrap();
+        assert_eq!(
+            plain,
+            [2, 0, 0, 0, 5, 0, 0, 0, b'h', b'e', b'l', b'l', b'o']
+        );
+
+        req.checksum = true;
+        req.compress = true;
+        let checked = chunk_payload(2, 5, b"hello", &req, false).unwrap();
+        // CRC-32 of "hello", then the encoding byte
+        assert_eq!(&checked[8..12], &0x3610_A686u32.to_le_bytes());
+        assert_eq!(checked[12], protocol::file_encoding::NONE);
+        assert_eq!(&checked[13..], b"hello");
+    }
+
+    #[test]
+    fn test_chunk_fits_v1_message() {
+        let req = protocol::FileDownloadRequest {
+            path: "/tmp/a.bin".to_string(),
+            compress: true,
+            checksum: true,
+            chunk: None,
+            offset: None,
+            length: None,
+        };
+        let size = download_chunk_size(protocol::max_payload_for(1));
+        assert!(size < DOWNLOAD_CHUNK_SIZE);
+        assert_eq!(
+            download_chunk_size(protocol::max_payload_for(2)),
+            DOWNLOAD_CHUNK_SIZE
+        );
+
+        let chunk: Vec<u8> = (0..size).map(|i| (i * 7919 % 251) as u8).collect();
+        let payload = chunk_payload(0, 1, &chunk, &req, false).unwrap();
+        let msg = Message::control(protocol::FILE_DOWNLOAD_DATA, 9, payload.clone());
+        let wire = msg.encode_for(1).unwrap();
+
+        let (decoded, used) = Message::decode_for(&wire, 1).unwrap().unwrap();
+        assert_eq!(used, wire.len());
+        assert_eq!(decoded.payload, payload);
+        assert_eq!(&decoded.payload[CHUNK_HEADER_MAX..], &chunk[..]);
+    }
+}
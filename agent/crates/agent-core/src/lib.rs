@@ -1,8 +1,10 @@
-pub mod protocol;
-pub mod connection;
+pub mod auto_update;
 pub mod config;
-pub mod session;
+pub mod connection;
+pub mod control;
 pub mod desktop;
 pub mod files;
-pub mod auto_update;
+pub mod protocol;
+pub mod redact;
+pub mod session;
 pub mod telemetry;
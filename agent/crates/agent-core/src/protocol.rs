@@ -2,12 +2,39 @@ use bytes::{Buf, BufMut, BytesMut};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-/// Header size: 1 (type) + 2 (length) + 2 (channel) + 4 (request_id) = 9 bytes
-pub const HEADER_SIZE: usize = 9;
+/// Header size: 1 (type) + 4 (length) + 2 (channel) + 4 (request_id) = 11 bytes
+pub const HEADER_SIZE: usize = 11;
+
+/// Version 1 header size: 1 (type) + 2 (length) + 2 (channel) + 4 (request_id) = 9 bytes.
+/// Its u16 length field limits payloads to 65535 bytes.
+pub const LEGACY_HEADER_SIZE: usize = 9;
 
 /// Maximum payload size (16 MB)
 pub const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
 
+/// DESKTOP_FRAME tile header: x, y, w, h (u16 each) + encoding + flags = 10 bytes
+pub const DESKTOP_FRAME_HEADER_SIZE: usize = 10;
+
+/// Wire protocol version spoken by this agent, sent in AUTH_REQUEST
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// First protocol version with the u32 length header. AUTH_REQUEST and
+/// AUTH_RESPONSE always use the version 1 header, since the version isn't
+/// known until the handshake completes.
+pub const WIDE_HEADER_VERSION: u16 = 2;
+
+/// Oldest protocol version this agent can still talk
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Largest payload a peer speaking `version` can receive
+pub fn max_payload_for(version: u16) -> usize {
+    if version >= WIDE_HEADER_VERSION {
+        MAX_PAYLOAD_SIZE
+    } else {
+        u16::MAX as usize
+    }
+}
+
 // --- Command Types ---
 
 // Control plane (channel 0)
@@ -18,6 +45,14 @@ pub const HEARTBEAT_ACK: u8 = 0x04;
 pub const AGENT_INFO: u8 = 0x05;
 pub const COMMAND: u8 = 0x06;
 pub const COMMAND_RESULT: u8 = 0x07;
+pub const ERROR: u8 = 0x08;
+/// Sent after authenticating: asks for commands queued while disconnected.
+/// The server replays each as a COMMAND, then answers COMMAND_SYNC_RESP.
+pub const COMMAND_SYNC_REQ: u8 = 0x09;
+pub const COMMAND_SYNC_RESP: u8 = 0x0A;
+/// Progress of a long-running command, sent with the command's request id
+/// before its COMMAND_RESULT. JSON payload, e.g. `{"bytes":..,"total":..}`.
+pub const COMMAND_PROGRESS: u8 = 0x0B;
 
 // Desktop (channel 1+)
 pub const DESKTOP_OPEN: u8 = 0x10;
@@ -26,6 +61,7 @@ pub const DESKTOP_FRAME: u8 = 0x12;
 pub const DESKTOP_INPUT: u8 = 0x13;
 pub const DESKTOP_RESIZE: u8 = 0x14;
 pub const DESKTOP_QUALITY: u8 = 0x15;
+pub const DESKTOP_EVENT: u8 = 0x16;
 
 // Terminal (channel 1+)
 pub const TERMINAL_OPEN: u8 = 0x20;
@@ -43,11 +79,27 @@ pub const FILE_UPLOAD_DATA: u8 = 0x35;
 pub const FILE_UPLOAD_DONE: u8 = 0x36;
 pub const FILE_DELETE_REQ: u8 = 0x37;
 pub const FILE_RESULT: u8 = 0x38;
+pub const FILE_SEARCH_REQ: u8 = 0x39;
+pub const FILE_SEARCH_RESULT: u8 = 0x3A;
+pub const FILE_SEARCH_CANCEL: u8 = 0x3B;
+/// Flow control for uploads that ask for it (`ack_every`): the chunks
+/// received so far. JSON payload, see `FileUploadAck`.
+pub const FILE_UPLOAD_ACK: u8 = 0x3C;
 
 // Telemetry (channel 0)
 pub const TELEMETRY_REQ: u8 = 0x40;
 pub const TELEMETRY_DATA: u8 = 0x41;
 
+// Audio (channel per session)
+pub const AUDIO_OPEN: u8 = 0x50;
+pub const AUDIO_CLOSE: u8 = 0x51;
+pub const AUDIO_DATA: u8 = 0x52;
+
+// Service to helper over the local pipe only, never sent to the server
+/// Bytes the service's connection has queued but not yet sent, as a u64
+/// LE payload, so the helper's desktop sessions can pace themselves
+pub const HELPER_SEND_QUEUE: u8 = 0xF0;
+
 #[derive(Debug, Error)]
 pub enum ProtocolError {
     #[error("buffer too short: need {need} bytes, have {have}")]
@@ -56,6 +108,10 @@ pub enum ProtocolError {
     PayloadTooLarge { size: usize },
     #[error("invalid message type: 0x{0:02x}")]
     InvalidType(u8),
+    #[error(
+        "unsupported protocol version {0} (supported {MIN_PROTOCOL_VERSION}..={PROTOCOL_VERSION})"
+    )]
+    UnsupportedVersion(u16),
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
 }
@@ -64,7 +120,7 @@ pub enum ProtocolError {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Header {
     pub msg_type: u8,
-    pub length: u16,
+    pub length: u32,
     pub channel: u16,
     pub request_id: u32,
 }
@@ -78,10 +134,15 @@ pub struct Message {
 
 impl Message {
     pub fn new(msg_type: u8, channel: u16, request_id: u32, payload: Vec<u8>) -> Self {
+        debug_assert!(
+            payload.len() <= MAX_PAYLOAD_SIZE,
+            "payload of {} bytes exceeds MAX_PAYLOAD_SIZE",
+            payload.len()
+        );
         Self {
             header: Header {
                 msg_type,
-                length: payload.len() as u16,
+                length: payload.len() as u32,
                 channel,
                 request_id,
             },
@@ -118,7 +179,7 @@ impl Message {
     pub fn encode(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(HEADER_SIZE + self.payload.len());
         buf.put_u8(self.header.msg_type);
-        buf.put_u16_le(self.header.length);
+        buf.put_u32_le(self.header.length);
         buf.put_u16_le(self.header.channel);
         buf.put_u32_le(self.header.request_id);
         buf.extend_from_slice(&self.payload);
@@ -129,36 +190,77 @@ impl Message {
     pub fn encode_into(&self, buf: &mut BytesMut) {
         buf.reserve(HEADER_SIZE + self.payload.len());
         buf.put_u8(self.header.msg_type);
-        buf.put_u16_le(self.header.length);
+        buf.put_u32_le(self.header.length);
+        buf.put_u16_le(self.header.channel);
+        buf.put_u32_le(self.header.request_id);
+        buf.extend_from_slice(&self.payload);
+    }
+
+    /// Encode using the header layout of a negotiated protocol version.
+    /// Fails for version 1 peers when the payload doesn't fit the u16 length.
+    pub fn encode_for(&self, version: u16) -> Result<Vec<u8>, ProtocolError> {
+        if version >= WIDE_HEADER_VERSION {
+            return Ok(self.encode());
+        }
+
+        if self.payload.len() > max_payload_for(version) {
+            return Err(ProtocolError::PayloadTooLarge {
+                size: self.payload.len(),
+            });
+        }
+
+        let mut buf = Vec::with_capacity(LEGACY_HEADER_SIZE + self.payload.len());
+        buf.put_u8(self.header.msg_type);
+        buf.put_u16_le(self.payload.len() as u16);
         buf.put_u16_le(self.header.channel);
         buf.put_u32_le(self.header.request_id);
         buf.extend_from_slice(&self.payload);
+        Ok(buf)
     }
 
     /// Decode a message from bytes. Returns None if not enough data.
     pub fn decode(buf: &[u8]) -> Result<Option<(Message, usize)>, ProtocolError> {
-        if buf.len() < HEADER_SIZE {
+        Self::decode_for(buf, PROTOCOL_VERSION)
+    }
+
+    /// Decode a message framed for a negotiated protocol version.
+    /// Returns None if not enough data.
+    pub fn decode_for(buf: &[u8], version: u16) -> Result<Option<(Message, usize)>, ProtocolError> {
+        let wide = version >= WIDE_HEADER_VERSION;
+        let header_size = if wide {
+            HEADER_SIZE
+        } else {
+            LEGACY_HEADER_SIZE
+        };
+
+        if buf.len() < header_size {
             return Ok(None);
         }
 
         let mut cursor = &buf[..];
         let msg_type = cursor.get_u8();
-        let length = cursor.get_u16_le();
+        let length = if wide {
+            cursor.get_u32_le()
+        } else {
+            cursor.get_u16_le() as u32
+        };
         let channel = cursor.get_u16_le();
         let request_id = cursor.get_u32_le();
 
         let payload_len = length as usize;
-        let total_len = HEADER_SIZE + payload_len;
-
-        if buf.len() < total_len {
-            return Ok(None);
-        }
 
+        // Reject before waiting for (or allocating) an oversized payload
         if payload_len > MAX_PAYLOAD_SIZE {
             return Err(ProtocolError::PayloadTooLarge { size: payload_len });
         }
 
-        let payload = buf[HEADER_SIZE..total_len].to_vec();
+        let total_len = header_size + payload_len;
+
+        if buf.len() < total_len {
+            return Ok(None);
+        }
+
+        let payload = buf[header_size..total_len].to_vec();
 
         let msg = Message {
             header: Header {
@@ -185,6 +287,8 @@ pub struct AuthRequest {
     pub os: String,
     pub arch: String,
     pub hostname: String,
+    #[serde(default = "default_protocol_version")]
+    pub protocol_version: u16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -194,6 +298,87 @@ pub struct AuthResponse {
     pub session_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Version the server agreed to; absent from servers predating the handshake
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u16>,
+    /// Unix time (seconds) after which the session token is no longer
+    /// accepted; absent when tokens don't expire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_expires_at: Option<u64>,
+}
+
+/// Peers that don't send a version speak the original protocol
+fn default_protocol_version() -> u16 {
+    1
+}
+
+/// Validate the version agreed in AUTH_RESPONSE. Servers that predate the
+/// handshake don't echo a version and are treated as version 1.
+pub fn negotiated_version(agreed: Option<u16>) -> Result<u16, ProtocolError> {
+    let version = agreed.unwrap_or_else(default_protocol_version);
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    Ok(version)
+}
+
+/// Machine-readable failure category carried by ERROR messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Malformed payload or invalid parameters
+    InvalidRequest,
+    /// Message type this agent doesn't handle
+    UnknownMessageType,
+    /// Referenced file, session, or resource doesn't exist
+    NotFound,
+    /// The agent lacks the privileges for the operation
+    PermissionDenied,
+    /// Feature not supported on this platform or build
+    Unsupported,
+    /// Resource temporarily unavailable (no display, capture busy, ...)
+    Unavailable,
+    /// Unexpected failure inside the agent
+    Internal,
+}
+
+impl ErrorCode {
+    /// Best-fitting code for a failure: malformed payloads are invalid
+    /// requests and I/O errors map by kind, looking through the whole
+    /// `source()` chain. Anything else reports the resource as unavailable.
+    pub fn for_error(err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut next = Some(err);
+        while let Some(e) = next {
+            if e.is::<ProtocolError>() || e.is::<serde_json::Error>() {
+                return Self::InvalidRequest;
+            }
+            if let Some(io) = e.downcast_ref::<std::io::Error>() {
+                match io.kind() {
+                    std::io::ErrorKind::NotFound => return Self::NotFound,
+                    std::io::ErrorKind::PermissionDenied => return Self::PermissionDenied,
+                    std::io::ErrorKind::Unsupported => return Self::Unsupported,
+                    _ => {}
+                }
+            }
+            next = e.source();
+        }
+        Self::Unavailable
+    }
+}
+
+/// Generic failure reply, sent on the channel of the message that failed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub request_id: u32,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// End of a command catch-up: how many queued commands were replayed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandSyncResponse {
+    #[serde(default)]
+    pub count: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -211,6 +396,11 @@ pub struct AgentInfo {
     pub disks: Option<Vec<serde_json::Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub network: Option<Vec<serde_json::Value>>,
+    /// Features this agent can serve, detected at startup: "desktop",
+    /// "desktop.<backend>", "audio", "terminal", "files" and command
+    /// groups like "services". Missing from older agents.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -219,8 +409,33 @@ pub struct DesktopOpenRequest {
     pub quality: u8,
     #[serde(default = "default_fps")]
     pub fps: u16,
+    /// "jpeg", "rgb565" / "palette8" for reduced color on slow links, or
+    /// "auto" to pick JPEG quality, fps and subsampling from measured throughput
     #[serde(default = "default_encoding")]
     pub encoding: String,
+    /// Capture only the window with this title (exact, else substring match)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_title: Option<String>,
+    /// Capture only the window with this native handle (takes precedence over the title)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_handle: Option<u64>,
+    /// Capture every monitor composed into one frame spanning the virtual desktop
+    #[serde(default)]
+    pub stitched: bool,
+    /// "auto" encoding: how long frames may sit in the send queue, in ms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_latency_ms: Option<u32>,
+    /// "auto" encoding: bandwidth never to exceed, in kbit/s
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth_kbps: Option<u32>,
+    /// JPEG restart marker interval in MCU rows, so a decoder can resync
+    /// after a damaged segment; 0 (the default) writes no markers
+    #[serde(default)]
+    pub jpeg_restart_rows: u16,
+    /// Let the capture wait for the screen to change (DXGI) instead of
+    /// polling at the frame rate, which is then only a cap
+    #[serde(default)]
+    pub capture_on_change: bool,
 }
 
 fn default_quality() -> u8 {
@@ -233,6 +448,39 @@ fn default_encoding() -> String {
     "jpeg".to_string()
 }
 
+/// Out-of-band notification about the state of a desktop session
+/// (e.g. input blocked by the secure desktop).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DesktopEvent {
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioOpenRequest {
+    /// Target bitrate in bits/s, for codecs that take one
+    #[serde(default = "default_audio_bitrate")]
+    pub bitrate: u32,
+}
+
+fn default_audio_bitrate() -> u32 {
+    64_000
+}
+
+/// Sent back as AUDIO_OPEN once capture has started. Every AUDIO_DATA
+/// payload after it is one packet in this format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioStreamInfo {
+    /// "opus": one raw Opus packet per message, no container (Linux).
+    /// "pcm_s16le": interleaved signed 16-bit little-endian samples, any
+    /// number of whole frames per message (Windows, which has no Opus
+    /// encoder); about 190 KB/s for 48 kHz stereo.
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TerminalOpenRequest {
     pub shell: Option<String>,
@@ -240,6 +488,10 @@ pub struct TerminalOpenRequest {
     pub cols: u16,
     #[serde(default = "default_rows")]
     pub rows: u16,
+    /// Start the shell as a login shell (`-l`), sourcing profile scripts.
+    /// Only honored on Linux/macOS — ConPTY has no equivalent.
+    #[serde(default = "default_login")]
+    pub login: bool,
 }
 
 fn default_cols() -> u16 {
@@ -248,15 +500,62 @@ fn default_cols() -> u16 {
 fn default_rows() -> u16 {
     24
 }
+fn default_login() -> bool {
+    true
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileListRequest {
     pub path: String,
+    /// Entries to skip; the `next_offset` of the previous page
+    #[serde(default)]
+    pub offset: u64,
+    /// Page size. When set, FILE_LIST_RESP is a page object with `entries`
+    /// and `next_offset` in directory order; when absent it's the full,
+    /// sorted entry array.
+    #[serde(default)]
+    pub limit: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileDownloadRequest {
     pub path: String,
+    /// Compress chunks where it pays off. Every FILE_DOWNLOAD_DATA chunk
+    /// then has a `file_encoding` byte before its data.
+    #[serde(default)]
+    pub compress: bool,
+    /// Add a CRC-32 of each chunk's data after the seq/total header
+    #[serde(default)]
+    pub checksum: bool,
+    /// Send only this chunk, e.g. to replace one that failed its checksum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<u32>,
+    /// Start of a byte range to send instead of the whole file, for
+    /// resuming a download or previewing part of a file. Chunks are
+    /// numbered from the start of the range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    /// Length of the range; to the end of the file when absent. A range
+    /// running past the end is cut short.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
+}
+
+/// Find files whose name matches `pattern` under `root`. Matches stream back
+/// as FILE_SEARCH_RESULT batches with the request's id; the last one has
+/// `done` set. FILE_SEARCH_CANCEL with the same id stops the search.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSearchRequest {
+    pub root: String,
+    /// Glob (`*`, `?`) if it contains a wildcard, else a substring.
+    /// Case-insensitive either way.
+    pub pattern: String,
+    #[serde(default = "default_search_results")]
+    pub max_results: u32,
+}
+
+fn default_search_results() -> u32 {
+    200
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -265,6 +564,19 @@ pub struct FileUploadStart {
     pub size: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub checksum: Option<String>,
+    /// Send a FILE_UPLOAD_ACK after every this many FILE_UPLOAD_DATA chunks,
+    /// so the sender can bound the data in flight. 0 (the default) sends
+    /// no acks, for senders on fast links that just stream.
+    #[serde(default)]
+    pub ack_every: u32,
+}
+
+/// Upload progress in a FILE_UPLOAD_ACK. Every chunk up to and including
+/// `seq` has been taken in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileUploadAck {
+    pub seq: u32,
+    pub bytes: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -279,13 +591,57 @@ pub struct FileResult {
     pub error: Option<String>,
 }
 
+/// Message types that carry bulk data. They go out on the connection's
+/// low-priority queue so they can't hold up control traffic. DESKTOP_RESIZE
+/// rides along so it stays ordered with the frames it describes.
+pub fn is_bulk(msg_type: u8) -> bool {
+    matches!(
+        msg_type,
+        DESKTOP_FRAME | DESKTOP_RESIZE | FILE_DOWNLOAD_DATA | AUDIO_DATA
+    )
+}
+
+/// Encoding byte of FILE_DOWNLOAD_DATA chunks in a compressed download
+pub mod file_encoding {
+    /// Chunk data is sent as-is
+    pub const NONE: u8 = 0;
+    /// Chunk data is a standalone gzip stream
+    pub const GZIP: u8 = 1;
+}
+
 /// Desktop input sub-types
 pub mod desktop_input {
     pub const MOUSE_MOVE: u8 = 0x01;
     pub const MOUSE_BUTTON: u8 = 0x02;
     pub const MOUSE_SCROLL: u8 = 0x03;
+    /// `[scancode: u16][action: u8][mods: u8]`. The scancode is a Linux
+    /// evdev key code naming a physical key, so the remote's keyboard layout
+    /// decides the character, as with a local keyboard.
     pub const KEY_EVENT: u8 = 0x04;
     pub const TYPE_TEXT: u8 = 0x05;
+    /// Lock out the local keyboard and mouse while the viewer drives
+    pub const BLOCK_LOCAL_INPUT: u8 = 0x06;
+    /// Give the local keyboard and mouse back
+    pub const UNBLOCK_LOCAL_INPUT: u8 = 0x07;
+    /// `[keysym: u32][action: u8][mods: u8]`: the key producing an X11
+    /// keysym on whatever layout the remote uses
+    pub const KEY_SYM: u8 = 0x08;
+}
+
+/// DESKTOP_EVENT event names
+pub mod desktop_event {
+    /// Input injection is being dropped (e.g. UAC prompt or login screen)
+    pub const INPUT_BLOCKED: &str = "input_blocked";
+    /// Input injection works again
+    pub const INPUT_RESTORED: &str = "input_restored";
+    /// Local keyboard and mouse are locked out
+    pub const LOCAL_INPUT_BLOCKED: &str = "local_input_blocked";
+    /// Local keyboard and mouse work again
+    pub const LOCAL_INPUT_UNBLOCKED: &str = "local_input_unblocked";
+    /// A block or unblock request failed; `reason` says why
+    pub const LOCAL_INPUT_BLOCK_FAILED: &str = "local_input_block_failed";
+    /// The screen capture stalled and was re-initialized; a keyframe follows
+    pub const CAPTURE_RESTARTED: &str = "capture_restarted";
 }
 
 // --- Helper functions for building specific messages ---
@@ -310,6 +666,22 @@ pub fn auth_response(resp: &AuthResponse) -> Result<Message, ProtocolError> {
     Message::control_json(AUTH_RESPONSE, 0, resp)
 }
 
+/// Build an ERROR message replying to `request_id` on `channel`
+pub fn error_response(
+    channel: u16,
+    request_id: u32,
+    code: ErrorCode,
+    message: impl Into<String>,
+) -> Result<Message, ProtocolError> {
+    let body = ErrorResponse {
+        request_id,
+        code,
+        message: message.into(),
+    };
+    let payload = serde_json::to_vec(&body)?;
+    Ok(Message::new(ERROR, channel, request_id, payload))
+}
+
 /// Build a terminal data message
 pub fn terminal_data(channel: u16, data: Vec<u8>) -> Message {
     Message::session(TERMINAL_DATA, channel, 0, data)
@@ -334,7 +706,7 @@ pub fn desktop_frame(
     flags: u8,
     data: Vec<u8>,
 ) -> Message {
-    let mut payload = Vec::with_capacity(10 + data.len());
+    let mut payload = Vec::with_capacity(DESKTOP_FRAME_HEADER_SIZE + data.len());
     payload.put_u16_le(x);
     payload.put_u16_le(y);
     payload.put_u16_le(w);
@@ -345,6 +717,12 @@ pub fn desktop_frame(
     Message::session(DESKTOP_FRAME, channel, 0, payload)
 }
 
+/// Build a desktop event message
+pub fn desktop_event(channel: u16, event: &DesktopEvent) -> Result<Message, ProtocolError> {
+    let payload = serde_json::to_vec(event)?;
+    Ok(Message::session(DESKTOP_EVENT, channel, 0, payload))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -373,7 +751,7 @@ mod tests {
         let (decoded, consumed) = Message::decode(&encoded).unwrap().unwrap();
         assert_eq!(consumed, HEADER_SIZE + payload.len());
         assert_eq!(decoded.header.msg_type, AUTH_REQUEST);
-        assert_eq!(decoded.header.length, payload.len() as u16);
+        assert_eq!(decoded.header.length, payload.len() as u32);
         assert_eq!(decoded.payload, payload);
     }
 
@@ -392,6 +770,55 @@ mod tests {
         assert!(Message::decode(truncated).unwrap().is_none());
     }
 
+    #[test]
+    fn test_large_payload_roundtrip() {
+        // Larger than the old u16 length field could describe
+        let payload: Vec<u8> = (0..100 * 1024).map(|i| (i % 251) as u8).collect();
+        let msg = Message::new(FILE_DOWNLOAD_DATA, 0, 9, payload.clone());
+        let encoded = msg.encode();
+        assert_eq!(encoded.len(), HEADER_SIZE + payload.len());
+
+        let (decoded, consumed) = Message::decode(&encoded).unwrap().unwrap();
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(decoded.header.length as usize, payload.len());
+        assert_eq!(decoded.payload, payload);
+    }
+
+    #[test]
+    fn test_decode_rejects_oversized_length() {
+        let mut buf = Vec::new();
+        buf.put_u8(DESKTOP_FRAME);
+        buf.put_u32_le(MAX_PAYLOAD_SIZE as u32 + 1);
+        buf.put_u16_le(1);
+        buf.put_u32_le(0);
+        assert!(matches!(
+            Message::decode(&buf),
+            Err(ProtocolError::PayloadTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_legacy_header_roundtrip() {
+        let payload = b"hello world".to_vec();
+        let msg = Message::new(COMMAND, 0, 7, payload.clone());
+        let encoded = msg.encode_for(1).unwrap();
+        assert_eq!(encoded.len(), LEGACY_HEADER_SIZE + payload.len());
+
+        let (decoded, consumed) = Message::decode_for(&encoded, 1).unwrap().unwrap();
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(decoded.header.request_id, 7);
+        assert_eq!(decoded.payload, payload);
+
+        // Version 1 can't carry payloads past the u16 length field
+        assert_eq!(max_payload_for(1), u16::MAX as usize);
+        assert_eq!(max_payload_for(PROTOCOL_VERSION), MAX_PAYLOAD_SIZE);
+        let big = Message::new(DESKTOP_FRAME, 1, 0, vec![0; u16::MAX as usize + 1]);
+        assert!(matches!(
+            big.encode_for(1),
+            Err(ProtocolError::PayloadTooLarge { .. })
+        ));
+    }
+
     #[test]
     fn test_json_roundtrip() {
         let req = AuthRequest {
@@ -401,6 +828,7 @@ mod tests {
             os: "linux".to_string(),
             arch: "x86_64".to_string(),
             hostname: "test-host".to_string(),
+            protocol_version: PROTOCOL_VERSION,
         };
 
         let msg = auth_request(&req).unwrap();
@@ -409,6 +837,91 @@ mod tests {
         let decoded_req: AuthRequest = msg.parse_json().unwrap();
         assert_eq!(decoded_req.token, "test-token");
         assert_eq!(decoded_req.hostname, "test-host");
+        assert_eq!(decoded_req.protocol_version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_protocol_version_negotiation() {
+        assert_eq!(negotiated_version(None).unwrap(), 1);
+        assert_eq!(
+            negotiated_version(Some(PROTOCOL_VERSION)).unwrap(),
+            PROTOCOL_VERSION
+        );
+        assert!(matches!(
+            negotiated_version(Some(PROTOCOL_VERSION + 1)),
+            Err(ProtocolError::UnsupportedVersion(_))
+        ));
+
+        let resp: AuthResponse =
+            serde_json::from_str(r#"{"success":true,"device_id":"d","session_token":"t"}"#)
+                .unwrap();
+        assert_eq!(resp.protocol_version, None);
+        assert_eq!(resp.token_expires_at, None);
+    }
+
+    #[test]
+    fn test_auth_response_token_expiry() {
+        let resp: AuthResponse = serde_json::from_str(
+            r#"{"success":true,"device_id":"d","session_token":"t","token_expires_at":1900000000}"#,
+        )
+        .unwrap();
+        assert_eq!(resp.token_expires_at, Some(1_900_000_000));
+    }
+
+    #[test]
+    fn test_bulk_message_types() {
+        assert!(is_bulk(DESKTOP_FRAME));
+        assert!(is_bulk(DESKTOP_RESIZE));
+        assert!(is_bulk(FILE_DOWNLOAD_DATA));
+        assert!(is_bulk(AUDIO_DATA));
+        assert!(!is_bulk(HEARTBEAT));
+        assert!(!is_bulk(TERMINAL_DATA));
+        assert!(!is_bulk(COMMAND_RESULT));
+    }
+
+    #[test]
+    fn test_error_response_message() {
+        let msg = error_response(4, 17, ErrorCode::Unavailable, "capture unavailable").unwrap();
+        assert_eq!(msg.header.msg_type, ERROR);
+        assert_eq!(msg.header.channel, 4);
+        assert_eq!(msg.header.request_id, 17);
+
+        let body: serde_json::Value = msg.parse_json().unwrap();
+        assert_eq!(body["request_id"], 17);
+        assert_eq!(body["code"], "unavailable");
+        assert_eq!(body["message"], "capture unavailable");
+    }
+
+    #[test]
+    fn test_error_code_for_error() {
+        use anyhow::Context;
+
+        let bad_json = Message::control(COMMAND, 0, b"{".to_vec())
+            .parse_json::<serde_json::Value>()
+            .context("failed to parse DESKTOP_OPEN")
+            .unwrap_err();
+        assert_eq!(
+            ErrorCode::for_error(bad_json.as_ref()),
+            ErrorCode::InvalidRequest
+        );
+
+        let missing = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context("failed to spawn terminal")
+            .unwrap_err();
+        assert_eq!(ErrorCode::for_error(missing.as_ref()), ErrorCode::NotFound);
+
+        let denied = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(
+            ErrorCode::for_error(denied.as_ref()),
+            ErrorCode::PermissionDenied
+        );
+
+        let capture =
+            anyhow::anyhow!("DXGI duplication failed").context("failed to create screen capture");
+        assert_eq!(
+            ErrorCode::for_error(capture.as_ref()),
+            ErrorCode::Unavailable
+        );
     }
 
     #[test]
@@ -455,6 +968,21 @@ mod tests {
         assert_eq!(msg.payload.len(), 10 + jpeg_data.len());
     }
 
+    #[test]
+    fn test_desktop_event_message() {
+        let event = DesktopEvent {
+            event: desktop_event::INPUT_BLOCKED.to_string(),
+            reason: Some("secure desktop".to_string()),
+        };
+        let msg = desktop_event(2, &event).unwrap();
+        assert_eq!(msg.header.msg_type, DESKTOP_EVENT);
+        assert_eq!(msg.header.channel, 2);
+
+        let decoded: DesktopEvent = msg.parse_json().unwrap();
+        assert_eq!(decoded.event, desktop_event::INPUT_BLOCKED);
+        assert_eq!(decoded.reason.as_deref(), Some("secure desktop"));
+    }
+
     #[test]
     fn test_multiple_messages_in_buffer() {
         let msg1 = heartbeat();
@@ -470,6 +998,16 @@ mod tests {
         assert_eq!(consumed1 + consumed2, buf.len());
     }
 
+    #[test]
+    fn test_terminal_open_login_default() {
+        let req: TerminalOpenRequest = serde_json::from_str(r#"{"shell":null}"#).unwrap();
+        assert!(req.login);
+
+        let req: TerminalOpenRequest =
+            serde_json::from_str(r#"{"shell":"/bin/sh","login":false}"#).unwrap();
+        assert!(!req.login);
+    }
+
     #[test]
     fn test_session_message() {
         let msg = Message::session(DESKTOP_OPEN, 5, 100, b"{}".to_vec());
@@ -1,20 +1,52 @@
-use std::collections::HashMap;
 use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use agent_platform::terminal::Terminal;
+use crate::config::AgentConfig;
 use crate::connection::ConnectionHandle;
-use crate::desktop::{self, DesktopConfig};
+use crate::desktop::{self, CapturePool, DesktopConfig, IndicatorState};
 use crate::protocol::{self, Message};
+use agent_platform::audio::AudioCapture;
+use agent_platform::system_info::SystemInfo;
+use agent_platform::terminal::Terminal;
 
-/// Manages active sessions (terminal, desktop, file) on different channels
+/// Manages active sessions (terminal, desktop, audio, file) on different channels
 pub struct SessionManager {
     terminal_sessions: HashMap<u16, TerminalSession>,
     desktop_sessions: HashMap<u16, DesktopSession>,
+    /// Screen captures shared by the desktop sessions
+    captures: CapturePool,
+    audio_sessions: HashMap<u16, AudioSession>,
+    /// "Remote session active" overlay, shown while any desktop is open
+    indicator: IndicatorState,
+    terminal_settings: TerminalSettings,
+    /// Frame rate ceiling for desktop sessions
+    max_fps: u16,
+    /// Whether desktop sessions may send uncompressed RAW tiles
+    raw_tiles: bool,
+    /// Source of the available-memory reading checked before opening sessions
+    sys_info: Option<Box<dyn SystemInfo>>,
+    /// Desktop and terminal sessions are refused below this much free memory
+    min_free_memory: u64,
+    /// Tagged onto every session span so logs can be correlated per device
+    device_id: String,
     handle: ConnectionHandle,
 }
 
+/// Per-agent settings applied to every terminal session
+#[derive(Debug, Clone, Copy)]
+struct TerminalSettings {
+    /// Window for coalescing terminal output
+    batch_window: Duration,
+    /// Close the terminal after this long without input or output
+    idle_timeout: Option<Duration>,
+    /// CreatePseudoConsole flags (Windows only)
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    conpty_flags: u32,
+}
+
 struct TerminalSession {
     /// Sender to forward stdin data to the terminal task
     stdin_tx: mpsc::Sender<Vec<u8>>,
@@ -29,21 +61,52 @@ struct DesktopSession {
     input_tx: mpsc::Sender<Vec<u8>>,
     /// Sender to forward quality changes
     quality_tx: mpsc::Sender<DesktopConfig>,
-    /// Handle to the spawned task
-    _task: tokio::task::JoinHandle<()>,
+    /// Handle to the spawned task; finished once the session has ended
+    task: tokio::task::JoinHandle<()>,
+}
+
+struct AudioSession {
+    /// Handle to the spawned task; aborting it drops (and stops) the capture
+    task: tokio::task::JoinHandle<()>,
 }
 
 impl SessionManager {
-    pub fn new(handle: ConnectionHandle) -> Self {
+    pub fn new(handle: ConnectionHandle, config: &AgentConfig) -> Self {
+        let sys_info = if config.min_free_memory_mb > 0 {
+            create_platform_system_info()
+                .map_err(|e| warn!("memory guard disabled: {:#}", e))
+                .ok()
+        } else {
+            None
+        };
+
         Self {
             terminal_sessions: HashMap::new(),
             desktop_sessions: HashMap::new(),
+            captures: CapturePool::new(),
+            audio_sessions: HashMap::new(),
+            indicator: IndicatorState::new(config.session_indicator, create_platform_indicator),
+            terminal_settings: TerminalSettings {
+                batch_window: Duration::from_millis(config.terminal_batch_ms),
+                idle_timeout: (config.terminal_idle_timeout_mins > 0)
+                    .then(|| Duration::from_secs(config.terminal_idle_timeout_mins * 60)),
+                conpty_flags: config.conpty_flags,
+            },
+            max_fps: config.max_fps,
+            raw_tiles: config.raw_tiles,
+            sys_info,
+            min_free_memory: config.min_free_memory_mb * 1024 * 1024,
+            device_id: config
+                .device_id
+                .clone()
+                .unwrap_or_else(|| "default".to_string()),
             handle,
         }
     }
 
     /// Handle an incoming message from the server for session management
     pub async fn handle_message(&mut self, msg: Message) -> Result<()> {
+        self.close_ended_desktops();
         match msg.header.msg_type {
             protocol::TERMINAL_OPEN => {
                 self.open_terminal(msg).await?;
@@ -69,6 +132,12 @@ impl SessionManager {
             protocol::DESKTOP_QUALITY => {
                 self.desktop_quality(msg).await;
             }
+            protocol::AUDIO_OPEN => {
+                self.open_audio(msg)?;
+            }
+            protocol::AUDIO_CLOSE => {
+                self.close_audio(msg.header.channel);
+            }
             _ => {
                 warn!("session manager: unhandled message type 0x{:02x}", msg.header.msg_type);
             }
@@ -76,43 +145,114 @@ impl SessionManager {
         Ok(())
     }
 
+    /// Why a new session should be refused for lack of memory, if it should.
+    /// A failed reading lets the session through.
+    fn memory_pressure(&self) -> Option<String> {
+        let sys_info = self.sys_info.as_ref()?;
+        let memory = match sys_info.memory_info() {
+            Ok(memory) => memory,
+            Err(e) => {
+                debug!("memory guard: failed to read memory info: {:#}", e);
+                return None;
+            }
+        };
+        (memory.available_bytes < self.min_free_memory).then(|| {
+            format!(
+                "low memory: {} MB available, {} MB required",
+                memory.available_bytes / (1024 * 1024),
+                self.min_free_memory / (1024 * 1024)
+            )
+        })
+    }
+
+    /// Refuse a session open with an ERROR when memory is below the floor.
+    /// Returns true if the open was refused. Also guards the sessions a
+    /// Session 0 service forwards to its helper.
+    pub async fn refuse_under_memory_pressure(&self, msg: &Message) -> Result<bool> {
+        let Some(reason) = self.memory_pressure() else {
+            return Ok(false);
+        };
+        error!(
+            "refusing session on channel {}: {}",
+            msg.header.channel, reason
+        );
+        self.handle
+            .send_error(
+                msg.header.channel,
+                msg.header.request_id,
+                protocol::ErrorCode::Unavailable,
+                reason,
+            )
+            .await?;
+        Ok(true)
+    }
+
     async fn open_terminal(&mut self, msg: Message) -> Result<()> {
         let channel = msg.header.channel;
 
         if self.terminal_sessions.contains_key(&channel) {
-            warn!("terminal already exists on channel {}, closing old one", channel);
+            warn!(
+                "terminal already exists on channel {}, closing old one",
+                channel
+            );
             self.close_terminal(channel);
         }
 
-        let req: protocol::TerminalOpenRequest = msg.parse_json()
-            .context("failed to parse TERMINAL_OPEN")?;
+        if self.refuse_under_memory_pressure(&msg).await? {
+            return Ok(());
+        }
+
+        let req: protocol::TerminalOpenRequest =
+            msg.parse_json().context("failed to parse TERMINAL_OPEN")?;
 
         info!(
-            "opening terminal on channel {}: shell={:?}, cols={}, rows={}",
-            channel, req.shell, req.cols, req.rows
+            "opening terminal on channel {}: shell={:?}, cols={}, rows={}, login={}",
+            channel, req.shell, req.cols, req.rows, req.login
         );
 
+        let request_id = msg.header.request_id;
         let (stdin_tx, stdin_rx) = mpsc::channel::<Vec<u8>>(256);
         let (resize_tx, resize_rx) = mpsc::channel::<(u16, u16)>(16);
         let handle = self.handle.clone();
+        let settings = self.terminal_settings;
+        let span = info_span!("terminal", channel, device_id = %self.device_id);
 
-        let shell = req.shell.clone();
-        let cols = req.cols;
-        let rows = req.rows;
-
-        let task = tokio::spawn(async move {
-            if let Err(e) = run_terminal_session(
-                channel, shell, cols, rows, stdin_rx, resize_rx, handle,
-            ).await {
-                error!("terminal session on channel {} ended with error: {:#}", channel, e);
+        let task = tokio::spawn(
+            async move {
+                if let Err(e) = run_terminal_session(
+                    channel,
+                    req,
+                    stdin_rx,
+                    resize_rx,
+                    handle.clone(),
+                    settings,
+                )
+                .await
+                {
+                    error!(
+                        "terminal session on channel {} ended with error: {:#}",
+                        channel, e
+                    );
+                    // The shell never started: say why, then close the session
+                    let code = protocol::ErrorCode::for_error(e.as_ref());
+                    let _ = handle
+                        .send_error(channel, request_id, code, format!("{:#}", e))
+                        .await;
+                    let close_msg = Message::session(protocol::TERMINAL_CLOSE, channel, 0, vec![]);
+                    let _ = handle.send_message(&close_msg).await;
+                }
             }
-        });
+            .instrument(span),
+        );
 
-        self.terminal_sessions.insert(channel, TerminalSession {
-            stdin_tx,
-            resize_tx,
-            _task: task,
-        });
+        self.terminal_sessions.insert(
+            channel,
+            TerminalSession {
+                stdin_tx,
+                resize_tx,
+                _task: task,
+            },
+        );
 
         Ok(())
     }
@@ -159,62 +299,117 @@ impl SessionManager {
         let channel = msg.header.channel;
 
         if self.desktop_sessions.contains_key(&channel) {
-            warn!("desktop already exists on channel {}, closing old one", channel);
+            warn!(
+                "desktop already exists on channel {}, closing old one",
+                channel
+            );
             self.close_desktop(channel);
         }
 
-        let req: protocol::DesktopOpenRequest = msg.parse_json()
-            .context("failed to parse DESKTOP_OPEN")?;
+        if self.refuse_under_memory_pressure(&msg).await? {
+            return Ok(());
+        }
+
+        let req: protocol::DesktopOpenRequest =
+            msg.parse_json().context("failed to parse DESKTOP_OPEN")?;
 
         info!(
             "opening desktop on channel {}: quality={}, fps={}, encoding={}",
             channel, req.quality, req.fps, req.encoding
         );
 
-        let config = DesktopConfig {
-            quality: req.quality,
-            fps: req.fps,
-            encoding: req.encoding,
-        };
+        if !platform_has_interactive_session() {
+            warn!(
+                "desktop open refused on channel {}: {}",
+                channel, NO_INTERACTIVE_SESSION
+            );
+            self.handle
+                .send_error(
+                    channel,
+                    msg.header.request_id,
+                    protocol::ErrorCode::Unavailable,
+                    NO_INTERACTIVE_SESSION,
+                )
+                .await?;
+            return Ok(());
+        }
 
-        let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
-        let (quality_tx, mut quality_rx) = mpsc::channel::<DesktopConfig>(8);
-        let handle = self.handle.clone();
+        let config = DesktopConfig::from_request(req, self.max_fps, self.raw_tiles);
 
-        let task = tokio::spawn(async move {
-            // Create platform screen capture and input injector
-            let screen = match create_platform_screen() {
-                Ok(s) => s,
+        // Set up capture and input before spawning anything, so the viewer
+        // gets an immediate error instead of waiting for frames that never come
+        let (subscription, mut injector) =
+            match init_desktop_backends(&mut self.captures, channel, &config).await {
+                Ok(backends) => backends,
                 Err(e) => {
-                    error!("failed to create screen capture: {:#}", e);
-                    return;
+                    self.captures.unsubscribe(channel);
+                    error!("desktop open failed on channel {}: {:#}", channel, e);
+                    self.handle
+                        .send_error(
+                            channel,
+                            msg.header.request_id,
+                            protocol::ErrorCode::Unavailable,
+                            format!("capture unavailable: {:#}", e),
+                        )
+                        .await?;
+                    return Ok(());
                 }
             };
 
-            let mut injector = match create_platform_input() {
-                Ok(i) => i,
-                Err(e) => {
-                    error!("failed to create input injector: {:#}", e);
-                    return;
-                }
-            };
+        if let Err(e) = self.indicator.show() {
+            self.captures.unsubscribe(channel);
+            error!("desktop open refused on channel {}: {:#}", channel, e);
+            self.handle
+                .send_error(
+                    channel,
+                    msg.header.request_id,
+                    protocol::ErrorCode::Unavailable,
+                    format!("{:#}", e),
+                )
+                .await?;
+            return Ok(());
+        }
+
+        let request_id = msg.header.request_id;
+        let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(256);
+        let (quality_tx, mut quality_rx) = mpsc::channel::<DesktopConfig>(8);
+        let handle = self.handle.clone();
+        let span = info_span!("desktop", channel, device_id = %self.device_id);
 
+        let task = tokio::spawn(async move {
             // Spawn the capture loop in a separate task
             let capture_handle = handle.clone();
-            let capture_task = tokio::spawn(async move {
-                if let Err(e) = desktop::run_desktop_session(channel, config, screen, capture_handle).await {
+            let mut capture_task = tokio::spawn(async move {
+                if let Err(e) = desktop::run_desktop_session(channel, config, subscription, capture_handle.clone()).await {
                     error!("desktop capture on channel {} ended with error: {:#}", channel, e);
+                    let code = protocol::ErrorCode::for_error(e.as_ref());
+                    let _ = capture_handle.send_error(channel, request_id, code, format!("{:#}", e)).await;
                 }
-            });
+                // No more frames are coming: don't leave the viewer on a frozen screen
+                let close_msg = Message::session(protocol::DESKTOP_CLOSE, channel, 0, vec![]);
+                let _ = capture_handle.send_message(&close_msg).await;
+            }.in_current_span());
+
+            // Tell the viewer when the secure desktop swallows injected input
+            #[cfg(target_os = "windows")]
+            let monitor_task = tokio::spawn(monitor_input_desktop(channel, handle.clone()).in_current_span());
 
             // Process input events and quality changes
             loop {
                 tokio::select! {
+                    _ = &mut capture_task => break,
                     input = input_rx.recv() => {
                         match input {
                             Some(data) => {
-                                if let Err(e) = desktop::handle_desktop_input(&data, injector.as_mut()) {
-                                    warn!("desktop input error: {:#}", e);
+                                match desktop::handle_desktop_input(&data, injector.as_mut()) {
+                                    Ok(Some(event)) => match protocol::desktop_event(channel, &event) {
+                                        Ok(msg) => {
+                                            let _ = handle.send_message(&msg).await;
+                                        }
+                                        Err(e) => warn!("failed to build desktop event: {}", e),
+                                    },
+                                    Ok(None) => {}
+                                    Err(e) => warn!("desktop input error: {:#}", e),
                                 }
                             }
                             None => break,
@@ -233,15 +428,22 @@ impl SessionManager {
                 }
             }
 
+            // Never leave the local user locked out after the viewer leaves
+            desktop::release_local_input(injector.as_mut());
             capture_task.abort();
+            #[cfg(target_os = "windows")]
+            monitor_task.abort();
             info!("desktop session ended on channel {}", channel);
-        });
+        }.instrument(span));
 
-        self.desktop_sessions.insert(channel, DesktopSession {
-            input_tx,
-            quality_tx,
-            _task: task,
-        });
+        self.desktop_sessions.insert(
+            channel,
+            DesktopSession {
+                input_tx,
+                quality_tx,
+                task,
+            },
+        );
 
         Ok(())
     }
@@ -252,13 +454,31 @@ impl SessionManager {
             drop(session.input_tx);
             drop(session.quality_tx);
         }
+        self.captures.unsubscribe(channel);
+        if self.desktop_sessions.is_empty() {
+            self.indicator.hide();
+        }
+    }
+
+    /// Drop desktop sessions whose capture stopped on its own; the viewer
+    /// has already been sent DESKTOP_CLOSE
+    fn close_ended_desktops(&mut self) {
+        let ended: Vec<u16> = self
+            .desktop_sessions
+            .iter()
+            .filter(|(_, session)| session.task.is_finished())
+            .map(|(&channel, _)| channel)
+            .collect();
+        for channel in ended {
+            self.close_desktop(channel);
+        }
     }
 
     async fn desktop_input(&mut self, channel: u16, data: Vec<u8>) {
         if let Some(session) = self.desktop_sessions.get(&channel) {
             if session.input_tx.send(data).await.is_err() {
                 warn!("desktop input channel {} closed, removing session", channel);
-                self.desktop_sessions.remove(&channel);
+                self.close_desktop(channel);
             }
         } else {
             debug!("desktop input for unknown channel {}", channel);
@@ -268,20 +488,77 @@ impl SessionManager {
     async fn desktop_quality(&mut self, msg: Message) {
         let channel = msg.header.channel;
         if let Ok(req) = msg.parse_json::<protocol::DesktopOpenRequest>() {
-            let config = DesktopConfig {
-                quality: req.quality,
-                fps: req.fps,
-                encoding: req.encoding,
-            };
+            let config = DesktopConfig::from_request(req, self.max_fps, self.raw_tiles);
             if let Some(session) = self.desktop_sessions.get(&channel) {
                 let _ = session.quality_tx.send(config).await;
             }
         }
     }
 
+    // --- Audio session management ---
+
+    fn open_audio(&mut self, msg: Message) -> Result<()> {
+        let channel = msg.header.channel;
+
+        if self.audio_sessions.contains_key(&channel) {
+            warn!(
+                "audio already exists on channel {}, closing old one",
+                channel
+            );
+            self.close_audio(channel);
+        }
+
+        let req: protocol::AudioOpenRequest =
+            msg.parse_json().context("failed to parse AUDIO_OPEN")?;
+
+        info!(
+            "opening audio on channel {}: bitrate={}",
+            channel, req.bitrate
+        );
+
+        let capture = create_platform_audio().context("failed to create audio capture")?;
+        let handle = self.handle.clone();
+        let span = info_span!("audio", channel, device_id = %self.device_id);
+
+        let task = tokio::spawn(
+            async move {
+                if let Err(e) = run_audio_session(channel, req, capture, handle.clone()).await {
+                    error!(
+                        "audio session on channel {} ended with error: {:#}",
+                        channel, e
+                    );
+                    let code = protocol::ErrorCode::for_error(e.as_ref());
+                    let _ = handle
+                        .send_error(channel, 0, code, format!("{:#}", e))
+                        .await;
+                }
+                let close_msg = Message::session(protocol::AUDIO_CLOSE, channel, 0, vec![]);
+                let _ = handle.send_message(&close_msg).await;
+            }
+            .instrument(span),
+        );
+
+        self.audio_sessions.insert(channel, AudioSession { task });
+        Ok(())
+    }
+
+    fn close_audio(&mut self, channel: u16) {
+        if let Some(session) = self.audio_sessions.remove(&channel) {
+            info!("closing audio on channel {}", channel);
+            session.task.abort();
+        }
+    }
+
     /// Check if any sessions are active
     pub fn has_active_sessions(&self) -> bool {
-        !self.terminal_sessions.is_empty() || !self.desktop_sessions.is_empty()
+        !self.terminal_sessions.is_empty()
+            || !self.desktop_sessions.is_empty()
+            || !self.audio_sessions.is_empty()
+    }
+
+    /// Number of open desktop, terminal and audio sessions
+    pub fn session_count(&self) -> usize {
+        self.terminal_sessions.len() + self.desktop_sessions.len() + self.audio_sessions.len()
     }
 
     /// Close all sessions
@@ -294,31 +571,119 @@ impl SessionManager {
         for channel in desktop_channels {
             self.close_desktop(channel);
         }
+        let audio_channels: Vec<u16> = self.audio_sessions.keys().copied().collect();
+        for channel in audio_channels {
+            self.close_audio(channel);
+        }
     }
 }
 
+/// Subscribe to the screen capture and create the input injector for a
+/// desktop session.
+async fn init_desktop_backends(
+    captures: &mut CapturePool,
+    channel: u16,
+    config: &DesktopConfig,
+) -> Result<(
+    desktop::FrameSubscription,
+    Box<dyn agent_platform::input::InputInjector>,
+)> {
+    let subscription = captures
+        .subscribe(channel, config, create_platform_screen)
+        .await?;
+    let mut injector = create_platform_input().context("failed to create input injector")?;
+    injector.set_virtual_desktop(config.stitched);
+    Ok((subscription, injector))
+}
+
+/// Upper bound on buffered terminal output before it is sent regardless of
+/// the batch window
+const MAX_TERMINAL_BATCH: usize = 64 * 1024;
+
+/// Coalesces PTY reads into fewer TERMINAL_DATA messages. The first read
+/// after an idle period goes out at once so keystroke echo isn't delayed;
+/// reads that follow within the window are held and sent together.
+struct OutputBatch {
+    window: Duration,
+    pending: Vec<u8>,
+    last_flush: tokio::time::Instant,
+    deadline: Option<tokio::time::Instant>,
+}
+
+impl OutputBatch {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: Vec::new(),
+            last_flush: tokio::time::Instant::now(),
+            deadline: None,
+        }
+    }
+
+    /// Add a read. Returns true when the batch should be sent now.
+    fn push(&mut self, data: Vec<u8>) -> bool {
+        if self.pending.is_empty() && self.last_flush.elapsed() >= self.window {
+            self.pending = data;
+            return true;
+        }
+
+        self.pending.extend_from_slice(&data);
+        if self.pending.len() >= MAX_TERMINAL_BATCH {
+            return true;
+        }
+        self.deadline.get_or_insert(self.last_flush + self.window);
+        false
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        self.deadline = None;
+        self.last_flush = tokio::time::Instant::now();
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Send whatever output is batched up as one TERMINAL_DATA message.
+async fn flush_terminal_output(
+    channel: u16,
+    batch: &mut OutputBatch,
+    handle: &ConnectionHandle,
+) -> Result<()> {
+    let data = batch.take();
+    if data.is_empty() {
+        return Ok(());
+    }
+    handle
+        .send_message(&protocol::terminal_data(channel, data))
+        .await
+}
+
 /// Run a single terminal session — spawns PTY and relays data
 async fn run_terminal_session(
     channel: u16,
-    shell: Option<String>,
-    cols: u16,
-    rows: u16,
+    req: protocol::TerminalOpenRequest,
     mut stdin_rx: mpsc::Receiver<Vec<u8>>,
     mut resize_rx: mpsc::Receiver<(u16, u16)>,
     handle: ConnectionHandle,
+    settings: TerminalSettings,
 ) -> Result<()> {
-    let mut terminal = create_platform_terminal()?;
+    let mut terminal = create_platform_terminal(&settings)?;
 
     terminal
-        .spawn(shell.as_deref(), cols, rows)
+        .spawn(req.shell.as_deref(), req.cols, req.rows, req.login)
         .await
         .context("failed to spawn terminal")?;
 
     info!("terminal session started on channel {}", channel);
 
+    let mut batch = OutputBatch::new(settings.batch_window);
+    // Input or output; resizes don't count
+    let mut last_activity = tokio::time::Instant::now();
+
     loop {
+        let deadline = batch.deadline;
+
         tokio::select! {
-            // Read stdout from terminal -> send to server
+            // Read stdout from terminal -> batch for the server
             result = terminal.read_stdout() => {
                 match result {
                     Ok(data) if data.is_empty() => {
@@ -326,10 +691,12 @@ async fn run_terminal_session(
                         continue;
                     }
                     Ok(data) => {
-                        let msg = protocol::terminal_data(channel, data);
-                        if let Err(e) = handle.send_message(&msg).await {
-                            error!("failed to send terminal data: {}", e);
-                            break;
+                        last_activity = tokio::time::Instant::now();
+                        if batch.push(data) {
+                            if let Err(e) = flush_terminal_output(channel, &mut batch, &handle).await {
+                                error!("failed to send terminal data: {}", e);
+                                break;
+                            }
                         }
                     }
                     Err(e) => {
@@ -339,10 +706,19 @@ async fn run_terminal_session(
                 }
             }
 
+            // Batch window elapsed -> send what has accumulated
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                if let Err(e) = flush_terminal_output(channel, &mut batch, &handle).await {
+                    error!("failed to send terminal data: {}", e);
+                    break;
+                }
+            }
+
             // Receive stdin from server -> write to terminal
             data = stdin_rx.recv() => {
                 match data {
                     Some(data) => {
+                        last_activity = tokio::time::Instant::now();
                         if let Err(e) = terminal.write_stdin(&data).await {
                             error!("failed to write terminal stdin: {}", e);
                             break;
@@ -368,6 +744,12 @@ async fn run_terminal_session(
                     }
                 }
             }
+
+            // Nobody has typed or seen output for a while: free the PTY
+            _ = tokio::time::sleep_until(last_activity + settings.idle_timeout.unwrap_or_default()), if settings.idle_timeout.is_some() => {
+                info!("terminal on channel {} idle, closing", channel);
+                break;
+            }
         }
 
         // Check if terminal process is still alive
@@ -377,6 +759,9 @@ async fn run_terminal_session(
         }
     }
 
+    // Don't lose the tail of the output
+    let _ = flush_terminal_output(channel, &mut batch, &handle).await;
+
     // Send TERMINAL_CLOSE to server
     let close_msg = Message::session(protocol::TERMINAL_CLOSE, channel, 0, vec![]);
     let _ = handle.send_message(&close_msg).await;
@@ -385,10 +770,162 @@ async fn run_terminal_session(
     Ok(())
 }
 
+/// Run a single audio session — reports the stream format, then relays
+/// packets until the capture ends or the task is aborted
+async fn run_audio_session(
+    channel: u16,
+    req: protocol::AudioOpenRequest,
+    mut capture: Box<dyn AudioCapture>,
+    handle: ConnectionHandle,
+) -> Result<()> {
+    let format = capture
+        .start(req.bitrate)
+        .await
+        .context("failed to start audio capture")?;
+
+    let info = protocol::AudioStreamInfo {
+        codec: format.codec.as_str().to_string(),
+        sample_rate: format.sample_rate,
+        channels: format.channels,
+    };
+    handle
+        .send_message(&Message::session(
+            protocol::AUDIO_OPEN,
+            channel,
+            0,
+            serde_json::to_vec(&info)?,
+        ))
+        .await?;
+
+    info!("audio session started on channel {}", channel);
+
+    while let Some(packet) = capture.next_packet().await? {
+        handle
+            .send_message(&Message::session(protocol::AUDIO_DATA, channel, 0, packet))
+            .await?;
+    }
+
+    capture.stop();
+    info!("audio capture ended on channel {}", channel);
+    Ok(())
+}
+
+/// Tell the viewer on `channel` when the secure desktop swallows injected
+/// input
+#[cfg(target_os = "windows")]
+async fn monitor_input_desktop(channel: u16, handle: ConnectionHandle) {
+    desktop::monitor_input_state(
+        channel,
+        agent_windows::session_detect::input_blocked_reason,
+        |msg| {
+            let handle = handle.clone();
+            async move { handle.send_message(&msg).await.is_ok() }
+        },
+    )
+    .await
+}
+
 // --- Platform screen capture and input creation ---
 
+/// Take a one-off screenshot of the primary screen without opening a
+/// desktop session. The capture is torn down before this returns.
+pub async fn take_screenshot(
+    quality: u8,
+    scale: f32,
+    max_jpeg: usize,
+) -> Result<desktop::Screenshot> {
+    if !platform_has_interactive_session() {
+        anyhow::bail!(NO_INTERACTIVE_SESSION);
+    }
+    let mut screen = create_platform_screen(&DesktopConfig::default())?;
+    desktop::capture_screenshot(screen.as_mut(), quality, scale, max_jpeg).await
+}
+
+/// Session features this machine can serve, for `AgentInfo::capabilities`.
+/// Desktop entries name the capture backends; under X11 every monitor can
+/// be captured as one frame.
 #[cfg(target_os = "linux")]
-fn create_platform_screen() -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+pub fn session_capabilities() -> Vec<&'static str> {
+    use agent_linux::screen::DisplayBackend;
+
+    let mut caps = vec!["terminal"];
+    if let Some(backend) = agent_linux::screen::display_backend() {
+        caps.push("desktop");
+        caps.push(match backend {
+            DisplayBackend::X11 => "desktop.x11",
+            DisplayBackend::Wayland => "desktop.wayland",
+            DisplayBackend::Drm => "desktop.drm",
+        });
+        if backend == DisplayBackend::X11 {
+            caps.push("desktop.stitched");
+        }
+    }
+    if agent_linux::audio::is_available() {
+        caps.push("audio");
+    }
+    caps
+}
+
+/// Session features this machine can serve, for `AgentInfo::capabilities`.
+/// DXGI is listed when Desktop Duplication works right now; GDI takes over
+/// when it doesn't. Probed in the process that will capture, so from
+/// Session 0 only the terminal is reported.
+#[cfg(target_os = "windows")]
+pub fn session_capabilities() -> Vec<&'static str> {
+    let mut caps = vec!["terminal"];
+    if platform_has_interactive_session() {
+        caps.push("desktop");
+        if agent_windows::screen::dxgi_available() {
+            caps.push("desktop.dxgi");
+        }
+        caps.extend(["desktop.gdi", "desktop.stitched", "desktop.window"]);
+        if agent_windows::audio::is_available() {
+            caps.push("audio");
+        }
+    }
+    caps
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+pub fn session_capabilities() -> Vec<&'static str> {
+    Vec::new()
+}
+
+/// Reported when a desktop or screenshot is requested with nothing to capture
+#[cfg(target_os = "linux")]
+const NO_INTERACTIVE_SESSION: &str =
+    "no interactive desktop available: no X11 or Wayland display and no DRM console";
+#[cfg(target_os = "windows")]
+const NO_INTERACTIVE_SESSION: &str =
+    "no interactive desktop available: no session on the console, or the agent runs in Session 0";
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+const NO_INTERACTIVE_SESSION: &str = "screen capture not supported on this platform";
+
+#[cfg(target_os = "linux")]
+fn platform_has_interactive_session() -> bool {
+    agent_linux::screen::has_interactive_session()
+}
+
+#[cfg(target_os = "windows")]
+fn platform_has_interactive_session() -> bool {
+    agent_windows::session_detect::has_interactive_session()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn platform_has_interactive_session() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn create_platform_screen(
+    config: &DesktopConfig,
+) -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+    if config.targets_window() {
+        info!("window capture is not supported on Linux, capturing full screen");
+    }
+    if config.stitched {
+        return agent_linux::screen::create_stitched_screen_capture();
+    }
     agent_linux::screen::create_screen_capture()
 }
 
@@ -398,7 +935,9 @@ fn create_platform_input() -> Result<Box<dyn agent_platform::input::InputInjecto
 }
 
 #[cfg(target_os = "macos")]
-fn create_platform_screen() -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+fn create_platform_screen(
+    _config: &DesktopConfig,
+) -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
     anyhow::bail!("screen capture not yet implemented for macOS")
 }
 
@@ -408,7 +947,18 @@ fn create_platform_input() -> Result<Box<dyn agent_platform::input::InputInjecto
 }
 
 #[cfg(target_os = "windows")]
-fn create_platform_screen() -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+fn create_platform_screen(
+    config: &DesktopConfig,
+) -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+    if config.targets_window() {
+        return agent_windows::screen::create_window_capture(
+            config.window_title.as_deref(),
+            config.window_handle,
+        );
+    }
+    if config.stitched {
+        return agent_windows::screen::create_stitched_screen_capture();
+    }
     agent_windows::screen::create_screen_capture()
 }
 
@@ -418,7 +968,9 @@ fn create_platform_input() -> Result<Box<dyn agent_platform::input::InputInjecto
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
-fn create_platform_screen() -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
+fn create_platform_screen(
+    _config: &DesktopConfig,
+) -> Result<Box<dyn agent_platform::screen::ScreenCapture>> {
     anyhow::bail!("screen capture not supported on this platform")
 }
 
@@ -427,23 +979,77 @@ fn create_platform_input() -> Result<Box<dyn agent_platform::input::InputInjecto
     anyhow::bail!("input injection not supported on this platform")
 }
 
+#[cfg(target_os = "linux")]
+fn create_platform_system_info() -> Result<Box<dyn SystemInfo>> {
+    Ok(Box::new(agent_linux::system_info::LinuxSystemInfo::new()))
+}
+
+#[cfg(target_os = "macos")]
+fn create_platform_system_info() -> Result<Box<dyn SystemInfo>> {
+    anyhow::bail!("system info not yet implemented for macOS")
+}
+
+#[cfg(target_os = "windows")]
+fn create_platform_system_info() -> Result<Box<dyn SystemInfo>> {
+    Ok(Box::new(
+        agent_windows::system_info::WindowsSystemInfo::new(),
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn create_platform_system_info() -> Result<Box<dyn SystemInfo>> {
+    anyhow::bail!("system info not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+fn create_platform_indicator() -> Result<Box<dyn agent_platform::indicator::SessionIndicator>> {
+    agent_linux::indicator::create_session_indicator()
+}
+
+#[cfg(target_os = "windows")]
+fn create_platform_indicator() -> Result<Box<dyn agent_platform::indicator::SessionIndicator>> {
+    agent_windows::indicator::create_session_indicator()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn create_platform_indicator() -> Result<Box<dyn agent_platform::indicator::SessionIndicator>> {
+    anyhow::bail!("session indicator not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+fn create_platform_audio() -> Result<Box<dyn AudioCapture>> {
+    agent_linux::audio::create_audio_capture()
+}
+
+#[cfg(target_os = "windows")]
+fn create_platform_audio() -> Result<Box<dyn AudioCapture>> {
+    agent_windows::audio::create_audio_capture()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn create_platform_audio() -> Result<Box<dyn AudioCapture>> {
+    anyhow::bail!("audio capture not supported on this platform")
+}
+
 /// Create the platform-appropriate terminal implementation
 #[cfg(target_os = "linux")]
-fn create_platform_terminal() -> Result<Box<dyn Terminal>> {
+fn create_platform_terminal(_settings: &TerminalSettings) -> Result<Box<dyn Terminal>> {
     Ok(Box::new(agent_linux::terminal::LinuxTerminal::new()))
 }
 
 #[cfg(target_os = "macos")]
-fn create_platform_terminal() -> Result<Box<dyn Terminal>> {
+fn create_platform_terminal(_settings: &TerminalSettings) -> Result<Box<dyn Terminal>> {
     anyhow::bail!("terminal not yet implemented for macOS")
 }
 
 #[cfg(target_os = "windows")]
-fn create_platform_terminal() -> Result<Box<dyn Terminal>> {
-    Ok(Box::new(agent_windows::terminal::WindowsTerminal::new()))
+fn create_platform_terminal(settings: &TerminalSettings) -> Result<Box<dyn Terminal>> {
+    Ok(Box::new(
+        agent_windows::terminal::WindowsTerminal::with_conpty_flags(settings.conpty_flags),
+    ))
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
-fn create_platform_terminal() -> Result<Box<dyn Terminal>> {
+fn create_platform_terminal(_settings: &TerminalSettings) -> Result<Box<dyn Terminal>> {
     anyhow::bail!("terminal not supported on this platform")
 }
@@ -1,47 +1,251 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
 use anyhow::Result;
 use serde::Serialize;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
-use agent_platform::system_info::{CpuInfo, DiskInfo, MemoryInfo, NetworkInfo, SystemInfo};
+use crate::config::AgentConfig;
 use crate::connection::ConnectionHandle;
 use crate::protocol;
+use agent_platform::system_info::{
+    CpuInfo, DiskInfo, MemoryInfo, NetworkInfo, SensorInfo, SystemInfo, UserSession,
+};
+
+/// Pseudo and virtual filesystems left out of disk telemetry unless the
+/// config includes them
+pub const DEFAULT_EXCLUDED_FILESYSTEMS: &[&str] = &[
+    "proc",
+    "sysfs",
+    "devtmpfs",
+    "devpts",
+    "tmpfs",
+    "securityfs",
+    "cgroup",
+    "cgroup2",
+    "pstore",
+    "debugfs",
+    "hugetlbfs",
+    "mqueue",
+    "fusectl",
+    "configfs",
+    "binfmt_misc",
+    "autofs",
+    "tracefs",
+    "bpf",
+    "efivarfs",
+    "overlay",
+    "nsfs",
+    "ramfs",
+    "rpc_pipefs",
+    "nfsd",
+];
+
+/// Which volumes disk telemetry reports. Excludes win over includes, and
+/// includes win over `DEFAULT_EXCLUDED_FILESYSTEMS`. Mounts match by path
+/// prefix, filesystem types by name.
+#[derive(Debug, Clone, Default)]
+pub struct DiskFilter {
+    pub include_filesystems: Vec<String>,
+    pub exclude_filesystems: Vec<String>,
+    pub include_mounts: Vec<String>,
+    pub exclude_mounts: Vec<String>,
+}
+
+impl DiskFilter {
+    pub fn from_config(config: &AgentConfig) -> Self {
+        Self {
+            include_filesystems: config.disk_include_filesystems.clone(),
+            exclude_filesystems: config.disk_exclude_filesystems.clone(),
+            include_mounts: config.disk_include_mounts.clone(),
+            exclude_mounts: config.disk_exclude_mounts.clone(),
+        }
+    }
+
+    pub fn allows(&self, disk: &DiskInfo) -> bool {
+        let fs_listed = |list: &[String]| {
+            list.iter()
+                .any(|fs| fs.eq_ignore_ascii_case(&disk.filesystem))
+        };
+        let mount_listed = |list: &[String]| {
+            list.iter()
+                .any(|prefix| mount_under(&disk.mount_point, prefix))
+        };
+
+        if fs_listed(&self.exclude_filesystems) || mount_listed(&self.exclude_mounts) {
+            return false;
+        }
+        if fs_listed(&self.include_filesystems) || mount_listed(&self.include_mounts) {
+            return true;
+        }
+        !DEFAULT_EXCLUDED_FILESYSTEMS
+            .iter()
+            .any(|fs| fs.eq_ignore_ascii_case(&disk.filesystem))
+    }
+}
+
+/// Whether `mount_point` is `prefix` or lies below it. Matches whole path
+/// components, so `/snap` covers `/snap/core` but not `/snapshots`.
+fn mount_under(mount_point: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches(['/', '\\']);
+    match mount_point.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with(['/', '\\']) || prefix.is_empty(),
+        None => false,
+    }
+}
 
-/// Telemetry data sent to the server
+/// Telemetry data sent to the server. A section that couldn't be read is
+/// null, with the reason in `status`.
 #[derive(Debug, Clone, Serialize)]
 pub struct TelemetryData {
-    pub cpu: CpuInfo,
-    pub memory: MemoryInfo,
-    pub disks: Vec<DiskInfo>,
-    pub network: Vec<NetworkInfo>,
+    pub cpu: Option<CpuInfo>,
+    pub memory: Option<MemoryInfo>,
+    pub disks: Option<Vec<DiskInfo>>,
+    pub network: Option<Vec<NetworkInfo>>,
+    /// Users logged in right now
+    pub users: Option<Vec<UserSession>>,
+    /// Temperature and fan readings, left out when the device has none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensors: Option<SensorInfo>,
     pub uptime_ms: Option<u64>,
+    /// Some volume is below the configured free-space threshold
+    pub low_disk: bool,
+    /// Mount points of the volumes below the threshold
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub low_disk_mounts: Vec<String>,
+    /// Stalled screen captures re-initialized since the agent started
+    pub capture_restarts: u64,
     pub hostname: String,
     pub os_name: String,
     pub os_version: String,
     pub arch: String,
+    pub status: TelemetryStatus,
+}
+
+/// Per-section read status
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetryStatus {
+    pub cpu: SectionStatus,
+    pub memory: SectionStatus,
+    pub disks: SectionStatus,
+    pub network: SectionStatus,
+    pub users: SectionStatus,
+}
+
+/// `{"status": "ok"}` or `{"status": "error", "message": "..."}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum SectionStatus {
+    Ok,
+    Error { message: String },
+}
+
+/// Split a section result into its value and status, logging failures
+fn section<T>(name: &str, result: Result<T>) -> (Option<T>, SectionStatus) {
+    match result {
+        Ok(value) => (Some(value), SectionStatus::Ok),
+        Err(e) => {
+            warn!("telemetry: failed to read {}: {:#}", name, e);
+            (
+                None,
+                SectionStatus::Error {
+                    message: format!("{:#}", e),
+                },
+            )
+        }
+    }
 }
 
 /// Collects and sends system telemetry
 pub struct TelemetryCollector {
     sys_info: Box<dyn SystemInfo>,
+    /// Free-space percentage below which a volume counts as low (0 = off)
+    low_disk_percent: u8,
+    disk_filter: DiskFilter,
+    /// Interface byte counters from the previous collection, for throughput
+    last_network_sample: Mutex<Option<NetworkSample>>,
+    /// Unix seconds of the last telemetry sent to the server (0 = never)
+    last_sent_unix: AtomicU64,
+}
+
+/// Byte counters of every interface at one point in time
+struct NetworkSample {
+    at: Instant,
+    /// Interface name -> (rx_bytes, tx_bytes)
+    counters: HashMap<String, (Option<u64>, Option<u64>)>,
 }
 
 impl TelemetryCollector {
-    pub fn new(sys_info: Box<dyn SystemInfo>) -> Self {
-        Self { sys_info }
+    pub fn new(
+        sys_info: Box<dyn SystemInfo>,
+        low_disk_percent: u8,
+        disk_filter: DiskFilter,
+    ) -> Self {
+        Self {
+            sys_info,
+            low_disk_percent,
+            disk_filter,
+            last_network_sample: Mutex::new(None),
+            last_sent_unix: AtomicU64::new(0),
+        }
     }
 
     /// Collect current telemetry data
     pub fn collect(&self) -> TelemetryData {
+        let (cpu, cpu_status) = section("cpu", self.sys_info.cpu_info());
+        let (memory, memory_status) = section("memory", self.sys_info.memory_info());
+        let (disks, disks_status) = section("disks", self.sys_info.disk_info());
+        let disks = disks.map(|d| {
+            d.into_iter()
+                .filter(|d| self.disk_filter.allows(d))
+                .collect::<Vec<_>>()
+        });
+        let (mut network, network_status) = section("network", self.sys_info.network_interfaces());
+        if let Some(interfaces) = network.as_mut() {
+            let mut last = self
+                .last_network_sample
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            *last = Some(fill_network_rates(
+                interfaces,
+                last.as_ref(),
+                Instant::now(),
+            ));
+        }
+        let (users, users_status) = section("users", self.sys_info.user_sessions());
+        let sensors = Some(self.sys_info.sensors()).filter(|s| !s.is_empty());
+        let low_disk_mounts = disks
+            .as_deref()
+            .map(|d| low_disk_mounts(d, self.low_disk_percent))
+            .unwrap_or_default();
+        if !low_disk_mounts.is_empty() {
+            debug!("low disk space on {}", low_disk_mounts.join(", "));
+        }
+
         TelemetryData {
-            cpu: self.sys_info.cpu_info(),
-            memory: self.sys_info.memory_info(),
-            disks: self.sys_info.disk_info(),
-            network: self.sys_info.network_interfaces(),
+            cpu,
+            memory,
+            disks,
+            network,
+            users,
+            sensors,
             uptime_ms: read_uptime_ms(),
+            low_disk: !low_disk_mounts.is_empty(),
+            low_disk_mounts,
+            capture_restarts: crate::desktop::capture_restarts(),
             hostname: self.sys_info.hostname(),
             os_name: self.sys_info.os_name(),
             os_version: self.sys_info.os_version(),
             arch: self.sys_info.arch(),
+            status: TelemetryStatus {
+                cpu: cpu_status,
+                memory: memory_status,
+                disks: disks_status,
+                network: network_status,
+                users: users_status,
+            },
         }
     }
 
@@ -50,14 +254,33 @@ impl TelemetryCollector {
         let data = self.collect();
         let msg = protocol::Message::control_json(protocol::TELEMETRY_DATA, request_id, &data)?;
         handle.send_message(&msg).await?;
-        info!("telemetry sent (cpu: {:.1}%, mem: {}/{})",
-            data.cpu.usage_percent,
-            format_bytes(data.memory.used_bytes),
-            format_bytes(data.memory.total_bytes),
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.last_sent_unix.store(now, Ordering::Relaxed);
+        let cpu = data
+            .cpu
+            .as_ref()
+            .map_or_else(|| "n/a".to_string(), |c| format!("{:.1}%", c.usage_percent));
+        let mem = data.memory.as_ref().map_or_else(
+            || "n/a".to_string(),
+            |m| {
+                format!(
+                    "{}/{}",
+                    format_bytes(m.used_bytes),
+                    format_bytes(m.total_bytes)
+                )
+            },
         );
+        info!("telemetry sent (cpu: {}, mem: {})", cpu, mem);
         Ok(())
     }
 
+    /// When telemetry was last sent, in unix seconds
+    pub fn last_sent_unix(&self) -> Option<u64> {
+        Some(self.last_sent_unix.load(Ordering::Relaxed)).filter(|&t| t > 0)
+    }
+
     /// Send telemetry, logging errors instead of propagating
     pub async fn send_telemetry_quiet(&self, handle: &ConnectionHandle) {
         if let Err(e) = self.send_telemetry(handle, 0).await {
@@ -66,6 +289,19 @@ impl TelemetryCollector {
     }
 }
 
+/// Mount points whose free space is below `percent` of their size
+fn low_disk_mounts(disks: &[DiskInfo], percent: u8) -> Vec<String> {
+    if percent == 0 {
+        return Vec::new();
+    }
+    disks
+        .iter()
+        .filter(|d| d.total_bytes > 0)
+        .filter(|d| (d.available_bytes as f64 / d.total_bytes as f64) * 100.0 < percent as f64)
+        .map(|d| d.mount_point.clone())
+        .collect()
+}
+
 fn read_uptime_ms() -> Option<u64> {
     #[cfg(target_os = "linux")]
     {
@@ -93,3 +329,126 @@ fn format_bytes(bytes: u64) -> String {
         format!("{:.1} {}", val, units[i])
     }
 }
+
+/// Set each interface's throughput from the change in its counters since
+/// `previous`, and return the sample to compare the next collection with.
+/// Rates stay None on the first sample and when a counter went backwards
+/// (interface reset).
+fn fill_network_rates(
+    interfaces: &mut [NetworkInfo],
+    previous: Option<&NetworkSample>,
+    now: Instant,
+) -> NetworkSample {
+    let rate = |current: Option<u64>, before: Option<u64>, secs: f64| {
+        let delta = current?.checked_sub(before?)?;
+        (secs > 0.0).then(|| delta as f64 / secs)
+    };
+
+    if let Some(prev) = previous {
+        let secs = now.duration_since(prev.at).as_secs_f64();
+        for iface in interfaces.iter_mut() {
+            if let Some(&(rx, tx)) = prev.counters.get(&iface.name) {
+                iface.rx_bytes_per_sec = rate(iface.rx_bytes, rx, secs);
+                iface.tx_bytes_per_sec = rate(iface.tx_bytes, tx, secs);
+            }
+        }
+    }
+
+    NetworkSample {
+        at: now,
+        counters: interfaces
+            .iter()
+            .map(|i| (i.name.clone(), (i.rx_bytes, i.tx_bytes)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(mount_point: &str, total_bytes: u64, available_bytes: u64) -> DiskInfo {
+        fs_disk(mount_point, "ext4", total_bytes, available_bytes)
+    }
+
+    fn fs_disk(
+        mount_point: &str,
+        filesystem: &str,
+        total_bytes: u64,
+        available_bytes: u64,
+    ) -> DiskInfo {
+        DiskInfo {
+            mount_point: mount_point.to_string(),
+            filesystem: filesystem.to_string(),
+            total_bytes,
+            used_bytes: total_bytes - available_bytes,
+            available_bytes,
+        }
+    }
+
+    #[test]
+    fn test_low_disk_mounts() {
+        let disks = [
+            disk("/", 100, 5),
+            disk("/home", 100, 50),
+            disk("/boot", 100, 10),
+        ];
+        assert_eq!(low_disk_mounts(&disks, 10), vec!["/".to_string()]);
+        assert_eq!(
+            low_disk_mounts(&disks, 11),
+            vec!["/".to_string(), "/boot".to_string()]
+        );
+        assert!(low_disk_mounts(&disks, 0).is_empty());
+    }
+
+    #[test]
+    fn test_disk_filter() {
+        let root = disk("/", 100, 50);
+        let snap = fs_disk("/snap/core/123", "squashfs", 100, 0);
+        let share = fs_disk("/mnt/share", "cifs", 100, 50);
+        let run = fs_disk("/run", "tmpfs", 100, 90);
+
+        let defaults = DiskFilter::default();
+        assert!(defaults.allows(&root) && defaults.allows(&snap) && defaults.allows(&share));
+        assert!(!defaults.allows(&run));
+
+        let filter = DiskFilter {
+            include_filesystems: vec!["TMPFS".to_string()],
+            exclude_filesystems: vec!["cifs".to_string()],
+            include_mounts: vec![],
+            exclude_mounts: vec!["/snap/".to_string()],
+        };
+        assert!(filter.allows(&root) && filter.allows(&run));
+        assert!(!filter.allows(&snap) && !filter.allows(&share));
+        assert!(filter.allows(&disk("/snapshots", 100, 50)));
+    }
+
+    #[test]
+    fn test_network_rates_from_counter_deltas() {
+        let iface = |rx, tx| NetworkInfo {
+            name: "eth0".to_string(),
+            mac_address: None,
+            ipv4: None,
+            ipv6: None,
+            rx_bytes: Some(rx),
+            tx_bytes: Some(tx),
+            rx_bytes_per_sec: None,
+            tx_bytes_per_sec: None,
+        };
+        let start = Instant::now();
+
+        let mut first = [iface(1_000, 500)];
+        let sample = fill_network_rates(&mut first, None, start);
+        assert_eq!(first[0].rx_bytes_per_sec, None);
+
+        let mut second = [iface(21_000, 400)];
+        fill_network_rates(
+            &mut second,
+            Some(&sample),
+            start + std::time::Duration::from_secs(10),
+        );
+        assert_eq!(second[0].rx_bytes_per_sec, Some(2_000.0));
+        // The tx counter went backwards: no rate rather than a bogus one
+        assert_eq!(second[0].tx_bytes_per_sec, None);
+    }
+}
@@ -0,0 +1,246 @@
+//! Audio capture from the default output's monitor source, encoded as Opus.
+//!
+//! Runs a GStreamer pipeline (`pulsesrc` → `opusenc` → `oggmux`) as a child
+//! process and splits its Ogg stream back into Opus packets. `pulsesrc` talks
+//! to PulseAudio or to PipeWire through pipewire-pulse. The agent has to be
+//! able to reach the desktop user's sound server: a system service running
+//! as root needs `PULSE_SERVER` (or `XDG_RUNTIME_DIR`) pointing at it.
+
+use std::collections::VecDeque;
+use std::process::Stdio;
+
+use agent_platform::audio::{AudioCapture, AudioCodec, AudioFormat};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, ChildStdout, Command};
+use tracing::info;
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: u16 = 2;
+/// How long oggmux may hold a page back, in nanoseconds. Opus frames are
+/// 20ms, so this flushes roughly one packet per page.
+const MAX_PAGE_DELAY_NS: u64 = 20_000_000;
+
+/// Monitor-source capture through a `gst-launch-1.0` pipeline
+pub struct PulseAudioCapture {
+    child: Option<Child>,
+    stdout: Option<ChildStdout>,
+    packets: OggPackets,
+}
+
+impl PulseAudioCapture {
+    pub fn new() -> Self {
+        Self {
+            child: None,
+            stdout: None,
+            packets: OggPackets::default(),
+        }
+    }
+}
+
+impl Default for PulseAudioCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl AudioCapture for PulseAudioCapture {
+    async fn start(&mut self, bitrate: u32) -> Result<AudioFormat> {
+        let mut child = Command::new("gst-launch-1.0")
+            .args([
+                "--quiet",
+                "pulsesrc",
+                "device=@DEFAULT_MONITOR@",
+                "!",
+                "audioconvert",
+                "!",
+                "audioresample",
+                "!",
+                &format!("audio/x-raw,rate={},channels={}", SAMPLE_RATE, CHANNELS),
+                "!",
+                "opusenc",
+                &format!("bitrate={}", bitrate),
+                "frame-size=20",
+                "!",
+                "oggmux",
+                &format!("max-delay={}", MAX_PAGE_DELAY_NS),
+                &format!("max-page-delay={}", MAX_PAGE_DELAY_NS),
+                "!",
+                "fdsink",
+                "fd=1",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("failed to start gst-launch-1.0 — is gstreamer1.0-tools installed (with the pulseaudio and opus plugins)?")?;
+
+        self.stdout = child.stdout.take();
+        self.child = Some(child);
+        self.packets = OggPackets::default();
+
+        info!(
+            "audio capture started: default monitor source, opus {} bps",
+            bitrate
+        );
+        Ok(AudioFormat {
+            codec: AudioCodec::Opus,
+            sample_rate: SAMPLE_RATE,
+            channels: CHANNELS,
+        })
+    }
+
+    async fn next_packet(&mut self) -> Result<Option<Vec<u8>>> {
+        let stdout = self.stdout.as_mut().context("audio capture not started")?;
+        let mut buf = [0u8; 4096];
+
+        loop {
+            if let Some(packet) = self.packets.next_audio_packet() {
+                return Ok(Some(packet));
+            }
+
+            let n = stdout
+                .read(&mut buf)
+                .await
+                .context("failed to read audio pipeline")?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.packets.push(&buf[..n]);
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stdout = None;
+        if let Some(mut child) = self.child.take() {
+            let _ = child.start_kill();
+        }
+    }
+}
+
+impl Drop for PulseAudioCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Splits an Ogg byte stream into its packets
+#[derive(Default)]
+struct OggPackets {
+    /// Bytes not yet parsed into a page
+    buf: Vec<u8>,
+    /// Packet continuing onto the next page
+    partial: Vec<u8>,
+    ready: VecDeque<Vec<u8>>,
+}
+
+impl OggPackets {
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        while self.parse_page() {}
+    }
+
+    /// Next Opus audio packet, skipping the OpusHead/OpusTags headers
+    fn next_audio_packet(&mut self) -> Option<Vec<u8>> {
+        while let Some(packet) = self.ready.pop_front() {
+            if !packet.starts_with(b"OpusHead") && !packet.starts_with(b"OpusTags") {
+                return Some(packet);
+            }
+        }
+        None
+    }
+
+    /// Parse one complete page off the front of the buffer, if there is one
+    fn parse_page(&mut self) -> bool {
+        // Resync on the capture pattern if the stream got garbled
+        match self.buf.windows(4).position(|w| w == b"OggS") {
+            Some(0) => {}
+            Some(start) => {
+                self.buf.drain(..start);
+            }
+            None => {
+                let keep = self.buf.len().min(3);
+                self.buf.drain(..self.buf.len() - keep);
+                return false;
+            }
+        }
+
+        const HEADER_LEN: usize = 27;
+        if self.buf.len() < HEADER_LEN {
+            return false;
+        }
+        let segments = self.buf[26] as usize;
+        if self.buf.len() < HEADER_LEN + segments {
+            return false;
+        }
+        let lacing = &self.buf[HEADER_LEN..HEADER_LEN + segments];
+        let body_len: usize = lacing.iter().map(|&l| l as usize).sum();
+        let page_len = HEADER_LEN + segments + body_len;
+        if self.buf.len() < page_len {
+            return false;
+        }
+
+        // A lacing value below 255 ends a packet; 255 means it continues
+        let mut offset = HEADER_LEN + segments;
+        for &len in lacing {
+            let len = len as usize;
+            self.partial
+                .extend_from_slice(&self.buf[offset..offset + len]);
+            offset += len;
+            if len < 255 {
+                self.ready.push_back(std::mem::take(&mut self.partial));
+            }
+        }
+
+        self.buf.drain(..page_len);
+        true
+    }
+}
+
+/// Whether `gst-launch-1.0`, which runs the capture pipeline, is on PATH
+pub fn is_available() -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| dir.join("gst-launch-1.0").is_file())
+    })
+}
+
+/// Create the audio capture for this system
+pub fn create_audio_capture() -> Result<Box<dyn AudioCapture>> {
+    Ok(Box::new(PulseAudioCapture::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(lacing: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0; 22]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(lacing);
+        page.extend_from_slice(body);
+        page
+    }
+
+    #[test]
+    fn test_ogg_packets_across_pages() {
+        let mut packets = OggPackets::default();
+        packets.push(&page(&[8], b"OpusHead"));
+
+        // A 300-byte packet split over two pages, then a small one
+        let long = vec![7u8; 300];
+        let mut stream = page(&[255], &long[..255]);
+        stream.extend(page(&[45, 3], &[&long[255..], b"abc"].concat()));
+
+        // Feed it in awkward pieces
+        for piece in stream.chunks(10) {
+            packets.push(piece);
+        }
+
+        assert_eq!(packets.next_audio_packet(), Some(long));
+        assert_eq!(packets.next_audio_packet(), Some(b"abc".to_vec()));
+        assert_eq!(packets.next_audio_packet(), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! Linux system clock — reads the clock and its NTP state via adjtimex,
+//! steps it with clock_settime, and changes the time zone through timedatectl.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use agent_platform::clock::{ClockInfo, SystemClock};
+
+/// adjtimex clock state meaning the kernel clock isn't synchronized
+const TIME_ERROR: i32 = 5;
+
+pub struct LinuxClock;
+
+impl SystemClock for LinuxClock {
+    fn info(&self) -> Result<ClockInfo> {
+        let unix_millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .context("system clock is before the Unix epoch")?
+            .as_millis() as u64;
+
+        let timezone = std::fs::read_link("/etc/localtime")
+            .ok()
+            .and_then(|target| timezone_from_localtime(&target))
+            .or_else(|| {
+                std::fs::read_to_string("/etc/timezone")
+                    .ok()
+                    .map(|tz| tz.trim().to_string())
+                    .filter(|tz| !tz.is_empty())
+            });
+
+        Ok(ClockInfo {
+            unix_millis,
+            timezone,
+            ntp_synchronized: ntp_synchronized(),
+        })
+    }
+
+    fn set_time(&self, unix_millis: u64) -> Result<()> {
+        info!("setting system clock to {} ms", unix_millis);
+        let ts = libc::timespec {
+            tv_sec: (unix_millis / 1000) as libc::time_t,
+            tv_nsec: ((unix_millis % 1000) * 1_000_000) as _,
+        };
+        if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &ts) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("clock_settime failed");
+        }
+        Ok(())
+    }
+
+    fn set_timezone(&self, timezone: &str) -> Result<()> {
+        if timezone.is_empty() || timezone.starts_with('-') {
+            anyhow::bail!("invalid time zone: {:?}", timezone);
+        }
+        info!("setting time zone to {}", timezone);
+        run("timedatectl", &["set-timezone", "--", timezone])
+    }
+
+    fn sync(&self) -> Result<()> {
+        info!("resynchronizing system clock");
+        // chrony steps right away; systemd-timesyncd syncs when (re)started
+        if run("chronyc", &["makestep"]).is_ok() {
+            return Ok(());
+        }
+        run("timedatectl", &["set-ntp", "true"])?;
+        run("systemctl", &["restart", "systemd-timesyncd"])
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run {}", program))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} {} failed: {}", program, args[0], stderr.trim());
+    }
+    Ok(())
+}
+
+/// Whether the kernel considers the clock NTP-synchronized
+fn ntp_synchronized() -> Option<bool> {
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut tx) };
+    (state >= 0).then_some(state != TIME_ERROR)
+}
+
+/// Zone name from the /etc/localtime symlink target
+/// (`/usr/share/zoneinfo/Europe/Berlin` -> `Europe/Berlin`)
+fn timezone_from_localtime(target: &Path) -> Option<String> {
+    let target = target.to_str()?;
+    let (_, zone) = target.split_once("zoneinfo/")?;
+    (!zone.is_empty()).then(|| zone.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timezone_from_localtime() {
+        assert_eq!(
+            timezone_from_localtime(Path::new("/usr/share/zoneinfo/Europe/Berlin")).as_deref(),
+            Some("Europe/Berlin")
+        );
+        assert_eq!(
+            timezone_from_localtime(Path::new("../usr/share/zoneinfo/UTC")).as_deref(),
+            Some("UTC")
+        );
+        assert_eq!(
+            timezone_from_localtime(Path::new("/etc/alt-localtime")),
+            None
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::time::UNIX_EPOCH;
 
+use agent_platform::filesystem::{page_dir_entries, range_len, DirPage, FileEntry, FileSystem};
 use anyhow::{Context, Result};
-use agent_platform::filesystem::{FileEntry, FileSystem};
 
 pub struct LinuxFileSystem;
 
@@ -71,10 +72,42 @@ impl FileSystem for LinuxFileSystem {
         Ok(result)
     }
 
+    fn list_dir_page(&self, path: &str, offset: u64, limit: usize) -> Result<DirPage> {
+        let entries =
+            fs::read_dir(path).with_context(|| format!("failed to read directory {}", path))?;
+
+        Ok(page_dir_entries(entries, offset, limit, |entry| {
+            let entry = entry
+                .map_err(|e| tracing::warn!("skipping dir entry: {}", e))
+                .ok()?;
+            Self::to_file_entry(&entry.path())
+                .map_err(|e| tracing::warn!("skipping {}: {}", entry.path().display(), e))
+                .ok()
+        }))
+    }
+
     fn read_file(&self, path: &str) -> Result<Vec<u8>> {
         fs::read(path).with_context(|| format!("failed to read file {}", path))
     }
 
+    fn read_file_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut file =
+            fs::File::open(path).with_context(|| format!("failed to open file {}", path))?;
+        let size = file
+            .metadata()
+            .with_context(|| format!("failed to stat file {}", path))?
+            .len();
+        let len = range_len(size, offset, len)?;
+
+        file.seek(SeekFrom::Start(offset))
+            .with_context(|| format!("failed to seek in file {}", path))?;
+        let mut data = Vec::with_capacity(len as usize);
+        file.take(len)
+            .read_to_end(&mut data)
+            .with_context(|| format!("failed to read file {}", path))?;
+        Ok(data)
+    }
+
     fn write_file(&self, path: &str, data: &[u8]) -> Result<()> {
         // Create parent directories if they don't exist
         if let Some(parent) = Path::new(path).parent() {
@@ -84,6 +117,20 @@ impl FileSystem for LinuxFileSystem {
         fs::write(path, data).with_context(|| format!("failed to write file {}", path))
     }
 
+    fn append_file(&self, path: &str, data: &[u8]) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open file {}", path))?;
+        file.write_all(data)
+            .with_context(|| format!("failed to write file {}", path))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        fs::rename(from, to).with_context(|| format!("failed to move {} to {}", from, to))
+    }
+
     fn delete(&self, path: &str) -> Result<()> {
         let p = Path::new(path);
         if p.is_dir() {
@@ -103,3 +150,71 @@ impl FileSystem for LinuxFileSystem {
         Self::to_file_entry(Path::new(path))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_file_range() {
+        let path = std::env::temp_dir().join(format!("range-test-{}", std::process::id()));
+        fs::write(&path, b"0123456789").unwrap();
+        let path_str = path.to_str().unwrap();
+        let fs_impl = LinuxFileSystem::new();
+
+        assert_eq!(fs_impl.read_file_range(path_str, 3, 4).unwrap(), b"3456");
+        // Truncated at end of file; empty right at the end
+        assert_eq!(fs_impl.read_file_range(path_str, 8, 100).unwrap(), b"89");
+        assert!(fs_impl.read_file_range(path_str, 10, 5).unwrap().is_empty());
+        assert!(fs_impl.read_file_range(path_str, 11, 1).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_append_then_rename() {
+        let dir = std::env::temp_dir().join(format!("append-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let part = dir.join("a.part");
+        let dest = dir.join("a");
+        fs::write(&dest, b"old").unwrap();
+        let fs_impl = LinuxFileSystem::new();
+
+        fs_impl.append_file(part.to_str().unwrap(), b"01").unwrap();
+        fs_impl.append_file(part.to_str().unwrap(), b"23").unwrap();
+        fs_impl
+            .rename(part.to_str().unwrap(), dest.to_str().unwrap())
+            .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"0123");
+        assert!(!part.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_list_dir_page() {
+        let dir = std::env::temp_dir().join(format!("page-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for i in 0..5 {
+            fs::write(dir.join(format!("f{}", i)), b"x").unwrap();
+        }
+        let dir_str = dir.to_str().unwrap();
+        let fs_impl = LinuxFileSystem::new();
+
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = fs_impl.list_dir_page(dir_str, offset, 2).unwrap();
+            assert!(page.entries.len() <= 2);
+            names.extend(page.entries.into_iter().map(|e| e.name));
+            match page.next_offset {
+                Some(next) => offset = next,
+                None => break,
+            }
+        }
+        names.sort();
+        assert_eq!(names, ["f0", "f1", "f2", "f3", "f4"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
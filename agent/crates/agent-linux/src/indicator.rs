@@ -0,0 +1,209 @@
+//! "Remote session active" overlay for X11: a small override-redirect window
+//! centered at the top of the screen, kept above other windows.
+//!
+//! The window is owned by its own thread and connection, which redraws it on
+//! Expose and re-raises it periodically until hidden.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use agent_platform::indicator::SessionIndicator;
+use anyhow::{bail, Context, Result};
+
+/// Width of a glyph in the "fixed" core font
+const CHAR_WIDTH: u16 = 6;
+const PADDING: u16 = 12;
+const HEIGHT: u16 = 24;
+/// Dark red as a TrueColor pixel value
+const BACKGROUND: u32 = 0x00B0_0000;
+/// How often the window is raised back above other windows
+const RAISE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Overlay window shown while a remote desktop session is active.
+pub struct X11SessionIndicator {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl X11SessionIndicator {
+    pub fn new() -> Self {
+        Self {
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl Default for X11SessionIndicator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionIndicator for X11SessionIndicator {
+    fn show(&mut self, text: &str) -> Result<()> {
+        if self.thread.is_some() {
+            return Ok(());
+        }
+
+        let overlay = Overlay::create(text)?;
+        self.stop.store(false, Ordering::SeqCst);
+        let stop = self.stop.clone();
+
+        let thread = std::thread::Builder::new()
+            .name("session-indicator".into())
+            .spawn(move || overlay.run(&stop))
+            .context("failed to spawn session indicator thread")?;
+        self.thread = Some(thread);
+
+        tracing::info!("session indicator shown");
+        Ok(())
+    }
+
+    fn hide(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::SeqCst);
+            let _ = thread.join();
+            tracing::info!("session indicator hidden");
+        }
+    }
+}
+
+impl Drop for X11SessionIndicator {
+    fn drop(&mut self) {
+        self.hide();
+    }
+}
+
+struct Overlay {
+    conn: xcb::Connection,
+    window: u32,
+    gc: u32,
+    font: u32,
+    text: String,
+}
+
+// SAFETY: the overlay is handed to its thread once and only used there
+unsafe impl Send for Overlay {}
+
+impl Overlay {
+    fn create(text: &str) -> Result<Self> {
+        let (conn, screen_num) =
+            xcb::Connection::connect(None).context("failed to connect to X11 display")?;
+
+        let setup = conn.get_setup();
+        let screen = setup
+            .roots()
+            .nth(screen_num as usize)
+            .context("no X11 screen found")?;
+
+        let width = text.len() as u16 * CHAR_WIDTH + 2 * PADDING;
+        let x = (screen.width_in_pixels().saturating_sub(width) / 2) as i16;
+
+        let window = conn.generate_id();
+        let cookie = xcb::create_window_checked(
+            &conn,
+            xcb::COPY_FROM_PARENT as u8,
+            window,
+            screen.root(),
+            x,
+            0,
+            width,
+            HEIGHT,
+            0,
+            xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
+            screen.root_visual(),
+            &[
+                (xcb::CW_BACK_PIXEL, BACKGROUND),
+                (xcb::CW_OVERRIDE_REDIRECT, 1),
+                (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_EXPOSURE),
+            ],
+        );
+        if cookie.request_check().is_err() {
+            bail!("failed to create indicator window");
+        }
+
+        let font = conn.generate_id();
+        xcb::open_font(&conn, font, "fixed");
+
+        let gc = conn.generate_id();
+        xcb::create_gc(
+            &conn,
+            gc,
+            window,
+            &[
+                (xcb::GC_FOREGROUND, screen.white_pixel()),
+                (xcb::GC_BACKGROUND, BACKGROUND),
+                (xcb::GC_FONT, font),
+            ],
+        );
+
+        xcb::map_window(&conn, window);
+        conn.flush();
+
+        Ok(Self {
+            conn,
+            window,
+            gc,
+            font,
+            text: text.to_string(),
+        })
+    }
+
+    fn run(self, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            let mut exposed = false;
+            while let Some(event) = self.conn.poll_for_event() {
+                if event.response_type() & !0x80 == xcb::EXPOSE {
+                    exposed = true;
+                }
+            }
+            if exposed {
+                self.draw();
+            }
+
+            // Override-redirect windows aren't kept on top by the window
+            // manager, so raise it ourselves
+            xcb::configure_window(
+                &self.conn,
+                self.window,
+                &[(xcb::CONFIG_WINDOW_STACK_MODE as u16, xcb::STACK_MODE_ABOVE)],
+            );
+            self.conn.flush();
+
+            std::thread::sleep(RAISE_INTERVAL);
+        }
+
+        xcb::free_gc(&self.conn, self.gc);
+        xcb::close_font(&self.conn, self.font);
+        xcb::destroy_window(&self.conn, self.window);
+        self.conn.flush();
+    }
+
+    fn draw(&self) {
+        // Baseline sits roughly centered for the 13px "fixed" font
+        xcb::image_text_8(
+            &self.conn,
+            self.window,
+            self.gc,
+            PADDING as i16,
+            (HEIGHT as i16 + 9) / 2,
+            &self.text,
+        );
+        self.conn.flush();
+    }
+}
+
+/// Create the session indicator for the running display server.
+pub fn create_session_indicator() -> Result<Box<dyn SessionIndicator>> {
+    if std::env::var("DISPLAY").is_ok() {
+        return Ok(Box::new(X11SessionIndicator::new()));
+    }
+
+    if std::env::var("WAYLAND_DISPLAY").is_ok() {
+        bail!("session indicator is not yet implemented for Wayland");
+    }
+
+    bail!("no display server detected for session indicator");
+}
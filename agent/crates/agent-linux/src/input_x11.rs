@@ -1,8 +1,10 @@
 //! X11 input injection using XTest extension.
 
 use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+
 use agent_platform::input::{
-    ButtonAction, InputInjector, KeyAction, Modifiers, MouseButton,
+    char_to_keysym, ButtonAction, InputInjector, KeyAction, Modifiers, MouseButton,
 };
 
 /// X11 input injector using XTest
@@ -10,8 +12,58 @@ pub struct X11InputInjector {
     conn: xcb::Connection,
     root: u32,
     initialized: bool,
+    /// Physical devices disabled while local input is blocked
+    blocked_devices: Vec<u32>,
+    /// Keys pressed through `key_sym`, so the release hits the same key
+    held_keysyms: HashMap<u32, KeysymKey>,
+}
+
+/// The key a keysym was typed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeysymKey {
+    keycode: u8,
+    /// The keysym is the key's shifted symbol
+    shift: bool,
+    /// A spare keycode was bound to the keysym and must be unbound after
+    remapped: bool,
+}
+
+/// The core keyboard mapping: the keysyms of each keycode, which reflect the
+/// active XKB layout
+struct KeyboardMapping {
+    min_keycode: u8,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<u32>,
+}
+
+impl KeyboardMapping {
+    /// Key producing `keysym` unshifted or shifted. Other levels (AltGr)
+    /// aren't used: their modifiers vary between layouts.
+    fn find(&self, keysym: u32) -> Option<(u8, bool)> {
+        self.keysyms
+            .chunks(self.keysyms_per_keycode.max(1))
+            .enumerate()
+            .find_map(|(i, syms)| {
+                let keycode = self.min_keycode.checked_add(i as u8)?;
+                match syms.iter().take(2).position(|&s| s == keysym)? {
+                    0 => Some((keycode, false)),
+                    _ => Some((keycode, true)),
+                }
+            })
+    }
+
+    /// A keycode with no keysyms, free to be bound temporarily
+    fn spare_keycode(&self) -> Option<u8> {
+        self.keysyms
+            .chunks(self.keysyms_per_keycode.max(1))
+            .position(|syms| syms.iter().all(|&s| s == 0))
+            .and_then(|i| self.min_keycode.checked_add(i as u8))
+    }
 }
 
+/// Time for clients to pick up a temporary key binding before it's removed
+const REMAP_SETTLE: std::time::Duration = std::time::Duration::from_millis(20);
+
 // SAFETY: xcb::Connection is thread-safe when accessed serially
 unsafe impl Send for X11InputInjector {}
 unsafe impl Sync for X11InputInjector {}
@@ -44,6 +96,8 @@ impl X11InputInjector {
             conn: unsafe { std::mem::zeroed() },
             root: 0,
             initialized: false,
+            blocked_devices: Vec::new(),
+            held_keysyms: HashMap::new(),
         }
     }
 
@@ -98,6 +152,64 @@ impl X11InputInjector {
         self.fake_input(event_type, keycode, 0, 0)
     }
 
+    fn keyboard_mapping(&self) -> Result<KeyboardMapping> {
+        if !self.initialized {
+            bail!("input injector not initialized");
+        }
+        let setup = self.conn.get_setup();
+        let min_keycode = setup.min_keycode();
+        let count = setup.max_keycode() - min_keycode + 1;
+        let reply = xcb::get_keyboard_mapping(&self.conn, min_keycode, count)
+            .get_reply()
+            .context("failed to read the keyboard mapping")?;
+        Ok(KeyboardMapping {
+            min_keycode,
+            keysyms_per_keycode: reply.keysyms_per_keycode() as usize,
+            keysyms: reply.keysyms().to_vec(),
+        })
+    }
+
+    /// Bind `keysym` (unshifted and shifted) to `keycode`, or unbind the
+    /// keycode when `keysym` is 0
+    fn bind_keycode(&self, mapping: &KeyboardMapping, keycode: u8, keysym: u32) -> Result<()> {
+        let syms = vec![keysym; mapping.keysyms_per_keycode.max(1)];
+        xcb::change_keyboard_mapping_checked(&self.conn, 1, keycode, syms.len() as u8, &syms)
+            .request_check()
+            .context("failed to change the keyboard mapping")?;
+        self.conn.flush();
+        Ok(())
+    }
+
+    /// The key to press for `keysym`, binding it to a spare keycode if the
+    /// current layout has no key for it
+    fn key_for_keysym(&self, mapping: &KeyboardMapping, keysym: u32) -> Result<KeysymKey> {
+        if let Some((keycode, shift)) = mapping.find(keysym) {
+            return Ok(KeysymKey {
+                keycode,
+                shift,
+                remapped: false,
+            });
+        }
+        let keycode = mapping
+            .spare_keycode()
+            .context("no spare keycode to bind the keysym to")?;
+        self.bind_keycode(mapping, keycode, keysym)?;
+        std::thread::sleep(REMAP_SETTLE);
+        Ok(KeysymKey {
+            keycode,
+            shift: false,
+            remapped: true,
+        })
+    }
+
+    fn release_keysym_key(&self, mapping: &KeyboardMapping, key: KeysymKey) -> Result<()> {
+        if key.remapped {
+            std::thread::sleep(REMAP_SETTLE);
+            self.bind_keycode(mapping, key.keycode, 0)?;
+        }
+        Ok(())
+    }
+
     fn apply_modifiers(&self, mods: Modifiers, press: bool) -> Result<()> {
         if mods.shift {
             self.press_modifier(XK_SHIFT_L, press)?;
@@ -175,72 +287,169 @@ impl InputInjector for X11InputInjector {
         Ok(())
     }
 
+    fn set_local_input_blocked(&mut self, blocked: bool) -> Result<()> {
+        if blocked {
+            if self.blocked_devices.is_empty() {
+                self.blocked_devices = disable_physical_devices()?;
+            }
+        } else {
+            enable_devices(&std::mem::take(&mut self.blocked_devices));
+        }
+        Ok(())
+    }
+
     fn type_text(&mut self, text: &str) -> Result<()> {
-        // For text typing, use XTest to simulate key events.
-        // This is a simplified version — for full Unicode support,
-        // XInput2 or xdotool approach would be better.
-        // Here we handle ASCII by mapping to keycodes.
+        // Keys are looked up in the active layout; characters it lacks are
+        // bound to a spare keycode for the keystroke, as xdotool does
+        let mapping = self.keyboard_mapping()?;
         for ch in text.chars() {
-            if let Some((keycode, shift)) = char_to_keycode(ch) {
-                if shift {
+            let key = self.key_for_keysym(&mapping, char_to_keysym(ch))?;
+            if key.shift {
+                self.press_modifier(XK_SHIFT_L, true)?;
+            }
+            self.fake_input(KEY_PRESS, key.keycode, 0, 0)?;
+            self.fake_input(KEY_RELEASE, key.keycode, 0, 0)?;
+            if key.shift {
+                self.press_modifier(XK_SHIFT_L, false)?;
+            }
+            self.release_keysym_key(&mapping, key)?;
+        }
+        Ok(())
+    }
+
+    fn key_sym(&mut self, keysym: u32, action: KeyAction, mods: Modifiers) -> Result<()> {
+        let mapping = self.keyboard_mapping()?;
+        match action {
+            KeyAction::Press => {
+                let key = self.key_for_keysym(&mapping, keysym)?;
+                self.held_keysyms.insert(keysym, key);
+                self.apply_modifiers(mods, true)?;
+                if key.shift && !mods.shift {
                     self.press_modifier(XK_SHIFT_L, true)?;
                 }
-                self.fake_input(KEY_PRESS, keycode, 0, 0)?;
-                self.fake_input(KEY_RELEASE, keycode, 0, 0)?;
-                if shift {
+                self.fake_input(KEY_PRESS, key.keycode, 0, 0)?;
+            }
+            KeyAction::Release => {
+                let Some(key) = self.held_keysyms.remove(&keysym) else {
+                    return Ok(());
+                };
+                self.fake_input(KEY_RELEASE, key.keycode, 0, 0)?;
+                if key.shift && !mods.shift {
                     self.press_modifier(XK_SHIFT_L, false)?;
                 }
+                self.apply_modifiers(mods, false)?;
+                self.release_keysym_key(&mapping, key)?;
             }
         }
         Ok(())
     }
 }
 
-/// Map ASCII character to X11 keycode + shift flag.
-/// Keycodes here are for a standard US keyboard layout (evdev + 8).
-fn char_to_keycode(ch: char) -> Option<(u8, bool)> {
-    // X11 keycode = evdev + 8. These are standard US QWERTY keycodes.
-    match ch {
-        'a'..='z' => Some((ch as u8 - b'a' + 38, false)),  // 'a' = keycode 38
-        'A'..='Z' => Some((ch as u8 - b'A' + 38, true)),
-        '0' => Some((19, false)),
-        '1'..='9' => Some((ch as u8 - b'1' + 10, false)),
-        ' ' => Some((65, false)),
-        '\n' | '\r' => Some((36, false)),     // Return
-        '\t' => Some((23, false)),             // Tab
-        '-' => Some((20, false)),
-        '=' => Some((21, false)),
-        '[' => Some((34, false)),
-        ']' => Some((35, false)),
-        '\\' => Some((51, false)),
-        ';' => Some((47, false)),
-        '\'' => Some((48, false)),
-        ',' => Some((59, false)),
-        '.' => Some((60, false)),
-        '/' => Some((61, false)),
-        '`' => Some((49, false)),
-        // Shifted variants
-        '!' => Some((10, true)),
-        '@' => Some((11, true)),
-        '#' => Some((12, true)),
-        '$' => Some((13, true)),
-        '%' => Some((14, true)),
-        '^' => Some((15, true)),
-        '&' => Some((16, true)),
-        '*' => Some((17, true)),
-        '(' => Some((18, true)),
-        ')' => Some((19, true)),
-        '_' => Some((20, true)),
-        '+' => Some((21, true)),
-        '{' => Some((34, true)),
-        '}' => Some((35, true)),
-        '|' => Some((51, true)),
-        ':' => Some((47, true)),
-        '"' => Some((48, true)),
-        '<' => Some((59, true)),
-        '>' => Some((60, true)),
-        '?' => Some((61, true)),
-        '~' => Some((49, true)),
-        _ => None,
+impl Drop for X11InputInjector {
+    fn drop(&mut self) {
+        // Never leave the local keyboard and mouse disabled
+        enable_devices(&self.blocked_devices);
+    }
+}
+
+// --- Local input blocking ---
+//
+// A pointer/keyboard grab would also capture our own XTest events, so the
+// physical slave devices are disabled through `xinput` instead. The XTEST
+// slave devices stay enabled and injected input keeps working.
+
+/// Disable every physical slave pointer and keyboard. Returns the ids of the
+/// devices that were disabled.
+fn disable_physical_devices() -> Result<Vec<u32>> {
+    let output = std::process::Command::new("xinput")
+        .args(["list", "--short"])
+        .output()
+        .context("failed to run xinput (is it installed?)")?;
+    if !output.status.success() {
+        bail!(
+            "xinput list failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let ids = physical_device_ids(&String::from_utf8_lossy(&output.stdout));
+    if ids.is_empty() {
+        bail!("no physical input devices found");
+    }
+
+    let mut disabled = Vec::new();
+    for id in ids {
+        if xinput_set_enabled(id, false) {
+            disabled.push(id);
+        }
+    }
+    if disabled.is_empty() {
+        bail!("xinput could not disable any input device");
+    }
+
+    tracing::info!("disabled {} local input device(s)", disabled.len());
+    Ok(disabled)
+}
+
+fn enable_devices(ids: &[u32]) {
+    for &id in ids {
+        if !xinput_set_enabled(id, true) {
+            tracing::warn!("failed to re-enable input device {}", id);
+        }
+    }
+}
+
+fn xinput_set_enabled(id: u32, enabled: bool) -> bool {
+    std::process::Command::new("xinput")
+        .args([if enabled { "enable" } else { "disable" }, &id.to_string()])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Pick the slave devices out of `xinput list --short`, skipping the XTEST
+/// devices that carry injected input.
+fn physical_device_ids(list: &str) -> Vec<u32> {
+    list.lines()
+        .filter(|line| line.contains("[slave") && !line.contains("XTEST"))
+        .filter_map(|line| {
+            let rest = &line[line.find("id=")? + 3..];
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            rest[..end].parse().ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_physical_device_ids_skip_xtest_and_masters() {
+        let list = "\
+⎡ Virtual core pointer                    \tid=2\t[master pointer  (3)]
+⎜   ↳ Virtual core XTEST pointer              \tid=4\t[slave  pointer  (2)]
+⎜   ↳ Logitech USB Receiver                   \tid=10\t[slave  pointer  (2)]
+⎣ Virtual core keyboard                   \tid=3\t[master keyboard (2)]
+    ↳ Virtual core XTEST keyboard             \tid=5\t[slave  keyboard (3)]
+    ↳ AT Translated Set 2 keyboard            \tid=12\t[slave  keyboard (3)]
+";
+        assert_eq!(physical_device_ids(list), vec![10, 12]);
+    }
+
+    #[test]
+    fn test_keyboard_mapping_lookup() {
+        // Keycodes 8-10 on a German layout: z/Z, ö/Ö, then an unbound key
+        let mapping = KeyboardMapping {
+            min_keycode: 8,
+            keysyms_per_keycode: 2,
+            keysyms: vec![0x7a, 0x5a, 0xf6, 0xd6, 0, 0],
+        };
+        assert_eq!(mapping.find(char_to_keysym('z')), Some((8, false)));
+        assert_eq!(mapping.find(char_to_keysym('Ö')), Some((9, true)));
+        assert_eq!(mapping.find(char_to_keysym('€')), None);
+        assert_eq!(mapping.spare_keycode(), Some(10));
     }
 }
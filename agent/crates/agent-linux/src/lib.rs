@@ -13,16 +13,37 @@ pub mod input_x11;
 #[cfg(target_os = "linux")]
 pub mod input;
 
+#[cfg(target_os = "linux")]
+pub mod indicator;
+
 #[cfg(target_os = "linux")]
 pub mod filesystem;
 
+#[cfg(target_os = "linux")]
+pub mod audio;
+
 #[cfg(target_os = "linux")]
 pub mod system_info;
 
 #[cfg(target_os = "linux")]
 pub mod screen_wayland;
 
+#[cfg(target_os = "linux")]
+pub mod screen_drm;
+
 // pub mod input_wayland;  // Wayland input via uinput (future)
 
 #[cfg(target_os = "linux")]
 pub mod service;
+
+#[cfg(target_os = "linux")]
+pub mod clock;
+
+#[cfg(target_os = "linux")]
+pub mod network;
+
+#[cfg(target_os = "linux")]
+pub mod notification;
+
+#[cfg(target_os = "linux")]
+pub mod sd_notify;
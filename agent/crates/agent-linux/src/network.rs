@@ -0,0 +1,107 @@
+//! Linux network troubleshooting — flushes systemd-resolved or nscd, and
+//! renews leases through systemd-networkd, dhclient or NetworkManager,
+//! whichever manages the interface. No lease is released first, so the
+//! connection to the server survives a renewal.
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use agent_platform::network::NetworkControl;
+
+pub struct LinuxNetwork;
+
+impl NetworkControl for LinuxNetwork {
+    fn flush_dns(&self) -> Result<()> {
+        info!("flushing DNS cache");
+        if run("resolvectl", &["flush-caches"]).is_ok() {
+            return Ok(());
+        }
+        if run("nscd", &["-i", "hosts"]).is_ok() {
+            return Ok(());
+        }
+        // Resolvers without a flush command drop their cache on restart
+        run("systemctl", &["restart", "systemd-resolved"])
+            .context("no DNS cache found to flush (systemd-resolved or nscd)")
+    }
+
+    fn renew_dhcp(&self, interface: Option<&str>) -> Result<()> {
+        let name = match interface {
+            Some(name) => name.to_string(),
+            None => default_route_interface()?,
+        };
+        info!("renewing DHCP lease on {}", name);
+
+        if run("networkctl", &["renew", &name]).is_ok() {
+            return Ok(());
+        }
+        if run("dhclient", &[&name]).is_ok() {
+            return Ok(());
+        }
+        run("nmcli", &["device", "up", &name])
+            .context("no DHCP client found (systemd-networkd, dhclient or NetworkManager)")
+    }
+}
+
+/// The interface carrying the default IPv4 route
+fn default_route_interface() -> Result<String> {
+    let table =
+        std::fs::read_to_string("/proc/net/route").context("failed to read /proc/net/route")?;
+    parse_default_route(&table).context("no default route; name the interface to renew")
+}
+
+/// Pick the interface of the lowest-metric default route that is up from
+/// a /proc/net/route table
+fn parse_default_route(table: &str) -> Option<String> {
+    const RTF_UP: u32 = 0x1;
+
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (iface, destination, flags, metric) = (
+                fields.first()?,
+                fields.get(1)?,
+                fields.get(3)?,
+                fields.get(6)?,
+            );
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            let metric: u32 = metric.parse().ok()?;
+            (*destination == "00000000" && flags & RTF_UP != 0).then(|| (metric, iface.to_string()))
+        })
+        .min()
+        .map(|(_, iface)| iface)
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run {}", program))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} {} failed: {}", program, args[0], stderr.trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_route() {
+        let table = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(parse_default_route(table).as_deref(), Some("eth0"));
+
+        let no_default = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+eth0\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\n";
+        assert_eq!(parse_default_route(no_default), None);
+    }
+}
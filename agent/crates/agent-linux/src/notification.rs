@@ -0,0 +1,120 @@
+//! Linux desktop notifications through notify-send, which reaches each
+//! user's notification daemon over their D-Bus session bus.
+
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+
+use agent_platform::notification::UserNotifier;
+
+pub struct LinuxNotifier;
+
+/// A logged-in user's session bus socket
+struct SessionBus {
+    uid: u32,
+    gid: u32,
+    runtime_dir: PathBuf,
+}
+
+impl UserNotifier for LinuxNotifier {
+    fn notify(&self, title: &str, body: &str) -> Result<()> {
+        // Not root: the agent runs inside the user's own session
+        if !nix::unistd::geteuid().is_root() {
+            return notify_send(title, body, None);
+        }
+
+        let buses = session_buses(Path::new("/run/user"));
+        if buses.is_empty() {
+            anyhow::bail!("no logged-in user with a session bus to notify");
+        }
+        let mut last_error = None;
+        let mut notified = 0;
+        for bus in &buses {
+            match notify_send(title, body, Some(bus)) {
+                Ok(()) => notified += 1,
+                Err(e) => {
+                    warn!("failed to notify uid {}: {:#}", bus.uid, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        info!(
+            "notification shown to {} of {} user sessions",
+            notified,
+            buses.len()
+        );
+        match last_error {
+            Some(e) if notified == 0 => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Users with a session bus under `run_user` (`/run/user/<uid>/bus`)
+fn session_buses(run_user: &Path) -> Vec<SessionBus> {
+    let Ok(entries) = std::fs::read_dir(run_user) else {
+        return Vec::new();
+    };
+    let mut buses: Vec<SessionBus> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let runtime_dir = entry.path();
+            let meta = std::fs::metadata(runtime_dir.join("bus")).ok()?;
+            Some(SessionBus {
+                uid: meta.uid(),
+                gid: meta.gid(),
+                runtime_dir,
+            })
+        })
+        .collect();
+    buses.sort_by_key(|bus| bus.uid);
+    buses
+}
+
+/// Run notify-send, as the bus's user when `bus` is given
+fn notify_send(title: &str, body: &str, bus: Option<&SessionBus>) -> Result<()> {
+    let mut cmd = std::process::Command::new("notify-send");
+    // `--` keeps a title starting with '-' from being read as an option
+    cmd.args(["--app-name", "Remote Support", "--", title, body]);
+    if let Some(bus) = bus {
+        cmd.uid(bus.uid)
+            .gid(bus.gid)
+            .env("XDG_RUNTIME_DIR", &bus.runtime_dir)
+            .env(
+                "DBUS_SESSION_BUS_ADDRESS",
+                format!("unix:path={}", bus.runtime_dir.join("bus").display()),
+            );
+    }
+    let output = cmd.output().context("failed to run notify-send")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("notify-send failed: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_session_buses() {
+        let run_user = std::env::temp_dir().join(format!("run-user-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&run_user);
+        fs::create_dir_all(run_user.join("1000")).unwrap();
+        fs::write(run_user.join("1000/bus"), "").unwrap();
+        // Runtime dir without a session bus (no graphical login)
+        fs::create_dir_all(run_user.join("1001")).unwrap();
+
+        let buses = session_buses(&run_user);
+        let uid = fs::metadata(&run_user).unwrap().uid();
+        fs::remove_dir_all(&run_user).unwrap();
+        assert_eq!(buses.len(), 1);
+        assert_eq!(buses[0].uid, uid);
+        assert_eq!(buses[0].runtime_dir, run_user.join("1000"));
+    }
+}
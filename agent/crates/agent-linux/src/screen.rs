@@ -1,25 +1,76 @@
 //! Screen capture auto-detection for Linux.
-//! Supports X11 (xcb + SHM) and Wayland (xdg-desktop-portal + PipeWire/GStreamer).
+//! Supports X11 (xcb + SHM) and Wayland (xdg-desktop-portal + PipeWire/GStreamer),
+//! with DRM/KMS framebuffer reads as a last resort on headless servers.
 
-use anyhow::{Result, bail};
 use agent_platform::screen::ScreenCapture;
+use anyhow::{bail, Result};
 
-pub use crate::screen_x11::X11ScreenCapture;
+use crate::screen_drm;
+pub use crate::screen_drm::DrmScreenCapture;
 pub use crate::screen_wayland::WaylandScreenCapture;
+pub use crate::screen_x11::X11ScreenCapture;
 
-/// Detect the display server and return the appropriate ScreenCapture implementation.
-pub fn create_screen_capture() -> Result<Box<dyn ScreenCapture>> {
+/// Capture backend `create_screen_capture` would pick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayBackend {
+    X11,
+    Wayland,
+    Drm,
+}
+
+/// Detect the display server to capture, if there is one
+pub fn display_backend() -> Option<DisplayBackend> {
     // Prefer X11 if DISPLAY is set (works for X11 and XWayland)
     if std::env::var("DISPLAY").is_ok() {
-        tracing::info!("detected X11 display, using xcb screen capture");
-        return Ok(Box::new(X11ScreenCapture::new()));
+        return Some(DisplayBackend::X11);
     }
 
     // Fall back to Wayland via xdg-desktop-portal
     if std::env::var("WAYLAND_DISPLAY").is_ok() {
-        tracing::info!("detected Wayland display, using portal + PipeWire screen capture");
-        return Ok(Box::new(WaylandScreenCapture::new()));
+        return Some(DisplayBackend::Wayland);
+    }
+
+    // Headless server: read the console framebuffer straight from KMS
+    if screen_drm::is_available() {
+        return Some(DisplayBackend::Drm);
+    }
+
+    None
+}
+
+/// Whether there is a desktop to capture: an X11 or Wayland display, or a
+/// DRM console
+pub fn has_interactive_session() -> bool {
+    display_backend().is_some()
+}
+
+/// Detect the display server and return the appropriate ScreenCapture implementation.
+pub fn create_screen_capture() -> Result<Box<dyn ScreenCapture>> {
+    match display_backend() {
+        Some(DisplayBackend::X11) => {
+            tracing::info!("detected X11 display, using xcb screen capture");
+            Ok(Box::new(X11ScreenCapture::new()))
+        }
+        Some(DisplayBackend::Wayland) => {
+            tracing::info!("detected Wayland display, using portal + PipeWire screen capture");
+            Ok(Box::new(WaylandScreenCapture::new()))
+        }
+        Some(DisplayBackend::Drm) => {
+            tracing::info!("no display server detected, using DRM/KMS framebuffer capture");
+            Ok(Box::new(DrmScreenCapture::new()))
+        }
+        None => {
+            bail!("no display server detected — set DISPLAY for X11 or WAYLAND_DISPLAY for Wayland")
+        }
     }
+}
 
-    bail!("no display server detected — set DISPLAY for X11 or WAYLAND_DISPLAY for Wayland");
+/// Capture of every monitor as one frame. Under X11 the root window already
+/// spans the whole virtual desktop across RandR outputs, so this is the
+/// regular X11 capture; Wayland and DRM capture a single output.
+pub fn create_stitched_screen_capture() -> Result<Box<dyn ScreenCapture>> {
+    if std::env::var("DISPLAY").is_err() {
+        tracing::warn!("stitched capture needs X11, capturing a single screen");
+    }
+    create_screen_capture()
 }
@@ -0,0 +1,411 @@
+//! DRM/KMS screen capture for headless servers with no X11 or Wayland session.
+//!
+//! Reads the framebuffer scanned out by the first active CRTC of
+//! `/dev/dri/card*` (the console at a TTY). Talks to the kernel with raw
+//! ioctls, so there is no libdrm dependency.
+//!
+//! Opening the device needs membership of the `video` group, and the kernel
+//! only hands out framebuffer handles to the DRM master or to a process with
+//! CAP_SYS_ADMIN — in practice the agent has to run as root.
+
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use agent_platform::screen::{ScreenCapture, ScreenFrame};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+
+const DRM_IOCTL_BASE: u64 = b'd' as u64;
+
+const fn iowr<T>(nr: u64) -> u64 {
+    (3 << 30) | ((std::mem::size_of::<T>() as u64) << 16) | (DRM_IOCTL_BASE << 8) | nr
+}
+
+const fn iow<T>(nr: u64) -> u64 {
+    (1 << 30) | ((std::mem::size_of::<T>() as u64) << 16) | (DRM_IOCTL_BASE << 8) | nr
+}
+
+const DRM_IOCTL_GEM_CLOSE: u64 = iow::<DrmGemClose>(0x09);
+const DRM_IOCTL_PRIME_HANDLE_TO_FD: u64 = iowr::<DrmPrimeHandle>(0x2D);
+const DRM_IOCTL_MODE_GETRESOURCES: u64 = iowr::<DrmModeCardRes>(0xA0);
+const DRM_IOCTL_MODE_GETCRTC: u64 = iowr::<DrmModeCrtc>(0xA1);
+const DRM_IOCTL_MODE_MAP_DUMB: u64 = iowr::<DrmModeMapDumb>(0xB3);
+const DRM_IOCTL_MODE_GETFB2: u64 = iowr::<DrmModeFbCmd2>(0xCE);
+
+/// fourcc codes of the 32-bit formats whose memory layout is already BGRA
+const DRM_FORMAT_XRGB8888: u32 = u32::from_le_bytes(*b"XR24");
+const DRM_FORMAT_ARGB8888: u32 = u32::from_le_bytes(*b"AR24");
+/// `flags` bit saying `modifier` is valid
+const DRM_MODE_FB_MODIFIERS: u32 = 1 << 1;
+const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeModeInfo {
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    type_: u32,
+    name: [u8; 32],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCrtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    mode: DrmModeModeInfo,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbCmd2 {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    flags: u32,
+    handles: [u32; 4],
+    pitches: [u32; 4],
+    offsets: [u32; 4],
+    modifier: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeMapDumb {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmPrimeHandle {
+    handle: u32,
+    flags: u32,
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmGemClose {
+    handle: u32,
+    pad: u32,
+}
+
+fn drm_ioctl<T>(fd: i32, request: u64, arg: &mut T) -> std::io::Result<()> {
+    loop {
+        let ret = unsafe { libc::ioctl(fd, request as _, arg as *mut T) };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if !matches!(err.raw_os_error(), Some(libc::EINTR) | Some(libc::EAGAIN)) {
+            return Err(err);
+        }
+    }
+}
+
+/// Framebuffer capture through the kernel's mode-setting interface
+pub struct DrmScreenCapture {
+    device: Option<File>,
+    crtc_id: u32,
+    width: u32,
+    height: u32,
+}
+
+impl DrmScreenCapture {
+    pub fn new() -> Self {
+        Self {
+            device: None,
+            crtc_id: 0,
+            width: 0,
+            height: 0,
+        }
+    }
+
+    fn fd(&self) -> Result<i32> {
+        self.device
+            .as_ref()
+            .map(|f| f.as_raw_fd())
+            .context("DRM capture not initialized")
+    }
+
+    /// Open the card and pick the first CRTC that is scanning out a framebuffer
+    fn find_active_crtc(path: &PathBuf) -> Result<(File, DrmModeCrtc)> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    anyhow::anyhow!(
+                        "permission denied opening {}: add the agent user to the 'video' group or run it as root",
+                        path.display()
+                    )
+                } else {
+                    anyhow::anyhow!("failed to open {}: {}", path.display(), e)
+                }
+            })?;
+        let fd = device.as_raw_fd();
+
+        // First call gets the counts, the second fills in the CRTC ids
+        let mut res = DrmModeCardRes::default();
+        drm_ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut res)
+            .with_context(|| format!("{} does not support mode setting", path.display()))?;
+        let mut crtc_ids = vec![0u32; res.count_crtcs as usize];
+        let mut res = DrmModeCardRes {
+            crtc_id_ptr: crtc_ids.as_mut_ptr() as u64,
+            count_crtcs: crtc_ids.len() as u32,
+            ..Default::default()
+        };
+        drm_ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut res)
+            .context("DRM_IOCTL_MODE_GETRESOURCES failed")?;
+        crtc_ids.truncate(res.count_crtcs as usize);
+
+        for crtc_id in crtc_ids {
+            let mut crtc = DrmModeCrtc {
+                crtc_id,
+                ..Default::default()
+            };
+            if drm_ioctl(fd, DRM_IOCTL_MODE_GETCRTC, &mut crtc).is_ok()
+                && crtc.fb_id != 0
+                && crtc.mode_valid != 0
+            {
+                return Ok((device, crtc));
+            }
+        }
+        bail!("no active display output on {}", path.display())
+    }
+
+    /// Copy the visible part of the framebuffer into a tightly packed frame
+    fn read_framebuffer(&self, fd: i32, fb: &DrmModeFbCmd2, x: u32, y: u32) -> Result<Vec<u8>> {
+        let pitch = fb.pitches[0] as usize;
+        let map_len = fb.offsets[0] as usize + pitch * fb.height as usize;
+
+        // Prefer mapping the buffer as a dma-buf; dumb buffers (simpledrm,
+        // virtio, most server GPUs) can also be mapped through the card
+        let mut prime = DrmPrimeHandle {
+            handle: fb.handles[0],
+            flags: libc::O_RDONLY as u32 | libc::O_CLOEXEC as u32,
+            fd: -1,
+        };
+        let (map_fd, map_offset, owned_fd) =
+            if drm_ioctl(fd, DRM_IOCTL_PRIME_HANDLE_TO_FD, &mut prime).is_ok() {
+                (prime.fd, 0i64, Some(prime.fd))
+            } else {
+                let mut dumb = DrmModeMapDumb {
+                    handle: fb.handles[0],
+                    ..Default::default()
+                };
+                drm_ioctl(fd, DRM_IOCTL_MODE_MAP_DUMB, &mut dumb)
+                    .context("framebuffer can't be mapped (neither PRIME nor dumb buffer)")?;
+                (fd, dumb.offset as i64, None)
+            };
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                map_fd,
+                map_offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            let err = std::io::Error::last_os_error();
+            if let Some(owned) = owned_fd {
+                unsafe { libc::close(owned) };
+            }
+            bail!("mmap of framebuffer failed: {}", err);
+        }
+
+        let row_bytes = self.width as usize * 4;
+        let mut data = vec![0u8; row_bytes * self.height as usize];
+        let src = unsafe { std::slice::from_raw_parts(ptr as *const u8, map_len) };
+        let base = fb.offsets[0] as usize + x as usize * 4;
+        for row in 0..self.height as usize {
+            let start = base + (y as usize + row) * pitch;
+            let end = start + row_bytes;
+            if end > src.len() {
+                break;
+            }
+            data[row * row_bytes..(row + 1) * row_bytes].copy_from_slice(&src[start..end]);
+        }
+
+        unsafe {
+            libc::munmap(ptr, map_len);
+            if let Some(owned) = owned_fd {
+                libc::close(owned);
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl Default for DrmScreenCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// DRM device nodes, card0 first
+fn card_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir("/dev/dri")
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_name().to_string_lossy().starts_with("card"))
+                .map(|e| e.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+/// Whether the machine has a DRM device to fall back on
+pub fn is_available() -> bool {
+    !card_paths().is_empty()
+}
+
+#[async_trait]
+impl ScreenCapture for DrmScreenCapture {
+    async fn init(&mut self) -> Result<(u32, u32)> {
+        let mut last_err = None;
+        for path in card_paths() {
+            match Self::find_active_crtc(&path) {
+                Ok((device, crtc)) => {
+                    self.width = crtc.mode.hdisplay as u32;
+                    self.height = crtc.mode.vdisplay as u32;
+                    self.crtc_id = crtc.crtc_id;
+                    self.device = Some(device);
+                    tracing::info!(
+                        "DRM capture on {} (crtc {}, {}x{})",
+                        path.display(),
+                        self.crtc_id,
+                        self.width,
+                        self.height
+                    );
+                    return Ok((self.width, self.height));
+                }
+                Err(e) => {
+                    tracing::debug!("skipping {}: {:#}", path.display(), e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no DRM devices found in /dev/dri")))
+    }
+
+    async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+        let fd = self.fd()?;
+
+        // Look the framebuffer up every frame: page flips swap it out
+        let mut crtc = DrmModeCrtc {
+            crtc_id: self.crtc_id,
+            ..Default::default()
+        };
+        drm_ioctl(fd, DRM_IOCTL_MODE_GETCRTC, &mut crtc)
+            .context("DRM_IOCTL_MODE_GETCRTC failed")?;
+        if crtc.fb_id == 0 {
+            bail!("display output was switched off");
+        }
+
+        let mut fb = DrmModeFbCmd2 {
+            fb_id: crtc.fb_id,
+            ..Default::default()
+        };
+        drm_ioctl(fd, DRM_IOCTL_MODE_GETFB2, &mut fb).context("DRM_IOCTL_MODE_GETFB2 failed")?;
+        if fb.handles[0] == 0 {
+            bail!("kernel withheld the framebuffer handle: DRM capture needs root (CAP_SYS_ADMIN)");
+        }
+
+        // GETFB2 hands out a fresh GEM handle each call; always release it
+        let result = (|| {
+            if fb.pixel_format != DRM_FORMAT_XRGB8888 && fb.pixel_format != DRM_FORMAT_ARGB8888 {
+                bail!(
+                    "unsupported framebuffer format {:?}",
+                    String::from_utf8_lossy(&fb.pixel_format.to_le_bytes())
+                );
+            }
+            if fb.flags & DRM_MODE_FB_MODIFIERS != 0 && fb.modifier[0] != DRM_FORMAT_MOD_LINEAR {
+                bail!(
+                    "tiled framebuffers are not supported (modifier 0x{:x})",
+                    fb.modifier[0]
+                );
+            }
+            if crtc.x + self.width > fb.width || crtc.y + self.height > fb.height {
+                bail!("framebuffer is smaller than the display mode");
+            }
+            self.read_framebuffer(fd, &fb, crtc.x, crtc.y)
+        })();
+
+        let mut close = DrmGemClose {
+            handle: fb.handles[0],
+            pad: 0,
+        };
+        let _ = drm_ioctl(fd, DRM_IOCTL_GEM_CLOSE, &mut close);
+
+        Ok(ScreenFrame {
+            width: self.width,
+            height: self.height,
+            data: result?,
+            stride: self.width * 4,
+        })
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ioctl_structs_match_kernel_abi() {
+        assert_eq!(std::mem::size_of::<DrmModeCardRes>(), 64);
+        assert_eq!(std::mem::size_of::<DrmModeCrtc>(), 104);
+        assert_eq!(std::mem::size_of::<DrmModeFbCmd2>(), 104);
+        assert_eq!(DRM_IOCTL_MODE_GETFB2, 0xC06864CE);
+        assert_eq!(DRM_IOCTL_MODE_GETCRTC, 0xC06864A1);
+    }
+}
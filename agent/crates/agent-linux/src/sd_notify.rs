@@ -0,0 +1,83 @@
+//! systemd notification protocol (sd_notify) — readiness and watchdog pings.
+//!
+//! Writes datagrams to the socket named by `NOTIFY_SOCKET`. When the agent is
+//! not started by systemd (or the unit isn't `Type=notify`) the variable is
+//! absent and every call is a no-op.
+
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use tracing::debug;
+
+/// Send a raw notification string, e.g. `"READY=1"`.
+/// Returns false if no notify socket is configured or the send failed.
+pub fn notify(state: &str) -> bool {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+
+    match send(&path.to_string_lossy(), state) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("sd_notify {:?} failed: {}", state, e);
+            false
+        }
+    }
+}
+
+fn send(path: &str, state: &str) -> std::io::Result<()> {
+    let addr = match path.strip_prefix('@') {
+        // Abstract namespace socket
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes())?
+        }
+        None => SocketAddr::from_pathname(path)?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Tell systemd startup is complete.
+pub fn ready() -> bool {
+    notify("READY=1")
+}
+
+/// Tell systemd the agent is re-establishing its connection. Followed by
+/// `ready()` once it is back up.
+pub fn reloading() -> bool {
+    notify("RELOADING=1")
+}
+
+/// Tell systemd the agent is shutting down.
+pub fn stopping() -> bool {
+    notify("STOPPING=1")
+}
+
+/// Keep-alive ping for `WatchdogSec`.
+pub fn watchdog() -> bool {
+    notify("WATCHDOG=1")
+}
+
+/// How often to send `WATCHDOG=1`, or None when the watchdog is disabled.
+///
+/// systemd passes the timeout in `WATCHDOG_USEC`; pinging at half of it
+/// leaves headroom for a slow loop iteration.
+pub fn watchdog_interval() -> Option<Duration> {
+    std::env::var_os("NOTIFY_SOCKET")?;
+
+    // WATCHDOG_PID, when set, names the process the watchdog applies to
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
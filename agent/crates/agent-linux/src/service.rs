@@ -1,11 +1,17 @@
-//! Linux systemd service management — install/uninstall/start/stop the agent service.
+//! Linux systemd service management — install/uninstall/start/stop the agent
+//! service, and listing/control of the system's other services.
+
+use std::collections::HashMap;
 
 use anyhow::{Context, Result};
 use tracing::info;
 
-use agent_platform::service::ServiceManager;
+use agent_platform::service::{
+    ServiceAction, ServiceInfo, ServiceManager, ServiceStartType, ServiceState, SystemServices,
+};
 
-const SERVICE_NAME: &str = "android-remote-agent";
+/// systemd unit name of the agent service
+pub const SERVICE_NAME: &str = "android-remote-agent";
 const SERVICE_UNIT_PATH: &str = "/etc/systemd/system/android-remote-agent.service";
 
 pub struct SystemdServiceManager {
@@ -38,12 +44,16 @@ After=network-online.target
 Wants=network-online.target
 
 [Service]
-Type=simple
+Type=notify
+NotifyAccess=main
 User={user}
 ExecStart={binary} --server-url {server}{config_arg}
 Restart=always
 RestartSec=10
-Environment=AGENT_LOG_LEVEL=info
+# READY=1 is sent once the agent authenticates with the server, which may
+# take a while on boot; the watchdog catches a hung agent after that
+TimeoutStartSec=infinity
+WatchdogSec=60
 
 # Security hardening
 NoNewPrivileges=true
@@ -119,6 +129,13 @@ impl ServiceManager for SystemdServiceManager {
         // Stop if running
         let _ = self.stop();
 
+        self.remove()?;
+
+        info!("service uninstalled: {}", SERVICE_NAME);
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<()> {
         // Disable service
         let _ = std::process::Command::new("systemctl")
             .args(["disable", SERVICE_NAME])
@@ -135,14 +152,14 @@ impl ServiceManager for SystemdServiceManager {
             .arg("daemon-reload")
             .status();
 
-        info!("service uninstalled: {}", SERVICE_NAME);
         Ok(())
     }
 
     fn start(&self) -> Result<()> {
         info!("starting service: {}", SERVICE_NAME);
+        // Don't wait for READY=1 — that depends on reaching the server
         let status = std::process::Command::new("systemctl")
-            .args(["start", SERVICE_NAME])
+            .args(["start", "--no-block", SERVICE_NAME])
             .status()
             .context("failed to start service")?;
 
@@ -174,4 +191,170 @@ impl ServiceManager for SystemdServiceManager {
         let stdout = String::from_utf8_lossy(&output.stdout);
         Ok(stdout.trim() == "active")
     }
+
+    fn is_installed(&self) -> Result<bool> {
+        Ok(std::path::Path::new(SERVICE_UNIT_PATH).exists())
+    }
+}
+
+/// The system's systemd service units
+pub struct SystemdServices;
+
+impl SystemServices for SystemdServices {
+    fn list(&self) -> Result<Vec<ServiceInfo>> {
+        let units = systemctl_output(&[
+            "list-units",
+            "--type=service",
+            "--all",
+            "--plain",
+            "--no-legend",
+            "--no-pager",
+        ])?;
+        let unit_files = systemctl_output(&[
+            "list-unit-files",
+            "--type=service",
+            "--no-legend",
+            "--no-pager",
+        ])?;
+        Ok(merge_service_lists(&units, &unit_files))
+    }
+
+    fn control(&self, name: &str, action: ServiceAction) -> Result<()> {
+        if name.is_empty() || name.starts_with('-') {
+            anyhow::bail!("invalid service name: {:?}", name);
+        }
+        info!("{} service: {}", action.as_str(), name);
+
+        let output = std::process::Command::new("systemctl")
+            .args([action.as_str(), "--", name])
+            .output()
+            .context("failed to run systemctl")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("systemctl {} failed: {}", action.as_str(), stderr.trim());
+        }
+        Ok(())
+    }
+}
+
+fn systemctl_output(args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new("systemctl")
+        .args(args)
+        .output()
+        .context("failed to run systemctl")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("systemctl {} failed: {}", args[0], stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Combine `systemctl list-units` (loaded units and their state) with
+/// `systemctl list-unit-files` (start type, plus installed units that
+/// aren't loaded and so aren't running).
+fn merge_service_lists(units: &str, unit_files: &str) -> Vec<ServiceInfo> {
+    // UNIT FILE  STATE  [VENDOR PRESET]
+    let start_types: HashMap<&str, ServiceStartType> = unit_files
+        .lines()
+        .filter_map(|line| {
+            let mut cols = line.split_whitespace();
+            Some((cols.next()?, start_type(cols.next()?)))
+        })
+        .collect();
+
+    // UNIT  LOAD  ACTIVE  SUB  DESCRIPTION...
+    let mut services: Vec<ServiceInfo> = units
+        .lines()
+        .filter_map(|line| {
+            let mut cols = line.split_whitespace();
+            let name = cols.next()?;
+            let load = cols.next()?;
+            let active = cols.next()?;
+            let _sub = cols.next()?;
+            if load == "not-found" {
+                return None;
+            }
+            Some(ServiceInfo {
+                name: name.to_string(),
+                display_name: cols.collect::<Vec<_>>().join(" "),
+                state: active_state(active),
+                start_type: start_types
+                    .get(name)
+                    .copied()
+                    .unwrap_or(ServiceStartType::Unknown),
+            })
+        })
+        .collect();
+
+    // Templates (foo@.service) are not services until instantiated
+    for (&name, &start_type) in &start_types {
+        if name.contains("@.") || services.iter().any(|s| s.name == name) {
+            continue;
+        }
+        services.push(ServiceInfo {
+            name: name.to_string(),
+            display_name: String::new(),
+            state: ServiceState::Stopped,
+            start_type,
+        });
+    }
+
+    services.sort_by(|a, b| a.name.cmp(&b.name));
+    services
+}
+
+fn active_state(active: &str) -> ServiceState {
+    match active {
+        "active" | "reloading" => ServiceState::Running,
+        "inactive" => ServiceState::Stopped,
+        "activating" => ServiceState::Starting,
+        "deactivating" => ServiceState::Stopping,
+        "failed" => ServiceState::Failed,
+        _ => ServiceState::Unknown,
+    }
+}
+
+fn start_type(state: &str) -> ServiceStartType {
+    match state {
+        "enabled" | "enabled-runtime" | "alias" => ServiceStartType::Auto,
+        "disabled" | "static" | "indirect" | "generated" | "transient" => ServiceStartType::Manual,
+        "masked" | "masked-runtime" => ServiceStartType::Disabled,
+        _ => ServiceStartType::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_service_lists() {
+        let units = "\
+cron.service loaded active running Regular background program processing daemon
+ghost.service not-found inactive dead ghost.service
+nginx.service loaded failed failed A high performance web server
+";
+        let unit_files = "\
+cron.service enabled enabled
+getty@.service enabled enabled
+nginx.service disabled enabled
+rsync.service masked enabled
+";
+        let services = merge_service_lists(units, unit_files);
+        let names: Vec<&str> = services.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["cron.service", "nginx.service", "rsync.service"]);
+
+        assert_eq!(
+            services[0].display_name,
+            "Regular background program processing daemon"
+        );
+        assert_eq!(services[0].state, ServiceState::Running);
+        assert_eq!(services[0].start_type, ServiceStartType::Auto);
+        assert_eq!(services[1].state, ServiceState::Failed);
+        assert_eq!(services[1].start_type, ServiceStartType::Manual);
+        assert_eq!(services[2].state, ServiceState::Stopped);
+        assert_eq!(services[2].start_type, ServiceStartType::Disabled);
+    }
 }
@@ -1,7 +1,12 @@
 use std::fs;
 use std::path::Path;
+use std::time::UNIX_EPOCH;
 
-use agent_platform::system_info::{CpuInfo, DiskInfo, MemoryInfo, NetworkInfo, SystemInfo};
+use agent_platform::system_info::{
+    CpuInfo, DiskInfo, FanSensor, InstalledSoftware, MemoryInfo, NetworkInfo, SensorInfo,
+    SystemInfo, TemperatureSensor, UserSession, UserSessionType,
+};
+use anyhow::{bail, Context, Result};
 
 pub struct LinuxSystemInfo;
 
@@ -42,38 +47,49 @@ impl SystemInfo for LinuxSystemInfo {
         std::env::consts::ARCH.to_string()
     }
 
-    fn cpu_info(&self) -> CpuInfo {
-        let model = parse_cpu_model().unwrap_or_else(|| "Unknown CPU".to_string());
-        let (cores, threads) = parse_cpu_count();
-        let usage_percent = parse_cpu_usage();
+    fn cpu_info(&self) -> Result<CpuInfo> {
+        let cpuinfo =
+            fs::read_to_string("/proc/cpuinfo").context("failed to read /proc/cpuinfo")?;
+        // ARM kernels often have no "model name" line; that isn't an error
+        let model = parse_cpu_model(&cpuinfo).unwrap_or_else(|| "Unknown CPU".to_string());
+        let (cores, threads) = parse_cpu_count(&cpuinfo);
+        let usage_percent = parse_cpu_usage()?;
 
-        CpuInfo {
+        Ok(CpuInfo {
             model,
             cores,
             threads,
             usage_percent,
-        }
+        })
     }
 
-    fn memory_info(&self) -> MemoryInfo {
-        parse_meminfo().unwrap_or(MemoryInfo {
-            total_bytes: 0,
-            used_bytes: 0,
-            available_bytes: 0,
-        })
+    fn memory_info(&self) -> Result<MemoryInfo> {
+        parse_meminfo()
     }
 
-    fn disk_info(&self) -> Vec<DiskInfo> {
+    fn disk_info(&self) -> Result<Vec<DiskInfo>> {
         parse_disk_info()
     }
 
-    fn network_interfaces(&self) -> Vec<NetworkInfo> {
+    fn network_interfaces(&self) -> Result<Vec<NetworkInfo>> {
         parse_network_info()
     }
+
+    fn user_sessions(&self) -> Result<Vec<UserSession>> {
+        let utmp = fs::read("/var/run/utmp").context("failed to read /var/run/utmp")?;
+        Ok(parse_utmp(&utmp))
+    }
+
+    fn sensors(&self) -> SensorInfo {
+        read_hwmon_sensors(Path::new("/sys/class/hwmon"))
+    }
+
+    fn installed_software(&self) -> Result<Vec<InstalledSoftware>> {
+        read_installed_software()
+    }
 }
 
-fn parse_cpu_model() -> Option<String> {
-    let content = fs::read_to_string("/proc/cpuinfo").ok()?;
+fn parse_cpu_model(content: &str) -> Option<String> {
     for line in content.lines() {
         if line.starts_with("model name") {
             if let Some(val) = line.split(':').nth(1) {
@@ -84,12 +100,7 @@ fn parse_cpu_model() -> Option<String> {
     None
 }
 
-fn parse_cpu_count() -> (u32, u32) {
-    let content = match fs::read_to_string("/proc/cpuinfo") {
-        Ok(c) => c,
-        Err(_) => return (1, 1),
-    };
-
+fn parse_cpu_count(content: &str) -> (u32, u32) {
     let mut processor_count = 0u32;
     let mut core_ids = std::collections::HashSet::new();
 
@@ -115,18 +126,11 @@ fn parse_cpu_count() -> (u32, u32) {
     (cores.max(1), processor_count.max(1))
 }
 
-fn parse_cpu_usage() -> f64 {
+fn parse_cpu_usage() -> Result<f64> {
     // Read /proc/stat for aggregate CPU usage
     // First line: cpu user nice system idle iowait irq softirq steal
-    let content = match fs::read_to_string("/proc/stat") {
-        Ok(c) => c,
-        Err(_) => return 0.0,
-    };
-
-    let first_line = match content.lines().next() {
-        Some(l) => l,
-        None => return 0.0,
-    };
+    let content = fs::read_to_string("/proc/stat").context("failed to read /proc/stat")?;
+    let first_line = content.lines().next().context("/proc/stat is empty")?;
 
     let parts: Vec<u64> = first_line
         .split_whitespace()
@@ -135,7 +139,7 @@ fn parse_cpu_usage() -> f64 {
         .collect();
 
     if parts.len() < 4 {
-        return 0.0;
+        bail!("unexpected /proc/stat cpu line: {}", first_line);
     }
 
     let user = parts[0];
@@ -148,14 +152,14 @@ fn parse_cpu_usage() -> f64 {
     let busy = user + nice + system;
 
     if total == 0 {
-        return 0.0;
+        return Ok(0.0);
     }
 
-    (busy as f64 / total as f64) * 100.0
+    Ok((busy as f64 / total as f64) * 100.0)
 }
 
-fn parse_meminfo() -> Option<MemoryInfo> {
-    let content = fs::read_to_string("/proc/meminfo").ok()?;
+fn parse_meminfo() -> Result<MemoryInfo> {
+    let content = fs::read_to_string("/proc/meminfo").context("failed to read /proc/meminfo")?;
 
     let mut total_kb = 0u64;
     let mut available_kb = 0u64;
@@ -183,6 +187,10 @@ fn parse_meminfo() -> Option<MemoryInfo> {
         }
     }
 
+    if total_kb == 0 {
+        bail!("no MemTotal in /proc/meminfo");
+    }
+
     // If MemAvailable is 0 (older kernels), estimate it
     if available_kb == 0 {
         available_kb = free_kb + buffers_kb + cached_kb;
@@ -192,18 +200,15 @@ fn parse_meminfo() -> Option<MemoryInfo> {
     let available_bytes = available_kb * 1024;
     let used_bytes = total_bytes.saturating_sub(available_bytes);
 
-    Some(MemoryInfo {
+    Ok(MemoryInfo {
         total_bytes,
         used_bytes,
         available_bytes,
     })
 }
 
-fn parse_disk_info() -> Vec<DiskInfo> {
-    let content = match fs::read_to_string("/proc/mounts") {
-        Ok(c) => c,
-        Err(_) => return Vec::new(),
-    };
+fn parse_disk_info() -> Result<Vec<DiskInfo>> {
+    let content = fs::read_to_string("/proc/mounts").context("failed to read /proc/mounts")?;
 
     let mut disks = Vec::new();
 
@@ -217,18 +222,7 @@ fn parse_disk_info() -> Vec<DiskInfo> {
         let mount_point = parts[1];
         let filesystem = parts[2];
 
-        // Skip virtual/pseudo filesystems
-        if matches!(
-            filesystem,
-            "proc" | "sysfs" | "devtmpfs" | "devpts" | "tmpfs" | "securityfs"
-                | "cgroup" | "cgroup2" | "pstore" | "debugfs" | "hugetlbfs"
-                | "mqueue" | "fusectl" | "configfs" | "binfmt_misc" | "autofs"
-                | "tracefs" | "bpf" | "efivarfs" | "overlay" | "nsfs"
-                | "ramfs" | "rpc_pipefs" | "nfsd"
-        ) {
-            continue;
-        }
-
+        // Pseudo filesystems are left to the telemetry disk filter
         // Use statvfs to get sizes
         let mount_cstr = match std::ffi::CString::new(mount_point) {
             Ok(c) => c,
@@ -261,15 +255,12 @@ fn parse_disk_info() -> Vec<DiskInfo> {
         });
     }
 
-    disks
+    Ok(disks)
 }
 
-fn parse_network_info() -> Vec<NetworkInfo> {
+fn parse_network_info() -> Result<Vec<NetworkInfo>> {
     let net_dir = Path::new("/sys/class/net");
-    let entries = match fs::read_dir(net_dir) {
-        Ok(e) => e,
-        Err(_) => return Vec::new(),
-    };
+    let entries = fs::read_dir(net_dir).context("failed to list /sys/class/net")?;
 
     let mut interfaces = Vec::new();
 
@@ -291,16 +282,32 @@ fn parse_network_info() -> Vec<NetworkInfo> {
         // Get IP addresses from /proc/net/if_inet6 and the operstate
         let ipv4 = get_ipv4_address(&name);
         let ipv6 = get_ipv6_address(&name);
+        let (rx_bytes, tx_bytes) = read_interface_counters(&iface_dir);
 
         interfaces.push(NetworkInfo {
             name,
             mac_address,
             ipv4,
             ipv6,
+            rx_bytes,
+            tx_bytes,
+            rx_bytes_per_sec: None,
+            tx_bytes_per_sec: None,
         });
     }
 
-    interfaces
+    Ok(interfaces)
+}
+
+/// Received and sent byte counters of the interface at `iface_dir`
+/// (`/sys/class/net/<if>`)
+fn read_interface_counters(iface_dir: &Path) -> (Option<u64>, Option<u64>) {
+    let read = |name: &str| {
+        fs::read_to_string(iface_dir.join("statistics").join(name))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+    };
+    (read("rx_bytes"), read("tx_bytes"))
 }
 
 fn get_ipv4_address(iface: &str) -> Option<String> {
@@ -349,3 +356,330 @@ fn get_ipv6_address(iface: &str) -> Option<String> {
     }
     None
 }
+
+/// glibc `struct utmp` layout (the same on 32- and 64-bit Linux)
+const UTMP_RECORD_LEN: usize = 384;
+const UTMP_USER_PROCESS: i32 = 7;
+
+/// Login sessions from utmp records: one per terminal a user is logged in on
+fn parse_utmp(data: &[u8]) -> Vec<UserSession> {
+    fn text(field: &[u8]) -> String {
+        let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+        String::from_utf8_lossy(&field[..end]).into_owned()
+    }
+
+    data.chunks_exact(UTMP_RECORD_LEN)
+        .filter(|r| i32::from_ne_bytes([r[0], r[1], r[2], r[3]]) == UTMP_USER_PROCESS)
+        .filter_map(|r| {
+            let line = text(&r[8..40]);
+            let username = text(&r[44..76]);
+            let host = text(&r[76..332]);
+            let login_secs = i32::from_ne_bytes([r[340], r[341], r[342], r[343]]);
+            if username.is_empty() {
+                return None;
+            }
+
+            // Local logins are on a VT or an X display (":0"); a pty with a
+            // remote host is an SSH login
+            let session_type = if line.starts_with("tty") || host.starts_with(':') {
+                UserSessionType::Console
+            } else if line.starts_with("pts/") && !host.is_empty() {
+                UserSessionType::Ssh
+            } else {
+                UserSessionType::Other
+            };
+
+            Some(UserSession {
+                username,
+                session_type,
+                login_time: u64::try_from(login_secs).ok().filter(|&t| t > 0),
+            })
+        })
+        .collect()
+}
+
+/// Read every `temp*_input` (millidegrees C) and `fan*_input` (RPM) under
+/// the hwmon class directory. Sensors are named after their chip plus the
+/// driver's label, or the channel when there is no label.
+fn read_hwmon_sensors(root: &Path) -> SensorInfo {
+    let mut sensors = SensorInfo::default();
+    let Ok(chips) = fs::read_dir(root) else {
+        return sensors;
+    };
+
+    for chip in chips.flatten() {
+        let dir = chip.path();
+        let chip_name = fs::read_to_string(dir.join("name"))
+            .map(|n| n.trim().to_string())
+            .unwrap_or_else(|_| chip.file_name().to_string_lossy().into_owned());
+        let Ok(files) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for file in files.flatten() {
+            let file_name = file.file_name();
+            let Some(channel) = file_name.to_str().and_then(|f| f.strip_suffix("_input")) else {
+                continue;
+            };
+            let Some(value) = fs::read_to_string(file.path())
+                .ok()
+                .and_then(|v| v.trim().parse::<i64>().ok())
+            else {
+                continue;
+            };
+            let label = fs::read_to_string(dir.join(format!("{}_label", channel)))
+                .map(|l| l.trim().to_string())
+                .unwrap_or_else(|_| channel.to_string());
+            let name = format!("{} {}", chip_name, label);
+
+            if channel.starts_with("temp") {
+                sensors.temperatures.push(TemperatureSensor {
+                    name,
+                    temp_c: value as f64 / 1000.0,
+                });
+            } else if channel.starts_with("fan") {
+                sensors.fans.push(FanSensor {
+                    name,
+                    rpm: value.max(0) as u32,
+                });
+            }
+        }
+    }
+
+    sensors.temperatures.sort_by(|a, b| a.name.cmp(&b.name));
+    sensors.fans.sort_by(|a, b| a.name.cmp(&b.name));
+    sensors
+}
+
+/// Tab-separated dpkg-query fields. `binary:Package` carries the `:arch`
+/// suffix that multi-arch packages use in their file list's name.
+const DPKG_FORMAT: &str = "${binary:Package}\t${Version}\t${Maintainer}\t${db:Status-Status}\n";
+const RPM_FORMAT: &str = "%{NAME}\t%{VERSION}-%{RELEASE}\t%{VENDOR}\t%{INSTALLTIME}\n";
+
+fn read_installed_software() -> Result<Vec<InstalledSoftware>> {
+    if let Some(output) = package_query("dpkg-query", &["-W", "-f", DPKG_FORMAT])? {
+        return Ok(parse_dpkg_packages(&output, dpkg_install_date));
+    }
+    if let Some(output) = package_query("rpm", &["-qa", "--queryformat", RPM_FORMAT])? {
+        return Ok(parse_rpm_packages(&output));
+    }
+    bail!("no supported package database found (dpkg or rpm)");
+}
+
+/// Run a package manager query, or `None` if the tool isn't installed
+fn package_query(program: &str, args: &[&str]) -> Result<Option<String>> {
+    let output = match std::process::Command::new(program).args(args).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to run {}", program)),
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} failed: {}", program, stderr.trim());
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+fn parse_dpkg_packages(
+    output: &str,
+    install_date: impl Fn(&str) -> Option<String>,
+) -> Vec<InstalledSoftware> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut cols = line.split('\t');
+            let package = cols.next()?;
+            let version = cols.next()?;
+            let maintainer = cols.next()?;
+            // Removed packages linger as config-files until purged
+            if cols.next()? != "installed" {
+                return None;
+            }
+            Some(InstalledSoftware {
+                name: package.split(':').next().unwrap_or(package).to_string(),
+                version: non_empty(version),
+                publisher: non_empty(maintainer),
+                install_date: install_date(package),
+            })
+        })
+        .collect()
+}
+
+/// dpkg records no install date; the package's file list is written when
+/// it is unpacked, so its mtime stands in.
+fn dpkg_install_date(package: &str) -> Option<String> {
+    let modified = fs::metadata(format!("/var/lib/dpkg/info/{}.list", package))
+        .ok()?
+        .modified()
+        .ok()?;
+    Some(date_from_unix(
+        modified.duration_since(UNIX_EPOCH).ok()?.as_secs(),
+    ))
+}
+
+fn parse_rpm_packages(output: &str) -> Vec<InstalledSoftware> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut cols = line.split('\t');
+            let name = cols.next()?;
+            let version = cols.next()?;
+            let vendor = cols.next()?;
+            let installed = cols.next()?;
+            Some(InstalledSoftware {
+                name: name.to_string(),
+                version: non_empty(version),
+                publisher: non_empty(vendor).filter(|v| v != "(none)"),
+                install_date: installed.parse().ok().map(date_from_unix),
+            })
+        })
+        .collect()
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+/// YYYY-MM-DD (UTC) for a Unix timestamp
+fn date_from_unix(secs: u64) -> String {
+    // Days since 1970-01-01 to a civil date, shifted so years start in March
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utmp_record(kind: i32, line: &str, user: &str, host: &str, login_secs: i32) -> Vec<u8> {
+        let mut record = vec![0u8; UTMP_RECORD_LEN];
+        record[0..4].copy_from_slice(&kind.to_ne_bytes());
+        record[8..8 + line.len()].copy_from_slice(line.as_bytes());
+        record[44..44 + user.len()].copy_from_slice(user.as_bytes());
+        record[76..76 + host.len()].copy_from_slice(host.as_bytes());
+        record[340..344].copy_from_slice(&login_secs.to_ne_bytes());
+        record
+    }
+
+    #[test]
+    fn test_parse_utmp() {
+        let mut utmp = utmp_record(2, "~", "reboot", "6.1.0", 1_700_000_000); // BOOT_TIME
+        utmp.extend(utmp_record(
+            UTMP_USER_PROCESS,
+            "tty1",
+            "alice",
+            "",
+            1_700_000_100,
+        ));
+        utmp.extend(utmp_record(
+            UTMP_USER_PROCESS,
+            "pts/0",
+            "bob",
+            "10.0.0.5",
+            1_700_000_200,
+        ));
+        utmp.extend(utmp_record(8, "pts/1", "", "", 0)); // DEAD_PROCESS
+
+        let sessions = parse_utmp(&utmp);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].username, "alice");
+        assert_eq!(sessions[0].session_type, UserSessionType::Console);
+        assert_eq!(sessions[0].login_time, Some(1_700_000_100));
+        assert_eq!(sessions[1].username, "bob");
+        assert_eq!(sessions[1].session_type, UserSessionType::Ssh);
+    }
+
+    #[test]
+    fn test_read_hwmon_sensors() {
+        let root = std::env::temp_dir().join(format!("hwmon-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let coretemp = root.join("hwmon0");
+        let fan_chip = root.join("hwmon1");
+        fs::create_dir_all(&coretemp).unwrap();
+        fs::create_dir_all(&fan_chip).unwrap();
+
+        fs::write(coretemp.join("name"), "coretemp\n").unwrap();
+        fs::write(coretemp.join("temp1_input"), "45000\n").unwrap();
+        fs::write(coretemp.join("temp1_label"), "Package id 0\n").unwrap();
+        fs::write(coretemp.join("temp2_input"), "41500\n").unwrap();
+        fs::write(coretemp.join("temp2_crit"), "100000\n").unwrap();
+        fs::write(fan_chip.join("name"), "nct6775\n").unwrap();
+        fs::write(fan_chip.join("fan1_input"), "1200\n").unwrap();
+        fs::write(fan_chip.join("in0_input"), "1024\n").unwrap();
+
+        let sensors = read_hwmon_sensors(&root);
+        fs::remove_dir_all(&root).unwrap();
+
+        let temps: Vec<(&str, f64)> = sensors
+            .temperatures
+            .iter()
+            .map(|t| (t.name.as_str(), t.temp_c))
+            .collect();
+        assert_eq!(
+            temps,
+            [("coretemp Package id 0", 45.0), ("coretemp temp2", 41.5)]
+        );
+        assert_eq!(sensors.fans.len(), 1);
+        assert_eq!(
+            (sensors.fans[0].name.as_str(), sensors.fans[0].rpm),
+            ("nct6775 fan1", 1200)
+        );
+
+        assert!(read_hwmon_sensors(Path::new("/nonexistent/hwmon")).is_empty());
+    }
+
+    #[test]
+    fn test_read_interface_counters() {
+        let iface = std::env::temp_dir().join(format!("net-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&iface);
+        fs::create_dir_all(iface.join("statistics")).unwrap();
+        fs::write(iface.join("statistics/rx_bytes"), "123456789\n").unwrap();
+        fs::write(iface.join("statistics/tx_bytes"), "garbage\n").unwrap();
+
+        let counters = read_interface_counters(&iface);
+        fs::remove_dir_all(&iface).unwrap();
+        assert_eq!(counters, (Some(123456789), None));
+    }
+
+    #[test]
+    fn test_date_from_unix() {
+        assert_eq!(date_from_unix(0), "1970-01-01");
+        assert_eq!(date_from_unix(951_782_400), "2000-02-29");
+        assert_eq!(date_from_unix(1_704_067_199), "2023-12-31");
+    }
+
+    #[test]
+    fn test_parse_packages() {
+        let dpkg = "\
+bash\t5.2.15-2\tDebian Bash Maintainers <bash@packages.debian.org>\tinstalled
+libc6:amd64\t2.36-9\t\tinstalled
+oldpkg\t1.0\tSomeone\tconfig-files
+";
+        let packages = parse_dpkg_packages(dpkg, |p| {
+            (p == "libc6:amd64").then(|| "2024-01-02".to_string())
+        });
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "bash");
+        assert_eq!(packages[0].version.as_deref(), Some("5.2.15-2"));
+        assert_eq!(packages[1].name, "libc6");
+        assert_eq!(packages[1].publisher, None);
+        assert_eq!(packages[1].install_date.as_deref(), Some("2024-01-02"));
+
+        let rpm =
+            "bash\t5.2.26-3.fc40\tFedora Project\t1704067199\ngpg-pubkey\t(none)-1\t(none)\t0\n";
+        let packages = parse_rpm_packages(rpm);
+        assert_eq!(packages[0].publisher.as_deref(), Some("Fedora Project"));
+        assert_eq!(packages[0].install_date.as_deref(), Some("2023-12-31"));
+        assert_eq!(packages[1].publisher, None);
+    }
+}
@@ -1,6 +1,7 @@
 use agent_platform::terminal::Terminal;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use std::io::{Read, Write};
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::process::Command;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -38,14 +39,32 @@ impl LinuxTerminal {
     }
 }
 
+/// Pipe the child reports a failed exec through. Both ends are
+/// close-on-exec, so the parent reads EOF once the shell is running.
+fn exec_status_pipe() -> Result<(std::fs::File, std::fs::File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to create exec status pipe");
+    }
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    Ok((read.into(), write.into()))
+}
+
 #[async_trait]
 impl Terminal for LinuxTerminal {
-    async fn spawn(&mut self, shell: Option<&str>, cols: u16, rows: u16) -> Result<()> {
-        let shell_path = shell
-            .map(String::from)
-            .unwrap_or_else(Self::detect_shell);
+    async fn spawn(
+        &mut self,
+        shell: Option<&str>,
+        cols: u16,
+        rows: u16,
+        login: bool,
+    ) -> Result<()> {
+        let shell_path = shell.map(String::from).unwrap_or_else(Self::detect_shell);
 
-        info!("spawning terminal: shell={}, cols={}, rows={}", shell_path, cols, rows);
+        info!(
+            "spawning terminal: shell={}, cols={}, rows={}, login={}",
+            shell_path, cols, rows, login
+        );
 
         // Set initial window size
         let winsize = nix::pty::Winsize {
@@ -55,6 +74,8 @@ impl Terminal for LinuxTerminal {
             ws_ypixel: 0,
         };
 
+        let (mut status_rx, mut status_tx) = exec_status_pipe()?;
+
         // Open PTY and fork
         let pty_result = unsafe {
             nix::pty::forkpty(Some(&winsize), None)
@@ -67,15 +88,35 @@ impl Terminal for LinuxTerminal {
                 // Set TERM for proper terminal support
                 std::env::set_var("TERM", "xterm-256color");
 
-                let err = Command::new(&shell_path)
-                    .arg("-l") // login shell
-                    .exec(); // replaces process
+                let mut cmd = Command::new(&shell_path);
+                if login {
+                    cmd.arg("-l"); // login shell
+                }
+                let err = cmd.exec(); // replaces process
 
-                // If exec returns, it failed
+                // If exec returns, it failed; tell the parent why
+                let errno = err.raw_os_error().unwrap_or(libc::EIO);
+                let _ = status_tx.write_all(&errno.to_ne_bytes());
                 eprintln!("exec failed: {}", err);
-                std::process::exit(1);
+                std::process::exit(127);
             }
             nix::unistd::ForkResult::Parent { child } => {
+                // EOF means the exec went through; an errno means it didn't
+                drop(status_tx);
+                let mut report = Vec::new();
+                status_rx
+                    .read_to_end(&mut report)
+                    .context("failed to read exec status")?;
+                if let Ok(errno) = <[u8; 4]>::try_from(report.as_slice()) {
+                    let _ = nix::sys::wait::waitpid(child, None);
+                    let err = std::io::Error::from_raw_os_error(i32::from_ne_bytes(errno));
+                    return if err.kind() == std::io::ErrorKind::NotFound {
+                        Err(err).with_context(|| format!("shell not found: {}", shell_path))
+                    } else {
+                        Err(err).with_context(|| format!("failed to start shell {}", shell_path))
+                    };
+                }
+
                 // Parent — store master FD and child PID
                 let master_raw = pty_result.master.as_raw_fd();
 
@@ -102,21 +143,25 @@ impl Terminal for LinuxTerminal {
     }
 
     async fn write_stdin(&mut self, data: &[u8]) -> Result<()> {
-        let fd = self.master_fd.as_ref().context("terminal not spawned")?;
-        let raw = fd.as_raw_fd();
+        let async_fd = self.master_read.as_ref().context("terminal not spawned")?;
+        let raw = *async_fd.get_ref();
 
-        // Write using nix (synchronous but on non-blocking fd)
-        match nix::unistd::write(raw, data) {
-            Ok(_) => Ok(()),
-            Err(nix::errno::Errno::EAGAIN) => {
-                // Would block — try once more after a brief yield
-                tokio::task::yield_now().await;
-                nix::unistd::write(raw, data)
-                    .map(|_| ())
-                    .map_err(|e| anyhow::anyhow!("write to PTY failed: {}", e))
+        // The fd is non-blocking, so a large paste can be accepted only in
+        // part; keep writing the rest as the PTY drains
+        let mut rest = data;
+        while !rest.is_empty() {
+            let mut guard = async_fd
+                .writable()
+                .await
+                .context("failed waiting for writable")?;
+
+            match guard.try_io(|_| nix::unistd::write(raw, rest).map_err(std::io::Error::from)) {
+                Ok(Ok(n)) => rest = &rest[n..],
+                Ok(Err(e)) => return Err(anyhow::anyhow!("write to PTY failed: {}", e)),
+                Err(_would_block) => continue,
             }
-            Err(e) => Err(anyhow::anyhow!("write to PTY failed: {}", e)),
         }
+        Ok(())
     }
 
     async fn read_stdout(&mut self) -> Result<Vec<u8>> {
@@ -213,3 +258,55 @@ impl Drop for LinuxTerminal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_write_stdin_is_binary_safe() {
+        let mut terminal = LinuxTerminal::new();
+        terminal
+            .spawn(Some("/bin/cat"), 80, 24, false)
+            .await
+            .unwrap();
+
+        // Raw mode: no echo, no line editing, no signal keys, no CR/LF
+        // translation, so cat hands back exactly what reached the PTY
+        let master = terminal.master_fd.as_ref().unwrap();
+        let mut termios = nix::sys::termios::tcgetattr(master).unwrap();
+        nix::sys::termios::cfmakeraw(&mut termios);
+        nix::sys::termios::tcsetattr(master, nix::sys::termios::SetArg::TCSANOW, &termios).unwrap();
+
+        let mut input: Vec<u8> = vec![0x00, 0x1b, b'[', b'A', 0x1b, 0x03, 0x04, 0x7f, b'\r', b'\n'];
+        // Invalid UTF-8: a lone continuation byte, a truncated sequence, 0xff
+        input.extend_from_slice(&[0x80, 0xe2, 0x82, 0xff, 0xfe]);
+        input.extend((0u8..=255).cycle().take(1000));
+
+        terminal.write_stdin(&input).await.unwrap();
+
+        let mut output = Vec::new();
+        let read = async {
+            while output.len() < input.len() {
+                output.extend(terminal.read_stdout().await.unwrap());
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), read)
+            .await
+            .unwrap();
+
+        assert_eq!(output, input);
+    }
+
+    #[tokio::test]
+    async fn test_missing_shell_fails_spawn() {
+        let mut terminal = LinuxTerminal::new();
+        let err = terminal
+            .spawn(Some("/nonexistent/shell"), 80, 24, false)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("shell not found: /nonexistent/shell"));
+        assert!(terminal.child_pid.is_none());
+    }
+}
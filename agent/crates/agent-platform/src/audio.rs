@@ -0,0 +1,41 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// Encoding of the packets an `AudioCapture` produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    /// One raw Opus packet per call (no container)
+    Opus,
+    /// Interleaved signed 16-bit little-endian samples
+    PcmS16le,
+}
+
+impl AudioCodec {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Opus => "opus",
+            Self::PcmS16le => "pcm_s16le",
+        }
+    }
+}
+
+/// Stream parameters reported once capture has started
+#[derive(Debug, Clone, Copy)]
+pub struct AudioFormat {
+    pub codec: AudioCodec,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Capture of what the machine is playing (loopback / monitor source)
+#[async_trait]
+pub trait AudioCapture: Send {
+    /// Start capturing. `bitrate` is a hint for codecs that take one.
+    async fn start(&mut self, bitrate: u32) -> Result<AudioFormat>;
+
+    /// Wait for the next packet. Returns None once the stream has ended.
+    async fn next_packet(&mut self) -> Result<Option<Vec<u8>>>;
+
+    /// Stop capturing. Also done on drop.
+    fn stop(&mut self);
+}
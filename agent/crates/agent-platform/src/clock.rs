@@ -0,0 +1,30 @@
+use anyhow::Result;
+use serde::Serialize;
+
+/// The system clock as the agent sees it
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockInfo {
+    /// Current UTC time, milliseconds since the Unix epoch
+    pub unix_millis: u64,
+    /// IANA name on Linux (`Europe/Berlin`), Windows time zone id
+    /// (`W. Europe Standard Time`) on Windows
+    pub timezone: Option<String>,
+    /// Whether the clock is disciplined by NTP; `None` when unknown, as
+    /// always on Windows
+    pub ntp_synchronized: Option<bool>,
+}
+
+/// Reading and correcting the system clock. The setters need root or
+/// administrator rights.
+pub trait SystemClock: Send + Sync {
+    fn info(&self) -> Result<ClockInfo>;
+
+    /// Step the clock to `unix_millis` (UTC)
+    fn set_time(&self, unix_millis: u64) -> Result<()>;
+
+    /// Switch the system time zone, named as in `ClockInfo::timezone`
+    fn set_timezone(&self, timezone: &str) -> Result<()>;
+
+    /// Have the time service resynchronize with its NTP source now
+    fn sync(&self) -> Result<()>;
+}
@@ -11,11 +11,70 @@ pub struct FileEntry {
     pub permissions: Option<String>,
 }
 
+/// One page of a directory listing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirPage {
+    pub entries: Vec<FileEntry>,
+    /// Offset the next page starts at; absent on the last page
+    pub next_offset: Option<u64>,
+}
+
 pub trait FileSystem: Send + Sync {
     fn list_dir(&self, path: &str) -> Result<Vec<FileEntry>>;
+    /// List up to `limit` entries after skipping the first `offset`. Entries
+    /// come in directory order, unsorted, so only the page is held in memory.
+    fn list_dir_page(&self, path: &str, offset: u64, limit: usize) -> Result<DirPage>;
     fn read_file(&self, path: &str) -> Result<Vec<u8>>;
+    /// Read at most `len` bytes starting at `offset`; shorter when the file
+    /// ends first. Fails if `offset` is past the end of the file.
+    fn read_file_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>>;
     fn write_file(&self, path: &str, data: &[u8]) -> Result<()>;
+    /// Append `data` to the end of the file, creating it if needed
+    fn append_file(&self, path: &str, data: &[u8]) -> Result<()>;
+    /// Move a file, replacing `to` if it exists
+    fn rename(&self, from: &str, to: &str) -> Result<()>;
     fn delete(&self, path: &str) -> Result<()>;
     fn exists(&self, path: &str) -> bool;
     fn metadata(&self, path: &str) -> Result<FileEntry>;
 }
+
+/// Build a page from a directory iterator, skipping the first `offset`
+/// entries and converting up to `limit` of the rest. Entries that fail to
+/// read or convert are dropped but still count towards the offset, so the
+/// next page starts where this one stopped.
+pub fn page_dir_entries<T, E>(
+    entries: impl Iterator<Item = std::result::Result<T, E>>,
+    offset: u64,
+    limit: usize,
+    mut convert: impl FnMut(std::result::Result<T, E>) -> Option<FileEntry>,
+) -> DirPage {
+    let mut entries = entries.skip(offset as usize).peekable();
+    let mut page = Vec::new();
+    let mut next_offset = offset;
+
+    while page.len() < limit {
+        let Some(entry) = entries.next() else {
+            break;
+        };
+        next_offset += 1;
+        page.extend(convert(entry));
+    }
+
+    DirPage {
+        entries: page,
+        next_offset: entries.peek().is_some().then_some(next_offset),
+    }
+}
+
+/// Number of bytes a range read of `len` bytes at `offset` returns from a
+/// file of `size` bytes
+pub fn range_len(size: u64, offset: u64, len: u64) -> Result<u64> {
+    if offset > size {
+        anyhow::bail!(
+            "offset {} is past the end of the file ({} bytes)",
+            offset,
+            size
+        );
+    }
+    Ok(len.min(size - offset))
+}
@@ -0,0 +1,11 @@
+use anyhow::Result;
+
+/// On-screen notice telling the local user that a remote desktop session is
+/// active. Shown while at least one desktop channel is open.
+pub trait SessionIndicator: Send {
+    /// Show the indicator with the given text. No-op if already shown.
+    fn show(&mut self, text: &str) -> Result<()>;
+
+    /// Remove the indicator from the screen. Also done on drop.
+    fn hide(&mut self);
+}
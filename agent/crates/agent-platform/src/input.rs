@@ -32,6 +32,119 @@ pub trait InputInjector: Send + Sync {
     fn mouse_move(&mut self, x: u32, y: u32) -> Result<()>;
     fn mouse_button(&mut self, btn: MouseButton, action: ButtonAction) -> Result<()>;
     fn mouse_scroll(&mut self, dx: i32, dy: i32) -> Result<()>;
+    /// Press or release a physical key. `scancode` is a Linux evdev key code
+    /// (`KEY_*`), i.e. a key position: the remote's own keyboard layout
+    /// decides which character it produces, as with a hardware keyboard.
     fn key_press(&mut self, scancode: u16, action: KeyAction, mods: Modifiers) -> Result<()>;
+    /// Type `text` as characters, whatever the remote keyboard layout
     fn type_text(&mut self, text: &str) -> Result<()>;
+
+    /// Press or release whichever key produces `keysym` (an X11 keysym,
+    /// including the Unicode range) on the remote's active layout. Unlike
+    /// `key_press`, the outcome doesn't depend on the layout.
+    fn key_sym(&mut self, _keysym: u32, _action: KeyAction, _mods: Modifiers) -> Result<()> {
+        anyhow::bail!("keysym input is not supported on this platform")
+    }
+
+    /// Lock out (or give back) the local keyboard and mouse while injected
+    /// input keeps working. Implementations must release the block when
+    /// dropped so the local user is never left locked out.
+    fn set_local_input_blocked(&mut self, _blocked: bool) -> Result<()> {
+        anyhow::bail!("blocking local input is not supported on this platform")
+    }
+
+    /// Treat mouse coordinates as relative to the whole virtual desktop
+    /// (every monitor) rather than the primary screen, to match a stitched
+    /// capture. A no-op where the two are already the same.
+    fn set_virtual_desktop(&mut self, _enabled: bool) {}
+}
+
+/// X11 keysyms for the non-character keys
+pub mod keysym {
+    pub const BACKSPACE: u32 = 0xff08;
+    pub const TAB: u32 = 0xff09;
+    pub const RETURN: u32 = 0xff0d;
+    pub const ESCAPE: u32 = 0xff1b;
+    pub const HOME: u32 = 0xff50;
+    pub const LEFT: u32 = 0xff51;
+    pub const UP: u32 = 0xff52;
+    pub const RIGHT: u32 = 0xff53;
+    pub const DOWN: u32 = 0xff54;
+    pub const PAGE_UP: u32 = 0xff55;
+    pub const PAGE_DOWN: u32 = 0xff56;
+    pub const END: u32 = 0xff57;
+    pub const INSERT: u32 = 0xff63;
+    pub const MENU: u32 = 0xff67;
+    pub const F1: u32 = 0xffbe;
+    pub const F12: u32 = 0xffc9;
+    pub const SHIFT_L: u32 = 0xffe1;
+    pub const SHIFT_R: u32 = 0xffe2;
+    pub const CONTROL_L: u32 = 0xffe3;
+    pub const CONTROL_R: u32 = 0xffe4;
+    pub const CAPS_LOCK: u32 = 0xffe5;
+    pub const ALT_L: u32 = 0xffe9;
+    pub const ALT_R: u32 = 0xffea;
+    pub const SUPER_L: u32 = 0xffeb;
+    pub const SUPER_R: u32 = 0xffec;
+    pub const DELETE: u32 = 0xffff;
+    /// Keysyms from here on are Unicode code points plus this offset
+    pub const UNICODE_OFFSET: u32 = 0x0100_0000;
+}
+
+/// Keysym that types `ch`: Latin-1 characters are their own keysym, others
+/// use the Unicode range. Line breaks, tabs and backspace map to their keys.
+pub fn char_to_keysym(ch: char) -> u32 {
+    match ch {
+        '\n' | '\r' => keysym::RETURN,
+        '\t' => keysym::TAB,
+        '\u{8}' => keysym::BACKSPACE,
+        ' '..='~' | '\u{a0}'..='\u{ff}' => ch as u32,
+        _ => keysym::UNICODE_OFFSET + ch as u32,
+    }
+}
+
+/// Character typed by a character keysym (Latin-1 or Unicode range)
+pub fn keysym_to_char(sym: u32) -> Option<char> {
+    match sym {
+        0x20..=0x7e | 0xa0..=0xff => char::from_u32(sym),
+        0x0100_0100..=0x0110_ffff => char::from_u32(sym - keysym::UNICODE_OFFSET),
+        _ => None,
+    }
+}
+
+/// Linux evdev key code of a non-character keysym, for backends that only
+/// inject physical keys
+pub fn keysym_to_evdev(sym: u32) -> Option<u16> {
+    let code = match sym {
+        keysym::BACKSPACE => 14,
+        keysym::TAB => 15,
+        keysym::RETURN => 28,
+        keysym::ESCAPE => 1,
+        keysym::HOME => 102,
+        keysym::LEFT => 105,
+        keysym::UP => 103,
+        keysym::RIGHT => 106,
+        keysym::DOWN => 108,
+        keysym::PAGE_UP => 104,
+        keysym::PAGE_DOWN => 109,
+        keysym::END => 107,
+        keysym::INSERT => 110,
+        keysym::MENU => 127,
+        // F1-F10 are contiguous in both; F11/F12 aren't in evdev
+        keysym::F1..=0xffc7 => (sym - keysym::F1) as u16 + 59,
+        0xffc8 => 87,
+        keysym::F12 => 88,
+        keysym::SHIFT_L => 42,
+        keysym::SHIFT_R => 54,
+        keysym::CONTROL_L => 29,
+        keysym::CONTROL_R => 97,
+        keysym::CAPS_LOCK => 58,
+        keysym::ALT_L => 56,
+        keysym::ALT_R => 100,
+        keysym::SUPER_L => 125,
+        keysym::SUPER_R => 126,
+        keysym::DELETE => 111,
+        _ => return None,
+    };
+    Some(code)
 }
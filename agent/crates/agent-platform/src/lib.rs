@@ -1,6 +1,15 @@
-pub mod screen;
-pub mod input;
-pub mod terminal;
+pub mod audio;
+pub mod clock;
 pub mod filesystem;
-pub mod system_info;
+pub mod indicator;
+pub mod input;
+pub mod network;
+pub mod notification;
+pub mod screen;
 pub mod service;
+pub mod system_info;
+pub mod terminal;
+
+/// Accepted values for the agent's `log_level` setting, shared by the config
+/// and the Windows install dialog
+pub const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
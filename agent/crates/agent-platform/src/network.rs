@@ -0,0 +1,57 @@
+use anyhow::Result;
+
+/// First-line network troubleshooting actions. Both need root or
+/// administrator rights.
+pub trait NetworkControl: Send + Sync {
+    /// Drop the resolver's cached DNS answers
+    fn flush_dns(&self) -> Result<()>;
+
+    /// Renew the DHCP lease of `interface` without releasing it first. When
+    /// None, the platform picks: the default-route interface on Linux, every
+    /// adapter on Windows.
+    fn renew_dhcp(&self, interface: Option<&str>) -> Result<()>;
+}
+
+/// Whether `name` is safe to pass to a network tool as an interface name:
+/// letters, digits, spaces and `._-:`, not starting with `-` so it can't be
+/// read as an option
+pub fn valid_interface_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 256
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '.' | '_' | '-' | ':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_interface_name() {
+        for name in [
+            "eth0",
+            "wlp3s0",
+            "br-lan",
+            "enp0s31f6.100",
+            "eth0:1",
+            "Wi-Fi",
+            "Ethernet 2",
+        ] {
+            assert!(valid_interface_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            "-r",
+            "--help",
+            "eth0;reboot",
+            "eth0 && id",
+            "a/b",
+            "$(id)",
+        ] {
+            assert!(!valid_interface_name(name), "{}", name);
+        }
+        assert!(!valid_interface_name(&"a".repeat(257)));
+    }
+}
@@ -0,0 +1,7 @@
+use anyhow::Result;
+
+/// Native desktop notifications shown to the logged-in user
+pub trait UserNotifier: Send + Sync {
+    /// Show a notification with `title` and `body`
+    fn notify(&self, title: &str, body: &str) -> Result<()>;
+}
@@ -21,6 +21,19 @@ pub trait ScreenCapture: Send + Sync {
     /// Capture the current screen frame
     async fn capture_frame(&mut self) -> Result<ScreenFrame>;
 
+    /// Capture the next frame, waiting at most the acquire timeout for the
+    /// screen to change. `None` means nothing changed in that time, which
+    /// still shows the capture is working. Backends that can't tell capture
+    /// every time.
+    async fn next_frame(&mut self) -> Result<Option<ScreenFrame>> {
+        self.capture_frame().await.map(Some)
+    }
+
     /// Get current screen dimensions
     fn dimensions(&self) -> (u32, u32);
+
+    /// Upper bound for a single wait on the next frame. Backends that wait
+    /// for screen updates (DXGI) report no change from `next_frame` after
+    /// this long; others ignore it.
+    fn set_acquire_timeout(&mut self, _timeout: std::time::Duration) {}
 }
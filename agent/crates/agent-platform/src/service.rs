@@ -1,4 +1,5 @@
 use anyhow::Result;
+use serde::Serialize;
 
 pub trait ServiceManager: Send + Sync {
     /// Install the agent as a system service
@@ -7,6 +8,10 @@ pub trait ServiceManager: Send + Sync {
     /// Uninstall the agent system service
     fn uninstall(&self) -> Result<()>;
 
+    /// Unregister the service without stopping it. A running instance keeps
+    /// going until it is stopped, and is not started again afterwards.
+    fn remove(&self) -> Result<()>;
+
     /// Start the service
     fn start(&self) -> Result<()>;
 
@@ -15,4 +20,74 @@ pub trait ServiceManager: Send + Sync {
 
     /// Check if the service is currently running
     fn is_running(&self) -> Result<bool>;
+
+    /// Check if the service is registered with the service manager
+    fn is_installed(&self) -> Result<bool>;
+}
+
+/// Run state of a system service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceState {
+    Running,
+    Stopped,
+    Starting,
+    Stopping,
+    Paused,
+    Failed,
+    Unknown,
+}
+
+/// When a system service is started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceStartType {
+    /// Started at boot (Windows auto start, systemd enabled)
+    Auto,
+    /// Only started on demand
+    Manual,
+    /// Can't be started (Windows disabled, systemd masked)
+    Disabled,
+    /// Windows boot and system drivers
+    Boot,
+    Unknown,
+}
+
+/// One entry of the system's service list
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceInfo {
+    /// Name the service manager knows it by (`cron.service`, `Spooler`)
+    pub name: String,
+    pub display_name: String,
+    pub state: ServiceState,
+    pub start_type: ServiceStartType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl ServiceAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Stop => "stop",
+            Self::Restart => "restart",
+        }
+    }
+}
+
+/// Listing and control of the system's services (systemd units, Windows
+/// services), as opposed to `ServiceManager`, which manages the agent's own.
+pub trait SystemServices: Send + Sync {
+    /// Every service the service manager knows about
+    fn list(&self) -> Result<Vec<ServiceInfo>>;
+
+    /// Start, stop or restart the service called `name`. Fails if the
+    /// service manager refuses the request.
+    fn control(&self, name: &str, action: ServiceAction) -> Result<()>;
 }
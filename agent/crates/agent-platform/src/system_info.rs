@@ -1,3 +1,4 @@
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +31,66 @@ pub struct NetworkInfo {
     pub mac_address: Option<String>,
     pub ipv4: Option<String>,
     pub ipv6: Option<String>,
+    /// Byte counters since the interface came up, where the platform has them
+    pub rx_bytes: Option<u64>,
+    pub tx_bytes: Option<u64>,
+    /// Throughput since the previous telemetry sample, filled in by telemetry
+    pub rx_bytes_per_sec: Option<f64>,
+    pub tx_bytes_per_sec: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemperatureSensor {
+    pub name: String,
+    pub temp_c: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanSensor {
+    pub name: String,
+    pub rpm: u32,
+}
+
+/// Hardware sensor readings; both lists are empty where the platform
+/// exposes no sensors
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SensorInfo {
+    pub temperatures: Vec<TemperatureSensor>,
+    pub fans: Vec<FanSensor>,
+}
+
+impl SensorInfo {
+    pub fn is_empty(&self) -> bool {
+        self.temperatures.is_empty() && self.fans.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserSessionType {
+    /// Logged in at the machine itself
+    Console,
+    Rdp,
+    Ssh,
+    Other,
+}
+
+/// An interactive login session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSession {
+    pub username: String,
+    pub session_type: UserSessionType,
+    /// Login time in seconds since the Unix epoch
+    pub login_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledSoftware {
+    pub name: String,
+    pub version: Option<String>,
+    pub publisher: Option<String>,
+    /// Install date as YYYY-MM-DD, where the package manager records one
+    pub install_date: Option<String>,
 }
 
 pub trait SystemInfo: Send + Sync {
@@ -37,8 +98,21 @@ pub trait SystemInfo: Send + Sync {
     fn os_name(&self) -> String;
     fn os_version(&self) -> String;
     fn arch(&self) -> String;
-    fn cpu_info(&self) -> CpuInfo;
-    fn memory_info(&self) -> MemoryInfo;
-    fn disk_info(&self) -> Vec<DiskInfo>;
-    fn network_interfaces(&self) -> Vec<NetworkInfo>;
+
+    // Telemetry sections. These fail rather than report zeros when the
+    // underlying source can't be read, so "0% CPU" means what it says.
+    fn cpu_info(&self) -> Result<CpuInfo>;
+    fn memory_info(&self) -> Result<MemoryInfo>;
+    /// Every mounted filesystem with a size, pseudo filesystems included;
+    /// telemetry decides which to report
+    fn disk_info(&self) -> Result<Vec<DiskInfo>>;
+    fn network_interfaces(&self) -> Result<Vec<NetworkInfo>>;
+    fn user_sessions(&self) -> Result<Vec<UserSession>>;
+
+    /// Temperature and fan sensors. Best effort: whatever can't be read is
+    /// left out rather than failing telemetry.
+    fn sensors(&self) -> SensorInfo;
+
+    /// Software inventory from the system's package database
+    fn installed_software(&self) -> Result<Vec<InstalledSoftware>>;
 }
@@ -3,10 +3,16 @@ use async_trait::async_trait;
 
 #[async_trait]
 pub trait Terminal: Send {
-    /// Spawn a new terminal session with the given shell and dimensions
-    async fn spawn(&mut self, shell: Option<&str>, cols: u16, rows: u16) -> Result<()>;
+    /// Spawn a new terminal session with the given shell and dimensions.
+    /// `login` requests a login shell where the platform supports it.
+    async fn spawn(&mut self, shell: Option<&str>, cols: u16, rows: u16, login: bool)
+        -> Result<()>;
 
-    /// Write data to the terminal's stdin
+    /// Write data to the terminal's stdin.
+    ///
+    /// The bytes are passed through unaltered and in full: no UTF-8
+    /// validation or re-encoding, so NULs, lone escape bytes and partial
+    /// multi-byte sequences reach the PTY exactly as the viewer sent them.
     async fn write_stdin(&mut self, data: &[u8]) -> Result<()>;
 
     /// Read available data from the terminal's stdout
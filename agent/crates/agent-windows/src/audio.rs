@@ -0,0 +1,251 @@
+//! Audio capture of the default render device through WASAPI loopback.
+//!
+//! There is no Opus encoder available to the Windows build, so packets are
+//! raw 16-bit PCM (about 190 KB/s for 48 kHz stereo). The mix format is
+//! converted on the capture thread; anything beyond the first two channels
+//! is dropped.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use agent_platform::audio::{AudioCapture, AudioCodec, AudioFormat};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use windows::Win32::Media::Audio::{
+    eConsole, eRender, IAudioCaptureClient, IAudioClient, IMMDeviceEnumerator, MMDeviceEnumerator,
+    AUDCLNT_BUFFERFLAGS_SILENT, AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_LOOPBACK,
+    WAVEFORMATEX,
+};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL,
+    COINIT_MULTITHREADED,
+};
+
+/// WASAPI buffer length in 100ns units (1 second)
+const BUFFER_DURATION: i64 = 10_000_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Packets buffered between the capture thread and the session
+const CHANNEL_DEPTH: usize = 64;
+
+/// Loopback capture of whatever the default output device is playing
+pub struct WasapiLoopbackCapture {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    rx: Option<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl WasapiLoopbackCapture {
+    pub fn new() -> Self {
+        Self {
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+            rx: None,
+        }
+    }
+}
+
+impl Default for WasapiLoopbackCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl AudioCapture for WasapiLoopbackCapture {
+    async fn start(&mut self, _bitrate: u32) -> Result<AudioFormat> {
+        self.stop();
+
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel::<Result<AudioFormat>>();
+        let (tx, rx) = mpsc::channel(CHANNEL_DEPTH);
+        self.stop.store(false, Ordering::SeqCst);
+        let stop = self.stop.clone();
+
+        let thread = std::thread::Builder::new()
+            .name("audio-capture".into())
+            .spawn(move || unsafe {
+                if let Err(e) = CoInitializeEx(None, COINIT_MULTITHREADED).ok() {
+                    let _ = ready_tx.send(Err(e).context("CoInitializeEx failed"));
+                    return;
+                }
+                if let Err(e) = capture_loop(&stop, &tx, &ready_tx) {
+                    // Fails start() if it is still waiting; a no-op afterwards
+                    let _ = ready_tx.send(Err(anyhow::anyhow!("{:#}", e)));
+                    warn!("audio capture stopped: {:#}", e);
+                }
+                CoUninitialize();
+            })
+            .context("failed to spawn audio capture thread")?;
+
+        let format = tokio::task::spawn_blocking(move || ready_rx.recv())
+            .await?
+            .context("audio capture thread exited early")??;
+
+        self.thread = Some(thread);
+        self.rx = Some(rx);
+        info!(
+            "audio capture started: WASAPI loopback, {} Hz, {} channel(s)",
+            format.sample_rate, format.channels
+        );
+        Ok(format)
+    }
+
+    async fn next_packet(&mut self) -> Result<Option<Vec<u8>>> {
+        let rx = self.rx.as_mut().context("audio capture not started")?;
+        Ok(rx.recv().await)
+    }
+
+    fn stop(&mut self) {
+        self.rx = None;
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::SeqCst);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WasapiLoopbackCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Runs on the capture thread after COM is initialized. Reports the stream
+/// format through `ready` once the client is running.
+unsafe fn capture_loop(
+    stop: &AtomicBool,
+    tx: &mpsc::Sender<Vec<u8>>,
+    ready: &std::sync::mpsc::Sender<Result<AudioFormat>>,
+) -> Result<()> {
+    let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)
+        .context("failed to create device enumerator")?;
+    let device = enumerator
+        .GetDefaultAudioEndpoint(eRender, eConsole)
+        .context("no default audio output device")?;
+    let client: IAudioClient = device
+        .Activate(CLSCTX_ALL, None)
+        .context("failed to activate audio client")?;
+
+    let mix_format = client.GetMixFormat().context("GetMixFormat failed")?;
+    let mix = MixFormat::from_wave_format(&*mix_format);
+    let init = client.Initialize(
+        AUDCLNT_SHAREMODE_SHARED,
+        AUDCLNT_STREAMFLAGS_LOOPBACK,
+        BUFFER_DURATION,
+        0,
+        mix_format,
+        None,
+    );
+    CoTaskMemFree(Some(mix_format as *const _));
+    init.context("failed to initialize loopback capture")?;
+
+    let capture: IAudioCaptureClient = client
+        .GetService()
+        .context("failed to get capture client")?;
+    client.Start().context("failed to start audio client")?;
+
+    let _ = ready.send(Ok(AudioFormat {
+        codec: AudioCodec::PcmS16le,
+        sample_rate: mix.sample_rate,
+        channels: mix.channels.min(2),
+    }));
+
+    while !stop.load(Ordering::SeqCst) {
+        std::thread::sleep(POLL_INTERVAL);
+
+        let mut pending = capture.GetNextPacketSize()?;
+        while pending > 0 {
+            let mut data: *mut u8 = std::ptr::null_mut();
+            let mut frames = 0u32;
+            let mut flags = 0u32;
+            capture.GetBuffer(&mut data, &mut frames, &mut flags, None, None)?;
+
+            let silent = flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0;
+            let len = frames as usize * mix.block_align;
+            let packet = if silent || data.is_null() {
+                vec![0u8; frames as usize * mix.channels.min(2) as usize * 2]
+            } else {
+                mix.to_s16_stereo(std::slice::from_raw_parts(data, len))
+            };
+            capture.ReleaseBuffer(frames)?;
+
+            if !packet.is_empty() && tx.blocking_send(packet).is_err() {
+                // Receiver dropped: the session has gone
+                let _ = client.Stop();
+                return Ok(());
+            }
+            pending = capture.GetNextPacketSize()?;
+        }
+    }
+
+    let _ = client.Stop();
+    Ok(())
+}
+
+/// The parts of the shared-mode mix format the conversion needs
+struct MixFormat {
+    sample_rate: u32,
+    channels: u16,
+    block_align: usize,
+    /// 32-bit samples are float (the shared-mode mix format always is);
+    /// 16-bit samples are integers
+    float: bool,
+}
+
+impl MixFormat {
+    fn from_wave_format(format: &WAVEFORMATEX) -> Self {
+        Self {
+            sample_rate: format.nSamplesPerSec,
+            channels: format.nChannels,
+            block_align: format.nBlockAlign as usize,
+            float: format.wBitsPerSample == 32,
+        }
+    }
+
+    /// Convert one buffer of interleaved frames to s16le, keeping at most
+    /// the first two channels
+    fn to_s16_stereo(&self, data: &[u8]) -> Vec<u8> {
+        let keep = self.channels.min(2) as usize;
+        let sample_size = if self.float { 4 } else { 2 };
+        let mut out = Vec::with_capacity(data.len() / self.block_align * keep * 2);
+
+        for frame in data.chunks_exact(self.block_align) {
+            for ch in 0..keep {
+                let s = &frame[ch * sample_size..(ch + 1) * sample_size];
+                let sample = if self.float {
+                    let f = f32::from_le_bytes([s[0], s[1], s[2], s[3]]);
+                    (f.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+                } else {
+                    i16::from_le_bytes([s[0], s[1]])
+                };
+                out.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        out
+    }
+}
+
+/// Whether there is a default output device to loop back. Probed on its
+/// own thread so COM is initialized the way the capture thread does it.
+pub fn is_available() -> bool {
+    let probe = std::thread::spawn(|| unsafe {
+        if CoInitializeEx(None, COINIT_MULTITHREADED).is_err() {
+            return false;
+        }
+        let found =
+            CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)
+                .and_then(|enumerator| enumerator.GetDefaultAudioEndpoint(eRender, eConsole))
+                .is_ok();
+        CoUninitialize();
+        found
+    });
+    probe.join().unwrap_or(false)
+}
+
+/// Factory function for creating the audio capture on Windows
+pub fn create_audio_capture() -> Result<Box<dyn AudioCapture>> {
+    Ok(Box::new(WasapiLoopbackCapture::new()))
+}
@@ -0,0 +1,90 @@
+//! Windows system clock — SetSystemTime for the clock, tzutil for the time
+//! zone and w32tm to resynchronize the Windows Time service.
+
+use anyhow::{Context, Result};
+use tracing::info;
+use windows::Win32::Foundation::{FILETIME, SYSTEMTIME};
+use windows::Win32::System::SystemInformation::SetSystemTime;
+use windows::Win32::System::Time::FileTimeToSystemTime;
+
+use agent_platform::clock::{ClockInfo, SystemClock};
+
+/// 100ns intervals between 1601-01-01 (FILETIME epoch) and 1970-01-01
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+pub struct WindowsClock;
+
+impl SystemClock for WindowsClock {
+    fn info(&self) -> Result<ClockInfo> {
+        let unix_millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .context("system clock is before the Unix epoch")?
+            .as_millis() as u64;
+
+        let timezone = command_output("tzutil", &["/g"])
+            .ok()
+            .map(|tz| tz.trim().to_string())
+            .filter(|tz| !tz.is_empty());
+
+        // w32tm /query /status is the only source, and its output is
+        // localized, so the NTP state is left unknown
+        Ok(ClockInfo {
+            unix_millis,
+            timezone,
+            ntp_synchronized: None,
+        })
+    }
+
+    fn set_time(&self, unix_millis: u64) -> Result<()> {
+        info!("setting system clock to {} ms", unix_millis);
+        let ticks = unix_millis
+            .checked_mul(10_000)
+            .and_then(|ticks| ticks.checked_add(FILETIME_UNIX_EPOCH))
+            .with_context(|| format!("time out of range: {} ms", unix_millis))?;
+        let filetime = FILETIME {
+            dwLowDateTime: ticks as u32,
+            dwHighDateTime: (ticks >> 32) as u32,
+        };
+
+        unsafe {
+            let mut system_time = SYSTEMTIME::default();
+            FileTimeToSystemTime(&filetime, &mut system_time).context("FileTimeToSystemTime")?;
+            SetSystemTime(&system_time).context("SetSystemTime")?;
+        }
+        Ok(())
+    }
+
+    fn set_timezone(&self, timezone: &str) -> Result<()> {
+        if timezone.is_empty() || timezone.starts_with('/') {
+            anyhow::bail!("invalid time zone: {:?}", timezone);
+        }
+        info!("setting time zone to {}", timezone);
+        command_output("tzutil", &["/s", timezone]).map(|_| ())
+    }
+
+    fn sync(&self) -> Result<()> {
+        info!("resynchronizing system clock");
+        command_output("w32tm", &["/resync", "/force"]).map(|_| ())
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run {}", program))?;
+
+    // w32tm reports errors on stdout
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "{} {} failed: {} {}",
+            program,
+            args[0],
+            stdout.trim(),
+            stderr.trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -1,8 +1,9 @@
 use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::time::UNIX_EPOCH;
 
-use agent_platform::filesystem::{FileEntry, FileSystem};
+use agent_platform::filesystem::{page_dir_entries, range_len, DirPage, FileEntry, FileSystem};
 use anyhow::{Context, Result};
 
 pub struct WindowsFileSystem;
@@ -30,6 +31,30 @@ impl WindowsFileSystem {
 
         Some(perms)
     }
+
+    fn to_file_entry(entry: &fs::DirEntry) -> Option<FileEntry> {
+        let meta = entry.metadata().ok()?;
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        let entry_path = entry.path().to_string_lossy().to_string();
+
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64);
+
+        let permissions = Self::get_permissions(&entry.path());
+
+        Some(FileEntry {
+            name,
+            path: entry_path,
+            is_dir: meta.is_dir(),
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            modified,
+            permissions,
+        })
+    }
 }
 
 impl FileSystem for WindowsFileSystem {
@@ -38,39 +63,9 @@ impl FileSystem for WindowsFileSystem {
         let entries = fs::read_dir(dir_path)
             .with_context(|| format!("failed to read directory: {}", path))?;
 
-        let mut result: Vec<FileEntry> = Vec::new();
-
-        for entry in entries {
-            let entry = match entry {
-                Ok(e) => e,
-                Err(_) => continue,
-            };
-
-            let meta = match entry.metadata() {
-                Ok(m) => m,
-                Err(_) => continue,
-            };
-
-            let name = entry.file_name().to_string_lossy().to_string();
-            let entry_path = entry.path().to_string_lossy().to_string();
-
-            let modified = meta
-                .modified()
-                .ok()
-                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
-                .map(|d| d.as_millis() as u64);
-
-            let permissions = Self::get_permissions(&entry.path());
-
-            result.push(FileEntry {
-                name,
-                path: entry_path,
-                is_dir: meta.is_dir(),
-                size: if meta.is_dir() { 0 } else { meta.len() },
-                modified,
-                permissions,
-            });
-        }
+        let mut result: Vec<FileEntry> = entries
+            .filter_map(|entry| Self::to_file_entry(&entry.ok()?))
+            .collect();
 
         // Sort: directories first, then alphabetical
         result.sort_by(|a, b| {
@@ -82,10 +77,37 @@ impl FileSystem for WindowsFileSystem {
         Ok(result)
     }
 
+    fn list_dir_page(&self, path: &str, offset: u64, limit: usize) -> Result<DirPage> {
+        let entries =
+            fs::read_dir(path).with_context(|| format!("failed to read directory: {}", path))?;
+
+        Ok(page_dir_entries(entries, offset, limit, |entry| {
+            Self::to_file_entry(&entry.ok()?)
+        }))
+    }
+
     fn read_file(&self, path: &str) -> Result<Vec<u8>> {
         fs::read(path).with_context(|| format!("failed to read file: {}", path))
     }
 
+    fn read_file_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut file =
+            fs::File::open(path).with_context(|| format!("failed to open file: {}", path))?;
+        let size = file
+            .metadata()
+            .with_context(|| format!("failed to stat file: {}", path))?
+            .len();
+        let len = range_len(size, offset, len)?;
+
+        file.seek(SeekFrom::Start(offset))
+            .with_context(|| format!("failed to seek in file: {}", path))?;
+        let mut data = Vec::with_capacity(len as usize);
+        file.take(len)
+            .read_to_end(&mut data)
+            .with_context(|| format!("failed to read file: {}", path))?;
+        Ok(data)
+    }
+
     fn write_file(&self, path: &str, data: &[u8]) -> Result<()> {
         // Create parent directories if needed
         if let Some(parent) = Path::new(path).parent() {
@@ -97,6 +119,20 @@ impl FileSystem for WindowsFileSystem {
         fs::write(path, data).with_context(|| format!("failed to write file: {}", path))
     }
 
+    fn append_file(&self, path: &str, data: &[u8]) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open file: {}", path))?;
+        file.write_all(data)
+            .with_context(|| format!("failed to write file: {}", path))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        fs::rename(from, to).with_context(|| format!("failed to move {} to {}", from, to))
+    }
+
     fn delete(&self, path: &str) -> Result<()> {
         let p = Path::new(path);
         if p.is_dir() {
@@ -135,3 +171,53 @@ impl FileSystem for WindowsFileSystem {
         })
     }
 }
+
+/// SIDs of LocalSystem and the built-in Administrators group, as icacls
+/// takes them
+const SYSTEM_SID: &str = "*S-1-5-18";
+const ADMINISTRATORS_SID: &str = "*S-1-5-32-544";
+
+/// Restrict `dir` and everything in it to SYSTEM and Administrators.
+///
+/// The agent's data directory lives under ProgramData, where ordinary users
+/// may create files. Anything the service later executes from it must not
+/// be writable by them, so ownership moves to Administrators and the
+/// inherited ACL is replaced with full control for SYSTEM and
+/// Administrators only.
+pub fn restrict_to_administrators(dir: &Path) -> Result<()> {
+    let dir = dir.to_string_lossy();
+    icacls(&[&dir, "/setowner", ADMINISTRATORS_SID, "/T", "/C", "/Q"])?;
+    let system = format!("{}:(OI)(CI)F", SYSTEM_SID);
+    let administrators = format!("{}:(OI)(CI)F", ADMINISTRATORS_SID);
+    icacls(&[
+        &dir,
+        "/inheritance:r",
+        "/grant:r",
+        &system,
+        &administrators,
+        "/Q",
+    ])?;
+    // Whatever was created before the ACL was fixed inherits it from now on.
+    // Fails harmlessly when the directory is empty.
+    let children = format!("{}\\*", dir.trim_end_matches('\\'));
+    icacls(&[&children, "/reset", "/T", "/C", "/Q"]).ok();
+    Ok(())
+}
+
+fn icacls(args: &[&str]) -> Result<()> {
+    let output = std::process::Command::new("icacls")
+        .args(args)
+        .output()
+        .context("failed to run icacls")?;
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "icacls {} failed: {} {}",
+            args.join(" "),
+            stdout.trim(),
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
@@ -43,6 +43,36 @@ extern "system" {
     fn DestroyEnvironmentBlock(lpEnvironment: *const std::ffi::c_void) -> BOOL;
 }
 
+/// Quote `arg` for a Windows command line following the rules the C
+/// runtime and CommandLineToArgvW parse by: backslashes are literal except
+/// in front of a double quote, where they and the quote are escaped.
+#[cfg(target_os = "windows")]
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        let escaped = if c == '"' {
+            backslashes * 2 + 1
+        } else {
+            backslashes
+        };
+        quoted.extend(std::iter::repeat_n('\\', escaped));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    // Backslashes before the closing quote must not escape it
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
 #[cfg(target_os = "windows")]
 fn to_wide(s: &str) -> Vec<u16> {
     use std::os::windows::ffi::OsStrExt;
@@ -57,6 +87,8 @@ fn to_wide(s: &str) -> Vec<u16> {
 pub struct HelperLauncher {
     exe_path: String,
     pipe_name: String,
+    /// Extra command-line arguments appended after the pipe name, quoted
+    extra_args: Vec<String>,
     process_handle: Option<HANDLE>,
     thread_handle: Option<HANDLE>,
     session_id: u32,
@@ -73,12 +105,20 @@ impl HelperLauncher {
         Self {
             exe_path,
             pipe_name,
+            extra_args: Vec::new(),
             process_handle: None,
             thread_handle: None,
             session_id: 0,
         }
     }
 
+    /// Append one argument to the helper command line, quoted so the
+    /// helper reads it back unchanged whatever it contains.
+    pub fn arg(mut self, arg: impl AsRef<str>) -> Self {
+        self.extra_args.push(quote_arg(arg.as_ref()));
+        self
+    }
+
     /// Spawn the helper process in the specified user session.
     ///
     /// Uses WTSQueryUserToken → DuplicateTokenEx → CreateEnvironmentBlock
@@ -124,10 +164,15 @@ impl HelperLauncher {
             }
 
             // 4. Build command line
-            let cmd_line = format!(
-                "\"{}\" --helper-mode --pipe-name \"{}\" --log-level info",
-                self.exe_path, self.pipe_name
+            let mut cmd_line = format!(
+                "\"{}\" --helper-mode --pipe-name {} --log-level info",
+                self.exe_path,
+                quote_arg(&self.pipe_name)
             );
+            for arg in &self.extra_args {
+                cmd_line.push(' ');
+                cmd_line.push_str(arg);
+            }
             let mut cmd_wide = to_wide(&cmd_line);
 
             // 5. Set up STARTUPINFOW with winsta0\default desktop
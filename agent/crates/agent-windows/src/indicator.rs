@@ -0,0 +1,204 @@
+//! "Remote session active" overlay: a small topmost, click-through layered
+//! window centered at the top of the primary monitor.
+//!
+//! The window lives on its own thread with a message loop; hiding posts
+//! WM_QUIT to that thread, which destroys the window and exits.
+
+use agent_platform::indicator::SessionIndicator;
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use tracing::{debug, info};
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{COLORREF, HINSTANCE, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    BeginPaint, CreateSolidBrush, DrawTextW, EndPaint, SetBkMode, SetTextColor, DT_CENTER,
+    DT_SINGLELINE, DT_VCENTER, PAINTSTRUCT, TRANSPARENT,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetClientRect, GetMessageW,
+    GetSystemMetrics, PeekMessageW, PostThreadMessageW, RegisterClassW, SetLayeredWindowAttributes,
+    ShowWindow, TranslateMessage, HMENU, LWA_ALPHA, MSG, PM_NOREMOVE, SM_CXSCREEN,
+    SW_SHOWNOACTIVATE, WM_PAINT, WM_QUIT, WM_USER, WNDCLASSW, WS_EX_LAYERED, WS_EX_NOACTIVATE,
+    WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_EX_TRANSPARENT, WS_POPUP,
+};
+
+const WIDTH: i32 = 260;
+const HEIGHT: i32 = 28;
+const ALPHA: u8 = 220;
+/// COLORREF is 0x00BBGGRR
+const BACKGROUND: u32 = 0x0000_00B0; // dark red
+const FOREGROUND: u32 = 0x00FF_FFFF; // white
+
+thread_local! {
+    /// Text drawn by the window procedure (UTF-16, not NUL-terminated)
+    static TEXT: RefCell<Vec<u16>> = const { RefCell::new(Vec::new()) };
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Overlay window shown while a remote desktop session is active.
+pub struct WindowsSessionIndicator {
+    thread_id: u32,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl WindowsSessionIndicator {
+    pub fn new() -> Self {
+        Self {
+            thread_id: 0,
+            thread: None,
+        }
+    }
+}
+
+impl Default for WindowsSessionIndicator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionIndicator for WindowsSessionIndicator {
+    fn show(&mut self, text: &str) -> Result<()> {
+        if self.thread.is_some() {
+            return Ok(());
+        }
+
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel::<Result<u32>>();
+        let text = text.to_string();
+
+        let thread = std::thread::Builder::new()
+            .name("session-indicator".into())
+            .spawn(move || unsafe {
+                // Make sure the thread has a message queue before anyone posts to it
+                let mut msg = MSG::default();
+                let _ = PeekMessageW(&mut msg, HWND::default(), WM_USER, WM_USER, PM_NOREMOVE);
+
+                let hwnd = match create_window(&text) {
+                    Ok(hwnd) => hwnd,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(GetCurrentThreadId()));
+
+                while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+                    let _ = TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+
+                let _ = DestroyWindow(hwnd);
+                debug!("session indicator closed");
+            })
+            .context("failed to spawn session indicator thread")?;
+
+        self.thread_id = ready_rx
+            .recv()
+            .context("session indicator thread exited early")??;
+        self.thread = Some(thread);
+
+        info!("session indicator shown");
+        Ok(())
+    }
+
+    fn hide(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            unsafe {
+                let _ = PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0));
+            }
+            let _ = thread.join();
+            info!("session indicator hidden");
+        }
+    }
+}
+
+impl Drop for WindowsSessionIndicator {
+    fn drop(&mut self) {
+        self.hide();
+    }
+}
+
+/// Register the window class (once per process) and create the overlay on
+/// the calling thread.
+unsafe fn create_window(text: &str) -> Result<HWND> {
+    TEXT.with(|t| *t.borrow_mut() = text.encode_utf16().collect());
+
+    let module = GetModuleHandleW(PCWSTR::null()).context("GetModuleHandleW failed")?;
+    let instance = HINSTANCE(module.0);
+    let class_name = to_wide("AndroidRemoteSessionIndicator");
+
+    let class = WNDCLASSW {
+        lpfnWndProc: Some(indicator_wnd_proc),
+        hInstance: instance,
+        lpszClassName: PCWSTR(class_name.as_ptr()),
+        hbrBackground: CreateSolidBrush(COLORREF(BACKGROUND)),
+        ..Default::default()
+    };
+    // Fails harmlessly with ERROR_CLASS_ALREADY_EXISTS on later sessions
+    RegisterClassW(&class);
+
+    let screen_width = GetSystemMetrics(SM_CXSCREEN);
+    let title = to_wide(text);
+
+    let hwnd = CreateWindowExW(
+        WS_EX_LAYERED | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE | WS_EX_TRANSPARENT,
+        PCWSTR(class_name.as_ptr()),
+        PCWSTR(title.as_ptr()),
+        WS_POPUP,
+        (screen_width - WIDTH) / 2,
+        0,
+        WIDTH,
+        HEIGHT,
+        HWND::default(),
+        HMENU::default(),
+        instance,
+        None,
+    )
+    .context("CreateWindowExW failed")?;
+
+    SetLayeredWindowAttributes(hwnd, COLORREF(0), ALPHA, LWA_ALPHA)
+        .context("SetLayeredWindowAttributes failed")?;
+    let _ = ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+
+    Ok(hwnd)
+}
+
+unsafe extern "system" fn indicator_wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg == WM_PAINT {
+        let mut ps = PAINTSTRUCT::default();
+        let hdc = BeginPaint(hwnd, &mut ps);
+
+        let mut rect = RECT::default();
+        let _ = GetClientRect(hwnd, &mut rect);
+        SetBkMode(hdc, TRANSPARENT);
+        SetTextColor(hdc, COLORREF(FOREGROUND));
+        TEXT.with(|t| {
+            let mut text = t.borrow().clone();
+            DrawTextW(
+                hdc,
+                &mut text,
+                &mut rect,
+                DT_CENTER | DT_VCENTER | DT_SINGLELINE,
+            );
+        });
+
+        let _ = EndPaint(hwnd, &ps);
+        return LRESULT(0);
+    }
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+/// Factory function for creating the session indicator on Windows
+pub fn create_session_indicator() -> Result<Box<dyn SessionIndicator>> {
+    Ok(Box::new(WindowsSessionIndicator::new()))
+}
@@ -1,25 +1,39 @@
 //! Windows input injection using SendInput API.
 
-use anyhow::{Result, Context};
 use agent_platform::input::{
-    ButtonAction, InputInjector, KeyAction, Modifiers, MouseButton,
+    keysym_to_char, keysym_to_evdev, ButtonAction, InputInjector, KeyAction, Modifiers, MouseButton,
 };
+use anyhow::{Context, Result};
 use tracing::debug;
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::Threading::GetCurrentThreadId;
 use windows::Win32::UI::Input::KeyboardAndMouse::{
-    SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, MOUSEINPUT,
-    KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE,
-    MOUSEEVENTF_ABSOLUTE, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP,
-    MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE,
-    MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, MOUSEEVENTF_WHEEL,
-    MOUSEEVENTF_HWHEEL,
+    GetKeyboardLayout, MapVirtualKeyExW, SendInput, VkKeyScanExW, INPUT, INPUT_0, INPUT_KEYBOARD,
+    INPUT_MOUSE, KEYBDINPUT, KEYBD_EVENT_FLAGS, KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP,
+    KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE, MAPVK_VK_TO_VSC_EX, MOUSEEVENTF_ABSOLUTE,
+    MOUSEEVENTF_HWHEEL, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN,
+    MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP,
+    MOUSEEVENTF_VIRTUALDESK, MOUSEEVENTF_WHEEL, MOUSEINPUT,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, GetMessageW, PeekMessageW, PostThreadMessageW, SetWindowsHookExW,
+    UnhookWindowsHookEx, HC_ACTION, HHOOK, KBDLLHOOKSTRUCT, LLKHF_INJECTED, LLMHF_INJECTED, MSG,
+    MSLLHOOKSTRUCT, PM_NOREMOVE, WH_KEYBOARD_LL, WH_MOUSE_LL, WM_QUIT, WM_USER,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetForegroundWindow, GetSystemMetrics, GetWindowThreadProcessId,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    SM_CXSCREEN, SM_CXVIRTUALSCREEN, SM_CYSCREEN, SM_CYVIRTUALSCREEN,
 };
-use windows::Win32::UI::WindowsAndMessaging::GetSystemMetrics;
-use windows::Win32::UI::WindowsAndMessaging::{SM_CXSCREEN, SM_CYSCREEN};
 
 /// Windows input injector using SendInput API
 pub struct WindowsInputInjector {
     screen_width: i32,
     screen_height: i32,
+    /// Coordinates span the virtual desktop instead of the primary screen
+    virtual_desktop: bool,
+    local_block: Option<LocalInputBlock>,
 }
 
 // SAFETY: SendInput is thread-safe when accessed serially
@@ -33,6 +47,8 @@ impl WindowsInputInjector {
         Self {
             screen_width: screen_width.max(1),
             screen_height: screen_height.max(1),
+            virtual_desktop: false,
+            local_block: None,
         }
     }
 
@@ -48,6 +64,40 @@ impl WindowsInputInjector {
         Ok(())
     }
 
+    /// Press or release a key with the given modifiers held around it
+    fn send_key(&self, key: SetOneKey, action: KeyAction, mods: Modifiers) -> Result<()> {
+        let mut inputs = Vec::new();
+        let up = match action {
+            KeyAction::Press => KEYBD_EVENT_FLAGS(0),
+            KeyAction::Release => KEYEVENTF_KEYUP,
+        };
+
+        // Press modifier keys first (on press), release after (on release)
+        let modifiers = [
+            (mods.shift, LEFT_SHIFT),
+            (mods.ctrl, LEFT_CTRL),
+            (mods.alt, LEFT_ALT),
+            (mods.meta, LEFT_WIN),
+        ];
+        if action == KeyAction::Press {
+            for (_, modifier) in modifiers.iter().filter(|(held, _)| *held) {
+                inputs.push(make_scancode_input(*modifier, up));
+            }
+        }
+
+        // The actual key
+        inputs.push(make_scancode_input(key, up));
+
+        // Release modifiers (on key release)
+        if action == KeyAction::Release {
+            for (_, modifier) in modifiers.iter().rev().filter(|(held, _)| *held) {
+                inputs.push(make_scancode_input(*modifier, up));
+            }
+        }
+
+        self.send_inputs(&inputs)
+    }
+
     /// Convert absolute pixel coordinates to normalized 0-65535 range
     fn normalize_coords(&self, x: u32, y: u32) -> (i32, i32) {
         let nx = ((x as i64 * 65535) / self.screen_width as i64) as i32;
@@ -59,6 +109,12 @@ impl WindowsInputInjector {
 impl InputInjector for WindowsInputInjector {
     fn mouse_move(&mut self, x: u32, y: u32) -> Result<()> {
         let (nx, ny) = self.normalize_coords(x, y);
+        let mut flags = MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE;
+        if self.virtual_desktop {
+            // Normalized coordinates then map onto the virtual desktop,
+            // whose top-left is the stitched frame's origin
+            flags |= MOUSEEVENTF_VIRTUALDESK;
+        }
         let input = INPUT {
             r#type: INPUT_MOUSE,
             Anonymous: INPUT_0 {
@@ -66,7 +122,7 @@ impl InputInjector for WindowsInputInjector {
                     dx: nx,
                     dy: ny,
                     mouseData: 0,
-                    dwFlags: MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE,
+                    dwFlags: flags,
                     time: 0,
                     dwExtraInfo: 0,
                 },
@@ -145,49 +201,9 @@ impl InputInjector for WindowsInputInjector {
     }
 
     fn key_press(&mut self, scancode: u16, action: KeyAction, mods: Modifiers) -> Result<()> {
-        let mut inputs = Vec::new();
-
-        let key_flags = match action {
-            KeyAction::Press => KEYEVENTF_SCANCODE,
-            KeyAction::Release => KEYEVENTF_SCANCODE | KEYEVENTF_KEYUP,
-        };
-
-        // Press modifier keys first (on press), release after (on release)
-        if action == KeyAction::Press {
-            if mods.shift {
-                inputs.push(make_key_input(0x2A, KEYEVENTF_SCANCODE)); // Left Shift
-            }
-            if mods.ctrl {
-                inputs.push(make_key_input(0x1D, KEYEVENTF_SCANCODE)); // Left Ctrl
-            }
-            if mods.alt {
-                inputs.push(make_key_input(0x38, KEYEVENTF_SCANCODE)); // Left Alt
-            }
-            if mods.meta {
-                inputs.push(make_key_input(0x5B, KEYEVENTF_SCANCODE)); // Left Win (scancode 0x5B)
-            }
-        }
-
-        // The actual key
-        inputs.push(make_key_input(scancode, key_flags));
-
-        // Release modifiers (on key release)
-        if action == KeyAction::Release {
-            if mods.meta {
-                inputs.push(make_key_input(0x5B, KEYEVENTF_SCANCODE | KEYEVENTF_KEYUP));
-            }
-            if mods.alt {
-                inputs.push(make_key_input(0x38, KEYEVENTF_SCANCODE | KEYEVENTF_KEYUP));
-            }
-            if mods.ctrl {
-                inputs.push(make_key_input(0x1D, KEYEVENTF_SCANCODE | KEYEVENTF_KEYUP));
-            }
-            if mods.shift {
-                inputs.push(make_key_input(0x2A, KEYEVENTF_SCANCODE | KEYEVENTF_KEYUP));
-            }
-        }
-
-        self.send_inputs(&inputs)
+        let key = evdev_to_set1(scancode)
+            .with_context(|| format!("no Windows scancode for evdev key {}", scancode))?;
+        self.send_key(key, action, mods)
     }
 
     fn type_text(&mut self, text: &str) -> Result<()> {
@@ -227,6 +243,235 @@ impl InputInjector for WindowsInputInjector {
         }
         Ok(())
     }
+
+    fn key_sym(&mut self, keysym: u32, action: KeyAction, mods: Modifiers) -> Result<()> {
+        if let Some(scancode) = keysym_to_evdev(keysym) {
+            return self.key_press(scancode, action, mods);
+        }
+        let ch =
+            keysym_to_char(keysym).with_context(|| format!("unsupported keysym 0x{:x}", keysym))?;
+
+        // Find the key for the character on the foreground window's layout
+        let mut units = [0u16; 2];
+        if let [unit] = ch.encode_utf16(&mut units) {
+            let layout =
+                unsafe { GetKeyboardLayout(GetWindowThreadProcessId(GetForegroundWindow(), None)) };
+            let scan = unsafe { VkKeyScanExW(*unit, layout) };
+            if scan != -1 {
+                let vk = (scan & 0xff) as u32;
+                let shift_state = (scan >> 8) & 0xff;
+                let code = unsafe { MapVirtualKeyExW(vk, MAPVK_VK_TO_VSC_EX, layout) };
+                if code != 0 {
+                    let key = SetOneKey {
+                        scancode: (code & 0xff) as u16,
+                        extended: code & 0xff00 == 0xe000,
+                    };
+                    // Ctrl+Alt stands in for AltGr
+                    let mods = Modifiers {
+                        shift: mods.shift || shift_state & 1 != 0,
+                        ctrl: mods.ctrl || shift_state & 2 != 0,
+                        alt: mods.alt || shift_state & 4 != 0,
+                        meta: mods.meta,
+                    };
+                    return self.send_key(key, action, mods);
+                }
+            }
+        }
+
+        // Not on the layout: type it as a Unicode character instead
+        if action == KeyAction::Press {
+            self.type_text(ch.encode_utf8(&mut [0; 4]))?;
+        }
+        Ok(())
+    }
+
+    fn set_local_input_blocked(&mut self, blocked: bool) -> Result<()> {
+        if !blocked {
+            // Dropping the block unhooks and stops its thread
+            self.local_block = None;
+            return Ok(());
+        }
+        if self.local_block.is_none() {
+            self.local_block = Some(LocalInputBlock::start()?);
+        }
+        Ok(())
+    }
+
+    fn set_virtual_desktop(&mut self, enabled: bool) {
+        let (cx, cy) = if enabled {
+            (SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN)
+        } else {
+            (SM_CXSCREEN, SM_CYSCREEN)
+        };
+        self.screen_width = unsafe { GetSystemMetrics(cx) }.max(1);
+        self.screen_height = unsafe { GetSystemMetrics(cy) }.max(1);
+        self.virtual_desktop = enabled;
+    }
+}
+
+/// Low-level keyboard and mouse hooks that swallow physical input while
+/// letting SendInput events through.
+///
+/// BlockInput would also block our own injection from any thread other than
+/// the one that called it, so the hooks filter on the injected flag instead.
+/// Ctrl+Alt+Del is never hookable, which leaves the local user a way out.
+/// The hooks live on a dedicated thread with its own message loop; dropping
+/// the block posts WM_QUIT to that thread, which unhooks and exits.
+struct LocalInputBlock {
+    thread_id: u32,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl LocalInputBlock {
+    fn start() -> Result<Self> {
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel::<Result<u32>>();
+
+        let thread = std::thread::Builder::new()
+            .name("local-input-block".into())
+            .spawn(move || unsafe {
+                // Make sure the thread has a message queue before anyone posts to it
+                let mut msg = MSG::default();
+                let _ = PeekMessageW(&mut msg, HWND::default(), WM_USER, WM_USER, PM_NOREMOVE);
+
+                let keyboard = SetWindowsHookExW(
+                    WH_KEYBOARD_LL,
+                    Some(block_keyboard),
+                    HINSTANCE::default(),
+                    0,
+                );
+                let mouse =
+                    SetWindowsHookExW(WH_MOUSE_LL, Some(block_mouse), HINSTANCE::default(), 0);
+
+                let (keyboard, mouse) = match (keyboard, mouse) {
+                    (Ok(k), Ok(m)) => (k, m),
+                    (k, m) => {
+                        let err = k.as_ref().err().or(m.as_ref().err()).cloned();
+                        if let Ok(k) = k {
+                            let _ = UnhookWindowsHookEx(k);
+                        }
+                        if let Ok(m) = m {
+                            let _ = UnhookWindowsHookEx(m);
+                        }
+                        let _ = ready_tx
+                            .send(Err(anyhow::anyhow!("SetWindowsHookExW failed: {:?}", err)));
+                        return;
+                    }
+                };
+
+                let _ = ready_tx.send(Ok(GetCurrentThreadId()));
+
+                // Low-level hooks are called through this thread's message loop
+                while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {}
+
+                let _ = UnhookWindowsHookEx(keyboard);
+                let _ = UnhookWindowsHookEx(mouse);
+                debug!("local input block released");
+            })
+            .context("failed to spawn input block thread")?;
+
+        let thread_id = ready_rx
+            .recv()
+            .context("input block thread exited early")??;
+
+        debug!("local input blocked (hook thread {})", thread_id);
+        Ok(Self {
+            thread_id,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for LocalInputBlock {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0));
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+unsafe extern "system" fn block_keyboard(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let info = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        if info.flags.0 & LLKHF_INJECTED.0 == 0 {
+            return LRESULT(1); // swallow physical key
+        }
+    }
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+unsafe extern "system" fn block_mouse(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let info = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+        if info.flags & LLMHF_INJECTED == 0 {
+            return LRESULT(1); // swallow physical mouse event
+        }
+    }
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+/// A PC set-1 scancode, as SendInput takes it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SetOneKey {
+    scancode: u16,
+    /// Sent with the 0xE0 prefix (KEYEVENTF_EXTENDEDKEY)
+    extended: bool,
+}
+
+const LEFT_SHIFT: SetOneKey = SetOneKey {
+    scancode: 0x2A,
+    extended: false,
+};
+const LEFT_CTRL: SetOneKey = SetOneKey {
+    scancode: 0x1D,
+    extended: false,
+};
+const LEFT_ALT: SetOneKey = SetOneKey {
+    scancode: 0x38,
+    extended: false,
+};
+const LEFT_WIN: SetOneKey = SetOneKey {
+    scancode: 0x5B,
+    extended: true,
+};
+
+/// Translate a Linux evdev key code to its set-1 scancode. Codes up to 88
+/// (the main block, F1-F12 and the keypad) are the same in both; the
+/// navigation keys and right-hand modifiers are extended keys.
+fn evdev_to_set1(code: u16) -> Option<SetOneKey> {
+    let (scancode, extended) = match code {
+        1..=88 => (code, false),
+        96 => (0x1C, true),  // Keypad Enter
+        97 => (0x1D, true),  // Right Ctrl
+        98 => (0x35, true),  // Keypad /
+        99 => (0x37, true),  // Print Screen
+        100 => (0x38, true), // Right Alt
+        102 => (0x47, true), // Home
+        103 => (0x48, true), // Up
+        104 => (0x49, true), // Page Up
+        105 => (0x4B, true), // Left
+        106 => (0x4D, true), // Right
+        107 => (0x4F, true), // End
+        108 => (0x50, true), // Down
+        109 => (0x51, true), // Page Down
+        110 => (0x52, true), // Insert
+        111 => (0x53, true), // Delete
+        125 => (0x5B, true), // Left Win
+        126 => (0x5C, true), // Right Win
+        127 => (0x5D, true), // Menu
+        _ => return None,
+    };
+    Some(SetOneKey { scancode, extended })
+}
+
+fn make_scancode_input(key: SetOneKey, flags: KEYBD_EVENT_FLAGS) -> INPUT {
+    let mut flags = flags | KEYEVENTF_SCANCODE;
+    if key.extended {
+        flags = flags | KEYEVENTF_EXTENDEDKEY;
+    }
+    make_key_input(key.scancode, flags)
 }
 
 fn make_key_input(
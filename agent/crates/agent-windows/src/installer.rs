@@ -1,4 +1,4 @@
-//! Windows installer helpers: UAC elevation, explorer detection, and Win32 settings dialog.
+//! Windows installer helpers: UAC elevation, explorer detection, self-delete, and Win32 settings dialog.
 
 #[cfg(target_os = "windows")]
 use anyhow::{Context, Result};
@@ -12,6 +12,18 @@ pub struct InstallParams {
     pub enroll_token: String,
     pub install_service: bool,
     pub start_service: bool,
+    /// Log level to save in the config (None keeps the default)
+    pub log_level: Option<String>,
+    /// Telemetry interval in seconds (None keeps the default)
+    pub telemetry_interval_secs: Option<u64>,
+    /// Heartbeat interval in seconds (None keeps the default)
+    pub heartbeat_interval_secs: Option<u64>,
+    /// Let the server start, stop and restart services
+    pub allow_service_control: bool,
+    /// Let the server set the clock
+    pub allow_clock_changes: bool,
+    /// Let the server download files to the device
+    pub allow_downloads: bool,
 }
 
 // ── UAC Elevation ──────────────────────────────────────────────────────────
@@ -164,6 +176,42 @@ pub fn launched_from_explorer() -> bool {
     false
 }
 
+// ── Self-Delete ────────────────────────────────────────────────────────────
+
+/// Delete `dir` once this process has exited. A running executable can't
+/// delete its own image, so a detached cmd.exe waits a few seconds and then
+/// removes the tree.
+#[cfg(target_os = "windows")]
+pub fn schedule_directory_removal(dir: &std::path::Path) -> Result<()> {
+    use std::os::windows::process::CommandExt;
+
+    const DETACHED_PROCESS: u32 = 0x00000008;
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+    // ping is the usual console-less sleep; ~5s covers service shutdown
+    let script = format!(
+        "ping -n 6 127.0.0.1 >nul & rmdir /s /q \"{}\"",
+        dir.display()
+    );
+
+    std::process::Command::new("cmd.exe")
+        .arg("/C")
+        .raw_arg(format!("\"{}\"", script))
+        // Don't hold the directory open as the child's working directory
+        .current_dir(std::env::temp_dir())
+        .creation_flags(DETACHED_PROCESS | CREATE_NO_WINDOW)
+        .spawn()
+        .context("failed to spawn self-delete command")?;
+
+    info!("scheduled removal of {} after exit", dir.display());
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn schedule_directory_removal(_dir: &std::path::Path) -> Result<()> {
+    anyhow::bail!("deferred self-delete is only needed on Windows");
+}
+
 // ── Win32 Settings Dialog ──────────────────────────────────────────────────
 
 /// Show a native Win32 settings dialog and return the user's choices.
@@ -179,8 +227,14 @@ pub fn show_install_dialog() -> Option<InstallParams> {
     const IDC_ENROLL_TOKEN: u16 = 102;
     const IDC_INSTALL_SERVICE: u16 = 103;
     const IDC_START_SERVICE: u16 = 104;
-    const IDOK_BTN: u16 = 1;      // IDOK
-    const IDCANCEL_BTN: u16 = 2;  // IDCANCEL
+    const IDC_LOG_LEVEL: u16 = 105;
+    const IDC_TELEMETRY_INTERVAL: u16 = 106;
+    const IDC_HEARTBEAT_INTERVAL: u16 = 107;
+    const IDC_ALLOW_SERVICES: u16 = 108;
+    const IDC_ALLOW_CLOCK: u16 = 109;
+    const IDC_ALLOW_DOWNLOADS: u16 = 110;
+    const IDOK_BTN: u16 = 1; // IDOK
+    const IDCANCEL_BTN: u16 = 2; // IDCANCEL
 
     thread_local! {
         static DIALOG_RESULT: std::cell::RefCell<Option<InstallParams>> = std::cell::RefCell::new(None);
@@ -199,11 +253,11 @@ pub fn show_install_dialog() -> Option<InstallParams> {
             | 0x00000800             // DS_CENTER
             | 0x00000040;            // DS_SETFONT
         let ex_style: u32 = 0;
-        let item_count: u16 = 10;
+        let item_count: u16 = 19;
         let x: u16 = 0;
         let y: u16 = 0;
         let cx: u16 = 260;
-        let cy: u16 = 170;
+        let cy: u16 = 232;
 
         buf.push(style as u16);
         buf.push((style >> 16) as u16);
@@ -278,21 +332,175 @@ pub fn show_install_dialog() -> Option<InstallParams> {
         add_item(&mut buf, 0x50810080,
             10, 72, 240, 14, IDC_ENROLL_TOKEN, 0x0081, "");
 
+        // "Log level:" label
+        add_item(
+            &mut buf,
+            0x50000000,
+            10,
+            92,
+            75,
+            10,
+            0xFFFF,
+            0x0082,
+            "Log level:",
+        );
+
+        // Log level edit (blank = default)
+        add_item(
+            &mut buf,
+            0x50810080,
+            10,
+            104,
+            75,
+            14,
+            IDC_LOG_LEVEL,
+            0x0081,
+            "",
+        );
+
+        // "Telemetry (s):" label
+        add_item(
+            &mut buf,
+            0x50000000,
+            92,
+            92,
+            75,
+            10,
+            0xFFFF,
+            0x0082,
+            "Telemetry (s):",
+        );
+
+        // Telemetry interval edit (ES_NUMBER)
+        add_item(
+            &mut buf,
+            0x50812080,
+            92,
+            104,
+            75,
+            14,
+            IDC_TELEMETRY_INTERVAL,
+            0x0081,
+            "",
+        );
+
+        // "Heartbeat (s):" label
+        add_item(
+            &mut buf,
+            0x50000000,
+            175,
+            92,
+            75,
+            10,
+            0xFFFF,
+            0x0082,
+            "Heartbeat (s):",
+        );
+
+        // Heartbeat interval edit (ES_NUMBER)
+        add_item(
+            &mut buf,
+            0x50812080,
+            175,
+            104,
+            75,
+            14,
+            IDC_HEARTBEAT_INTERVAL,
+            0x0081,
+            "",
+        );
+
         // "Install as Windows service" checkbox
-        add_item(&mut buf, 0x50010003,
-            10, 95, 200, 12, IDC_INSTALL_SERVICE, 0x0080, "Install as Windows service");
+        add_item(
+            &mut buf,
+            0x50010003,
+            10,
+            127,
+            200,
+            12,
+            IDC_INSTALL_SERVICE,
+            0x0080,
+            "Install as Windows service",
+        );
 
         // "Start service after install" checkbox
-        add_item(&mut buf, 0x50010003,
-            10, 110, 200, 12, IDC_START_SERVICE, 0x0080, "Start service after install");
+        add_item(
+            &mut buf,
+            0x50010003,
+            10,
+            142,
+            200,
+            12,
+            IDC_START_SERVICE,
+            0x0080,
+            "Start service after install",
+        );
+
+        // "Allow the server to:" label
+        add_item(
+            &mut buf,
+            0x50000000,
+            10,
+            160,
+            240,
+            10,
+            0xFFFF,
+            0x0082,
+            "Allow the server to:",
+        );
+
+        // Feature checkboxes, all checked by default
+        add_item(
+            &mut buf,
+            0x50010003,
+            10,
+            172,
+            75,
+            12,
+            IDC_ALLOW_SERVICES,
+            0x0080,
+            "Control services",
+        );
+        add_item(
+            &mut buf,
+            0x50010003,
+            92,
+            172,
+            75,
+            12,
+            IDC_ALLOW_CLOCK,
+            0x0080,
+            "Set the clock",
+        );
+        add_item(
+            &mut buf,
+            0x50010003,
+            175,
+            172,
+            75,
+            12,
+            IDC_ALLOW_DOWNLOADS,
+            0x0080,
+            "Download files",
+        );
 
         // Install button
-        add_item(&mut buf, 0x50010001,
-            140, 140, 55, 18, IDOK_BTN, 0x0080, "Install");
+        add_item(
+            &mut buf, 0x50010001, 140, 202, 55, 18, IDOK_BTN, 0x0080, "Install",
+        );
 
         // Cancel button
-        add_item(&mut buf, 0x50010000,
-            200, 140, 50, 18, IDCANCEL_BTN, 0x0080, "Cancel");
+        add_item(
+            &mut buf,
+            0x50010000,
+            200,
+            202,
+            50,
+            18,
+            IDCANCEL_BTN,
+            0x0080,
+            "Cancel",
+        );
 
         buf
     }
@@ -309,15 +517,23 @@ pub fn show_install_dialog() -> Option<InstallParams> {
         const BST_CHECKED: u32 = 1;
         const IDC_INSTALL_SERVICE: i32 = 103;
         const IDC_START_SERVICE: i32 = 104;
+        const IDC_ALLOW_SERVICES: i32 = 108;
+        const IDC_ALLOW_CLOCK: i32 = 109;
+        const IDC_ALLOW_DOWNLOADS: i32 = 110;
 
         match msg {
             WM_INITDIALOG => {
-                // Check both checkboxes by default
-                if let Ok(h) = GetDlgItem(hwnd, IDC_INSTALL_SERVICE) {
-                    SendMessageW(h, BM_SETCHECK, WPARAM(BST_CHECKED as usize), LPARAM(0));
-                }
-                if let Ok(h) = GetDlgItem(hwnd, IDC_START_SERVICE) {
-                    SendMessageW(h, BM_SETCHECK, WPARAM(BST_CHECKED as usize), LPARAM(0));
+                // Check every checkbox by default
+                for id in [
+                    IDC_INSTALL_SERVICE,
+                    IDC_START_SERVICE,
+                    IDC_ALLOW_SERVICES,
+                    IDC_ALLOW_CLOCK,
+                    IDC_ALLOW_DOWNLOADS,
+                ] {
+                    if let Ok(h) = GetDlgItem(hwnd, id) {
+                        SendMessageW(h, BM_SETCHECK, WPARAM(BST_CHECKED as usize), LPARAM(0));
+                    }
                 }
                 return 1; // set default focus
             }
@@ -362,8 +578,43 @@ pub fn show_install_dialog() -> Option<InstallParams> {
                             return 1;
                         }
 
+                        let log_level = get_dlg_item_text(hwnd, 105).trim().to_lowercase();
+                        if !log_level.is_empty()
+                            && !agent_platform::LOG_LEVELS.contains(&log_level.as_str())
+                        {
+                            let msg_text: Vec<u16> = format!(
+                                "Log level must be one of: {}\0",
+                                agent_platform::LOG_LEVELS.join(", ")
+                            )
+                            .encode_utf16()
+                            .collect();
+                            let title: Vec<u16> = "Validation Error\0".encode_utf16().collect();
+                            MessageBoxW(
+                                hwnd,
+                                PCWSTR(msg_text.as_ptr()),
+                                PCWSTR(title.as_ptr()),
+                                MB_ICONWARNING | MB_OK,
+                            );
+                            return 1;
+                        }
+
+                        // Blank keeps the default; ES_NUMBER limits input to digits
+                        let telemetry_interval_secs = get_dlg_item_text(hwnd, 106)
+                            .trim()
+                            .parse::<u64>()
+                            .ok()
+                            .filter(|&secs| secs > 0);
+                        let heartbeat_interval_secs = get_dlg_item_text(hwnd, 107)
+                            .trim()
+                            .parse::<u64>()
+                            .ok()
+                            .filter(|&secs| secs > 0);
+
                         let install_service = is_checkbox_checked(hwnd, 103);
                         let start_service = is_checkbox_checked(hwnd, 104);
+                        let allow_service_control = is_checkbox_checked(hwnd, IDC_ALLOW_SERVICES);
+                        let allow_clock_changes = is_checkbox_checked(hwnd, IDC_ALLOW_CLOCK);
+                        let allow_downloads = is_checkbox_checked(hwnd, IDC_ALLOW_DOWNLOADS);
 
                         DIALOG_RESULT.with(|r| {
                             *r.borrow_mut() = Some(InstallParams {
@@ -371,6 +622,12 @@ pub fn show_install_dialog() -> Option<InstallParams> {
                                 enroll_token: enroll_token.trim().to_string(),
                                 install_service,
                                 start_service,
+                                log_level: (!log_level.is_empty()).then_some(log_level),
+                                telemetry_interval_secs,
+                                heartbeat_interval_secs,
+                                allow_service_control,
+                                allow_clock_changes,
+                                allow_downloads,
                             });
                         });
 
@@ -2,38 +2,52 @@
 //
 // The service (Session 0) creates a named pipe server.
 // The helper (user session) connects as a client.
-// Messages are length-prefixed: [u32 LE total_len][encoded Message bytes]
+// Messages are framed as: [u32 LE message_len][u32 LE seq][encoded Message bytes]
+//
+// Each direction numbers its frames from 0. A reader that sees a sequence
+// gap has lost framing (e.g. a partial read) and fails, so the caller tears
+// the pipe down and the helper gets respawned cleanly instead of desyncing.
+//
+// A zero-length frame is the SHUTDOWN control frame: the service sends it
+// before closing the pipe so the helper tears down its sessions and exits.
 
 #[cfg(target_os = "windows")]
 use anyhow::{bail, Result};
 #[cfg(target_os = "windows")]
-use tracing::info;
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(target_os = "windows")]
+use std::sync::Arc;
+#[cfg(target_os = "windows")]
+use tracing::{info, warn};
+#[cfg(target_os = "windows")]
+use windows::core::{w, PCWSTR};
 #[cfg(target_os = "windows")]
 use windows::Win32::Foundation::{
-    CloseHandle, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
-    GetLastError, ERROR_IO_PENDING, ERROR_PIPE_CONNECTED,
+    CloseHandle, GetLastError, ERROR_IO_PENDING, ERROR_PIPE_CONNECTED, HANDLE,
+    INVALID_HANDLE_VALUE, WAIT_OBJECT_0, WAIT_TIMEOUT,
 };
 #[cfg(target_os = "windows")]
-use windows::Win32::Storage::FileSystem::{
-    CreateFileW, ReadFile, WriteFile, FILE_ATTRIBUTE_NORMAL,
-    FILE_FLAG_OVERLAPPED, FILE_SHARE_NONE, OPEN_EXISTING,
-    FILE_GENERIC_READ, FILE_GENERIC_WRITE,
-};
+use windows::Win32::Foundation::{LocalFree, HLOCAL};
 #[cfg(target_os = "windows")]
-use windows::Win32::System::Pipes::{
-    CreateNamedPipeW, ConnectNamedPipe, DisconnectNamedPipe,
-    PIPE_TYPE_BYTE, PIPE_READMODE_BYTE, PIPE_WAIT,
+use windows::Win32::Security::Authorization::{
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
 };
 #[cfg(target_os = "windows")]
-use windows::Win32::System::IO::{
-    GetOverlappedResult, OVERLAPPED,
+use windows::Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES};
+#[cfg(target_os = "windows")]
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, ReadFile, WriteFile, FILE_ATTRIBUTE_NORMAL, FILE_FLAG_OVERLAPPED,
+    FILE_GENERIC_READ, FILE_GENERIC_WRITE, FILE_SHARE_NONE, OPEN_EXISTING,
 };
 #[cfg(target_os = "windows")]
-use windows::Win32::System::Threading::{
-    CreateEventW, WaitForSingleObject, INFINITE,
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
+    PIPE_WAIT,
 };
 #[cfg(target_os = "windows")]
-use windows::core::PCWSTR;
+use windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject, INFINITE};
+#[cfg(target_os = "windows")]
+use windows::Win32::System::IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED};
 
 /// Pipe buffer size (256 KB)
 #[cfg(target_os = "windows")]
@@ -43,10 +57,29 @@ const PIPE_BUFFER_SIZE: u32 = 256 * 1024;
 #[cfg(target_os = "windows")]
 const MAX_IPC_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
 
+/// Pipe instances allowed at once. More than one lets a respawned helper
+/// connect while the previous helper's instance is still being torn down.
+#[cfg(target_os = "windows")]
+const MAX_PIPE_INSTANCES: u32 = 4;
+
 /// PIPE_ACCESS_DUPLEX = 0x00000003 (not always exported as a named constant in windows 0.58)
 #[cfg(target_os = "windows")]
 const PIPE_ACCESS_DUPLEX: u32 = 0x00000003;
 
+/// DACL granting SYSTEM and Administrators full access and no one else any
+#[cfg(target_os = "windows")]
+const ADMIN_ONLY_SDDL: PCWSTR = w!("D:P(A;;GA;;;SY)(A;;GA;;;BA)");
+
+/// A frame received over the pipe.
+#[cfg(target_os = "windows")]
+#[derive(Debug)]
+pub enum IpcFrame {
+    /// An encoded protocol message
+    Message(Vec<u8>),
+    /// The peer is shutting down; no more frames follow
+    Shutdown,
+}
+
 /// Named pipe server (used by the service process in Session 0).
 #[cfg(target_os = "windows")]
 pub struct IpcServer {
@@ -60,16 +93,34 @@ pub struct IpcClient {
     handle: isize,
 }
 
+/// A connected pipe handle, closed exactly once when the last owner drops
+/// it. The reader and writer halves share one, and every in-flight read or
+/// write holds its own reference, so the handle can't be closed (and its
+/// value reused by another object) while I/O is still using it.
+#[cfg(target_os = "windows")]
+struct PipeHandle(isize);
+
+#[cfg(target_os = "windows")]
+impl PipeHandle {
+    fn get(&self) -> HANDLE {
+        h(self.0)
+    }
+}
+
 /// A split reader half for the IPC connection.
 #[cfg(target_os = "windows")]
 pub struct IpcReader {
-    handle: isize,
+    handle: Arc<PipeHandle>,
+    /// Sequence number the next frame must carry
+    expected_seq: u32,
 }
 
 /// A split writer half for the IPC connection.
 #[cfg(target_os = "windows")]
 pub struct IpcWriter {
-    handle: isize,
+    handle: Arc<PipeHandle>,
+    /// Sequence number for the next frame
+    next_seq: AtomicU32,
 }
 
 // isize is Send+Sync, so these impls are automatic,
@@ -100,6 +151,81 @@ fn to_wide(s: &str) -> Vec<u16> {
         .collect()
 }
 
+/// Call `create` with a `SECURITY_ATTRIBUTES` pointer that lets only SYSTEM
+/// and Administrators open what it creates (e.g. a named pipe). The
+/// attributes are only valid until `create` returns.
+#[cfg(target_os = "windows")]
+pub fn with_admin_only_security<T>(
+    create: impl FnOnce(*mut std::ffi::c_void) -> std::io::Result<T>,
+) -> Result<T> {
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            ADMIN_ONLY_SDDL,
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        )?;
+    }
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: descriptor.0,
+        bInheritHandle: false.into(),
+    };
+    let result = create(&mut attributes as *mut SECURITY_ATTRIBUTES as *mut std::ffi::c_void);
+    unsafe {
+        let _ = LocalFree(HLOCAL(descriptor.0));
+    }
+    Ok(result?)
+}
+
+/// Build the reader/writer pair for a connected pipe handle.
+#[cfg(target_os = "windows")]
+fn split_halves(raw: isize) -> (IpcReader, IpcWriter) {
+    let handle = Arc::new(PipeHandle(raw));
+    (
+        IpcReader {
+            handle: handle.clone(),
+            expected_seq: 0,
+        },
+        IpcWriter {
+            handle,
+            next_seq: AtomicU32::new(0),
+        },
+    )
+}
+
+/// Create one instance of the named pipe, returning its raw handle.
+#[cfg(target_os = "windows")]
+fn create_pipe_instance(pipe_name: &str) -> Result<isize> {
+    let wide_name = to_wide(pipe_name);
+
+    let handle = unsafe {
+        CreateNamedPipeW(
+            PCWSTR(wide_name.as_ptr()),
+            // PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED
+            windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES(
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED.0,
+            ),
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            MAX_PIPE_INSTANCES,
+            PIPE_BUFFER_SIZE, // out buffer
+            PIPE_BUFFER_SIZE, // in buffer
+            0,                // default timeout
+            None,             // default security
+        )
+    };
+
+    if handle == INVALID_HANDLE_VALUE {
+        bail!(
+            "CreateNamedPipeW failed: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    Ok(handle.0 as isize)
+}
+
 /// Reconstruct a HANDLE from its raw isize value.
 #[cfg(target_os = "windows")]
 #[inline]
@@ -111,40 +237,35 @@ fn h(raw: isize) -> HANDLE {
 impl IpcServer {
     /// Create a new named pipe server.
     pub fn create(pipe_name: &str) -> Result<Self> {
-        let wide_name = to_wide(pipe_name);
-
-        let handle = unsafe {
-            CreateNamedPipeW(
-                PCWSTR(wide_name.as_ptr()),
-                // PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED
-                windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES(
-                    PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED.0,
-                ),
-                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
-                1,                  // max instances
-                PIPE_BUFFER_SIZE,   // out buffer
-                PIPE_BUFFER_SIZE,   // in buffer
-                0,                  // default timeout
-                None,               // default security
-            )
-        };
-
-        if handle == INVALID_HANDLE_VALUE {
-            bail!("CreateNamedPipeW failed: {}", std::io::Error::last_os_error());
-        }
+        let handle = create_pipe_instance(pipe_name)?;
 
         info!("IPC server created: {}", pipe_name);
 
         Ok(Self {
-            handle: handle.0 as isize,
+            handle,
             pipe_name: pipe_name.to_string(),
         })
     }
 
-    /// Wait for a client (helper process) to connect.
-    pub async fn wait_for_connection(&self) -> Result<()> {
+    /// Wait up to `timeout` for a helper to connect and hand back its
+    /// reader/writer halves.
+    ///
+    /// A fresh pipe instance is created for the next client, so the server
+    /// stays usable: call `accept` again to take the connection of a
+    /// respawned helper without recreating the server. After a timeout the
+    /// current instance is still listening and `accept` can be retried.
+    pub async fn accept(&mut self, timeout: std::time::Duration) -> Result<(IpcReader, IpcWriter)> {
+        self.wait_for_connection(timeout).await?;
+        let next = create_pipe_instance(&self.pipe_name)?;
+        let connected = std::mem::replace(&mut self.handle, next);
+        Ok(split_halves(connected))
+    }
+
+    /// Wait up to `timeout` for a client (helper process) to connect.
+    pub async fn wait_for_connection(&self, timeout: std::time::Duration) -> Result<()> {
         let raw_handle = self.handle;
         let pipe_name = self.pipe_name.clone();
+        let timeout_ms = timeout.as_millis().min(INFINITE as u128 - 1) as u32;
 
         tokio::task::spawn_blocking(move || {
             unsafe {
@@ -158,10 +279,29 @@ impl IpcServer {
                 if result.is_err() {
                     let err = GetLastError();
                     if err == ERROR_IO_PENDING {
-                        let wait = WaitForSingleObject(event, INFINITE);
-                        let _ = CloseHandle(event);
-                        if wait != WAIT_OBJECT_0 {
-                            bail!("WaitForSingleObject failed waiting for pipe connection");
+                        let wait = WaitForSingleObject(event, timeout_ms);
+                        if wait == WAIT_TIMEOUT {
+                            // Take the pending connect back before the
+                            // OVERLAPPED goes out of scope. It may have
+                            // completed in the meantime, which still counts.
+                            let _ = CancelIoEx(handle, Some(&overlapped));
+                            let mut ignored = 0u32;
+                            let connected =
+                                GetOverlappedResult(handle, &overlapped, &mut ignored, true)
+                                    .is_ok();
+                            let _ = CloseHandle(event);
+                            if !connected {
+                                bail!(
+                                    "no client connected to {} within {}s",
+                                    pipe_name,
+                                    timeout.as_secs()
+                                );
+                            }
+                        } else {
+                            let _ = CloseHandle(event);
+                            if wait != WAIT_OBJECT_0 {
+                                bail!("WaitForSingleObject failed waiting for pipe connection");
+                            }
                         }
                     } else if err == ERROR_PIPE_CONNECTED {
                         let _ = CloseHandle(event);
@@ -181,16 +321,12 @@ impl IpcServer {
     }
 
     /// Split this server connection into reader and writer halves.
-    /// Note: after split, the server Drop will NOT close the handle
-    /// (caller is responsible via IpcReader/IpcWriter).
+    /// The halves share the handle, which is closed when both are dropped.
     pub fn split(self) -> (IpcReader, IpcWriter) {
         let raw = self.handle;
         // Prevent Drop from closing the handle — we transfer ownership to reader/writer
         std::mem::forget(self);
-        (
-            IpcReader { handle: raw },
-            IpcWriter { handle: raw },
-        )
+        split_halves(raw)
     }
 
     /// Get the pipe name.
@@ -230,10 +366,7 @@ impl IpcClient {
     pub fn split(self) -> (IpcReader, IpcWriter) {
         let raw = self.handle;
         std::mem::forget(self);
-        (
-            IpcReader { handle: raw },
-            IpcWriter { handle: raw },
-        )
+        split_halves(raw)
     }
 }
 
@@ -241,10 +374,36 @@ impl IpcClient {
 impl IpcReader {
     /// Read a single length-prefixed message from the pipe.
     ///
-    /// Wire format: [u32 LE message_len][message_bytes...]
+    /// A SHUTDOWN frame is reported as an error; use `recv` to handle it.
     pub async fn recv_raw(&mut self) -> Result<Vec<u8>> {
-        let len_bytes = self.read_exact(4).await?;
-        let msg_len = u32::from_le_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]);
+        match self.recv().await? {
+            IpcFrame::Message(data) => Ok(data),
+            IpcFrame::Shutdown => bail!("IPC peer sent shutdown"),
+        }
+    }
+
+    /// Read a single frame from the pipe.
+    ///
+    /// Wire format: [u32 LE message_len][u32 LE seq][message_bytes...]
+    ///
+    /// Fails on a sequence gap; the stream can't be trusted after that.
+    pub async fn recv(&mut self) -> Result<IpcFrame> {
+        let prefix = self.read_exact(8).await?;
+        let msg_len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+        let seq = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]);
+
+        if seq != self.expected_seq {
+            warn!(
+                "IPC sequence gap: expected frame {}, got {} (len {})",
+                self.expected_seq, seq, msg_len
+            );
+            bail!(
+                "IPC framing lost: expected seq {}, got {}",
+                self.expected_seq,
+                seq
+            );
+        }
+        self.expected_seq = self.expected_seq.wrapping_add(1);
 
         if msg_len > MAX_IPC_MESSAGE_SIZE {
             bail!(
@@ -255,23 +414,23 @@ impl IpcReader {
         }
 
         if msg_len == 0 {
-            bail!("IPC received zero-length message");
+            return Ok(IpcFrame::Shutdown);
         }
 
-        self.read_exact(msg_len as usize).await
+        Ok(IpcFrame::Message(self.read_exact(msg_len as usize).await?))
     }
 
     /// Read exactly `n` bytes from the pipe, using overlapped I/O
     /// dispatched to the blocking thread pool.
     async fn read_exact(&mut self, n: usize) -> Result<Vec<u8>> {
-        let raw_handle = self.handle;
+        let pipe = self.handle.clone();
         // Allocate the buffer here then send it into spawn_blocking
         let mut result = vec![0u8; n];
 
         // We do the whole read_exact in a single spawn_blocking call
         // to avoid per-chunk overhead and the Send issue with partial buffer pointers.
         let result = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
-            let handle = h(raw_handle);
+            let handle = pipe.get();
             let mut offset = 0;
 
             while offset < n {
@@ -326,11 +485,25 @@ impl IpcReader {
 impl IpcWriter {
     /// Send a length-prefixed message over the pipe.
     ///
-    /// Wire format: [u32 LE message_len][message_bytes...]
+    /// Wire format: [u32 LE message_len][u32 LE seq][message_bytes...]
     pub async fn send_raw(&self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            bail!("IPC refusing to send zero-length message");
+        }
+        self.send_frame(data).await
+    }
+
+    /// Send the SHUTDOWN control frame, asking the peer to exit.
+    pub async fn send_shutdown(&self) -> Result<()> {
+        self.send_frame(&[]).await
+    }
+
+    async fn send_frame(&self, data: &[u8]) -> Result<()> {
         let len = data.len() as u32;
-        let mut buf = Vec::with_capacity(4 + data.len());
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let mut buf = Vec::with_capacity(8 + data.len());
         buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&seq.to_le_bytes());
         buf.extend_from_slice(data);
 
         self.write_all(buf).await
@@ -338,10 +511,10 @@ impl IpcWriter {
 
     /// Write all bytes to the pipe using overlapped I/O.
     async fn write_all(&self, data: Vec<u8>) -> Result<()> {
-        let raw_handle = self.handle;
+        let pipe = self.handle.clone();
 
         tokio::task::spawn_blocking(move || {
-            let handle = h(raw_handle);
+            let handle = pipe.get();
             let mut offset = 0;
             while offset < data.len() {
                 unsafe {
@@ -408,16 +581,11 @@ impl Drop for IpcClient {
     }
 }
 
-// Reader closes the handle when dropped (it owns the handle after split)
 #[cfg(target_os = "windows")]
-impl Drop for IpcReader {
+impl Drop for PipeHandle {
     fn drop(&mut self) {
-        // Note: both reader and writer share the same handle.
-        // Only one should close it. We let the reader close it
-        // since the writer is typically dropped first (Arc<Mutex<Writer>>).
-        // In practice, closing an already-closed handle is harmless on Windows.
         unsafe {
-            let _ = CloseHandle(h(self.handle));
+            let _ = CloseHandle(h(self.0));
         }
     }
 }
@@ -3,21 +3,39 @@
 #[cfg(target_os = "windows")]
 pub mod screen;
 
+#[cfg(target_os = "windows")]
+pub mod window_capture;
+
 #[cfg(target_os = "windows")]
 pub mod input;
 
+#[cfg(target_os = "windows")]
+pub mod indicator;
+
 #[cfg(target_os = "windows")]
 pub mod terminal;
 
 #[cfg(target_os = "windows")]
 pub mod filesystem;
 
+#[cfg(target_os = "windows")]
+pub mod audio;
+
 #[cfg(target_os = "windows")]
 pub mod system_info;
 
 #[cfg(target_os = "windows")]
 pub mod service;
 
+#[cfg(target_os = "windows")]
+pub mod clock;
+
+#[cfg(target_os = "windows")]
+pub mod network;
+
+#[cfg(target_os = "windows")]
+pub mod notification;
+
 #[cfg(target_os = "windows")]
 pub mod session_detect;
 
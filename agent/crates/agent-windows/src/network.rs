@@ -0,0 +1,49 @@
+//! Windows network troubleshooting through ipconfig.
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use agent_platform::network::NetworkControl;
+
+pub struct WindowsNetwork;
+
+impl NetworkControl for WindowsNetwork {
+    fn flush_dns(&self) -> Result<()> {
+        info!("flushing DNS cache");
+        run("ipconfig", &["/flushdns"])
+    }
+
+    fn renew_dhcp(&self, interface: Option<&str>) -> Result<()> {
+        match interface {
+            Some(name) => {
+                info!("renewing DHCP lease on {}", name);
+                run("ipconfig", &["/renew", name])
+            }
+            None => {
+                info!("renewing DHCP leases on all adapters");
+                run("ipconfig", &["/renew"])
+            }
+        }
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run {}", program))?;
+
+    // ipconfig reports errors on stdout
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "{} {} failed: {} {}",
+            program,
+            args[0],
+            stdout.trim(),
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! Windows toast notifications, raised through the WinRT toast API from
+//! PowerShell. Toasts only show from the user's own session, so the Session 0
+//! service routes NOTIFY_USER through the helper.
+
+use std::os::windows::process::CommandExt;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use agent_platform::notification::UserNotifier;
+
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+/// Shows a toast with the text in NOTIFY_TITLE / NOTIFY_BODY, which are
+/// XML-escaped so the text can't alter the toast template. Uses PowerShell's
+/// AppUserModelID since the agent has no registered one.
+const TOAST_SCRIPT: &str = r#"
+$ErrorActionPreference = 'Stop'
+[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] | Out-Null
+[Windows.Data.Xml.Dom.XmlDocument, Windows.Data.Xml.Dom.XmlDocument, ContentType = WindowsRuntime] | Out-Null
+$title = [Security.SecurityElement]::Escape($env:NOTIFY_TITLE)
+$body = [Security.SecurityElement]::Escape($env:NOTIFY_BODY)
+$xml = New-Object Windows.Data.Xml.Dom.XmlDocument
+$xml.LoadXml("<toast><visual><binding template=`"ToastGeneric`"><text>$title</text><text>$body</text></binding></visual></toast>")
+$appId = '{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe'
+[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier($appId).Show([Windows.UI.Notifications.ToastNotification]::new($xml))
+"#;
+
+pub struct WindowsNotifier;
+
+impl UserNotifier for WindowsNotifier {
+    fn notify(&self, title: &str, body: &str) -> Result<()> {
+        info!("showing toast notification: {}", title);
+        let output = std::process::Command::new("powershell.exe")
+            .args([
+                "-NoProfile",
+                "-NonInteractive",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-Command",
+                TOAST_SCRIPT,
+            ])
+            .env("NOTIFY_TITLE", title)
+            .env("NOTIFY_BODY", body)
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+            .context("failed to run powershell")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("toast notification failed: {}", stderr.trim());
+        }
+        Ok(())
+    }
+}
@@ -1,24 +1,28 @@
 //! Windows screen capture using DXGI Desktop Duplication API.
 //! Requires Windows 8+ and a DirectX 11 capable GPU.
 //! Falls back to GDI capture for remote desktop sessions where DXGI is unavailable.
+//! Stitched mode duplicates every output and composes them into one frame
+//! covering the virtual desktop.
 
 use anyhow::{Context, Result, bail};
 use agent_platform::screen::{ScreenCapture, ScreenFrame};
 use async_trait::async_trait;
-use tracing::info;
+use tracing::{debug, info, warn};
 use windows::core::Interface;
 
+use crate::session_detect;
+
+use windows::Win32::Graphics::Direct3D::{D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_UNKNOWN};
 use windows::Win32::Graphics::Direct3D11::{
-    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D,
-    D3D11_CPU_ACCESS_READ, D3D11_MAP_READ, D3D11_MAPPED_SUBRESOURCE, D3D11_SDK_VERSION,
-    D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_CPU_ACCESS_READ,
+    D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC,
+    D3D11_USAGE_STAGING,
 };
-use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_HARDWARE;
+use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM;
 use windows::Win32::Graphics::Dxgi::{
-    IDXGIDevice, IDXGIAdapter, IDXGIOutput, IDXGIOutput1, IDXGIOutputDuplication,
-    DXGI_OUTDUPL_FRAME_INFO,
+    CreateDXGIFactory1, IDXGIAdapter, IDXGIDevice, IDXGIFactory1, IDXGIOutput, IDXGIOutput1,
+    IDXGIOutputDuplication, IDXGIResource, DXGI_OUTDUPL_FRAME_INFO,
 };
-use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM;
 
 /// DXGI Desktop Duplication screen capture
 pub struct DxgiScreenCapture {
@@ -28,9 +32,42 @@ pub struct DxgiScreenCapture {
     staging_texture: Option<ID3D11Texture2D>,
     width: u32,
     height: u32,
+    /// AcquireNextFrame timeout in milliseconds
+    acquire_timeout_ms: u32,
+    /// Whether the staging texture holds a frame yet
+    has_frame: bool,
     initialized: bool,
 }
 
+/// Default AcquireNextFrame timeout
+const DEFAULT_ACQUIRE_TIMEOUT_MS: u32 = 100;
+
+/// DXGI_ERROR_WAIT_TIMEOUT — no new frame within the acquire timeout
+const DXGI_ERROR_WAIT_TIMEOUT: u32 = 0x887A0027;
+
+/// DXGI init failures that clear up on their own, unlike e.g. the
+/// DXGI_ERROR_UNSUPPORTED an RDP session gets
+const RETRYABLE_DXGI_ERRORS: &[u32] = &[
+    0x887A0022, // DXGI_ERROR_NOT_CURRENTLY_AVAILABLE: output held by a fullscreen app
+    0x887A0026, // DXGI_ERROR_ACCESS_LOST
+    0x887A0005, // DXGI_ERROR_DEVICE_REMOVED: driver reset or update
+    0x887A0007, // DXGI_ERROR_DEVICE_RESET
+    0x80070005, // E_ACCESSDENIED: secure desktop showing
+];
+
+/// DXGI init attempts before falling back to GDI on a retryable error
+const DXGI_INIT_ATTEMPTS: u32 = 3;
+const DXGI_INIT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How often a GDI fallback caused by a retryable error tries DXGI again
+const DXGI_UPGRADE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Whether a DXGI init error is worth retrying
+fn is_retryable_dxgi_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<windows::core::Error>()
+        .is_some_and(|e| RETRYABLE_DXGI_ERRORS.contains(&(e.code().0 as u32)))
+}
+
 // SAFETY: D3D11 objects are thread-safe when accessed serially
 unsafe impl Send for DxgiScreenCapture {}
 unsafe impl Sync for DxgiScreenCapture {}
@@ -44,6 +81,8 @@ impl DxgiScreenCapture {
             staging_texture: None,
             width: 0,
             height: 0,
+            acquire_timeout_ms: DEFAULT_ACQUIRE_TIMEOUT_MS,
+            has_frame: false,
             initialized: false,
         }
     }
@@ -78,6 +117,119 @@ impl DxgiScreenCapture {
 
         texture.context("staging texture was None")
     }
+
+    /// One AcquireNextFrame attempt, copied into the staging texture.
+    /// Returns whether the screen changed within the acquire timeout.
+    async fn acquire_to_staging(&mut self) -> Result<bool> {
+        let duplication = SendCom(self.duplication.clone().context("duplication was None")?);
+        let Some(resource) = acquire_frame(duplication, self.acquire_timeout_ms).await? else {
+            return Ok(false);
+        };
+        let duplication = self.duplication.as_ref().context("duplication was None")?;
+        let context = self.context.as_ref().context("context was None")?;
+        let staging = self
+            .staging_texture
+            .as_ref()
+            .context("staging texture was None")?;
+
+        unsafe {
+            let texture: ID3D11Texture2D = resource
+                .into_inner()
+                .cast()
+                .context("cast to ID3D11Texture2D")?;
+            context.CopyResource(staging, &texture);
+            duplication.ReleaseFrame().context("ReleaseFrame")?;
+        }
+        self.has_frame = true;
+        Ok(true)
+    }
+
+    /// Read the staging texture, which holds the last acquired frame
+    fn read_staging(&self) -> Result<ScreenFrame> {
+        let context = self.context.as_ref().context("context was None")?;
+        let staging = self
+            .staging_texture
+            .as_ref()
+            .context("staging texture was None")?;
+
+        unsafe {
+            // Map the staging texture for CPU read
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            context
+                .Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))
+                .context("Map staging texture")?;
+
+            // Copy pixel data
+            let stride = mapped.RowPitch;
+            let data_size = (self.height * stride) as usize;
+            let src = std::slice::from_raw_parts(mapped.pData as *const u8, data_size);
+
+            // If stride matches width * 4, copy directly; otherwise, row by row
+            let expected_stride = self.width * 4;
+            let data = if stride == expected_stride {
+                src.to_vec()
+            } else {
+                let mut data = Vec::with_capacity((self.width * self.height * 4) as usize);
+                for y in 0..self.height {
+                    let row_start = (y * stride) as usize;
+                    let row_end = row_start + expected_stride as usize;
+                    data.extend_from_slice(&src[row_start..row_end]);
+                }
+                data
+            };
+
+            context.Unmap(staging, 0);
+
+            Ok(ScreenFrame {
+                width: self.width,
+                height: self.height,
+                data,
+                stride: self.width * 4,
+            })
+        }
+    }
+}
+
+/// A COM object handed to a blocking thread for one call
+struct SendCom<T>(T);
+
+// SAFETY: the duplication and its frames are only ever used by one thread
+// at a time; the capture's `&mut self` serializes the calls
+unsafe impl<T> Send for SendCom<T> {}
+
+impl<T> SendCom<T> {
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// Wait up to `timeout_ms` for `duplication`'s next frame on a blocking
+/// thread, so the wait neither holds a runtime worker nor keeps the capture
+/// watchdog from giving up on a hung driver. Returns None on
+/// DXGI_ERROR_WAIT_TIMEOUT.
+async fn acquire_frame(
+    duplication: SendCom<IDXGIOutputDuplication>,
+    timeout_ms: u32,
+) -> Result<Option<SendCom<IDXGIResource>>> {
+    let acquired = tokio::task::spawn_blocking(move || {
+        let duplication = duplication.into_inner();
+        let mut frame_info = DXGI_OUTDUPL_FRAME_INFO::default();
+        let mut resource = None;
+        let result =
+            unsafe { duplication.AcquireNextFrame(timeout_ms, &mut frame_info, &mut resource) };
+        SendCom(result.map(|()| resource))
+    })
+    .await
+    .context("AcquireNextFrame thread failed")?
+    .into_inner();
+
+    match acquired {
+        Ok(resource) => resource
+            .context("desktop resource was None")
+            .map(|r| Some(SendCom(r))),
+        Err(e) if e.code().0 as u32 == DXGI_ERROR_WAIT_TIMEOUT => Ok(None),
+        Err(e) => Err(e).context("AcquireNextFrame"),
+    }
 }
 
 #[async_trait]
@@ -134,6 +286,7 @@ impl ScreenCapture for DxgiScreenCapture {
             self.staging_texture = Some(staging);
             self.width = width;
             self.height = height;
+            self.has_frame = false;
             self.initialized = true;
 
             Ok((width, height))
@@ -145,91 +298,278 @@ impl ScreenCapture for DxgiScreenCapture {
             bail!("screen capture not initialized");
         }
 
-        let duplication = self.duplication.as_ref().unwrap();
-        let context = self.context.as_ref().unwrap();
-        let staging = self.staging_texture.as_ref().unwrap();
+        // An unchanged screen is still showing the last frame, which the
+        // staging texture keeps; only a fresh duplication has to wait
+        if !self.acquire_to_staging().await? && !self.has_frame {
+            bail!("no frame within {}ms", self.acquire_timeout_ms);
+        }
+        self.read_staging()
+    }
 
-        unsafe {
-            // Acquire next frame (100ms timeout)
-            let mut frame_info = DXGI_OUTDUPL_FRAME_INFO::default();
-            let mut desktop_resource = None;
-
-            let result = duplication.AcquireNextFrame(100, &mut frame_info, &mut desktop_resource);
-
-            match result {
-                Ok(()) => {}
-                Err(e) => {
-                    // DXGI_ERROR_WAIT_TIMEOUT — no new frame
-                    if e.code().0 as u32 == 0x887A0027 {
-                        // Return empty frame (no changes)
-                        return Ok(ScreenFrame {
-                            width: self.width,
-                            height: self.height,
-                            data: vec![],
-                            stride: self.width * 4,
-                        });
-                    }
-                    return Err(e).context("AcquireNextFrame");
+    async fn next_frame(&mut self) -> Result<Option<ScreenFrame>> {
+        if !self.initialized {
+            bail!("screen capture not initialized");
+        }
+        if !self.acquire_to_staging().await? {
+            return Ok(None);
+        }
+        self.read_staging().map(Some)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_acquire_timeout(&mut self, timeout: std::time::Duration) {
+        self.acquire_timeout_ms = (timeout.as_millis() as u32).max(1);
+    }
+}
+
+/// One duplicated output of a stitched capture
+struct StitchedOutput {
+    context: ID3D11DeviceContext,
+    duplication: IDXGIOutputDuplication,
+    staging: ID3D11Texture2D,
+    /// Position within the stitched frame
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// DXGI Desktop Duplication of every output attached to the desktop,
+/// composed into one frame spanning the virtual desktop bounds. Outputs
+/// are copied in as they change; areas no monitor covers stay black.
+pub struct StitchedDxgiCapture {
+    outputs: Vec<StitchedOutput>,
+    /// Composed BGRA frame, kept between captures
+    frame: Vec<u8>,
+    /// Whether any output has been copied into `frame` yet
+    has_frame: bool,
+    width: u32,
+    height: u32,
+    acquire_timeout_ms: u32,
+}
+
+// SAFETY: D3D11 objects are thread-safe when accessed serially
+unsafe impl Send for StitchedDxgiCapture {}
+unsafe impl Sync for StitchedDxgiCapture {}
+
+impl StitchedDxgiCapture {
+    pub fn new() -> Self {
+        Self {
+            outputs: Vec::new(),
+            frame: Vec::new(),
+            has_frame: false,
+            width: 0,
+            height: 0,
+            acquire_timeout_ms: DEFAULT_ACQUIRE_TIMEOUT_MS,
+        }
+    }
+
+    /// One pass over the outputs, copying each one that changed within its
+    /// share of the acquire timeout into the composed frame. Returns whether
+    /// any did.
+    async fn update_outputs(&mut self) -> Result<bool> {
+        // Split the acquire timeout across the outputs so one pass over all
+        // of them waits about as long as a single-output capture would
+        let timeout_ms = (self.acquire_timeout_ms / self.outputs.len() as u32).max(1);
+        let stride = self.width as usize * 4;
+        let mut updated = false;
+        for index in 0..self.outputs.len() {
+            let duplication = SendCom(self.outputs[index].duplication.clone());
+            if let Some(resource) = acquire_frame(duplication, timeout_ms).await? {
+                unsafe {
+                    Self::copy_output(
+                        &self.outputs[index],
+                        resource.into_inner(),
+                        &mut self.frame,
+                        stride,
+                    )?;
                 }
+                updated = true;
             }
+        }
+        self.has_frame |= updated;
+        Ok(updated)
+    }
 
-            let resource = desktop_resource.context("desktop resource was None")?;
-            let texture: ID3D11Texture2D = resource.cast().context("cast to ID3D11Texture2D")?;
+    /// Copy an output's acquired frame into the composed frame
+    unsafe fn copy_output(
+        output: &StitchedOutput,
+        resource: IDXGIResource,
+        frame: &mut [u8],
+        frame_stride: usize,
+    ) -> Result<()> {
+        let texture: ID3D11Texture2D = resource.cast().context("cast to ID3D11Texture2D")?;
+        output.context.CopyResource(&output.staging, &texture);
+        output.duplication.ReleaseFrame().context("ReleaseFrame")?;
 
-            // Copy desktop texture to staging texture
-            context.CopyResource(staging, &texture);
+        let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+        output
+            .context
+            .Map(&output.staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))
+            .context("Map staging texture")?;
 
-            // Release the frame
-            duplication
-                .ReleaseFrame()
-                .context("ReleaseFrame")?;
+        let src_stride = mapped.RowPitch as usize;
+        let row_len = output.width as usize * 4;
+        let src = std::slice::from_raw_parts(
+            mapped.pData as *const u8,
+            src_stride * output.height as usize,
+        );
+        for row in 0..output.height as usize {
+            let dst = (output.y as usize + row) * frame_stride + output.x as usize * 4;
+            frame[dst..dst + row_len]
+                .copy_from_slice(&src[row * src_stride..row * src_stride + row_len]);
+        }
 
-            // Map the staging texture for CPU read
-            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
-            context
-                .Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))
-                .context("Map staging texture")?;
+        output.context.Unmap(&output.staging, 0);
+        Ok(())
+    }
 
-            // Copy pixel data
-            let stride = mapped.RowPitch;
-            let data_size = (self.height * stride) as usize;
-            let src = std::slice::from_raw_parts(mapped.pData as *const u8, data_size);
+    fn composed_frame(&self) -> ScreenFrame {
+        ScreenFrame {
+            width: self.width,
+            height: self.height,
+            data: self.frame.clone(),
+            stride: self.width * 4,
+        }
+    }
+}
 
-            // If stride matches width * 4, copy directly; otherwise, row by row
-            let expected_stride = self.width * 4;
-            let data = if stride == expected_stride {
-                src.to_vec()
-            } else {
-                let mut data = Vec::with_capacity((self.width * self.height * 4) as usize);
-                for y in 0..self.height {
-                    let row_start = (y * stride) as usize;
-                    let row_end = row_start + expected_stride as usize;
-                    data.extend_from_slice(&src[row_start..row_end]);
+#[async_trait]
+impl ScreenCapture for StitchedDxgiCapture {
+    async fn init(&mut self) -> Result<(u32, u32)> {
+        info!("initializing stitched DXGI Desktop Duplication");
+
+        unsafe {
+            let factory: IDXGIFactory1 = CreateDXGIFactory1().context("CreateDXGIFactory1")?;
+
+            // Every output attached to the desktop, with its desktop rectangle
+            let mut found = Vec::new();
+            let mut adapter_index = 0;
+            while let Ok(adapter1) = factory.EnumAdapters1(adapter_index) {
+                adapter_index += 1;
+                let adapter: IDXGIAdapter = adapter1.cast().context("cast to IDXGIAdapter")?;
+
+                let mut device: Option<ID3D11Device> = None;
+                let mut context: Option<ID3D11DeviceContext> = None;
+                let mut output_index = 0;
+                while let Ok(output) = adapter.EnumOutputs(output_index) {
+                    output_index += 1;
+                    let desc = output.GetDesc().context("GetDesc")?;
+                    if !desc.AttachedToDesktop.as_bool() {
+                        continue;
+                    }
+
+                    // One device per adapter, created once it has an output
+                    if device.is_none() {
+                        D3D11CreateDevice(
+                            &adapter,
+                            D3D_DRIVER_TYPE_UNKNOWN,
+                            None,
+                            windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_FLAG(0),
+                            None,
+                            D3D11_SDK_VERSION,
+                            Some(&mut device),
+                            None,
+                            Some(&mut context),
+                        )
+                        .context("D3D11CreateDevice")?;
+                    }
+                    let device = device.as_ref().context("D3D11 device was None")?;
+                    let context = context.as_ref().context("D3D11 context was None")?;
+
+                    let output1: IDXGIOutput1 = output.cast().context("cast to IDXGIOutput1")?;
+                    let duplication = output1
+                        .DuplicateOutput(device)
+                        .context("DuplicateOutput — DXGI Desktop Duplication may not be available (e.g., RDP session)")?;
+                    let rect = desc.DesktopCoordinates;
+                    let width = (rect.right - rect.left) as u32;
+                    let height = (rect.bottom - rect.top) as u32;
+                    let staging = DxgiScreenCapture::create_staging_texture(device, width, height)?;
+
+                    found.push((rect, context.clone(), duplication, staging));
                 }
-                data
-            };
+            }
 
-            context.Unmap(staging, 0);
+            if found.is_empty() {
+                bail!("no DXGI outputs attached to the desktop");
+            }
 
-            Ok(ScreenFrame {
-                width: self.width,
-                height: self.height,
-                data,
-                stride: self.width * 4,
-            })
+            let left = found.iter().map(|(r, ..)| r.left).min().unwrap_or(0);
+            let top = found.iter().map(|(r, ..)| r.top).min().unwrap_or(0);
+            let right = found.iter().map(|(r, ..)| r.right).max().unwrap_or(0);
+            let bottom = found.iter().map(|(r, ..)| r.bottom).max().unwrap_or(0);
+
+            self.width = (right - left) as u32;
+            self.height = (bottom - top) as u32;
+            self.frame = vec![0u8; (self.width * self.height * 4) as usize];
+            self.has_frame = false;
+            self.outputs = found
+                .into_iter()
+                .map(|(rect, context, duplication, staging)| StitchedOutput {
+                    context,
+                    duplication,
+                    staging,
+                    x: (rect.left - left) as u32,
+                    y: (rect.top - top) as u32,
+                    width: (rect.right - rect.left) as u32,
+                    height: (rect.bottom - rect.top) as u32,
+                })
+                .collect();
+
+            info!(
+                "stitched {} output(s) into {}x{}",
+                self.outputs.len(),
+                self.width,
+                self.height
+            );
+            Ok((self.width, self.height))
         }
     }
 
+    async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+        if self.outputs.is_empty() {
+            bail!("screen capture not initialized");
+        }
+
+        // Outputs that didn't change still show what was composed before
+        if !self.update_outputs().await? && !self.has_frame {
+            bail!("no frame within {}ms", self.acquire_timeout_ms);
+        }
+        Ok(self.composed_frame())
+    }
+
+    async fn next_frame(&mut self) -> Result<Option<ScreenFrame>> {
+        if self.outputs.is_empty() {
+            bail!("screen capture not initialized");
+        }
+        if !self.update_outputs().await? {
+            return Ok(None);
+        }
+        Ok(Some(self.composed_frame()))
+    }
+
     fn dimensions(&self) -> (u32, u32) {
         (self.width, self.height)
     }
+
+    fn set_acquire_timeout(&mut self, timeout: std::time::Duration) {
+        self.acquire_timeout_ms = (timeout.as_millis() as u32).max(1);
+    }
 }
 
 /// GDI-based screen capture fallback for RDP sessions and environments
 /// where DXGI Desktop Duplication is unavailable.
 pub struct GdiScreenCapture {
+    /// Top-left of the captured area in screen coordinates
+    x: i32,
+    y: i32,
     width: u32,
     height: u32,
+    /// Capture the whole virtual desktop rather than the primary screen
+    virtual_desktop: bool,
     initialized: bool,
 }
 
@@ -239,11 +579,22 @@ unsafe impl Sync for GdiScreenCapture {}
 impl GdiScreenCapture {
     pub fn new() -> Self {
         Self {
+            x: 0,
+            y: 0,
             width: 0,
             height: 0,
+            virtual_desktop: false,
             initialized: false,
         }
     }
+
+    /// GDI capture of every monitor; areas no monitor covers come out black
+    pub fn virtual_desktop() -> Self {
+        Self {
+            virtual_desktop: true,
+            ..Self::new()
+        }
+    }
 }
 
 #[async_trait]
@@ -252,15 +603,33 @@ impl ScreenCapture for GdiScreenCapture {
         info!("initializing GDI screen capture (fallback)");
 
         unsafe {
-            use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN};
-            let width = GetSystemMetrics(SM_CXSCREEN) as u32;
-            let height = GetSystemMetrics(SM_CYSCREEN) as u32;
+            use windows::Win32::UI::WindowsAndMessaging::{
+                GetSystemMetrics, SM_CXSCREEN, SM_CXVIRTUALSCREEN, SM_CYSCREEN, SM_CYVIRTUALSCREEN,
+                SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN,
+            };
+            let (x, y, width, height) = if self.virtual_desktop {
+                (
+                    GetSystemMetrics(SM_XVIRTUALSCREEN),
+                    GetSystemMetrics(SM_YVIRTUALSCREEN),
+                    GetSystemMetrics(SM_CXVIRTUALSCREEN) as u32,
+                    GetSystemMetrics(SM_CYVIRTUALSCREEN) as u32,
+                )
+            } else {
+                (
+                    0,
+                    0,
+                    GetSystemMetrics(SM_CXSCREEN) as u32,
+                    GetSystemMetrics(SM_CYSCREEN) as u32,
+                )
+            };
 
             if width == 0 || height == 0 {
                 bail!("GetSystemMetrics returned zero dimensions");
             }
 
             info!("GDI screen dimensions: {}x{}", width, height);
+            self.x = x;
+            self.y = y;
             self.width = width;
             self.height = height;
             self.initialized = true;
@@ -304,13 +673,16 @@ impl ScreenCapture for GdiScreenCapture {
             // BitBlt the screen into our bitmap
             BitBlt(
                 hdc_mem,
-                0, 0,
+                0,
+                0,
                 self.width as i32,
                 self.height as i32,
                 hdc_screen,
-                0, 0,
+                self.x,
+                self.y,
                 SRCCOPY,
-            ).context("BitBlt failed")?;
+            )
+            .context("BitBlt failed")?;
 
             // Read pixel data via GetDIBits (BGRA format, top-down)
             // BI_RGB = 0
@@ -370,13 +742,29 @@ impl ScreenCapture for GdiScreenCapture {
 
 /// Windows screen capture that tries DXGI first, falling back to GDI.
 /// The fallback decision happens in init(), which runs inside the async task.
+/// Retryable DXGI failures are retried briefly first, and a fallback caused
+/// by one keeps trying to get back to DXGI while capturing.
+///
+/// Capture follows the input desktop: when it switches (UAC prompt, lock
+/// screen) the capturing thread is re-attached and the backend re-initialized,
+/// provided the process has access to the new desktop.
 pub struct WindowsScreenCapture {
     inner: WindowsCaptureInner,
+    /// Name of the desktop the backend was initialized against
+    desktop: Option<String>,
+    /// Acquire timeout applied to the DXGI backend on (re)initialization
+    acquire_timeout: Option<std::time::Duration>,
+    /// Capture every monitor into one frame instead of the primary one
+    stitched: bool,
+    /// When the GDI fallback next tries DXGI again; None when not on a
+    /// fallback that may recover
+    dxgi_retry_at: Option<std::time::Instant>,
 }
 
 enum WindowsCaptureInner {
     Uninitialized,
     Dxgi(DxgiScreenCapture),
+    Stitched(StitchedDxgiCapture),
     Gdi(GdiScreenCapture),
 }
 
@@ -387,46 +775,237 @@ impl WindowsScreenCapture {
     pub fn new() -> Self {
         Self {
             inner: WindowsCaptureInner::Uninitialized,
+            desktop: None,
+            acquire_timeout: None,
+            stitched: false,
+            dxgi_retry_at: None,
         }
     }
-}
 
-#[async_trait]
-impl ScreenCapture for WindowsScreenCapture {
-    async fn init(&mut self) -> Result<(u32, u32)> {
+    /// Capture of all monitors stitched into one virtual-desktop frame
+    pub fn stitched() -> Self {
+        Self {
+            stitched: true,
+            ..Self::new()
+        }
+    }
+
+    /// Make sure the calling thread captures the current input desktop.
+    ///
+    /// Tokio may run us on a different worker thread each frame, so the
+    /// thread desktop is checked every time. Returns true when the input
+    /// desktop itself changed and the backend must be re-initialized.
+    fn follow_input_desktop(&mut self) -> bool {
+        let input = match session_detect::input_desktop_name() {
+            Some(name) => name,
+            // No access to the input desktop (secure desktop without SYSTEM)
+            None => return false,
+        };
+
+        let thread = session_detect::thread_desktop_name();
+        if thread.as_deref() != Some(input.as_str()) {
+            if let Err(e) = session_detect::attach_to_input_desktop() {
+                debug!("cannot switch capture to desktop \"{}\": {:#}", input, e);
+                return false;
+            }
+        }
+
+        let changed = self.desktop.as_deref() != Some(input.as_str());
+        if changed {
+            info!(
+                "input desktop changed ({:?} -> \"{}\"), re-initializing capture",
+                self.desktop, input
+            );
+        }
+        self.desktop = Some(input);
+        changed
+    }
+
+    /// Create and initialize the DXGI backend for this capture mode
+    async fn init_dxgi(&self) -> Result<(WindowsCaptureInner, (u32, u32))> {
+        if self.stitched {
+            let mut dxgi = StitchedDxgiCapture::new();
+            if let Some(timeout) = self.acquire_timeout {
+                dxgi.set_acquire_timeout(timeout);
+            }
+            let dims = dxgi.init().await?;
+            Ok((WindowsCaptureInner::Stitched(dxgi), dims))
+        } else {
+            let mut dxgi = DxgiScreenCapture::new();
+            if let Some(timeout) = self.acquire_timeout {
+                dxgi.set_acquire_timeout(timeout);
+            }
+            let dims = dxgi.init().await?;
+            Ok((WindowsCaptureInner::Dxgi(dxgi), dims))
+        }
+    }
+
+    async fn init_backend(&mut self) -> Result<(u32, u32)> {
+        self.dxgi_retry_at = None;
+
         // Try DXGI first (GPU-accelerated, faster)
-        let mut dxgi = DxgiScreenCapture::new();
-        match dxgi.init().await {
-            Ok(dims) => {
-                info!("using DXGI Desktop Duplication for screen capture");
-                self.inner = WindowsCaptureInner::Dxgi(dxgi);
-                Ok(dims)
+        let mut attempt = 1;
+        let error = loop {
+            match self.init_dxgi().await {
+                Ok((inner, dims)) => {
+                    info!("using DXGI Desktop Duplication for screen capture");
+                    self.inner = inner;
+                    return Ok(dims);
+                }
+                Err(e) if attempt < DXGI_INIT_ATTEMPTS && is_retryable_dxgi_error(&e) => {
+                    debug!(
+                        "DXGI init failed ({:#}), retrying ({}/{})",
+                        e, attempt, DXGI_INIT_ATTEMPTS
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(DXGI_INIT_RETRY_DELAY).await;
+                }
+                Err(e) => break e,
+            }
+        };
+
+        info!("DXGI unavailable ({}), falling back to GDI capture", error);
+        if is_retryable_dxgi_error(&error) {
+            self.dxgi_retry_at = Some(std::time::Instant::now() + DXGI_UPGRADE_INTERVAL);
+        }
+        let mut gdi = if self.stitched {
+            GdiScreenCapture::virtual_desktop()
+        } else {
+            GdiScreenCapture::new()
+        };
+        let dims = gdi.init().await?;
+        self.inner = WindowsCaptureInner::Gdi(gdi);
+        Ok(dims)
+    }
+
+    /// Before each capture: follow the input desktop, re-initializing when
+    /// it changed, and move off a GDI fallback once DXGI is back.
+    async fn prepare_backend(&mut self) -> Result<()> {
+        if self.follow_input_desktop() {
+            self.init_backend().await?;
+        } else if matches!(self.inner, WindowsCaptureInner::Gdi(_)) {
+            self.try_upgrade_to_dxgi().await;
+        }
+        Ok(())
+    }
+
+    /// On a GDI fallback that may recover, try DXGI again once
+    /// `DXGI_UPGRADE_INTERVAL` has passed (e.g. the fullscreen app exited).
+    /// Only switches when DXGI captures the same size, since the session
+    /// can't change frame size mid-stream.
+    async fn try_upgrade_to_dxgi(&mut self) {
+        let now = std::time::Instant::now();
+        if !matches!(self.dxgi_retry_at, Some(at) if now >= at) {
+            return;
+        }
+        let current = self.dimensions();
+        match self.init_dxgi().await {
+            Ok((inner, dims)) if dims == current => {
+                info!("DXGI available again, switching back from GDI capture");
+                self.inner = inner;
+                self.dxgi_retry_at = None;
+            }
+            Ok((_, (width, height))) => {
+                info!(
+                    "DXGI available again but captures {}x{} instead of {}x{}, staying on GDI",
+                    width, height, current.0, current.1
+                );
+                self.dxgi_retry_at = None;
+            }
+            Err(e) if is_retryable_dxgi_error(&e) => {
+                debug!("DXGI still unavailable: {:#}", e);
+                self.dxgi_retry_at = Some(now + DXGI_UPGRADE_INTERVAL);
             }
             Err(e) => {
-                info!("DXGI unavailable ({}), falling back to GDI capture", e);
-                let mut gdi = GdiScreenCapture::new();
-                let dims = gdi.init().await?;
-                self.inner = WindowsCaptureInner::Gdi(gdi);
-                Ok(dims)
+                info!("DXGI unavailable ({:#}), staying on GDI capture", e);
+                self.dxgi_retry_at = None;
             }
         }
     }
+}
+
+#[async_trait]
+impl ScreenCapture for WindowsScreenCapture {
+    async fn init(&mut self) -> Result<(u32, u32)> {
+        self.follow_input_desktop();
+        self.init_backend().await
+    }
 
     async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+        self.prepare_backend().await?;
         match &mut self.inner {
             WindowsCaptureInner::Dxgi(d) => d.capture_frame().await,
+            WindowsCaptureInner::Stitched(s) => s.capture_frame().await,
             WindowsCaptureInner::Gdi(g) => g.capture_frame().await,
             WindowsCaptureInner::Uninitialized => bail!("screen capture not initialized"),
         }
     }
 
+    async fn next_frame(&mut self) -> Result<Option<ScreenFrame>> {
+        self.prepare_backend().await?;
+        match &mut self.inner {
+            WindowsCaptureInner::Dxgi(d) => d.next_frame().await,
+            WindowsCaptureInner::Stitched(s) => s.next_frame().await,
+            WindowsCaptureInner::Gdi(g) => g.capture_frame().await.map(Some),
+            WindowsCaptureInner::Uninitialized => bail!("screen capture not initialized"),
+        }
+    }
+
     fn dimensions(&self) -> (u32, u32) {
         match &self.inner {
             WindowsCaptureInner::Dxgi(d) => d.dimensions(),
+            WindowsCaptureInner::Stitched(s) => s.dimensions(),
             WindowsCaptureInner::Gdi(g) => g.dimensions(),
             WindowsCaptureInner::Uninitialized => (0, 0),
         }
     }
+
+    fn set_acquire_timeout(&mut self, timeout: std::time::Duration) {
+        self.acquire_timeout = Some(timeout);
+        match &mut self.inner {
+            WindowsCaptureInner::Dxgi(d) => d.set_acquire_timeout(timeout),
+            WindowsCaptureInner::Stitched(s) => s.set_acquire_timeout(timeout),
+            _ => {}
+        }
+    }
+}
+
+/// Whether DXGI Desktop Duplication works here: a D3D11 device can be
+/// created and the primary output duplicated. It can't from Session 0 or
+/// on some RDP and virtual display adapters, where GDI takes over.
+pub fn dxgi_available() -> bool {
+    match unsafe { probe_dxgi() } {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("DXGI Desktop Duplication unavailable: {:#}", e);
+            false
+        }
+    }
+}
+
+unsafe fn probe_dxgi() -> Result<()> {
+    let mut device: Option<ID3D11Device> = None;
+    D3D11CreateDevice(
+        None,
+        D3D_DRIVER_TYPE_HARDWARE,
+        None,
+        windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_FLAG(0),
+        None,
+        D3D11_SDK_VERSION,
+        Some(&mut device),
+        None,
+        None,
+    )
+    .context("D3D11CreateDevice")?;
+    let device = device.context("D3D11 device was None")?;
+    let dxgi_device: IDXGIDevice = device.cast().context("cast to IDXGIDevice")?;
+    let adapter: IDXGIAdapter = dxgi_device.GetAdapter().context("GetAdapter")?;
+    let output: IDXGIOutput = adapter.EnumOutputs(0).context("EnumOutputs(0)")?;
+    let output1: IDXGIOutput1 = output.cast().context("cast to IDXGIOutput1")?;
+    output1
+        .DuplicateOutput(&device)
+        .context("DuplicateOutput")?;
+    Ok(())
 }
 
 /// Factory function for creating screen capture on Windows.
@@ -434,3 +1013,28 @@ pub fn create_screen_capture() -> Result<Box<dyn ScreenCapture>> {
     info!("using DXGI Desktop Duplication for screen capture");
     Ok(Box::new(WindowsScreenCapture::new()))
 }
+
+/// Factory for capturing every monitor stitched into one frame spanning
+/// the virtual desktop.
+pub fn create_stitched_screen_capture() -> Result<Box<dyn ScreenCapture>> {
+    info!("using stitched multi-monitor screen capture");
+    Ok(Box::new(WindowsScreenCapture::stitched()))
+}
+
+/// Factory for capturing a single window by title or handle. Falls back to
+/// full-screen capture when the window can't be found or is minimized.
+pub fn create_window_capture(
+    title: Option<&str>,
+    handle: Option<u64>,
+) -> Result<Box<dyn ScreenCapture>> {
+    match crate::window_capture::WindowCapture::find(title, handle) {
+        Ok(capture) => Ok(Box::new(capture)),
+        Err(e) => {
+            warn!(
+                "window capture unavailable ({:#}), capturing full screen",
+                e
+            );
+            create_screen_capture()
+        }
+    }
+}
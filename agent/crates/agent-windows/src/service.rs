@@ -1,15 +1,49 @@
-//! Windows Service Control Manager (SCM) — install/uninstall/start/stop the agent service.
+//! Windows Service Control Manager (SCM) — install/uninstall/start/stop the
+//! agent service, and listing/control of the system's other services.
 
 #[cfg(target_os = "windows")]
 use anyhow::{Context, Result};
 #[cfg(target_os = "windows")]
-use tracing::info;
+use std::ffi::OsString;
+#[cfg(target_os = "windows")]
+use std::sync::OnceLock;
+#[cfg(target_os = "windows")]
+use tokio::sync::watch;
+#[cfg(target_os = "windows")]
+use tracing::{error, info};
+#[cfg(target_os = "windows")]
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+};
 
 #[cfg(target_os = "windows")]
-use agent_platform::service::ServiceManager;
+use agent_platform::service::{
+    ServiceAction, ServiceInfo, ServiceManager, ServiceStartType, SystemServices,
+};
+#[cfg(target_os = "windows")]
+use windows::core::PCWSTR;
+#[cfg(target_os = "windows")]
+use windows::Win32::Foundation::ERROR_MORE_DATA;
+#[cfg(target_os = "windows")]
+use windows::Win32::System::Services::{
+    CloseServiceHandle, EnumServicesStatusExW, OpenSCManagerW, OpenServiceW, QueryServiceConfigW,
+    ENUM_SERVICE_STATUS_PROCESSW, QUERY_SERVICE_CONFIGW, SC_ENUM_PROCESS_INFO, SC_HANDLE,
+    SC_MANAGER_CONNECT, SC_MANAGER_ENUMERATE_SERVICE, SERVICE_AUTO_START, SERVICE_BOOT_START,
+    SERVICE_CONTINUE_PENDING, SERVICE_DEMAND_START, SERVICE_DISABLED, SERVICE_PAUSED,
+    SERVICE_PAUSE_PENDING, SERVICE_QUERY_CONFIG, SERVICE_RUNNING, SERVICE_START_PENDING,
+    SERVICE_STATE_ALL, SERVICE_STATUS_CURRENT_STATE, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+    SERVICE_SYSTEM_START, SERVICE_WIN32,
+};
 
+/// SCM name of the agent service
 #[cfg(target_os = "windows")]
-const SERVICE_NAME: &str = "AndroidRemoteAgent";
+pub const SERVICE_NAME: &str = "AndroidRemoteAgent";
 #[cfg(target_os = "windows")]
 const DISPLAY_NAME: &str = "Android Remote Agent";
 
@@ -97,6 +131,15 @@ impl ServiceManager for WindowsServiceManager {
         // Wait briefly for stop to take effect
         std::thread::sleep(std::time::Duration::from_secs(2));
 
+        self.remove()?;
+
+        info!("service uninstalled: {}", SERVICE_NAME);
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<()> {
+        // A running service is only marked for deletion; the SCM removes it
+        // once the process stops
         let output = std::process::Command::new("sc.exe")
             .args(["delete", SERVICE_NAME])
             .output()
@@ -107,7 +150,6 @@ impl ServiceManager for WindowsServiceManager {
             anyhow::bail!("sc.exe delete failed: {}", stderr);
         }
 
-        info!("service uninstalled: {}", SERVICE_NAME);
         Ok(())
     }
 
@@ -156,4 +198,307 @@ impl ServiceManager for WindowsServiceManager {
         // sc.exe query output contains "STATE" line with "RUNNING"
         Ok(stdout.contains("RUNNING"))
     }
+
+    fn is_installed(&self) -> Result<bool> {
+        let output = std::process::Command::new("sc.exe")
+            .args(["query", SERVICE_NAME])
+            .output()
+            .context("failed to query service")?;
+
+        // sc.exe query fails with 1060 (service does not exist) when not registered
+        Ok(output.status.success())
+    }
+}
+
+/// Daemon entry point run once the SCM starts the service. It receives a
+/// shutdown signal that flips to `true` on SERVICE_CONTROL_STOP/SHUTDOWN.
+#[cfg(target_os = "windows")]
+type ServiceEntry = Box<dyn Fn(watch::Receiver<bool>) -> Result<()> + Send + Sync>;
+
+#[cfg(target_os = "windows")]
+static SERVICE_ENTRY: OnceLock<ServiceEntry> = OnceLock::new();
+
+/// Status handle of the running service, set once it is registered
+#[cfg(target_os = "windows")]
+static STATUS_HANDLE: OnceLock<ServiceStatusHandle> = OnceLock::new();
+
+#[cfg(target_os = "windows")]
+define_windows_service!(ffi_service_main, service_main);
+
+/// Hand the process over to the SCM service dispatcher, running `entry` as
+/// the service body. Blocks until the service stops.
+///
+/// Fails straight away when the process was not started by the SCM, so the
+/// caller can fall back to running in the foreground.
+#[cfg(target_os = "windows")]
+pub fn run_service_dispatcher<F>(entry: F) -> Result<()>
+where
+    F: Fn(watch::Receiver<bool>) -> Result<()> + Send + Sync + 'static,
+{
+    let _ = SERVICE_ENTRY.set(Box::new(entry));
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("failed to start service dispatcher")
+}
+
+#[cfg(target_os = "windows")]
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("service failed: {:#}", e);
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn run_service() -> Result<()> {
+    let entry = SERVICE_ENTRY.get().context("service entry point not set")?;
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                info!("service stop requested");
+                let _ = shutdown_tx.send(true);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })
+        .context("failed to register service control handler")?;
+    let _ = STATUS_HANDLE.set(status_handle);
+
+    set_state(
+        status_handle,
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+    )
+    .context("failed to report service running")?;
+
+    let result = entry(shutdown_rx);
+
+    let exit_code = if result.is_ok() { 0 } else { 1 };
+    set_state(
+        status_handle,
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    )
+    .context("failed to report service stopped")?;
+
+    result
+}
+
+#[cfg(target_os = "windows")]
+fn set_state(
+    status_handle: ServiceStatusHandle,
+    state: ServiceState,
+    accepted: ServiceControlAccept,
+    exit_code: u32,
+) -> windows_service::Result<()> {
+    status_handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: std::time::Duration::default(),
+        process_id: None,
+    })
+}
+
+/// Report SERVICE_STOPPED to the SCM for a process about to exit on its own,
+/// so the exit is not treated as a crash to recover from. Does nothing when
+/// the agent isn't running as a service.
+#[cfg(target_os = "windows")]
+pub fn report_stopped() {
+    if let Some(&status_handle) = STATUS_HANDLE.get() {
+        if let Err(e) = set_state(
+            status_handle,
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            0,
+        ) {
+            error!("failed to report service stopped: {}", e);
+        }
+    }
+}
+
+/// How long a restart waits for the service to stop before starting it again
+#[cfg(target_os = "windows")]
+const RESTART_STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// The system's Win32 services, as registered with the SCM
+#[cfg(target_os = "windows")]
+pub struct ScmServices;
+
+#[cfg(target_os = "windows")]
+impl SystemServices for ScmServices {
+    fn list(&self) -> Result<Vec<ServiceInfo>> {
+        unsafe {
+            let scm = OpenSCManagerW(
+                PCWSTR::null(),
+                PCWSTR::null(),
+                SC_MANAGER_CONNECT | SC_MANAGER_ENUMERATE_SERVICE,
+            )
+            .context("OpenSCManagerW failed")?;
+            let services = enum_services(scm);
+            let _ = CloseServiceHandle(scm);
+            services
+        }
+    }
+
+    fn control(&self, name: &str, action: ServiceAction) -> Result<()> {
+        if name.is_empty() || name.starts_with(['-', '/']) {
+            anyhow::bail!("invalid service name: {:?}", name);
+        }
+        info!("{} service: {}", action.as_str(), name);
+
+        match action {
+            ServiceAction::Start => sc_control("start", name),
+            ServiceAction::Stop => sc_control("stop", name),
+            ServiceAction::Restart => {
+                sc_control("stop", name)?;
+                wait_for_stopped(name)?;
+                sc_control("start", name)
+            }
+        }
+    }
+}
+
+/// Read every Win32 service with its state, then look up each start type.
+#[cfg(target_os = "windows")]
+unsafe fn enum_services(scm: SC_HANDLE) -> Result<Vec<ServiceInfo>> {
+    let mut services = Vec::new();
+    let mut resume = 0u32;
+    // u64 storage keeps the entries in the buffer aligned
+    let mut buf: Vec<u64> = vec![0; 8 * 1024];
+
+    loop {
+        let mut needed = 0u32;
+        let mut returned = 0u32;
+        let bytes = std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 8);
+        let more = match EnumServicesStatusExW(
+            scm,
+            SC_ENUM_PROCESS_INFO,
+            SERVICE_WIN32,
+            SERVICE_STATE_ALL,
+            Some(bytes),
+            &mut needed,
+            &mut returned,
+            Some(&mut resume),
+            PCWSTR::null(),
+        ) {
+            Ok(()) => false,
+            Err(e) if e.code() == ERROR_MORE_DATA.to_hresult() => true,
+            Err(e) => return Err(e).context("EnumServicesStatusExW failed"),
+        };
+
+        let entries = std::slice::from_raw_parts(
+            buf.as_ptr() as *const ENUM_SERVICE_STATUS_PROCESSW,
+            returned as usize,
+        );
+        for entry in entries {
+            services.push(ServiceInfo {
+                name: entry.lpServiceName.to_string().unwrap_or_default(),
+                display_name: entry.lpDisplayName.to_string().unwrap_or_default(),
+                state: current_state(entry.ServiceStatusProcess.dwCurrentState),
+                start_type: query_start_type(scm, PCWSTR(entry.lpServiceName.0)),
+            });
+        }
+
+        if !more {
+            break;
+        }
+        if returned == 0 {
+            // Not even one entry fit; grow to what the SCM asked for
+            buf = vec![0; (needed as usize).div_ceil(8)];
+        }
+    }
+
+    services.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    Ok(services)
+}
+
+#[cfg(target_os = "windows")]
+fn current_state(state: SERVICE_STATUS_CURRENT_STATE) -> agent_platform::service::ServiceState {
+    use agent_platform::service::ServiceState as State;
+    match state {
+        SERVICE_RUNNING => State::Running,
+        SERVICE_STOPPED => State::Stopped,
+        SERVICE_START_PENDING | SERVICE_CONTINUE_PENDING => State::Starting,
+        SERVICE_STOP_PENDING | SERVICE_PAUSE_PENDING => State::Stopping,
+        SERVICE_PAUSED => State::Paused,
+        _ => State::Unknown,
+    }
+}
+
+/// Start type from the service's configuration. Services the agent may not
+/// query report `Unknown` rather than failing the whole listing.
+#[cfg(target_os = "windows")]
+unsafe fn query_start_type(scm: SC_HANDLE, name: PCWSTR) -> ServiceStartType {
+    let Ok(service) = OpenServiceW(scm, name, SERVICE_QUERY_CONFIG) else {
+        return ServiceStartType::Unknown;
+    };
+
+    let mut needed = 0u32;
+    let _ = QueryServiceConfigW(service, None, 0, &mut needed);
+    let mut buf: Vec<u64> = vec![0; (needed as usize).div_ceil(8).max(1)];
+    let config = buf.as_mut_ptr() as *mut QUERY_SERVICE_CONFIGW;
+
+    let start_type =
+        match QueryServiceConfigW(service, Some(config), (buf.len() * 8) as u32, &mut needed) {
+            Ok(()) => match (*config).dwStartType {
+                SERVICE_AUTO_START => ServiceStartType::Auto,
+                SERVICE_DEMAND_START => ServiceStartType::Manual,
+                SERVICE_DISABLED => ServiceStartType::Disabled,
+                SERVICE_BOOT_START | SERVICE_SYSTEM_START => ServiceStartType::Boot,
+                _ => ServiceStartType::Unknown,
+            },
+            Err(_) => ServiceStartType::Unknown,
+        };
+
+    let _ = CloseServiceHandle(service);
+    start_type
+}
+
+/// Run `sc.exe <verb> <name>`. Stopping a service that isn't running is
+/// not an error.
+#[cfg(target_os = "windows")]
+fn sc_control(verb: &str, name: &str) -> Result<()> {
+    let output = std::process::Command::new("sc.exe")
+        .args([verb, name])
+        .output()
+        .with_context(|| format!("failed to {} service", verb))?;
+
+    if !output.status.success() {
+        // sc.exe reports failures on stdout
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if verb == "stop" && stdout.contains("1062") {
+            return Ok(());
+        }
+        anyhow::bail!("sc.exe {} failed: {}", verb, stdout.trim());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn wait_for_stopped(name: &str) -> Result<()> {
+    let deadline = std::time::Instant::now() + RESTART_STOP_TIMEOUT;
+    loop {
+        let output = std::process::Command::new("sc.exe")
+            .args(["query", name])
+            .output()
+            .context("failed to query service")?;
+        if String::from_utf8_lossy(&output.stdout).contains("STOPPED") {
+            return Ok(());
+        }
+        if std::time::Instant::now() >= deadline {
+            anyhow::bail!(
+                "service {} did not stop within {}s",
+                name,
+                RESTART_STOP_TIMEOUT.as_secs()
+            );
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
 }
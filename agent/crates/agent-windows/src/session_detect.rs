@@ -91,6 +91,140 @@ pub fn get_active_console_session() -> Option<u32> {
     }
 }
 
+/// Returns true if this process can reach a desktop to capture: it runs
+/// outside Session 0 and a session is attached to the console. The login
+/// screen counts, since the console session then shows Winlogon.
+#[cfg(target_os = "windows")]
+pub fn has_interactive_session() -> bool {
+    !is_system_service_context() && get_active_console_session().is_some()
+}
+
+/// Returns the name of the desktop currently receiving user input
+/// (normally "Default", "Winlogon" for UAC prompts and the login screen).
+///
+/// Returns None when the input desktop cannot be opened, which for a
+/// process running as the logged-in user means the secure desktop is active.
+#[cfg(target_os = "windows")]
+pub fn input_desktop_name() -> Option<String> {
+    use windows::Win32::System::StationsAndDesktops::{
+        CloseDesktop, DESKTOP_CONTROL_FLAGS, DESKTOP_READOBJECTS,
+    };
+
+    unsafe {
+        let desktop =
+            OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS).ok()?;
+        let name = desktop_name(desktop);
+        let _ = CloseDesktop(desktop);
+        name
+    }
+}
+
+/// Returns the name of the desktop the calling thread is attached to.
+#[cfg(target_os = "windows")]
+pub fn thread_desktop_name() -> Option<String> {
+    use windows::Win32::System::StationsAndDesktops::GetThreadDesktop;
+    use windows::Win32::System::Threading::GetCurrentThreadId;
+
+    unsafe {
+        // The handle from GetThreadDesktop must not be closed
+        let desktop = GetThreadDesktop(GetCurrentThreadId()).ok()?;
+        desktop_name(desktop)
+    }
+}
+
+#[cfg(target_os = "windows")]
+unsafe fn desktop_name(
+    desktop: windows::Win32::System::StationsAndDesktops::HDESK,
+) -> Option<String> {
+    use windows::Win32::System::StationsAndDesktops::{GetUserObjectInformationW, UOI_NAME};
+
+    let mut buf = [0u16; 256];
+    let mut needed: u32 = 0;
+    GetUserObjectInformationW(
+        HANDLE(desktop.0),
+        UOI_NAME,
+        Some(buf.as_mut_ptr() as *mut _),
+        (buf.len() * 2) as u32,
+        Some(&mut needed),
+    )
+    .ok()?;
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Some(String::from_utf16_lossy(&buf[..len]))
+}
+
+#[cfg(target_os = "windows")]
+thread_local! {
+    /// Desktop handle the current thread was attached to by
+    /// `attach_to_input_desktop`; kept open while the thread uses it.
+    static ATTACHED_DESKTOP: std::cell::Cell<Option<windows::Win32::System::StationsAndDesktops::HDESK>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// Attach the calling thread to the desktop currently receiving input
+/// (e.g. the secure "Winlogon" desktop during a UAC prompt) so GDI/DXGI
+/// capture on this thread sees what the user sees. Returns the desktop name.
+///
+/// Opening the secure desktop requires SYSTEM; a helper running with the
+/// user's token gets access denied and stays on its current desktop.
+#[cfg(target_os = "windows")]
+pub fn attach_to_input_desktop() -> anyhow::Result<String> {
+    use anyhow::Context;
+    use windows::Win32::System::StationsAndDesktops::{
+        CloseDesktop, SetThreadDesktop, DESKTOP_ACCESS_FLAGS, DESKTOP_CONTROL_FLAGS,
+    };
+
+    // GENERIC_ALL — capture needs read access plus the right to switch to it
+    const GENERIC_ALL: u32 = 0x1000_0000;
+
+    unsafe {
+        let desktop = OpenInputDesktop(
+            DESKTOP_CONTROL_FLAGS(0),
+            false,
+            DESKTOP_ACCESS_FLAGS(GENERIC_ALL),
+        )
+        .context("OpenInputDesktop")?;
+
+        if let Err(e) = SetThreadDesktop(desktop) {
+            let _ = CloseDesktop(desktop);
+            return Err(e).context("SetThreadDesktop");
+        }
+
+        // The previous desktop is no longer in use by this thread
+        if let Some(previous) = ATTACHED_DESKTOP.with(|d| d.replace(Some(desktop))) {
+            let _ = CloseDesktop(previous);
+        }
+
+        let name = desktop_name(desktop).unwrap_or_default();
+        debug!("thread attached to input desktop \"{}\"", name);
+        Ok(name)
+    }
+}
+
+/// Returns why injected input is currently being dropped, or None if the
+/// input desktop is the one this process is attached to.
+///
+/// SendInput silently fails when the input desktop is a different (usually
+/// the secure "Winlogon") desktop, so callers use this to tell the viewer.
+#[cfg(target_os = "windows")]
+pub fn input_blocked_reason() -> Option<String> {
+    let input = match input_desktop_name() {
+        Some(name) => name,
+        None => return Some("secure desktop is active (UAC prompt or login screen)".to_string()),
+    };
+
+    if input.eq_ignore_ascii_case("Winlogon") {
+        return Some("secure desktop is active (UAC prompt or login screen)".to_string());
+    }
+
+    match thread_desktop_name() {
+        Some(current) if !current.eq_ignore_ascii_case(&input) => Some(format!(
+            "input desktop \"{}\" differs from capture desktop \"{}\"",
+            input, current
+        )),
+        _ => None,
+    }
+}
+
 /// Log the current session context for diagnostic purposes.
 #[cfg(target_os = "windows")]
 pub fn log_session_info() {
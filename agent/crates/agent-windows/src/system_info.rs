@@ -1,7 +1,11 @@
 use std::ffi::OsString;
 use std::os::windows::ffi::OsStringExt;
 
-use agent_platform::system_info::{CpuInfo, DiskInfo, MemoryInfo, NetworkInfo, SystemInfo};
+use agent_platform::system_info::{
+    CpuInfo, DiskInfo, FanSensor, InstalledSoftware, MemoryInfo, NetworkInfo, SensorInfo,
+    SystemInfo, TemperatureSensor, UserSession, UserSessionType,
+};
+use anyhow::{Context, Result};
 use windows::Win32::System::SystemInformation::{
     GetSystemInfo, GlobalMemoryStatusEx, MEMORYSTATUSEX, SYSTEM_INFO,
 };
@@ -32,34 +36,42 @@ impl SystemInfo for WindowsSystemInfo {
         std::env::consts::ARCH.to_string()
     }
 
-    fn cpu_info(&self) -> CpuInfo {
+    fn cpu_info(&self) -> Result<CpuInfo> {
         let model = read_cpu_model().unwrap_or_else(|| "Unknown CPU".to_string());
         let (cores, threads) = read_cpu_count();
-        let usage_percent = read_cpu_usage();
+        let usage_percent = read_cpu_usage()?;
 
-        CpuInfo {
+        Ok(CpuInfo {
             model,
             cores,
             threads,
             usage_percent,
-        }
+        })
     }
 
-    fn memory_info(&self) -> MemoryInfo {
-        read_memory_info().unwrap_or(MemoryInfo {
-            total_bytes: 0,
-            used_bytes: 0,
-            available_bytes: 0,
-        })
+    fn memory_info(&self) -> Result<MemoryInfo> {
+        read_memory_info()
     }
 
-    fn disk_info(&self) -> Vec<DiskInfo> {
+    fn disk_info(&self) -> Result<Vec<DiskInfo>> {
         read_disk_info()
     }
 
-    fn network_interfaces(&self) -> Vec<NetworkInfo> {
+    fn network_interfaces(&self) -> Result<Vec<NetworkInfo>> {
         read_network_info()
     }
+
+    fn user_sessions(&self) -> Result<Vec<UserSession>> {
+        unsafe { read_user_sessions() }
+    }
+
+    fn sensors(&self) -> SensorInfo {
+        read_sensors()
+    }
+
+    fn installed_software(&self) -> Result<Vec<InstalledSoftware>> {
+        read_installed_software()
+    }
 }
 
 fn hostname_string() -> Option<String> {
@@ -221,7 +233,7 @@ fn read_cpu_count() -> (u32, u32) {
     }
 }
 
-fn read_cpu_usage() -> f64 {
+fn read_cpu_usage() -> Result<f64> {
     // Use GetSystemTimes for a snapshot-based CPU usage
     // This gives total/idle since boot, so a single sample gives cumulative average.
     // For real-time usage, two samples with a delay would be needed.
@@ -232,15 +244,8 @@ fn read_cpu_usage() -> f64 {
         let mut kernel = windows::Win32::Foundation::FILETIME::default();
         let mut user = windows::Win32::Foundation::FILETIME::default();
 
-        if GetSystemTimes(
-            Some(&mut idle),
-            Some(&mut kernel),
-            Some(&mut user),
-        )
-        .is_err()
-        {
-            return 0.0;
-        }
+        GetSystemTimes(Some(&mut idle), Some(&mut kernel), Some(&mut user))
+            .context("GetSystemTimes failed")?;
 
         let idle_val = filetime_to_u64(&idle);
         let kernel_val = filetime_to_u64(&kernel);
@@ -250,10 +255,10 @@ fn read_cpu_usage() -> f64 {
         let busy = total - idle_val;
 
         if total == 0 {
-            return 0.0;
+            return Ok(0.0);
         }
 
-        (busy as f64 / total as f64) * 100.0
+        Ok((busy as f64 / total as f64) * 100.0)
     }
 }
 
@@ -261,19 +266,19 @@ fn filetime_to_u64(ft: &windows::Win32::Foundation::FILETIME) -> u64 {
     ((ft.dwHighDateTime as u64) << 32) | (ft.dwLowDateTime as u64)
 }
 
-fn read_memory_info() -> Option<MemoryInfo> {
+fn read_memory_info() -> Result<MemoryInfo> {
     unsafe {
         let mut status = MEMORYSTATUSEX {
             dwLength: std::mem::size_of::<MEMORYSTATUSEX>() as u32,
             ..Default::default()
         };
-        GlobalMemoryStatusEx(&mut status).ok()?;
+        GlobalMemoryStatusEx(&mut status).context("GlobalMemoryStatusEx failed")?;
 
         let total = status.ullTotalPhys;
         let available = status.ullAvailPhys;
         let used = total.saturating_sub(available);
 
-        Some(MemoryInfo {
+        Ok(MemoryInfo {
             total_bytes: total,
             used_bytes: used,
             available_bytes: available,
@@ -281,16 +286,16 @@ fn read_memory_info() -> Option<MemoryInfo> {
     }
 }
 
-fn read_disk_info() -> Vec<DiskInfo> {
-    use windows::Win32::Storage::FileSystem::GetLogicalDriveStringsW;
+fn read_disk_info() -> Result<Vec<DiskInfo>> {
+    use windows::core::PCWSTR;
     use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+    use windows::Win32::Storage::FileSystem::GetLogicalDriveStringsW;
     use windows::Win32::Storage::FileSystem::GetVolumeInformationW;
-    use windows::core::PCWSTR;
 
     let mut buf = [0u16; 512];
     let len = unsafe { GetLogicalDriveStringsW(Some(&mut buf)) } as usize;
     if len == 0 {
-        return Vec::new();
+        return Err(windows::core::Error::from_win32()).context("GetLogicalDriveStringsW failed");
     }
 
     let mut disks = Vec::new();
@@ -359,23 +364,21 @@ fn read_disk_info() -> Vec<DiskInfo> {
         });
     }
 
-    disks
+    Ok(disks)
 }
 
-fn read_network_info() -> Vec<NetworkInfo> {
+fn read_network_info() -> Result<Vec<NetworkInfo>> {
     // For a robust implementation, we'd use GetAdaptersAddresses from iphlpapi.
     // This requires the Win32_NetworkManagement_IpHelper feature.
     // For now, provide a basic implementation that detects interfaces.
     // Full implementation can be added when the feature is available.
 
     // Fallback: use std::process::Command to parse ipconfig output
-    let output = match std::process::Command::new("ipconfig")
+    let output = std::process::Command::new("ipconfig")
         .arg("/all")
         .output()
-    {
-        Ok(o) => String::from_utf8_lossy(&o.stdout).to_string(),
-        Err(_) => return Vec::new(),
-    };
+        .context("failed to run ipconfig")?;
+    let output = String::from_utf8_lossy(&output.stdout).to_string();
 
     let mut interfaces = Vec::new();
     let mut current_name: Option<String> = None;
@@ -395,6 +398,10 @@ fn read_network_info() -> Vec<NetworkInfo> {
                     mac_address: current_mac.take(),
                     ipv4: current_ipv4.take(),
                     ipv6: current_ipv6.take(),
+                    rx_bytes: None,
+                    tx_bytes: None,
+                    rx_bytes_per_sec: None,
+                    tx_bytes_per_sec: None,
                 });
             }
             current_name = Some(trimmed.trim_end_matches(':').to_string());
@@ -438,12 +445,448 @@ fn read_network_info() -> Vec<NetworkInfo> {
             mac_address: current_mac,
             ipv4: current_ipv4,
             ipv6: current_ipv6,
+            rx_bytes: None,
+            tx_bytes: None,
+            rx_bytes_per_sec: None,
+            tx_bytes_per_sec: None,
         });
     }
 
     // Filter out disconnected interfaces (no IPs at all)
-    interfaces
+    let counters = read_interface_counters();
+    Ok(interfaces
         .into_iter()
         .filter(|i| i.ipv4.is_some() || i.ipv6.is_some())
-        .collect()
+        .map(|mut iface| {
+            // ipconfig names sections "<type> adapter <alias>"
+            let alias = iface
+                .name
+                .split_once(" adapter ")
+                .map_or(iface.name.as_str(), |(_, a)| a);
+            if let Some(&(rx, tx)) = counters.get(alias) {
+                iface.rx_bytes = Some(rx);
+                iface.tx_bytes = Some(tx);
+            }
+            iface
+        })
+        .collect())
+}
+
+/// Received and sent byte counters of every interface, by alias (the name
+/// ipconfig shows). Empty if the table can't be read.
+fn read_interface_counters() -> std::collections::HashMap<String, (u64, u64)> {
+    use windows::Win32::NetworkManagement::IpHelper::{FreeMibTable, GetIfTable2, MIB_IF_TABLE2};
+
+    let mut counters = std::collections::HashMap::new();
+    let mut table: *mut MIB_IF_TABLE2 = std::ptr::null_mut();
+    if let Err(e) = unsafe { GetIfTable2(&mut table) }.ok() {
+        tracing::debug!("GetIfTable2 failed: {}", e);
+        return counters;
+    }
+
+    // SAFETY: GetIfTable2 succeeded, so `table` holds NumEntries rows until
+    // it is freed below
+    unsafe {
+        let rows =
+            std::slice::from_raw_parts((*table).Table.as_ptr(), (*table).NumEntries as usize);
+        for row in rows {
+            let len = row
+                .Alias
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(row.Alias.len());
+            let alias = OsString::from_wide(&row.Alias[..len])
+                .to_string_lossy()
+                .to_string();
+            counters.insert(alias, (row.InOctets, row.OutOctets));
+        }
+        FreeMibTable(table as *const _);
+    }
+    counters
+}
+
+/// Seconds between 1601-01-01 (FILETIME epoch) and 1970-01-01
+const FILETIME_UNIX_OFFSET_SECS: i64 = 11_644_473_600;
+/// WTSClientProtocolType values
+const WTS_PROTOCOL_CONSOLE: u16 = 0;
+const WTS_PROTOCOL_RDP: u16 = 2;
+
+/// Sessions with a user logged on, connected or not, from Terminal Services
+unsafe fn read_user_sessions() -> Result<Vec<UserSession>> {
+    use windows::Win32::System::RemoteDesktop::{
+        WTSEnumerateSessionsW, WTSFreeMemory, WTS_CURRENT_SERVER_HANDLE, WTS_SESSION_INFOW,
+    };
+
+    let mut info: *mut WTS_SESSION_INFOW = std::ptr::null_mut();
+    let mut count = 0u32;
+    WTSEnumerateSessionsW(WTS_CURRENT_SERVER_HANDLE, 0, 1, &mut info, &mut count)
+        .context("WTSEnumerateSessionsW failed")?;
+
+    let mut users = Vec::new();
+    for session in std::slice::from_raw_parts(info, count as usize) {
+        let Some(username) = query_session_string(session.SessionId).filter(|u| !u.is_empty())
+        else {
+            continue;
+        };
+        let session_type = match query_session_protocol(session.SessionId) {
+            Some(WTS_PROTOCOL_CONSOLE) => UserSessionType::Console,
+            Some(WTS_PROTOCOL_RDP) => UserSessionType::Rdp,
+            _ => UserSessionType::Other,
+        };
+        users.push(UserSession {
+            username,
+            session_type,
+            login_time: query_session_logon_time(session.SessionId),
+        });
+    }
+
+    WTSFreeMemory(info as *mut std::ffi::c_void);
+    Ok(users)
+}
+
+/// Query one WTS_INFO_CLASS of a session, passing the raw buffer to `read`
+unsafe fn query_session<T>(
+    session_id: u32,
+    class: windows::Win32::System::RemoteDesktop::WTS_INFO_CLASS,
+    read: impl FnOnce(*const u8, u32) -> Option<T>,
+) -> Option<T> {
+    use windows::core::PWSTR;
+    use windows::Win32::System::RemoteDesktop::{
+        WTSFreeMemory, WTSQuerySessionInformationW, WTS_CURRENT_SERVER_HANDLE,
+    };
+
+    let mut buf = PWSTR::null();
+    let mut len = 0u32;
+    WTSQuerySessionInformationW(
+        WTS_CURRENT_SERVER_HANDLE,
+        session_id,
+        class,
+        &mut buf,
+        &mut len,
+    )
+    .ok()?;
+    let value = read(buf.0 as *const u8, len);
+    WTSFreeMemory(buf.0 as *mut std::ffi::c_void);
+    value
+}
+
+unsafe fn query_session_string(session_id: u32) -> Option<String> {
+    use windows::Win32::System::RemoteDesktop::WTSUserName;
+    query_session(session_id, WTSUserName, |buf, _| {
+        windows::core::PCWSTR(buf as *const u16).to_string().ok()
+    })
+}
+
+unsafe fn query_session_protocol(session_id: u32) -> Option<u16> {
+    use windows::Win32::System::RemoteDesktop::WTSClientProtocolType;
+    query_session(session_id, WTSClientProtocolType, |buf, len| {
+        (len >= 2).then(|| std::ptr::read_unaligned(buf as *const u16))
+    })
+}
+
+unsafe fn query_session_logon_time(session_id: u32) -> Option<u64> {
+    use windows::Win32::System::RemoteDesktop::{WTSSessionInfo, WTSINFOW};
+    query_session(session_id, WTSSessionInfo, |buf, len| {
+        if (len as usize) < std::mem::size_of::<WTSINFOW>() {
+            return None;
+        }
+        let info = std::ptr::read_unaligned(buf as *const WTSINFOW);
+        let secs = info.LogonTime / 10_000_000 - FILETIME_UNIX_OFFSET_SECS;
+        u64::try_from(secs).ok().filter(|&t| t > 0)
+    })
+}
+
+/// Sensors from LibreHardwareMonitor's WMI provider when it is running,
+/// otherwise the ACPI thermal zones (temperatures only; Windows has no
+/// generic fan source). Runs on its own thread so COM can be initialized
+/// without touching the caller's apartment.
+fn read_sensors() -> SensorInfo {
+    let sensors = std::thread::spawn(|| unsafe {
+        use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED};
+
+        if CoInitializeEx(None, COINIT_MULTITHREADED).is_err() {
+            return SensorInfo::default();
+        }
+        let sensors = match read_lhm_sensors() {
+            Ok(sensors) if !sensors.is_empty() => sensors,
+            _ => read_acpi_thermal_zones().unwrap_or_default(),
+        };
+        CoUninitialize();
+        sensors
+    })
+    .join();
+
+    sensors.unwrap_or_default()
+}
+
+unsafe fn read_lhm_sensors() -> Result<SensorInfo> {
+    let mut sensors = SensorInfo::default();
+    let rows = wmi_query(
+        "ROOT\\LibreHardwareMonitor",
+        "SELECT Name, Parent, SensorType, Value FROM Sensor WHERE SensorType = 'Temperature' OR SensorType = 'Fan'",
+        &["Name", "Parent", "SensorType", "Value"],
+    )?;
+
+    for row in rows {
+        let [name, parent, kind, value] = &row[..] else {
+            continue;
+        };
+        let (Ok(name), Ok(value)) = (windows::core::BSTR::try_from(name), f64::try_from(value))
+        else {
+            continue;
+        };
+        // Parent is the hardware identifier (/intelcpu/0); keeps names unique
+        let name = match windows::core::BSTR::try_from(parent) {
+            Ok(parent) => format!("{} {}", parent, name),
+            Err(_) => name.to_string(),
+        };
+        match windows::core::BSTR::try_from(kind)
+            .map(|k| k.to_string())
+            .as_deref()
+        {
+            Ok("Temperature") => sensors.temperatures.push(TemperatureSensor {
+                name,
+                temp_c: value,
+            }),
+            Ok("Fan") => sensors.fans.push(FanSensor {
+                name,
+                rpm: value.max(0.0) as u32,
+            }),
+            _ => {}
+        }
+    }
+    Ok(sensors)
+}
+
+unsafe fn read_acpi_thermal_zones() -> Result<SensorInfo> {
+    let mut sensors = SensorInfo::default();
+    let rows = wmi_query(
+        "ROOT\\WMI",
+        "SELECT InstanceName, CurrentTemperature FROM MSAcpi_ThermalZoneTemperature",
+        &["InstanceName", "CurrentTemperature"],
+    )?;
+
+    for row in rows {
+        let [name, kelvin_tenths] = &row[..] else {
+            continue;
+        };
+        let (Ok(name), Ok(kelvin_tenths)) = (
+            windows::core::BSTR::try_from(name),
+            u32::try_from(kelvin_tenths),
+        ) else {
+            continue;
+        };
+        sensors.temperatures.push(TemperatureSensor {
+            name: name.to_string(),
+            temp_c: kelvin_tenths as f64 / 10.0 - 273.15,
+        });
+    }
+    Ok(sensors)
+}
+
+/// Run a WQL query against `namespace`, returning the requested properties
+/// of each result. COM must already be initialized on this thread.
+unsafe fn wmi_query(
+    namespace: &str,
+    query: &str,
+    properties: &[&str],
+) -> Result<Vec<Vec<windows::core::VARIANT>>> {
+    use windows::core::{BSTR, HSTRING, PCWSTR, VARIANT};
+    use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+    use windows::Win32::System::Wmi::{
+        IWbemClassObject, IWbemLocator, WbemLocator, WBEM_FLAG_FORWARD_ONLY,
+        WBEM_FLAG_RETURN_IMMEDIATELY, WBEM_INFINITE,
+    };
+
+    let locator: IWbemLocator = CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER)
+        .context("failed to create WMI locator")?;
+    let services = locator
+        .ConnectServer(
+            &BSTR::from(namespace),
+            &BSTR::new(),
+            &BSTR::new(),
+            &BSTR::new(),
+            0,
+            &BSTR::new(),
+            None,
+        )
+        .with_context(|| format!("failed to connect to WMI namespace {}", namespace))?;
+    let results = services
+        .ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from(query),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        )
+        .context("WMI query failed")?;
+
+    let names: Vec<HSTRING> = properties.iter().map(|p| HSTRING::from(*p)).collect();
+    let mut rows = Vec::new();
+    loop {
+        let mut objects: [Option<IWbemClassObject>; 1] = [None];
+        let mut returned = 0u32;
+        let _ = results.Next(WBEM_INFINITE.0, &mut objects, &mut returned);
+        let Some(object) = objects[0].take().filter(|_| returned > 0) else {
+            break;
+        };
+
+        let mut row = Vec::with_capacity(names.len());
+        for name in &names {
+            let mut value = VARIANT::default();
+            let _ = object.Get(PCWSTR(name.as_ptr()), 0, &mut value, None, None);
+            row.push(value);
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Uninstall key path, relative to HKLM and HKCU
+const UNINSTALL_KEY: &str = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
+/// 32-bit programs on 64-bit Windows register here instead (HKLM only)
+const UNINSTALL_KEY_WOW64: &str =
+    "SOFTWARE\\WOW6432Node\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
+
+fn read_installed_software() -> Result<Vec<InstalledSoftware>> {
+    use windows::Win32::System::Registry::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+
+    let mut software = Vec::new();
+    let mut found_any = false;
+    for (root, path) in [
+        (HKEY_LOCAL_MACHINE, UNINSTALL_KEY),
+        (HKEY_LOCAL_MACHINE, UNINSTALL_KEY_WOW64),
+        (HKEY_CURRENT_USER, UNINSTALL_KEY),
+    ] {
+        if let Some(entries) = unsafe { read_uninstall_entries(root, path) } {
+            found_any = true;
+            software.extend(entries);
+        }
+    }
+    if !found_any {
+        anyhow::bail!("failed to open the registry Uninstall keys");
+    }
+
+    // The same product can be registered for both views
+    software.sort_by(|a, b| {
+        a.name
+            .to_lowercase()
+            .cmp(&b.name.to_lowercase())
+            .then(a.version.cmp(&b.version))
+    });
+    software.dedup_by(|a, b| a.name == b.name && a.version == b.version);
+    Ok(software)
+}
+
+/// Read every product under one Uninstall key, or None if it can't be opened.
+/// Entries without a display name, and components hidden from Programs and
+/// Features (SystemComponent = 1), are skipped.
+unsafe fn read_uninstall_entries(
+    root: windows::Win32::System::Registry::HKEY,
+    path: &str,
+) -> Option<Vec<InstalledSoftware>> {
+    use windows::core::{PCWSTR, PWSTR};
+    use windows::Win32::Foundation::ERROR_NO_MORE_ITEMS;
+    use windows::Win32::System::Registry::{
+        RegCloseKey, RegEnumKeyExW, RegOpenKeyExW, HKEY, KEY_READ,
+    };
+
+    let wide_path: Vec<u16> = format!("{}\0", path).encode_utf16().collect();
+    let mut uninstall = HKEY::default();
+    if RegOpenKeyExW(
+        root,
+        PCWSTR(wide_path.as_ptr()),
+        0,
+        KEY_READ,
+        &mut uninstall,
+    )
+    .is_err()
+    {
+        return None;
+    }
+
+    let display_name: Vec<u16> = "DisplayName\0".encode_utf16().collect();
+    let display_version: Vec<u16> = "DisplayVersion\0".encode_utf16().collect();
+    let publisher: Vec<u16> = "Publisher\0".encode_utf16().collect();
+    let install_date: Vec<u16> = "InstallDate\0".encode_utf16().collect();
+    let system_component: Vec<u16> = "SystemComponent\0".encode_utf16().collect();
+
+    let mut entries = Vec::new();
+    let mut index = 0u32;
+    loop {
+        // Registry key names are at most 255 characters
+        let mut name = [0u16; 256];
+        let mut name_len = name.len() as u32;
+        let status = RegEnumKeyExW(
+            uninstall,
+            index,
+            PWSTR(name.as_mut_ptr()),
+            &mut name_len,
+            None,
+            PWSTR::null(),
+            None,
+            None,
+        );
+        index += 1;
+        if status == ERROR_NO_MORE_ITEMS {
+            break;
+        }
+        if status.is_err() {
+            continue;
+        }
+
+        let mut product = HKEY::default();
+        if RegOpenKeyExW(uninstall, PCWSTR(name.as_ptr()), 0, KEY_READ, &mut product).is_err() {
+            continue;
+        }
+
+        let hidden = read_reg_dword(product, &system_component) == Some(1);
+        if let Some(name) =
+            read_reg_string(product, &display_name).filter(|n| !hidden && !n.trim().is_empty())
+        {
+            entries.push(InstalledSoftware {
+                name: name.trim().to_string(),
+                version: read_reg_string(product, &display_version).filter(|v| !v.is_empty()),
+                publisher: read_reg_string(product, &publisher).filter(|p| !p.is_empty()),
+                install_date: read_reg_string(product, &install_date)
+                    .and_then(|d| format_install_date(&d)),
+            });
+        }
+        let _ = RegCloseKey(product);
+    }
+
+    let _ = RegCloseKey(uninstall);
+    Some(entries)
+}
+
+unsafe fn read_reg_dword(
+    hkey: windows::Win32::System::Registry::HKEY,
+    value_name: &[u16],
+) -> Option<u32> {
+    use windows::core::PCWSTR;
+    use windows::Win32::System::Registry::{RegQueryValueExW, REG_DWORD, REG_VALUE_TYPE};
+
+    let mut data_type = REG_VALUE_TYPE::default();
+    let mut value = [0u8; 4];
+    let mut size = value.len() as u32;
+    let status = RegQueryValueExW(
+        hkey,
+        PCWSTR(value_name.as_ptr()),
+        None,
+        Some(&mut data_type),
+        Some(value.as_mut_ptr()),
+        Some(&mut size),
+    );
+    if status.is_err() || data_type != REG_DWORD {
+        return None;
+    }
+    Some(u32::from_le_bytes(value))
+}
+
+/// InstallDate is written as YYYYMMDD; anything else is dropped
+fn format_install_date(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if raw.len() != 8 || !raw.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{}-{}-{}", &raw[..4], &raw[4..6], &raw[6..]))
 }
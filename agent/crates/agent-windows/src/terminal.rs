@@ -5,7 +5,9 @@ use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
 use tracing::{debug, info};
 use windows::Win32::Foundation::{CloseHandle, HANDLE};
 use windows::Win32::System::Console::{
-    ClosePseudoConsole, CreatePseudoConsole, ResizePseudoConsole, COORD, HPCON,
+    AttachConsole, ClosePseudoConsole, CreatePseudoConsole, FreeConsole, GetConsoleMode,
+    ResizePseudoConsole, SetConsoleMode, CONSOLE_MODE, COORD, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    HPCON,
 };
 use windows::Win32::System::Pipes::CreatePipe;
 use windows::Win32::System::Threading::{
@@ -15,8 +17,21 @@ use windows::Win32::System::Threading::{
 };
 use windows::core::PWSTR;
 
+/// `CreatePseudoConsole` flag: start from the cursor position of the
+/// attached terminal instead of the top-left corner. ConPTY asks for the
+/// position with a DSR query (`ESC[6n`) and holds output until the viewer
+/// answers with `ESC[row;colR`, which xterm-compatible terminals do
+/// automatically. Without it ConPTY assumes a blank screen and repaints from
+/// 1;1, which garbles programs that draw relative to the cursor.
+pub const PSEUDOCONSOLE_INHERIT_CURSOR: u32 = 0x1;
+
+/// ConPTY flags used unless configured otherwise
+pub const DEFAULT_CONPTY_FLAGS: u32 = PSEUDOCONSOLE_INHERIT_CURSOR;
+
 /// Windows terminal implementation using ConPTY (Pseudo Console)
 pub struct WindowsTerminal {
+    /// Flags passed to CreatePseudoConsole
+    conpty_flags: u32,
     hpc: Option<HPCON>,
     pipe_in: Option<OwnedHandle>,  // write end → goes to PTY stdin
     pipe_out: Option<OwnedHandle>, // read end → comes from PTY stdout
@@ -25,7 +40,14 @@ pub struct WindowsTerminal {
 
 impl WindowsTerminal {
     pub fn new() -> Self {
+        Self::with_conpty_flags(DEFAULT_CONPTY_FLAGS)
+    }
+
+    /// Create a terminal whose pseudo console uses the given
+    /// CreatePseudoConsole flags (0 for ConPTY's defaults).
+    pub fn with_conpty_flags(conpty_flags: u32) -> Self {
         Self {
+            conpty_flags,
             hpc: None,
             pipe_in: None,
             pipe_out: None,
@@ -62,10 +84,15 @@ unsafe impl Send for WindowsTerminal {}
 
 #[async_trait]
 impl Terminal for WindowsTerminal {
-    async fn spawn(&mut self, shell: Option<&str>, cols: u16, rows: u16) -> Result<()> {
-        let shell_path = shell
-            .map(String::from)
-            .unwrap_or_else(Self::detect_shell);
+    // ConPTY has no login-shell concept, so `login` is ignored here.
+    async fn spawn(
+        &mut self,
+        shell: Option<&str>,
+        cols: u16,
+        rows: u16,
+        _login: bool,
+    ) -> Result<()> {
+        let shell_path = shell.map(String::from).unwrap_or_else(Self::detect_shell);
 
         info!(
             "spawning terminal: shell={}, cols={}, rows={}",
@@ -89,8 +116,9 @@ impl Terminal for WindowsTerminal {
                 X: cols as i16,
                 Y: rows as i16,
             };
-            let hpc = CreatePseudoConsole(size, pty_input_read, pty_output_write, 0)
-                .context("CreatePseudoConsole")?;
+            let hpc =
+                CreatePseudoConsole(size, pty_input_read, pty_output_write, self.conpty_flags)
+                    .context("CreatePseudoConsole")?;
 
             // Close the pipe ends that the PTY owns
             let _ = CloseHandle(pty_input_read);
@@ -149,6 +177,10 @@ impl Terminal for WindowsTerminal {
             )
             .context("CreateProcessW")?;
 
+            if let Err(e) = enable_vt_processing(pi.dwProcessId) {
+                debug!("could not enable VT processing for the shell: {:#}", e);
+            }
+
             self.hpc = Some(hpc);
             self.pipe_in = Some(OwnedHandle::from_raw_handle(pty_input_write.0 as *mut _));
             self.pipe_out = Some(OwnedHandle::from_raw_handle(pty_output_read.0 as *mut _));
@@ -167,15 +199,19 @@ impl Terminal for WindowsTerminal {
         let handle = self.pipe_in.as_ref().context("terminal not spawned")?;
         let raw = HANDLE(handle.as_raw_handle() as *mut std::ffi::c_void);
 
-        unsafe {
+        let mut rest = data;
+        while !rest.is_empty() {
             let mut written: u32 = 0;
-            windows::Win32::Storage::FileSystem::WriteFile(
-                raw,
-                Some(data),
-                Some(&mut written),
-                None,
-            )
-            .context("WriteFile to PTY")?;
+            unsafe {
+                windows::Win32::Storage::FileSystem::WriteFile(
+                    raw,
+                    Some(rest),
+                    Some(&mut written),
+                    None,
+                )
+                .context("WriteFile to PTY")?;
+            }
+            rest = &rest[written as usize..];
         }
 
         Ok(())
@@ -248,6 +284,36 @@ impl Terminal for WindowsTerminal {
     }
 }
 
+/// Turn on ENABLE_VIRTUAL_TERMINAL_PROCESSING for the pseudo console's
+/// output buffer, so programs that write raw ANSI sequences (instead of
+/// calling the console color APIs) get their colors through to the viewer
+/// rather than printed as literal escape codes.
+///
+/// Console mode belongs to the console, so this attaches to the shell's
+/// console briefly. It fails harmlessly when the agent already owns a
+/// console (foreground mode); the shell's own mode applies then.
+unsafe fn enable_vt_processing(pid: u32) -> Result<()> {
+    AttachConsole(pid).context("AttachConsole")?;
+
+    let result = (|| -> Result<()> {
+        let conout = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("CONOUT$")
+            .context("open CONOUT$")?;
+        let handle = HANDLE(conout.as_raw_handle() as *mut std::ffi::c_void);
+
+        let mut mode = CONSOLE_MODE::default();
+        GetConsoleMode(handle, &mut mode).context("GetConsoleMode")?;
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING)
+            .context("SetConsoleMode")?;
+        Ok(())
+    })();
+
+    let _ = FreeConsole();
+    result
+}
+
 impl Drop for WindowsTerminal {
     fn drop(&mut self) {
         // Close the pseudo console
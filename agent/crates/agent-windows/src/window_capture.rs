@@ -0,0 +1,236 @@
+//! Single-window capture using PrintWindow with PW_RENDERFULLCONTENT.
+//! Captures the client area of one top-level window, including GPU/DWM
+//! rendered content that a plain BitBlt of the window DC comes back black for.
+
+use agent_platform::screen::{ScreenCapture, ScreenFrame};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use tracing::info;
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, RECT};
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, FindWindowW, GetClientRect, GetWindowTextW, IsIconic, IsWindow, IsWindowVisible,
+};
+
+/// PrintWindow flags (PW_RENDERFULLCONTENT isn't exported as a named constant in windows 0.58)
+const PW_CLIENTONLY: u32 = 0x00000001;
+const PW_RENDERFULLCONTENT: u32 = 0x00000002;
+
+/// Capture of a single window's client area.
+///
+/// Dimensions are fixed when the window is found; if it is resized later the
+/// frame is clipped or padded to the original size.
+pub struct WindowCapture {
+    hwnd: isize, // raw HWND value — isize is Send
+    width: u32,
+    height: u32,
+}
+
+unsafe impl Send for WindowCapture {}
+unsafe impl Sync for WindowCapture {}
+
+#[inline]
+fn h(raw: isize) -> HWND {
+    HWND(raw as *mut std::ffi::c_void)
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+struct TitleSearch {
+    needle: String,
+    found: Option<isize>,
+}
+
+/// EnumWindows callback: first visible window whose title contains the needle.
+unsafe extern "system" fn match_title(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let search = &mut *(lparam.0 as *mut TitleSearch);
+
+    if !IsWindowVisible(hwnd).as_bool() {
+        return BOOL(1);
+    }
+
+    let mut buf = [0u16; 512];
+    let len = GetWindowTextW(hwnd, &mut buf);
+    if len <= 0 {
+        return BOOL(1);
+    }
+
+    let title = String::from_utf16_lossy(&buf[..len as usize]).to_lowercase();
+    if title.contains(&search.needle) {
+        search.found = Some(hwnd.0 as isize);
+        return BOOL(0); // stop enumerating
+    }
+    BOOL(1)
+}
+
+impl WindowCapture {
+    /// Find the target window by handle, or by title: an exact match first,
+    /// then the first visible window whose title contains `title`
+    /// (case-insensitive). Fails if the window doesn't exist or is minimized,
+    /// so the caller can fall back to full-screen capture.
+    pub fn find(title: Option<&str>, handle: Option<u64>) -> Result<Self> {
+        let hwnd = match (handle, title) {
+            (Some(handle), _) => handle as isize,
+            (None, Some(title)) => {
+                find_by_title(title).with_context(|| format!("no window titled \"{}\"", title))?
+            }
+            (None, None) => bail!("no window title or handle given"),
+        };
+
+        unsafe {
+            if !IsWindow(h(hwnd)).as_bool() {
+                bail!("window 0x{:x} does not exist", hwnd);
+            }
+            if IsIconic(h(hwnd)).as_bool() {
+                bail!("window 0x{:x} is minimized", hwnd);
+            }
+        }
+
+        let (width, height) = client_size(hwnd)?;
+        Ok(Self {
+            hwnd,
+            width,
+            height,
+        })
+    }
+}
+
+fn find_by_title(title: &str) -> Option<isize> {
+    let wide = to_wide(title);
+    let exact = unsafe { FindWindowW(PCWSTR::null(), PCWSTR(wide.as_ptr())) };
+    if let Ok(hwnd) = exact {
+        if !hwnd.0.is_null() {
+            return Some(hwnd.0 as isize);
+        }
+    }
+
+    let mut search = TitleSearch {
+        needle: title.to_lowercase(),
+        found: None,
+    };
+    // Returns an error when the callback stops early; the result is in `search`
+    let _ = unsafe { EnumWindows(Some(match_title), LPARAM(&mut search as *mut _ as isize)) };
+    search.found
+}
+
+fn client_size(hwnd: isize) -> Result<(u32, u32)> {
+    let mut rect = RECT::default();
+    unsafe { GetClientRect(h(hwnd), &mut rect) }.context("GetClientRect failed")?;
+
+    let width = (rect.right - rect.left).max(0) as u32;
+    let height = (rect.bottom - rect.top).max(0) as u32;
+    if width == 0 || height == 0 {
+        bail!("window 0x{:x} has an empty client area", hwnd);
+    }
+    Ok((width, height))
+}
+
+#[async_trait]
+impl ScreenCapture for WindowCapture {
+    async fn init(&mut self) -> Result<(u32, u32)> {
+        info!(
+            "capturing window 0x{:x} ({}x{}) via PrintWindow",
+            self.hwnd, self.width, self.height
+        );
+        Ok((self.width, self.height))
+    }
+
+    async fn capture_frame(&mut self) -> Result<ScreenFrame> {
+        unsafe {
+            use windows::Win32::Graphics::Gdi::{
+                CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, GetDC,
+                GetDIBits, ReleaseDC, SelectObject, BITMAPINFO, BITMAPINFOHEADER, DIB_RGB_COLORS,
+            };
+            use windows::Win32::Storage::Xps::{PrintWindow, PRINT_WINDOW_FLAGS};
+
+            let hwnd = h(self.hwnd);
+            if !IsWindow(hwnd).as_bool() {
+                bail!("captured window was closed");
+            }
+            if IsIconic(hwnd).as_bool() {
+                bail!("captured window is minimized");
+            }
+
+            let hdc_window = GetDC(hwnd);
+            if hdc_window.0.is_null() {
+                bail!("GetDC(window) failed");
+            }
+
+            let hdc_mem = CreateCompatibleDC(hdc_window);
+            if hdc_mem.0.is_null() {
+                ReleaseDC(hwnd, hdc_window);
+                bail!("CreateCompatibleDC failed");
+            }
+
+            let hbmp = CreateCompatibleBitmap(hdc_window, self.width as i32, self.height as i32);
+            if hbmp.0.is_null() {
+                let _ = DeleteDC(hdc_mem);
+                ReleaseDC(hwnd, hdc_window);
+                bail!("CreateCompatibleBitmap failed");
+            }
+
+            let old_bmp = SelectObject(hdc_mem, hbmp);
+
+            let printed = PrintWindow(
+                hwnd,
+                hdc_mem,
+                PRINT_WINDOW_FLAGS(PW_CLIENTONLY | PW_RENDERFULLCONTENT),
+            );
+
+            let mut bmi = BITMAPINFO {
+                bmiHeader: BITMAPINFOHEADER {
+                    biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+                    biWidth: self.width as i32,
+                    biHeight: -(self.height as i32), // negative = top-down
+                    biPlanes: 1,
+                    biBitCount: 32,
+                    biCompression: 0, // BI_RGB
+                    biSizeImage: 0,
+                    biXPelsPerMeter: 0,
+                    biYPelsPerMeter: 0,
+                    biClrUsed: 0,
+                    biClrImportant: 0,
+                },
+                bmiColors: [Default::default()],
+            };
+
+            let mut data = vec![0u8; (self.width * self.height * 4) as usize];
+            let lines = GetDIBits(
+                hdc_mem,
+                hbmp,
+                0,
+                self.height,
+                Some(data.as_mut_ptr() as *mut _),
+                &mut bmi,
+                DIB_RGB_COLORS,
+            );
+
+            // Cleanup GDI objects
+            SelectObject(hdc_mem, old_bmp);
+            let _ = DeleteObject(hbmp);
+            let _ = DeleteDC(hdc_mem);
+            ReleaseDC(hwnd, hdc_window);
+
+            if !printed.as_bool() {
+                bail!("PrintWindow failed");
+            }
+            if lines == 0 {
+                bail!("GetDIBits returned 0 lines");
+            }
+
+            Ok(ScreenFrame {
+                width: self.width,
+                height: self.height,
+                data,
+                stride: self.width * 4,
+            })
+        }
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
@@ -632,6 +632,7 @@ app.get('/api/agent/status/:deviceId', authMiddleware, (req: Request, res: Respo
     os: conn?.os || null,
     arch: conn?.arch || null,
     hostname: conn?.hostname || null,
+    capabilities: conn?.capabilities ?? [],
     lastHeartbeat: conn?.lastHeartbeat || null,
     activeSessions: conn ? conn.activeSessions.size : 0,
   });
@@ -650,6 +651,7 @@ app.get('/api/agent/connections', authMiddleware, (_req: Request, res: Response)
       os: conn?.os,
       arch: conn?.arch,
       hostname: conn?.hostname,
+      capabilities: conn?.capabilities,
       lastHeartbeat: conn?.lastHeartbeat,
       activeSessions: conn ? conn.activeSessions.size : 0,
     };
@@ -990,6 +992,15 @@ app.post('/api/commands', (req: Request, res: Response) => {
     }
   }
 
+  if (type === 'START_SERVICE' || type === 'STOP_SERVICE' || type === 'RESTART_SERVICE') {
+    if (!payload?.name) {
+      res.status(400).json({
+        error: `${type} requires payload with name`,
+      });
+      return;
+    }
+  }
+
   // Enrich INSTALL_APK payload with policy settings (foregroundApp, autoStart, etc.)
   let enrichedPayload = payload || {};
   if (type === 'INSTALL_APK' && device.policyId && payload?.packageName) {
@@ -1074,6 +1085,15 @@ app.post('/api/devices/:id/commands', (req: Request, res: Response) => {
     }
   }
 
+  if (type === 'START_SERVICE' || type === 'STOP_SERVICE' || type === 'RESTART_SERVICE') {
+    if (!payload?.name) {
+      res.status(400).json({
+        error: `${type} requires payload with name`,
+      });
+      return;
+    }
+  }
+
   // For START_REMOTE, inject the signaling URL server-side so the web UI
   // doesn't need to know the external server address.
   const finalPayload = { ...(payload || {}) };
@@ -10,7 +10,7 @@ export const COMMAND_TYPES = [
   'UPDATE_APP', 'CLEAR_APP_DATA', 'CLEAR_APP_CACHE', 'ENABLE_APP', 'DISABLE_APP',
   'SET_DEFAULT_APP', 'LAUNCH_APP', 'STOP_APP',
   // Device Control
-  'UNLOCK', 'SET_VOLUME', 'SET_BRIGHTNESS', 'TAKE_SCREENSHOT', 'SCREEN_ON', 'SCREEN_OFF',
+  'UNLOCK', 'SET_VOLUME', 'SET_BRIGHTNESS', 'TAKE_SCREENSHOT', 'SCREENSHOT', 'SCREEN_ON', 'SCREEN_OFF',
   // Security
   'LOST_MODE', 'EXIT_LOST_MODE', 'SET_PASSWORD', 'CLEAR_PASSWORD', 'ENCRYPT_DEVICE',
   // Policy
@@ -18,11 +18,21 @@ export const COMMAND_TYPES = [
   // Telemetry
   'REFRESH_TELEMETRY', 'GET_LOCATION', 'SYNC_APPS',
   // Files
-  'LIST_FILES', 'DOWNLOAD_FILE', 'UPLOAD_FILE', 'DELETE_FILE',
+  'LIST_FILES', 'DOWNLOAD_FILE', 'UPLOAD_FILE', 'DELETE_FILE', 'DOWNLOAD_URL',
   // Shell
-  'RUN_SHELL',
+  'RUN_SHELL', 'GET_ENV',
+  // Inventory
+  'LIST_INSTALLED_SOFTWARE',
+  // Services
+  'LIST_SERVICES', 'START_SERVICE', 'STOP_SERVICE', 'RESTART_SERVICE',
+  // Clock
+  'GET_TIME', 'SET_TIME', 'SYNC_TIME',
+  // Network
+  'FLUSH_DNS', 'RENEW_DHCP',
+  // Connection
+  'RECONNECT', 'GET_CONNECTION_STATS',
   // Messaging
-  'SEND_MESSAGE', 'PLAY_SOUND',
+  'SEND_MESSAGE', 'PLAY_SOUND', 'NOTIFY_USER',
 ] as const;
 
 /**
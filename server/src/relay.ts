@@ -6,6 +6,7 @@ import { getJwtSecret } from './middleware/auth';
 import { agentConnectionStore, AgentConnection } from './services/agentConnectionStore';
 import { deviceStore } from './services/deviceStore';
 import { telemetryStore } from './services/telemetryStore';
+import { commandStore } from './services/commandStore';
 import { getDatabase } from './db/connection';
 
 // Binary protocol constants (must match agent-core/protocol.rs)
@@ -17,6 +18,12 @@ const AUTH_RESPONSE = 0x02;
 const HEARTBEAT = 0x03;
 const HEARTBEAT_ACK = 0x04;
 const AGENT_INFO = 0x05;
+const COMMAND = 0x06;
+const COMMAND_RESULT = 0x07;
+const ERROR = 0x08;
+const COMMAND_SYNC_REQ = 0x09;
+const COMMAND_SYNC_RESP = 0x0a;
+const COMMAND_PROGRESS = 0x0b;
 
 // Session types
 const DESKTOP_OPEN = 0x10;
@@ -25,6 +32,7 @@ const DESKTOP_FRAME = 0x12;
 const DESKTOP_INPUT = 0x13;
 const DESKTOP_RESIZE = 0x14;
 const DESKTOP_QUALITY = 0x15;
+const DESKTOP_EVENT = 0x16;
 
 const TERMINAL_OPEN = 0x20;
 const TERMINAL_CLOSE = 0x21;
@@ -40,10 +48,25 @@ const FILE_UPLOAD_DATA = 0x35;
 const FILE_UPLOAD_DONE = 0x36;
 const FILE_DELETE_REQ = 0x37;
 const FILE_RESULT = 0x38;
+const FILE_SEARCH_RESULT = 0x3a;
+const FILE_UPLOAD_ACK = 0x3c;
 
 const TELEMETRY_REQ = 0x40;
 const TELEMETRY_DATA = 0x41;
 
+const AUDIO_OPEN = 0x50;
+const AUDIO_CLOSE = 0x51;
+const AUDIO_DATA = 0x52;
+
+// Request ids for commands replayed from the queue, kept clear of the
+// small ids viewers use
+const SYNC_REQUEST_ID_BASE = 0x8000_0000;
+let nextSyncRequestId = 0;
+
+// `${deviceId}:${requestId}` of replayed commands -> command id, so their
+// COMMAND_RESULT can complete the queued command
+const syncedCommands = new Map<string, string>();
+
 // Heartbeat interval & timeout
 const HEARTBEAT_INTERVAL_MS = 30_000;
 const HEARTBEAT_TIMEOUT_MS = 90_000;
@@ -118,6 +141,7 @@ export function createRelayWss(): WebSocketServer {
       | 'desktop'
       | 'terminal'
       | 'files'
+      | 'audio'
       | null;
     const token = params.get('token');
 
@@ -361,6 +385,10 @@ function handleAgentMessage(
       handleAgentInfo(deviceId, payload);
       break;
 
+    case COMMAND_SYNC_REQ:
+      handleCommandSync(conn, deviceId, header.requestId);
+      break;
+
     case TELEMETRY_DATA:
       handleAgentTelemetry(deviceId, payload);
       relayToViewer(conn, header, payload);
@@ -369,13 +397,30 @@ function handleAgentMessage(
     // Session messages — relay to viewer on the corresponding channel
     case DESKTOP_FRAME:
     case DESKTOP_RESIZE:
+    case DESKTOP_EVENT:
     case TERMINAL_DATA:
     case TERMINAL_CLOSE:
     case FILE_LIST_RESP:
     case FILE_DOWNLOAD_DATA:
     case FILE_UPLOAD_DONE:
     case FILE_RESULT:
-    case 0x07: // COMMAND_RESULT
+    case FILE_SEARCH_RESULT:
+    case FILE_UPLOAD_ACK:
+    case AUDIO_OPEN:
+    case AUDIO_DATA:
+    case AUDIO_CLOSE:
+    case COMMAND_PROGRESS:
+      relayToViewer(conn, header, payload);
+      break;
+
+    case COMMAND_RESULT:
+      completeSyncedCommand(deviceId, header.requestId, payload);
+      relayToViewer(conn, header, payload);
+      break;
+
+    // Typed failure for a request: { request_id, code, message }
+    case ERROR:
+      console.warn(`[Relay] Agent ${deviceId} error on channel ${header.channel}: ${payload.toString('utf-8')}`);
       relayToViewer(conn, header, payload);
       break;
 
@@ -393,6 +438,12 @@ function handleAgentInfo(deviceId: string, payload: Buffer): void {
       `[Relay] Agent info for ${deviceId}: ${info.hostname} (${info.os_name} ${info.os_version})`
     );
 
+    // Older agents don't advertise capabilities
+    const conn = agentConnectionStore.getAgent(deviceId);
+    if (conn && Array.isArray(info.capabilities)) {
+      conn.capabilities = info.capabilities.filter((c: unknown): c is string => typeof c === 'string');
+    }
+
     // Update device info in database
     const db = getDatabase();
     try {
@@ -418,6 +469,44 @@ function handleAgentInfo(deviceId: string, payload: Buffer): void {
   }
 }
 
+/**
+ * Replay commands queued while the agent was disconnected: each goes out as
+ * a COMMAND, followed by a COMMAND_SYNC_RESP with the count.
+ */
+function handleCommandSync(conn: AgentConnection, deviceId: string, requestId: number): void {
+  const commands = commandStore.getPendingCommands(deviceId);
+  for (const command of commands) {
+    const syncId = SYNC_REQUEST_ID_BASE + (nextSyncRequestId++ % 0x7fff_ffff);
+    syncedCommands.set(`${deviceId}:${syncId}`, command.id);
+    const body = Buffer.from(JSON.stringify({ ...command.payload, type: command.type }), 'utf-8');
+    sendBinary(conn.ws, encodeMessage(COMMAND, 0, syncId, body));
+  }
+
+  if (commands.length > 0) {
+    console.log(`[Relay] Replayed ${commands.length} queued command(s) to ${deviceId}`);
+  }
+  const done = Buffer.from(JSON.stringify({ count: commands.length }), 'utf-8');
+  sendBinary(conn.ws, encodeMessage(COMMAND_SYNC_RESP, 0, requestId, done));
+}
+
+function completeSyncedCommand(deviceId: string, requestId: number, payload: Buffer): void {
+  const key = `${deviceId}:${requestId}`;
+  const commandId = syncedCommands.get(key);
+  if (!commandId) return;
+  syncedCommands.delete(key);
+
+  try {
+    const result = JSON.parse(payload.toString('utf-8'));
+    commandStore.acknowledgeCommand(
+      commandId,
+      result.success === false ? 'failed' : 'completed',
+      typeof result.error === 'string' ? result.error : undefined
+    );
+  } catch {
+    commandStore.acknowledgeCommand(commandId, 'failed', 'malformed command result');
+  }
+}
+
 function handleAgentTelemetry(deviceId: string, payload: Buffer): void {
   try {
     const data = JSON.parse(payload.toString('utf-8'));
@@ -425,6 +514,14 @@ function handleAgentTelemetry(deviceId: string, payload: Buffer): void {
       `[Relay] Telemetry from ${deviceId}: cpu=${data.cpu?.usage_percent?.toFixed(1)}%, mem=${data.memory?.used_bytes}/${data.memory?.total_bytes}`
     );
 
+    // Sections the agent couldn't read are null, with the reason in status
+    const failed = Object.entries(data.status ?? {})
+      .filter(([, s]) => (s as { status?: string }).status === 'error')
+      .map(([name, s]) => `${name}: ${(s as { message?: string }).message}`);
+    if (failed.length > 0) {
+      console.warn(`[Relay] Telemetry from ${deviceId} incomplete: ${failed.join('; ')}`);
+    }
+
     // Map agent telemetry into the existing telemetry store
     // Overlap: memory, uptime. Agent also provides CPU, disks, network (stored as JSON).
     telemetryStore.updateTelemetry({
@@ -495,7 +592,7 @@ function relayToViewer(
 function handleViewerConnection(
   ws: WebSocket,
   deviceId: string,
-  sessionType: 'desktop' | 'terminal' | 'files',
+  sessionType: 'desktop' | 'terminal' | 'files' | 'audio',
   token: string
 ): void {
   // Validate JWT token
@@ -591,7 +688,7 @@ function handleViewerConnection(
 function sendSessionOpen(
   conn: AgentConnection,
   channelId: number,
-  sessionType: 'desktop' | 'terminal' | 'files'
+  sessionType: 'desktop' | 'terminal' | 'files' | 'audio'
 ): void {
   let type: number;
   let payload: object;
@@ -609,6 +706,10 @@ function sendSessionOpen(
       type = FILE_LIST_REQ;
       payload = { path: '/' };
       break;
+    case 'audio':
+      type = AUDIO_OPEN;
+      payload = { bitrate: 64000 };
+      break;
     default:
       return;
   }
@@ -620,7 +721,7 @@ function sendSessionOpen(
 function sendSessionClose(
   conn: AgentConnection,
   channelId: number,
-  sessionType: 'desktop' | 'terminal' | 'files'
+  sessionType: 'desktop' | 'terminal' | 'files' | 'audio'
 ): void {
   if (conn.ws.readyState !== WebSocket.OPEN) return;
 
@@ -632,6 +733,9 @@ function sendSessionClose(
     case 'terminal':
       type = TERMINAL_CLOSE;
       break;
+    case 'audio':
+      type = AUDIO_CLOSE;
+      break;
     default:
       return;
   }
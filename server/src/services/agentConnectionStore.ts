@@ -7,6 +7,8 @@ export interface AgentConnection {
   os: string;
   arch: string;
   hostname: string;
+  /** Features the agent advertised in AGENT_INFO, e.g. 'desktop', 'audio' */
+  capabilities: string[];
   lastHeartbeat: number;
   activeSessions: Map<number, ViewerSession>;
   nextChannelId: number;
@@ -15,7 +17,7 @@ export interface AgentConnection {
 export interface ViewerSession {
   ws: WebSocket;
   channelId: number;
-  sessionType: 'desktop' | 'terminal' | 'files';
+  sessionType: 'desktop' | 'terminal' | 'files' | 'audio';
   userId: string;
 }
 
@@ -51,6 +53,7 @@ class AgentConnectionStore {
       os: info.os,
       arch: info.arch,
       hostname: info.hostname,
+      capabilities: [],
       lastHeartbeat: Date.now(),
       activeSessions: new Map(),
       nextChannelId: 1,
@@ -110,7 +113,7 @@ class AgentConnectionStore {
   allocateChannel(
     deviceId: string,
     viewerWs: WebSocket,
-    sessionType: 'desktop' | 'terminal' | 'files',
+    sessionType: 'desktop' | 'terminal' | 'files' | 'audio',
     userId: string
   ): number | null {
     const conn = this.connections.get(deviceId);
@@ -18,10 +18,55 @@ interface FileEntry {
 
 interface DownloadState {
   path: string;
-  chunks: Map<number, Uint8Array>;
+  chunks: Map<number, Promise<Uint8Array>>;
   total: number;
 }
 
+interface UploadAckState {
+  requestId: number;
+  /** Highest chunk seq the agent has acknowledged, -1 before the first ack */
+  acked: number;
+  /** False once the agent turns out not to send acks (older agents) */
+  windowed: boolean;
+  wake: (() => void) | null;
+}
+
+// FILE_DOWNLOAD_DATA chunk encodings for compressed downloads (matches agent protocol.rs)
+const FILE_ENCODING_GZIP = 1;
+
+const UPLOAD_CHUNK_SIZE = 64 * 1024;
+// The agent acks every UPLOAD_ACK_EVERY chunks; no more than
+// UPLOAD_WINDOW_CHUNKS may be in flight unacknowledged
+const UPLOAD_ACK_EVERY = 16;
+const UPLOAD_WINDOW_CHUNKS = 64;
+// With no ack at all by then, the agent predates acks and the upload streams
+const UPLOAD_ACK_TIMEOUT_MS = 10_000;
+
+const CRC_TABLE = (() => {
+  const table = new Uint32Array(256);
+  for (let n = 0; n < 256; n++) {
+    let c = n;
+    for (let k = 0; k < 8; k++) {
+      c = c & 1 ? 0xedb88320 ^ (c >>> 1) : c >>> 1;
+    }
+    table[n] = c >>> 0;
+  }
+  return table;
+})();
+
+function crc32(data: Uint8Array): number {
+  let crc = 0xffffffff;
+  for (let i = 0; i < data.length; i++) {
+    crc = CRC_TABLE[(crc ^ data[i]) & 0xff] ^ (crc >>> 8);
+  }
+  return (crc ^ 0xffffffff) >>> 0;
+}
+
+async function gunzip(data: Uint8Array): Promise<Uint8Array> {
+  const stream = new Blob([data as BlobPart]).stream().pipeThrough(new DecompressionStream('gzip'));
+  return new Uint8Array(await new Response(stream).arrayBuffer());
+}
+
 export function RelayFileBrowser({ deviceId }: RelayFileBrowserProps) {
   const { token } = useAuth();
   const [currentPath, setCurrentPath] = useState('/');
@@ -31,6 +76,7 @@ export function RelayFileBrowser({ deviceId }: RelayFileBrowserProps) {
   const [statusMessage, setStatusMessage] = useState<string | null>(null);
 
   const downloadRef = useRef<DownloadState | null>(null);
+  const uploadAckRef = useRef<UploadAckState | null>(null);
   const requestIdRef = useRef(1);
 
   const nextRequestId = () => requestIdRef.current++;
@@ -60,6 +106,17 @@ export function RelayFileBrowser({ deviceId }: RelayFileBrowserProps) {
       } catch {
         // ignore
       }
+    } else if (msg.header.type === Protocol.FILE_UPLOAD_ACK) {
+      const upload = uploadAckRef.current;
+      if (upload && upload.requestId === msg.header.requestId) {
+        try {
+          const ack = Protocol.parseJsonPayload<{ seq: number; bytes: number }>(msg);
+          upload.acked = Math.max(upload.acked, ack.seq);
+          upload.wake?.();
+        } catch {
+          // ignore
+        }
+      }
     } else if (msg.header.type === Protocol.FILE_UPLOAD_DONE) {
       setStatusMessage('Upload complete');
       setTimeout(() => setStatusMessage(null), 2000);
@@ -125,8 +182,31 @@ export function RelayFileBrowser({ deviceId }: RelayFileBrowserProps) {
     navigateTo(parent);
   }, [currentPath, navigateTo]);
 
+  const saveDownload = useCallback(async (dl: DownloadState) => {
+    try {
+      // Reassemble
+      const parts: Uint8Array[] = [];
+      for (let i = 0; i < dl.total; i++) {
+        const chunk = dl.chunks.get(i);
+        if (chunk) parts.push(await chunk);
+      }
+      const blob = new Blob(parts as BlobPart[]);
+      const url = URL.createObjectURL(blob);
+      const a = document.createElement('a');
+      a.href = url;
+      a.download = dl.path.split('/').pop() || 'download';
+      a.click();
+      URL.revokeObjectURL(url);
+      setStatusMessage('Download complete');
+      setTimeout(() => setStatusMessage(null), 2000);
+    } catch {
+      setError('Failed to decompress download');
+    }
+  }, []);
+
   const handleDownloadChunk = useCallback((msg: Protocol.ProtocolMessage) => {
-    if (msg.payload.length < 8) return;
+    // Downloads are requested with a CRC-32 and an encoding byte after seq/total
+    if (msg.payload.length < 13) return;
 
     const view = new DataView(
       msg.payload.buffer,
@@ -135,40 +215,44 @@ export function RelayFileBrowser({ deviceId }: RelayFileBrowserProps) {
     );
     const seq = view.getUint32(0, true);
     const total = view.getUint32(4, true);
-    const data = msg.payload.slice(8);
+    const crc = view.getUint32(8, true);
+    const encoding = msg.payload[12];
+    const data = msg.payload.slice(13);
 
     const dl = downloadRef.current;
     if (!dl) return;
 
-    dl.chunks.set(seq, data);
+    // Corrupted chunk: ask for just that one again
+    if (crc32(data) !== crc) {
+      const ch = channelId ?? 0;
+      send(Protocol.encodeJson(Protocol.FILE_DOWNLOAD_REQ, ch, nextRequestId(), {
+        path: dl.path,
+        compress: true,
+        checksum: true,
+        chunk: seq,
+      }));
+      return;
+    }
+
+    dl.chunks.set(seq, encoding === FILE_ENCODING_GZIP ? gunzip(data) : Promise.resolve(data));
     dl.total = total;
 
     // Check if all chunks received
     if (dl.chunks.size >= total) {
-      // Reassemble
-      const parts: Uint8Array[] = [];
-      for (let i = 0; i < total; i++) {
-        const chunk = dl.chunks.get(i);
-        if (chunk) parts.push(chunk);
-      }
-      const blob = new Blob(parts as BlobPart[]);
-      const url = URL.createObjectURL(blob);
-      const a = document.createElement('a');
-      a.href = url;
-      a.download = dl.path.split('/').pop() || 'download';
-      a.click();
-      URL.revokeObjectURL(url);
       downloadRef.current = null;
-      setStatusMessage('Download complete');
-      setTimeout(() => setStatusMessage(null), 2000);
+      void saveDownload(dl);
     }
-  }, []);
+  }, [saveDownload, send, channelId]);
 
   const requestDownload = useCallback(
     (path: string) => {
       downloadRef.current = { path, chunks: new Map(), total: 0 };
       const ch = channelId ?? 0;
-      const msg = Protocol.encodeJson(Protocol.FILE_DOWNLOAD_REQ, ch, nextRequestId(), { path });
+      const msg = Protocol.encodeJson(Protocol.FILE_DOWNLOAD_REQ, ch, nextRequestId(), {
+        path,
+        compress: true,
+        checksum: true,
+      });
       send(msg);
       setStatusMessage(`Downloading ${path.split('/').pop()}...`);
     },
@@ -206,19 +290,40 @@ export function RelayFileBrowser({ deviceId }: RelayFileBrowserProps) {
       const ch = channelId ?? 0;
       const reqId = nextRequestId();
 
+      const upload: UploadAckState = { requestId: reqId, acked: -1, windowed: true, wake: null };
+      uploadAckRef.current = upload;
+      const waitForAck = () =>
+        new Promise<void>((resolve) => {
+          const timer = setTimeout(resolve, UPLOAD_ACK_TIMEOUT_MS);
+          upload.wake = () => {
+            clearTimeout(timer);
+            resolve();
+          };
+        });
+
       // Send FILE_UPLOAD_START
       const startMsg = Protocol.encodeJson(Protocol.FILE_UPLOAD_START, ch, reqId, {
         path: uploadPath,
         size: bytes.length,
+        ack_every: UPLOAD_ACK_EVERY,
       });
       send(startMsg);
+      setStatusMessage(`Uploading ${file.name}...`);
 
       // Send FILE_UPLOAD_DATA (raw bytes with seq header)
-      const chunkSize = 64 * 1024;
-      const totalChunks = Math.max(1, Math.ceil(bytes.length / chunkSize));
+      const totalChunks = Math.max(1, Math.ceil(bytes.length / UPLOAD_CHUNK_SIZE));
       for (let i = 0; i < totalChunks; i++) {
-        const start = i * chunkSize;
-        const end = Math.min(start + chunkSize, bytes.length);
+        // Hold off while too much is unacknowledged, so a slow agent isn't swamped
+        while (upload.windowed && i - upload.acked > UPLOAD_WINDOW_CHUNKS) {
+          const before = upload.acked;
+          await waitForAck();
+          if (upload.acked === before && before < 0) {
+            upload.windowed = false;
+          }
+        }
+
+        const start = i * UPLOAD_CHUNK_SIZE;
+        const end = Math.min(start + UPLOAD_CHUNK_SIZE, bytes.length);
         const chunk = bytes.slice(start, end);
 
         const payload = new Uint8Array(4 + chunk.length);
@@ -230,7 +335,9 @@ export function RelayFileBrowser({ deviceId }: RelayFileBrowserProps) {
         send(dataMsg);
       }
 
-      setStatusMessage(`Uploading ${file.name}...`);
+      if (uploadAckRef.current === upload) {
+        uploadAckRef.current = null;
+      }
     };
     input.click();
   }, [send, channelId, currentPath]);
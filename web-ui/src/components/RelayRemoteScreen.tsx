@@ -19,6 +19,8 @@ export function RelayRemoteScreen({ deviceId }: RelayRemoteScreenProps) {
   const containerRef = useRef<HTMLDivElement>(null);
   const [screenSize, setScreenSize] = useState<{ w: number; h: number } | null>(null);
   const [scale, setScale] = useState(1);
+  // Why injected input isn't reaching the desktop (UAC prompt, login screen)
+  const [inputBlocked, setInputBlocked] = useState<string | null>(null);
   const screenSizeRef = useRef(screenSize);
   screenSizeRef.current = screenSize;
 
@@ -32,6 +34,17 @@ export function RelayRemoteScreen({ deviceId }: RelayRemoteScreenProps) {
         const h = view.getUint16(2, true);
         setScreenSize({ w, h });
       }
+    } else if (msg.header.type === Protocol.DESKTOP_EVENT) {
+      try {
+        const event = Protocol.parseJsonPayload<{ event: string; reason?: string }>(msg);
+        if (event.event === 'input_blocked') {
+          setInputBlocked(event.reason || 'Input is blocked on the remote desktop');
+        } else if (event.event === 'input_restored') {
+          setInputBlocked(null);
+        }
+      } catch {
+        // ignore
+      }
     }
   }, []);
 
@@ -284,6 +297,20 @@ export function RelayRemoteScreen({ deviceId }: RelayRemoteScreenProps) {
         </div>
       </div>
 
+      {inputBlocked && (
+        <div
+          style={{
+            padding: '0.375rem 1rem',
+            backgroundColor: '#5c3c00',
+            color: '#ffd54f',
+            fontSize: '0.8125rem',
+            flexShrink: 0,
+          }}
+        >
+          {inputBlocked}
+        </div>
+      )}
+
       {/* Canvas area */}
       <div
         style={{
@@ -330,6 +357,11 @@ export function RelayRemoteScreen({ deviceId }: RelayRemoteScreenProps) {
 
 // --- Tile rendering ---
 
+// DESKTOP_FRAME encodings (matches agent desktop.rs)
+const ENCODING_RAW = 2;
+const ENCODING_RGB565 = 3;
+const ENCODING_PALETTE8 = 4;
+
 async function renderTile(
   canvas: HTMLCanvasElement | null,
   payload: Uint8Array
@@ -339,27 +371,82 @@ async function renderTile(
   const view = new DataView(payload.buffer, payload.byteOffset, payload.byteLength);
   const x = view.getUint16(0, true);
   const y = view.getUint16(2, true);
-  // w and h are at offset 4 and 6 (used for future reference but blob decode handles size)
-  // const w = view.getUint16(4, true);
-  // const h = view.getUint16(6, true);
-  // encoding = payload[8], flags = payload[9]
-  const jpegData = payload.subarray(10);
+  const w = view.getUint16(4, true);
+  const h = view.getUint16(6, true);
+  const encoding = payload[8];
+  // flags = payload[9]
+  const data = payload.subarray(10);
 
-  if (jpegData.length === 0) return;
+  if (data.length === 0) return;
 
   try {
-    const blob = new Blob([jpegData as BlobPart], { type: 'image/jpeg' });
-    const bitmap = await createImageBitmap(blob);
     const ctx = canvas.getContext('2d');
-    if (ctx) {
-      ctx.drawImage(bitmap, x, y);
+    if (!ctx) return;
+
+    if (encoding === ENCODING_RAW) {
+      ctx.putImageData(rawToImageData(data, w, h), x, y);
+      return;
     }
+
+    if (encoding === ENCODING_RGB565 || encoding === ENCODING_PALETTE8) {
+      const pixels = await inflate(data);
+      ctx.putImageData(reducedToImageData(pixels, w, h, encoding), x, y);
+      return;
+    }
+
+    const blob = new Blob([data as BlobPart], { type: 'image/jpeg' });
+    const bitmap = await createImageBitmap(blob);
+    ctx.drawImage(bitmap, x, y);
     bitmap.close();
   } catch {
     // Ignore decode errors for individual tiles
   }
 }
 
+/** Decompress a zlib stream */
+async function inflate(data: Uint8Array): Promise<Uint8Array> {
+  const stream = new Blob([data as BlobPart]).stream().pipeThrough(new DecompressionStream('deflate'));
+  return new Uint8Array(await new Response(stream).arrayBuffer());
+}
+
+/** Expand uncompressed RGB tile pixels to RGBA */
+function rawToImageData(pixels: Uint8Array, w: number, h: number): ImageData {
+  const image = new ImageData(w, h);
+  const out = image.data;
+  for (let i = 0; i < w * h; i++) {
+    out[i * 4] = pixels[i * 3];
+    out[i * 4 + 1] = pixels[i * 3 + 1];
+    out[i * 4 + 2] = pixels[i * 3 + 2];
+    out[i * 4 + 3] = 255;
+  }
+  return image;
+}
+
+/** Expand RGB565 or RGB332 tile pixels to RGBA */
+function reducedToImageData(pixels: Uint8Array, w: number, h: number, encoding: number): ImageData {
+  const image = new ImageData(w, h);
+  const out = image.data;
+  for (let i = 0; i < w * h; i++) {
+    let r: number, g: number, b: number;
+    if (encoding === ENCODING_RGB565) {
+      const v = pixels[i * 2] | (pixels[i * 2 + 1] << 8);
+      r = ((v >> 11) & 0x1f) * 255 / 31;
+      g = ((v >> 5) & 0x3f) * 255 / 63;
+      b = (v & 0x1f) * 255 / 31;
+    } else {
+      const v = pixels[i];
+      r = (v >> 5) * 255 / 7;
+      g = ((v >> 2) & 0x07) * 255 / 7;
+      b = (v & 0x03) * 255 / 3;
+    }
+    out[i * 4] = r;
+    out[i * 4 + 1] = g;
+    out[i * 4 + 2] = b;
+    out[i * 4 + 3] = 255;
+  }
+  return image;
+}
+
 // --- Keyboard scancode mapping ---
 // Maps KeyboardEvent.code to Linux evdev scancodes
 
@@ -11,7 +11,7 @@ export interface UseRelayWebSocketOptions {
   /** Device ID to connect to */
   deviceId: string;
   /** Session type */
-  sessionType: 'desktop' | 'terminal' | 'files';
+  sessionType: 'desktop' | 'terminal' | 'files' | 'audio';
   /** Auth token (JWT) */
   token: string;
   /** Auto-connect on mount */
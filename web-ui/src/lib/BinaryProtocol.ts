@@ -17,6 +17,9 @@ export const HEARTBEAT_ACK = 0x04;
 export const AGENT_INFO = 0x05;
 export const COMMAND = 0x06;
 export const COMMAND_RESULT = 0x07;
+// Typed failure reply: { request_id, code, message }
+export const ERROR = 0x08;
+export const COMMAND_PROGRESS = 0x0b;
 
 // Desktop (channel 1+)
 export const DESKTOP_OPEN = 0x10;
@@ -25,6 +28,8 @@ export const DESKTOP_FRAME = 0x12;
 export const DESKTOP_INPUT = 0x13;
 export const DESKTOP_RESIZE = 0x14;
 export const DESKTOP_QUALITY = 0x15;
+// JSON { event, reason? } about the session (input blocked, capture restarted, ...)
+export const DESKTOP_EVENT = 0x16;
 
 // Terminal (channel 1+)
 export const TERMINAL_OPEN = 0x20;
@@ -42,6 +47,8 @@ export const FILE_UPLOAD_DATA = 0x35;
 export const FILE_UPLOAD_DONE = 0x36;
 export const FILE_DELETE_REQ = 0x37;
 export const FILE_RESULT = 0x38;
+// Upload progress, sent every `ack_every` chunks: { seq, bytes }
+export const FILE_UPLOAD_ACK = 0x3c;
 
 // Telemetry (channel 0)
 export const TELEMETRY_REQ = 0x40;
@@ -53,6 +60,8 @@ export const INPUT_MOUSE_BUTTON = 0x02;
 export const INPUT_MOUSE_SCROLL = 0x03;
 export const INPUT_KEY_EVENT = 0x04;
 export const INPUT_TYPE_TEXT = 0x05;
+// [keysym: u32][action: u8][mods: u8], layout-independent
+export const INPUT_KEY_SYM = 0x08;
 
 // --- Interfaces ---
 
@@ -226,6 +235,7 @@ export function typeName(type: number): string {
     [AGENT_INFO]: 'AGENT_INFO',
     [COMMAND]: 'COMMAND',
     [COMMAND_RESULT]: 'COMMAND_RESULT',
+    [COMMAND_PROGRESS]: 'COMMAND_PROGRESS',
     [DESKTOP_OPEN]: 'DESKTOP_OPEN',
     [DESKTOP_CLOSE]: 'DESKTOP_CLOSE',
     [DESKTOP_FRAME]: 'DESKTOP_FRAME',
@@ -245,6 +255,7 @@ export function typeName(type: number): string {
     [FILE_UPLOAD_DONE]: 'FILE_UPLOAD_DONE',
     [FILE_DELETE_REQ]: 'FILE_DELETE_REQ',
     [FILE_RESULT]: 'FILE_RESULT',
+    [FILE_UPLOAD_ACK]: 'FILE_UPLOAD_ACK',
     [TELEMETRY_REQ]: 'TELEMETRY_REQ',
     [TELEMETRY_DATA]: 'TELEMETRY_DATA',
   };
@@ -254,6 +254,7 @@ export type CommandType =
   | 'SET_VOLUME'
   | 'SET_BRIGHTNESS'
   | 'TAKE_SCREENSHOT'
+  | 'SCREENSHOT'
   | 'SCREEN_ON'
   | 'SCREEN_OFF'
   | 'LOST_MODE'
@@ -270,9 +271,24 @@ export type CommandType =
   | 'DOWNLOAD_FILE'
   | 'UPLOAD_FILE'
   | 'DELETE_FILE'
+  | 'DOWNLOAD_URL'
   | 'RUN_SHELL'
+  | 'GET_ENV'
+  | 'LIST_INSTALLED_SOFTWARE'
+  | 'LIST_SERVICES'
+  | 'START_SERVICE'
+  | 'STOP_SERVICE'
+  | 'RESTART_SERVICE'
+  | 'GET_TIME'
+  | 'SET_TIME'
+  | 'SYNC_TIME'
+  | 'FLUSH_DNS'
+  | 'RENEW_DHCP'
+  | 'RECONNECT'
+  | 'GET_CONNECTION_STATS'
   | 'SEND_MESSAGE'
-  | 'PLAY_SOUND';
+  | 'PLAY_SOUND'
+  | 'NOTIFY_USER';
 
 export type CommandStatus = 'pending' | 'delivered' | 'executing' | 'completed' | 'failed';
 